@@ -0,0 +1,816 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::{HeaderName, HeaderValue, header},
+    response::{IntoResponseParts, ResponseParts},
+};
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use moka::{
+    Expiry,
+    future::{Cache, CacheBuilder},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::warn;
+use wictk_core::{CacheMetadata, Conditional, WictkError, format_http_date};
+
+mod redis_cache;
+#[cfg(test)]
+mod test_redis;
+
+pub use redis_cache::RedisCache;
+
+pub const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// How a value was served from the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheStatus {
+    /// Served from a fresh entry
+    Hit,
+    /// Served from an expired entry while it is refreshed in the background
+    Stale,
+    /// Fetched from the upstream for this request
+    Miss,
+}
+
+impl CacheStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Miss => "MISS",
+        }
+    }
+
+    fn metric_label(&self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "hit",
+            CacheStatus::Stale => "stale",
+            CacheStatus::Miss => "miss",
+        }
+    }
+}
+
+/// Cache status and age of a response, sent as `X-Cache`, `Age`,
+/// `Last-Modified` and `Cache-Control` headers. `Cache-Control` is `public`
+/// here and made `private` by the `ApiKeyLayer` when keys are required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheInfo {
+    pub status: CacheStatus,
+    pub age: Duration,
+    /// Time left until the entry is stale, clients may reuse the response as long
+    pub max_age: Duration,
+}
+
+impl CacheInfo {
+    /// Combines the info of two values used in the same response, keeping the
+    /// least fresh status, the oldest age and the shortest max age.
+    pub fn combine(self, other: CacheInfo) -> CacheInfo {
+        CacheInfo {
+            status: self.status.max(other.status),
+            age: self.age.max(other.age),
+            max_age: self.max_age.min(other.max_age),
+        }
+    }
+}
+
+impl IntoResponseParts for CacheInfo {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        let headers = res.headers_mut();
+        headers.insert(X_CACHE, HeaderValue::from_static(self.status.as_str()));
+        headers.insert(header::AGE, HeaderValue::from(self.age.as_secs()));
+        let fetched_at = Utc::now() - self.age;
+        if let Ok(last_modified) = HeaderValue::from_str(&format_http_date(&fetched_at)) {
+            headers.insert(header::LAST_MODIFIED, last_modified);
+        }
+        if let Ok(cache_control) =
+            HeaderValue::from_str(&format!("public, max-age={}", self.max_age.as_secs()))
+        {
+            headers.insert(header::CACHE_CONTROL, cache_control);
+        }
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry<V> {
+    value: V,
+    /// Wall clock time, so entries shared through Redis keep their age
+    fetched_at: DateTime<Utc>,
+    /// Caching headers the upstream sent with the value
+    #[serde(default)]
+    metadata: CacheMetadata,
+}
+
+impl<V> Entry<V> {
+    fn new(value: V, metadata: CacheMetadata) -> Self {
+        Self {
+            value,
+            fetched_at: Utc::now(),
+            metadata,
+        }
+    }
+
+    /// Builds the entry for a revalidation, keeping the cached value when the
+    /// upstream answered that it has not changed.
+    fn revalidated(previous: Option<Entry<V>>, result: Conditional<V>) -> Result<Self, WictkError> {
+        match (result, previous) {
+            (Conditional::Modified(value, metadata), _) => Ok(Entry::new(value, metadata)),
+            (Conditional::NotModified(metadata), Some(previous)) => Ok(Entry::new(
+                previous.value,
+                CacheMetadata {
+                    expires: metadata.expires,
+                    last_modified: metadata.last_modified.or(previous.metadata.last_modified),
+                },
+            )),
+            (Conditional::NotModified(_), None) => Err(WictkError::parse(
+                "304 Not Modified response without a cached value",
+            )),
+        }
+    }
+
+    fn age(&self) -> Duration {
+        (Utc::now() - self.fetched_at).to_std().unwrap_or_default()
+    }
+
+    /// The upstream `Expires` time, or `fresh_for` after the entry was fetched
+    fn fresh_until(&self, fresh_for: Duration) -> DateTime<Utc> {
+        self.metadata
+            .expires
+            .unwrap_or_else(|| after(self.fetched_at, fresh_for))
+    }
+
+    /// Whether the upstream asked not to be called again yet
+    fn unexpired(&self) -> bool {
+        self.metadata
+            .expires
+            .is_some_and(|expires| expires > Utc::now())
+    }
+}
+
+fn after(time: DateTime<Utc>, duration: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| time.checked_add_signed(duration))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+fn until(time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+/// Evicts each entry `max_staleness` after it stops being fresh.
+#[derive(Debug, Clone, Copy)]
+struct Staleness {
+    fresh_for: Duration,
+    max_staleness: Duration,
+}
+
+impl Staleness {
+    fn evict_at<V>(&self, entry: &Entry<V>) -> DateTime<Utc> {
+        after(entry.fresh_until(self.fresh_for), self.max_staleness)
+    }
+
+    fn time_to_live<V>(&self, entry: &Entry<V>) -> Duration {
+        until(self.evict_at(entry))
+    }
+}
+
+impl<V> Expiry<String, Entry<V>> for Staleness {
+    fn expire_after_create(
+        &self,
+        _key: &String,
+        entry: &Entry<V>,
+        _created_at: Instant,
+    ) -> Option<Duration> {
+        Some(self.time_to_live(entry))
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        entry: &Entry<V>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(self.time_to_live(entry))
+    }
+}
+
+/// A cache that coalesces concurrent misses for the same key and serves
+/// stale entries while they are refreshed in the background.
+///
+/// Entries are fresh until the upstream `Expires` time, or for `fresh_for`
+/// when the upstream does not send one. They are then served stale for at
+/// most `max_staleness` before they are evicted and the next request has to
+/// wait for the upstream again.
+///
+/// Entries are kept in memory, and optionally in Redis as a second level
+/// shared with other replicas.
+#[derive(Debug, Clone)]
+pub struct SwrCache<V: Clone + Send + Sync + 'static> {
+    /// Labels the cache in metrics
+    name: &'static str,
+    entries: Cache<String, Entry<V>>,
+    shared: Option<RedisCache>,
+    staleness: Staleness,
+    refreshing: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<V> SwrCache<V>
+where
+    V: Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    pub fn new(
+        name: &'static str,
+        max_capacity: u64,
+        fresh_for: Duration,
+        max_staleness: Duration,
+    ) -> Self {
+        let staleness = Staleness {
+            fresh_for,
+            max_staleness,
+        };
+        Self {
+            name,
+            entries: CacheBuilder::new(max_capacity)
+                .expire_after(staleness)
+                .build(),
+            shared: None,
+            staleness,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Uses `redis` as a second level cache, with keys below `namespace`.
+    pub fn with_redis(mut self, redis: &RedisCache, namespace: &str) -> Self {
+        self.shared = Some(redis.namespaced(namespace));
+        self
+    }
+
+    /// Returns the cached value for `key`, awaiting `fetch` on a miss.
+    ///
+    /// Concurrent misses for the same key share a single `fetch`. A stale
+    /// value is returned immediately, and `fetch` is spawned to refresh it
+    /// unless a refresh of the key is already running.
+    pub async fn get_or_fetch<F>(&self, key: &str, fetch: F) -> Result<(V, CacheInfo), WictkError>
+    where
+        F: Future<Output = Result<V, WictkError>> + Send + 'static,
+    {
+        self.get_or_fetch_conditional(key, unconditional(fetch))
+            .await
+    }
+
+    /// Like [`SwrCache::get_or_fetch`], but stale entries are revalidated by
+    /// passing their `Last-Modified` time to `fetch`, which may answer that
+    /// the cached value has not changed.
+    pub async fn get_or_fetch_conditional<F, Fut>(
+        &self,
+        key: &str,
+        fetch: F,
+    ) -> Result<(V, CacheInfo), WictkError>
+    where
+        F: FnOnce(Option<DateTime<Utc>>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Conditional<V>, WictkError>> + Send + 'static,
+    {
+        if let Some(entry) = self.cached(key).await {
+            let age = entry.age();
+            let fresh_until = entry.fresh_until(self.staleness.fresh_for);
+            if Utc::now() < fresh_until {
+                self.count(CacheStatus::Hit);
+                return Ok((
+                    entry.value,
+                    CacheInfo {
+                        status: CacheStatus::Hit,
+                        age,
+                        max_age: until(fresh_until),
+                    },
+                ));
+            }
+            self.count(CacheStatus::Stale);
+            self.refresh(key, entry.clone(), fetch);
+            return Ok((
+                entry.value,
+                CacheInfo {
+                    status: CacheStatus::Stale,
+                    age,
+                    max_age: Duration::ZERO,
+                },
+            ));
+        }
+
+        self.count(CacheStatus::Miss);
+        let cache = self.clone();
+        let shared_key = key.to_string();
+        let entry = self
+            .entries
+            .try_get_with(key.to_string(), async move {
+                let entry = Entry::revalidated(None, fetch(None).await?)?;
+                cache.store_shared(&shared_key, &entry).await;
+                Ok(entry)
+            })
+            .await
+            .map_err(|err: Arc<WictkError>| (*err).clone())?;
+        let age = entry.age();
+        let max_age = until(entry.fresh_until(self.staleness.fresh_for));
+        Ok((
+            entry.value,
+            CacheInfo {
+                status: CacheStatus::Miss,
+                age,
+                max_age,
+            },
+        ))
+    }
+
+    fn count(&self, status: CacheStatus) {
+        counter!("cache_requests_total", "cache" => self.name, "status" => status.metric_label())
+            .increment(1);
+    }
+
+    /// Fetches `key` and stores it, whether or not the cached entry is still fresh.
+    pub async fn prefetch<F>(&self, key: &str, fetch: F) -> Result<(), WictkError>
+    where
+        F: Future<Output = Result<V, WictkError>> + Send + 'static,
+    {
+        let entry = Entry::revalidated(None, unconditional(fetch)(None).await?)?;
+        self.store(key, entry).await;
+        Ok(())
+    }
+
+    /// Revalidates the cached entry for `key`, or fetches it when missing.
+    /// Entries are left alone until the upstream `Expires` time has passed.
+    pub async fn prefetch_conditional<F, Fut>(&self, key: &str, fetch: F) -> Result<(), WictkError>
+    where
+        F: FnOnce(Option<DateTime<Utc>>) -> Fut,
+        Fut: Future<Output = Result<Conditional<V>, WictkError>>,
+    {
+        let previous = self.cached(key).await;
+        if previous.as_ref().is_some_and(Entry::unexpired) {
+            return Ok(());
+        }
+        let since = previous
+            .as_ref()
+            .and_then(|entry| entry.metadata.last_modified);
+        let entry = Entry::revalidated(previous, fetch(since).await?)?;
+        self.store(key, entry).await;
+        Ok(())
+    }
+
+    /// Looks up `key` in memory, then in Redis, ignoring entries past the max staleness.
+    async fn cached(&self, key: &str) -> Option<Entry<V>> {
+        let entry = match self.entries.get(key).await {
+            Some(entry) => entry,
+            None => {
+                let entry: Entry<V> = self.shared.as_ref()?.get(key).await?;
+                self.entries.insert(key.to_string(), entry.clone()).await;
+                entry
+            }
+        };
+        if self.staleness.evict_at(&entry) <= Utc::now() {
+            self.entries.invalidate(key).await;
+            return None;
+        }
+        Some(entry)
+    }
+
+    async fn store(&self, key: &str, entry: Entry<V>) {
+        self.store_shared(key, &entry).await;
+        self.entries.insert(key.to_string(), entry).await;
+    }
+
+    async fn store_shared(&self, key: &str, entry: &Entry<V>) {
+        if let Some(shared) = &self.shared {
+            shared
+                .set(key, entry, self.staleness.time_to_live(entry))
+                .await;
+        }
+    }
+
+    fn refresh<F, Fut>(&self, key: &str, previous: Entry<V>, fetch: F)
+    where
+        F: FnOnce(Option<DateTime<Utc>>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Conditional<V>, WictkError>> + Send + 'static,
+    {
+        if !self.refreshing.lock().unwrap().insert(key.to_string()) {
+            return;
+        }
+        let cache = self.clone();
+        let key = key.to_string();
+        let guard = RefreshGuard {
+            refreshing: self.refreshing.clone(),
+            key: key.clone(),
+        };
+        tokio::spawn(async move {
+            let _guard = guard;
+            let since = previous.metadata.last_modified;
+            match fetch(since)
+                .await
+                .and_then(|result| Entry::revalidated(Some(previous), result))
+            {
+                Ok(entry) => cache.store(&key, entry).await,
+                Err(err) => warn!("Failed to refresh cache entry {}: {}", key, err),
+            }
+        });
+    }
+}
+
+/// Clears the refreshing mark of a key when its refresh ends, also when the
+/// fetch panics, so the entry can be refreshed again.
+struct RefreshGuard {
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    key: String,
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        self.refreshing.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// A boxed fetch that may be answered with `304 Not Modified`
+pub type ConditionalFetch<V> =
+    Pin<Box<dyn Future<Output = Result<Conditional<V>, WictkError>> + Send>>;
+
+/// Adapts a fetch without upstream caching headers to the conditional API.
+fn unconditional<V, F>(
+    fetch: F,
+) -> impl FnOnce(Option<DateTime<Utc>>) -> ConditionalFetch<V> + Send + 'static
+where
+    V: 'static,
+    F: Future<Output = Result<V, WictkError>> + Send + 'static,
+{
+    move |_| {
+        Box::pin(async move {
+            fetch
+                .await
+                .map(|value| Conditional::Modified(value, CacheMetadata::default()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use wictk_core::Upstream;
+
+    use super::*;
+
+    fn counting_fetch(
+        calls: &Arc<AtomicUsize>,
+        delay: Duration,
+    ) -> impl Future<Output = Result<usize, WictkError>> + Send + 'static {
+        let calls = calls.clone();
+        async move {
+            tokio::time::sleep(delay).await;
+            Ok(calls.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_misses() {
+        let cache = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let mut lookups = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            let cache = cache.clone();
+            let fetch = counting_fetch(&calls, Duration::from_millis(50));
+            lookups.spawn(async move { cache.get_or_fetch("oslo", fetch).await });
+        }
+        let results = lookups.join_all().await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        for result in results {
+            let (value, info) = result.unwrap();
+            assert_eq!(value, 1);
+            assert_eq!(info.status, CacheStatus::Miss);
+        }
+
+        let (value, info) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(info.status, CacheStatus::Hit);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn serves_stale_while_refreshing() {
+        let cache = SwrCache::new(
+            "test",
+            10,
+            Duration::from_millis(200),
+            Duration::from_secs(60),
+        );
+        let calls = Arc::new(AtomicUsize::new(0));
+        cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        let (value, info) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::from_millis(20)))
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+        assert_eq!(info.status, CacheStatus::Stale);
+        assert!(info.age >= Duration::from_millis(200));
+
+        // A second stale read does not start another refresh
+        let (value, _) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(value, 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let (value, info) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(value, 2);
+        assert_eq!(info.status, CacheStatus::Hit);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refreshes_again_after_a_panicking_refresh() {
+        let cache = SwrCache::new(
+            "test",
+            10,
+            Duration::from_millis(100),
+            Duration::from_secs(60),
+        );
+        let calls = Arc::new(AtomicUsize::new(0));
+        cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        let (_, info) = cache
+            .get_or_fetch("oslo", async { panic!("refresh failed") })
+            .await
+            .unwrap();
+        assert_eq!(info.status, CacheStatus::Stale);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn evicts_after_max_staleness() {
+        let cache = SwrCache::new(
+            "test",
+            10,
+            Duration::from_millis(20),
+            Duration::from_millis(20),
+        );
+        let calls = Arc::new(AtomicUsize::new(0));
+        cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let (value, info) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(value, 2);
+        assert_eq!(info.status, CacheStatus::Miss);
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache: SwrCache<usize> =
+            SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60));
+        let err = WictkError::UpstreamTimeout {
+            upstream: Upstream::Met,
+        };
+
+        let result = cache.get_or_fetch("oslo", async move { Err(err) }).await;
+        assert!(result.is_err());
+
+        let (value, info) = cache.get_or_fetch("oslo", async { Ok(7) }).await.unwrap();
+        assert_eq!(value, 7);
+        assert_eq!(info.status, CacheStatus::Miss);
+    }
+
+    #[tokio::test]
+    async fn replicas_share_entries_through_redis() {
+        let redis = RedisCache::connect(&test_redis::start().await)
+            .await
+            .unwrap();
+        let first = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60))
+            .with_redis(&redis, "test");
+        let second = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60))
+            .with_redis(&redis, "test");
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let (value, info) = first
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!((value, info.status), (1, CacheStatus::Miss));
+
+        let (value, info) = second
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!((value, info.status), (1, CacheStatus::Hit));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn shared_entries_past_max_age_are_refetched() {
+        let redis = RedisCache::connect(&test_redis::start().await)
+            .await
+            .unwrap()
+            .namespaced("test");
+        let old = Entry {
+            value: 1,
+            fetched_at: Utc::now() - chrono::Duration::hours(2),
+            metadata: CacheMetadata::default(),
+        };
+        redis.set("oslo", &old, Duration::from_secs(60)).await;
+
+        let cache = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60))
+            .with_redis(&redis, "test");
+        let (value, info) = cache.get_or_fetch("oslo", async { Ok(2) }).await.unwrap();
+        assert_eq!((value, info.status), (2, CacheStatus::Miss));
+    }
+
+    #[tokio::test]
+    async fn prefetch_replaces_fresh_entries() {
+        let cache = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60));
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        cache
+            .prefetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+        let (value, info) = cache
+            .get_or_fetch("oslo", counting_fetch(&calls, Duration::ZERO))
+            .await
+            .unwrap();
+
+        assert_eq!((value, info.status), (2, CacheStatus::Hit));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    fn metadata(expires_in: Option<Duration>, last_modified: &str) -> CacheMetadata {
+        CacheMetadata {
+            expires: expires_in.map(|expires_in| after(Utc::now(), expires_in)),
+            last_modified: Some(last_modified.parse().unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_expires_overrides_fresh_for() {
+        let cache = SwrCache::new(
+            "test",
+            10,
+            Duration::from_millis(10),
+            Duration::from_secs(60),
+        );
+        cache
+            .get_or_fetch_conditional("oslo", |_| async {
+                let expires_in = Some(Duration::from_secs(60));
+                Ok(Conditional::Modified(
+                    1,
+                    metadata(expires_in, "2025-01-20T12:00:00Z"),
+                ))
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let (value, info) = cache.get_or_fetch("oslo", async { Ok(2) }).await.unwrap();
+        assert_eq!((value, info.status), (1, CacheStatus::Hit));
+    }
+
+    #[tokio::test]
+    async fn not_modified_keeps_cached_value() {
+        let cache = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60));
+        cache
+            .get_or_fetch_conditional("oslo", |_| async {
+                Ok(Conditional::Modified(
+                    1,
+                    metadata(Some(Duration::ZERO), "2025-01-20T12:00:00Z"),
+                ))
+            })
+            .await
+            .unwrap();
+
+        let (value, info) = cache
+            .get_or_fetch_conditional("oslo", |since| async move {
+                assert_eq!(since, Some("2025-01-20T12:00:00Z".parse().unwrap()));
+                Ok(Conditional::NotModified(CacheMetadata {
+                    expires: Some(after(Utc::now(), Duration::from_secs(60))),
+                    last_modified: None,
+                }))
+            })
+            .await
+            .unwrap();
+        assert_eq!((value, info.status), (1, CacheStatus::Stale));
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let entry = cache.cached("oslo").await.unwrap();
+        assert_eq!(entry.value, 1);
+        assert!(entry.unexpired());
+        assert_eq!(
+            entry.metadata.last_modified,
+            Some("2025-01-20T12:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn prefetch_conditional_waits_for_expires() {
+        let cache = SwrCache::new("test", 10, Duration::from_secs(60), Duration::from_secs(60));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fetch = |expires_in| {
+            let calls = calls.clone();
+            move |_| async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Conditional::Modified(
+                    1,
+                    metadata(expires_in, "2025-01-20T12:00:00Z"),
+                ))
+            }
+        };
+
+        let expires_in = Some(Duration::from_secs(60));
+        cache
+            .prefetch_conditional("oslo", fetch(expires_in))
+            .await
+            .unwrap();
+        cache
+            .prefetch_conditional("oslo", fetch(expires_in))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        cache
+            .prefetch_conditional("bergen", fetch(Some(Duration::ZERO)))
+            .await
+            .unwrap();
+        cache
+            .prefetch_conditional("bergen", fetch(None))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn combine_keeps_least_fresh() {
+        let hit = CacheInfo {
+            status: CacheStatus::Hit,
+            age: Duration::from_secs(30),
+            max_age: Duration::from_secs(270),
+        };
+        let stale = CacheInfo {
+            status: CacheStatus::Stale,
+            age: Duration::from_secs(400),
+            max_age: Duration::ZERO,
+        };
+        let miss = CacheInfo {
+            status: CacheStatus::Miss,
+            age: Duration::ZERO,
+            max_age: Duration::from_secs(300),
+        };
+
+        assert_eq!(hit.combine(stale), stale);
+        assert_eq!(
+            stale.combine(miss),
+            CacheInfo {
+                status: CacheStatus::Miss,
+                age: Duration::from_secs(400),
+                max_age: Duration::ZERO,
+            }
+        );
+        assert_eq!(hit.combine(miss).max_age, Duration::from_secs(270));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fmt::Debug, time::Duration};
+
+use anyhow::Context;
+use redis::{
+    AsyncCommands,
+    aio::{ConnectionManager, ConnectionManagerConfig},
+};
+use serde::{Serialize, de::DeserializeOwned};
+use tracing::warn;
+
+/// Prefix of every key written by the backend
+const KEY_PREFIX: &str = "wictk";
+
+/// A Redis connection used as a second level cache shared between replicas.
+///
+/// Values are stored as JSON. Redis failures are logged and treated as cache
+/// misses, so an unavailable Redis only costs extra upstream requests.
+#[derive(Clone)]
+pub struct RedisCache {
+    connection: ConnectionManager,
+    namespace: String,
+}
+
+impl Debug for RedisCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisCache")
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+impl RedisCache {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url).context("Invalid Redis URL")?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(Some(Duration::from_secs(2)))
+            .set_response_timeout(Some(Duration::from_secs(1)))
+            .set_number_of_retries(1);
+        let connection = ConnectionManager::new_with_config(client, config)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self {
+            connection,
+            namespace: KEY_PREFIX.to_string(),
+        })
+    }
+
+    /// Returns a handle to the same Redis that keeps its keys below `namespace`.
+    pub fn namespaced(&self, namespace: &str) -> Self {
+        Self {
+            connection: self.connection.clone(),
+            namespace: format!("{KEY_PREFIX}:{namespace}"),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.namespace, key)
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        match connection.get::<_, Option<String>>(&key).await {
+            Ok(Some(value)) => serde_json::from_str(&value)
+                .inspect_err(|err| warn!("Failed to deserialize {} from Redis: {}", key, err))
+                .ok(),
+            Ok(None) => None,
+            Err(err) => {
+                warn!("Failed to read {} from Redis: {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// Checks that Redis answers, for the readiness probe.
+    pub async fn ping(&self) -> redis::RedisResult<()> {
+        let mut connection = self.connection.clone();
+        redis::cmd("PING")
+            .query_async::<String>(&mut connection)
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn set<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) {
+        let key = self.key(key);
+        let value = match serde_json::to_string(value) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("Failed to serialize {} for Redis: {}", key, err);
+                return;
+            }
+        };
+        let mut connection = self.connection.clone();
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX).max(1);
+        if let Err(err) = connection.pset_ex::<_, _, ()>(&key, value, ttl).await {
+            warn!("Failed to write {} to Redis: {}", key, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use geo::Point;
+    use pretty_assertions::assert_eq;
+    use wictk_core::{Coordinates, Lightning, MetNowcast, Nowcast, OpenWeatherMapLocation};
+
+    use super::*;
+    use crate::cache::test_redis;
+
+    #[tokio::test]
+    async fn round_trips_cached_types() {
+        let redis = RedisCache::connect(&test_redis::start().await)
+            .await
+            .unwrap()
+            .namespaced("test");
+        let ttl = Duration::from_secs(60);
+
+        let nowcast = Nowcast::Met(MetNowcast {
+            time: Utc.with_ymd_and_hms(2025, 1, 20, 12, 5, 0).unwrap(),
+            location: Coordinates::new(10.3951, 63.4305),
+            description: "cloudy".to_string(),
+            air_temperature: -3.4,
+            relative_humidity: 86.1,
+            precipitation_rate: 0.0,
+            precipitation_amount: 0.0,
+            wind_speed: 4.2,
+            wind_speed_gust: 7.9,
+            wind_from_direction: 152.3,
+        });
+        redis.set("met_trondheim", &nowcast, ttl).await;
+        let cached: Nowcast = redis.get("met_trondheim").await.unwrap();
+        assert_eq!(
+            serde_json::to_value(cached).unwrap(),
+            serde_json::to_value(&nowcast).unwrap()
+        );
+
+        let lightning = vec![Lightning::new(
+            Point::new(10.4187, 63.4412),
+            Utc.with_ymd_and_hms(2025, 1, 20, 11, 0, 0).unwrap(),
+            12,
+        )];
+        redis.set("recent_lightning", &lightning, ttl).await;
+        let cached: Vec<Lightning> = redis.get("recent_lightning").await.unwrap();
+        assert_eq!(cached[0].location, lightning[0].location);
+        assert_eq!(cached[0].time, lightning[0].time);
+
+        let location = OpenWeatherMapLocation {
+            name: "Trondheim".to_string(),
+            local_names: None,
+            location: Coordinates::new(10.3951, 63.4305),
+            country: "NO".to_string(),
+            state: Some("Trøndelag".to_string()),
+        };
+        redis.set("Trondheim", &location, ttl).await;
+        assert_eq!(redis.get("Trondheim").await, Some(location));
+    }
+
+    #[tokio::test]
+    async fn missing_and_expired_keys() {
+        let redis = RedisCache::connect(&test_redis::start().await)
+            .await
+            .unwrap();
+
+        assert_eq!(redis.get::<String>("missing").await, None);
+
+        redis
+            .set("short", &"lived".to_string(), Duration::from_millis(20))
+            .await;
+        assert_eq!(redis.get("short").await, Some("lived".to_string()));
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert_eq!(redis.get::<String>("short").await, None);
+    }
+
+    #[tokio::test]
+    async fn namespaces_do_not_collide() {
+        let redis = RedisCache::connect(&test_redis::start().await)
+            .await
+            .unwrap();
+        let nowcasts = redis.namespaced("nowcast");
+        let locations = redis.namespaced("location");
+
+        nowcasts.set("oslo", &1, Duration::from_secs(60)).await;
+        assert_eq!(nowcasts.get("oslo").await, Some(1));
+        assert_eq!(locations.get::<i32>("oslo").await, None);
+    }
+
+    #[tokio::test]
+    async fn connect_fails_without_server() {
+        let result = RedisCache::connect("redis://127.0.0.1:1").await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,103 @@
+//! A minimal Redis stand-in for tests, speaking just enough RESP for the cache.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+type Store = Arc<Mutex<HashMap<Vec<u8>, (Vec<u8>, Option<Instant>)>>>;
+
+/// Starts the stand-in on a local port and returns its URL.
+pub async fn start() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let store = Store::default();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve(stream, store.clone()));
+        }
+    });
+    format!("redis://{addr}")
+}
+
+async fn serve(stream: TcpStream, store: Store) {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    while let Some(command) = read_command(&mut reader).await {
+        let reply = execute(&store, &command);
+        if writer.write_all(&reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Option<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line).await {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
+
+async fn read_command(
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+) -> Option<Vec<Vec<u8>>> {
+    let count: usize = read_line(reader).await?.strip_prefix('*')?.parse().ok()?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let len: usize = read_line(reader).await?.strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
+
+fn bulk(value: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", value.len()).into_bytes();
+    reply.extend_from_slice(value);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn execute(store: &Store, command: &[Vec<u8>]) -> Vec<u8> {
+    let name = String::from_utf8_lossy(&command[0]).to_uppercase();
+    let mut store = store.lock().unwrap();
+    match (name.as_str(), &command[1..]) {
+        ("PING", _) => b"+PONG\r\n".to_vec(),
+        ("CLIENT", _) => b"+OK\r\n".to_vec(),
+        ("GET", [key]) => match store.get(key) {
+            Some((_, Some(expires))) if *expires <= Instant::now() => {
+                store.remove(key);
+                b"$-1\r\n".to_vec()
+            }
+            Some((value, _)) => bulk(value),
+            None => b"$-1\r\n".to_vec(),
+        },
+        ("SET", [key, value]) => {
+            store.insert(key.clone(), (value.clone(), None));
+            b"+OK\r\n".to_vec()
+        }
+        ("PSETEX", [key, millis, value]) => {
+            let millis: u64 = String::from_utf8_lossy(millis).parse().unwrap_or_default();
+            let expires = Instant::now() + Duration::from_millis(millis);
+            store.insert(key.clone(), (value.clone(), Some(expires)));
+            b"+OK\r\n".to_vec()
+        }
+        ("DEL", keys) => {
+            let removed = keys
+                .iter()
+                .filter(|key| store.remove(*key).is_some())
+                .count();
+            format!(":{removed}\r\n").into_bytes()
+        }
+        _ => format!("-ERR unknown command '{name}'\r\n").into_bytes(),
+    }
+}
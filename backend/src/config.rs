@@ -0,0 +1,1222 @@
+//! Layered backend configuration.
+//!
+//! Values are resolved from, in increasing precedence: the defaults, an optional
+//! TOML or YAML file, `WICTK_`-prefixed environment variables (`__` separates
+//! nested keys, e.g. `WICTK_CACHES__NOWCAST__TTL_SECS=600`) and finally the
+//! command line flags and their environment variables.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use axum::http::HeaderValue;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::Level;
+use wictk_core::{
+    Endpoints, Upstream, UpstreamClient, UpstreamPolicy, WictkError, telemetry::LogFormat,
+};
+
+const ENV_PREFIX: &str = "WICTK_";
+
+/// API versions that can be deprecated, `unversioned` is the `/api` alias of the latest
+pub const API_VERSIONS: [&str; 2] = ["unversioned", "v1"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trace" => Ok(LogLevel::Trace),
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err("unknown log level".to_string()),
+        }
+    }
+}
+
+impl From<LogLevel> for Level {
+    fn from(log_level: LogLevel) -> Self {
+        match log_level {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+        }
+    }
+}
+
+/// Capacity and lifetime of one cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Maximum number of entries
+    pub capacity: u64,
+    /// Seconds an entry is served as fresh
+    pub ttl_secs: u64,
+    /// Seconds an entry may be served stale while it is refreshed
+    pub max_staleness_secs: u64,
+}
+
+impl CacheConfig {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            ttl_secs: 5 * 60,
+            max_staleness_secs: 30 * 60,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+
+    pub fn max_staleness(&self) -> Duration {
+        Duration::from_secs(self.max_staleness_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CachesConfig {
+    pub alert: CacheConfig,
+    pub location: CacheConfig,
+    pub nowcast: CacheConfig,
+    pub lightning: CacheConfig,
+    pub observation: CacheConfig,
+}
+
+impl Default for CachesConfig {
+    fn default() -> Self {
+        Self {
+            alert: CacheConfig::new(1),
+            location: CacheConfig::new(20),
+            nowcast: CacheConfig::new(20),
+            lightning: CacheConfig::new(1),
+            observation: CacheConfig::new(20),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamsConfig {
+    /// Seconds before a request to an upstream is abandoned
+    pub timeout_secs: u64,
+    /// Seconds before connecting to an upstream is abandoned
+    pub connect_timeout_secs: u64,
+    /// Per-upstream overrides of `timeout_secs`, keyed by upstream name
+    pub timeouts: BTreeMap<String, u64>,
+    /// Retries of failed idempotent requests, with jittered exponential backoff
+    pub max_retries: u32,
+    pub retry_base_delay_ms: u64,
+    /// Longest wait between attempts, a longer `Retry-After` is not retried
+    pub retry_max_delay_ms: u64,
+    /// Consecutive failed requests that make an upstream fail fast
+    pub breaker_failure_threshold: u32,
+    /// Seconds an upstream fails fast before a trial request is let through
+    pub breaker_open_secs: u64,
+    pub urls: Endpoints,
+}
+
+impl Default for UpstreamsConfig {
+    fn default() -> Self {
+        let policy = UpstreamPolicy::default();
+        Self {
+            timeout_secs: policy.timeout.as_secs(),
+            connect_timeout_secs: 5,
+            timeouts: BTreeMap::new(),
+            max_retries: policy.max_retries,
+            retry_base_delay_ms: policy.base_delay.as_millis() as u64,
+            retry_max_delay_ms: policy.max_delay.as_millis() as u64,
+            breaker_failure_threshold: policy.failure_threshold,
+            breaker_open_secs: policy.open_for.as_secs(),
+            urls: Endpoints::default(),
+        }
+    }
+}
+
+impl UpstreamsConfig {
+    pub fn policy(&self, upstream: Upstream) -> UpstreamPolicy {
+        let timeout_secs = self
+            .timeouts
+            .get(upstream.name())
+            .copied()
+            .unwrap_or(self.timeout_secs);
+        UpstreamPolicy {
+            timeout: Duration::from_secs(timeout_secs),
+            max_retries: self.max_retries,
+            base_delay: Duration::from_millis(self.retry_base_delay_ms),
+            max_delay: Duration::from_millis(self.retry_max_delay_ms),
+            failure_threshold: self.breaker_failure_threshold,
+            open_for: Duration::from_secs(self.breaker_open_secs),
+        }
+    }
+
+    /// Wraps `client` to apply the policy of each upstream.
+    pub fn client(&self, client: reqwest::Client) -> UpstreamClient {
+        Upstream::ALL
+            .into_iter()
+            .fold(UpstreamClient::new(client), |client, upstream| {
+                client.with_policy(upstream, self.policy(upstream))
+            })
+    }
+}
+
+/// Upstreams the backend may call, requests needing a disabled one get a 503
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProvidersConfig {
+    pub met: bool,
+    pub openweathermap: bool,
+    pub yr: bool,
+    pub frost: bool,
+}
+
+impl Default for ProvidersConfig {
+    fn default() -> Self {
+        Self {
+            met: true,
+            openweathermap: true,
+            yr: true,
+            frost: true,
+        }
+    }
+}
+
+impl ProvidersConfig {
+    pub fn enabled(&self, upstream: Upstream) -> bool {
+        match upstream {
+            Upstream::Met => self.met,
+            Upstream::OpenWeatherMap => self.openweathermap,
+            Upstream::Yr => self.yr,
+            Upstream::Frost => self.frost,
+        }
+    }
+
+    /// Fails with the same error as an unreachable upstream if `upstream` is disabled.
+    pub fn check(&self, upstream: Upstream) -> Result<(), WictkError> {
+        if self.enabled(upstream) {
+            Ok(())
+        } else {
+            Err(WictkError::UpstreamUnavailable {
+                upstream,
+                reason: "disabled by configuration".to_string(),
+            })
+        }
+    }
+}
+
+/// Probing of the upstreams by `/status/health`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Seconds the probe results are reused, so health checks cannot load the upstreams
+    pub probe_interval_secs: u64,
+    /// Seconds before a probe is considered failed
+    pub probe_timeout_secs: u64,
+    /// Upstreams the service is down without, others only degrade it
+    pub critical: Vec<String>,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            probe_interval_secs: 30,
+            probe_timeout_secs: 5,
+            critical: vec!["met".to_string(), "openweathermap".to_string()],
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn probe_interval(&self) -> Duration {
+        Duration::from_secs(self.probe_interval_secs)
+    }
+
+    pub fn probe_timeout(&self) -> Duration {
+        Duration::from_secs(self.probe_timeout_secs)
+    }
+
+    pub fn is_critical(&self, upstream: Upstream) -> bool {
+        self.critical.iter().any(|name| name == upstream.name())
+    }
+}
+
+/// Pushing of new data to connected clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamsConfig {
+    /// Seconds between polls of the lightning upstream while anyone is subscribed
+    pub lightning_poll_secs: u64,
+    /// Seconds between checks of the alerts and nowcasts WebSocket clients subscribed to
+    pub topic_poll_secs: u64,
+    /// Seconds between comments sent to idle event streams and pings sent to
+    /// WebSocket clients, which are disconnected after two unanswered pings
+    pub keep_alive_secs: u64,
+    /// Most alerts and nowcast topics polled at once, subscriptions to further
+    /// locations are refused until some are no longer subscribed to
+    pub max_topics: usize,
+}
+
+impl Default for StreamsConfig {
+    fn default() -> Self {
+        Self {
+            lightning_poll_secs: 30,
+            topic_poll_secs: 60,
+            keep_alive_secs: 15,
+            max_topics: 1000,
+        }
+    }
+}
+
+impl StreamsConfig {
+    pub fn lightning_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.lightning_poll_secs)
+    }
+
+    pub fn topic_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.topic_poll_secs)
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive_secs)
+    }
+}
+
+/// Delivery of events to the registered webhooks
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    /// Attempts per event, including the first, before it is given up
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each retry
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    /// Seconds a webhook may take to answer before the attempt fails
+    pub timeout_secs: u64,
+    /// Lets webhooks be sent to loopback, private and link-local addresses,
+    /// only for trusted networks as anyone registering a webhook can reach them
+    pub allow_private_urls: bool,
+    /// Webhooks each API key may register
+    pub max_per_key: usize,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            retry_base_delay_ms: 5_000,
+            retry_max_delay_ms: 5 * 60 * 1000,
+            timeout_secs: 10,
+            allow_private_urls: false,
+            max_per_key: 100,
+        }
+    }
+}
+
+impl WebhooksConfig {
+    /// Delay before retrying a delivery that failed `attempt` times
+    pub fn retry_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .retry_base_delay_ms
+            .saturating_mul(1 << attempt.saturating_sub(1).min(20));
+        Duration::from_millis(delay.min(self.retry_max_delay_ms))
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+/// Log output and trace export
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// OTLP/HTTP collector to export traces to, e.g. `http://otel-collector:4318`
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Calls an upstream allows, counted over rolling windows
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    pub per_minute: Option<u64>,
+    pub per_day: Option<u64>,
+    /// Share of a limit after which the upstream is no longer called and only
+    /// cached data is served
+    pub degrade_at: f64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            per_minute: None,
+            per_day: None,
+            degrade_at: 0.9,
+        }
+    }
+}
+
+/// A token bucket per client, holding up to `requests` tokens and refilled at
+/// `requests` per `per_secs` seconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub requests: u64,
+    pub per_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    /// Identifies the key in logs and metrics
+    pub name: String,
+    /// Hex encoded SHA-256 hash of the key, the key itself is never configured
+    pub sha256: String,
+    /// Replaces `rate_limit` for requests with this key
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Announces that an API version is going away with the `Deprecation` (RFC 9745)
+/// and `Sunset` (RFC 8594) headers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecationConfig {
+    pub since: DateTime<Utc>,
+    /// Requests are answered with `410 Gone` from then on
+    pub sunset: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Rejects API requests without a key, otherwise they are limited per IP address
+    pub require_api_key: bool,
+    pub api_keys: Vec<ApiKeyConfig>,
+    /// Reverse proxies in front of the backend, each appending to
+    /// `X-Forwarded-For`. Requests without a key are limited by the address
+    /// that many entries from its end, instead of the peer address
+    pub trusted_proxy_hops: usize,
+}
+
+impl AuthConfig {
+    /// Finds the key whose hash is `sha256`.
+    pub fn key(&self, sha256: &str) -> Option<&ApiKeyConfig> {
+        self.api_keys
+            .iter()
+            .find(|key| key.sha256.eq_ignore_ascii_case(sha256))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: String,
+    pub log_level: LogLevel,
+    pub telemetry: TelemetryConfig,
+    /// Origins allowed to call the API from a browser, `*` allows any
+    pub cors_origins: Vec<String>,
+    pub caches: CachesConfig,
+    pub upstreams: UpstreamsConfig,
+    pub providers: ProvidersConfig,
+    pub health: HealthConfig,
+    pub streams: StreamsConfig,
+    pub webhooks: WebhooksConfig,
+    /// Keyed by upstream name
+    pub quotas: BTreeMap<String, QuotaConfig>,
+    pub auth: AuthConfig,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Keyed by API version, see [`API_VERSIONS`]
+    pub deprecations: BTreeMap<String, DeprecationConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0:3000".to_string(),
+            log_level: LogLevel::Info,
+            telemetry: TelemetryConfig::default(),
+            cors_origins: Vec::new(),
+            caches: CachesConfig::default(),
+            upstreams: UpstreamsConfig::default(),
+            providers: ProvidersConfig::default(),
+            health: HealthConfig::default(),
+            streams: StreamsConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            quotas: BTreeMap::new(),
+            auth: AuthConfig::default(),
+            rate_limit: None,
+            deprecations: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the defaults, overridden by `file` and then by `WICTK_` environment variables.
+    pub fn load(file: Option<&Path>) -> anyhow::Result<Self> {
+        let mut value = serde_json::to_value(Config::default())?;
+        if let Some(file) = file {
+            merge(&mut value, read_file(file)?);
+        }
+        apply_env(&mut value, std::env::vars())?;
+        serde_json::from_value(value).context("Invalid configuration")
+    }
+
+    /// Checks the values serde cannot, listing every problem found.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+        if self.host.trim().is_empty() {
+            problems.push("host must not be empty".to_string());
+        }
+        let caches = [
+            ("alert", &self.caches.alert),
+            ("location", &self.caches.location),
+            ("nowcast", &self.caches.nowcast),
+            ("lightning", &self.caches.lightning),
+            ("observation", &self.caches.observation),
+        ];
+        for (name, cache) in caches {
+            if cache.capacity == 0 {
+                problems.push(format!("caches.{name}.capacity must be greater than 0"));
+            }
+            if cache.ttl_secs == 0 {
+                problems.push(format!("caches.{name}.ttl_secs must be greater than 0"));
+            }
+        }
+        if self.upstreams.timeout_secs == 0 {
+            problems.push("upstreams.timeout_secs must be greater than 0".to_string());
+        }
+        if self.upstreams.connect_timeout_secs == 0 {
+            problems.push("upstreams.connect_timeout_secs must be greater than 0".to_string());
+        }
+        for (name, timeout_secs) in &self.upstreams.timeouts {
+            if Upstream::from_name(name).is_none() {
+                problems.push(format!(
+                    "upstreams.timeouts must be keyed by met, openweathermap, yr or frost, got '{name}'"
+                ));
+            } else if *timeout_secs == 0 {
+                problems.push(format!("upstreams.timeouts.{name} must be greater than 0"));
+            }
+        }
+        if self.upstreams.retry_base_delay_ms > self.upstreams.retry_max_delay_ms {
+            problems.push(
+                "upstreams.retry_base_delay_ms must not be greater than upstreams.retry_max_delay_ms"
+                    .to_string(),
+            );
+        }
+        if self.upstreams.breaker_failure_threshold == 0 || self.upstreams.breaker_open_secs == 0 {
+            problems.push(
+                "upstreams.breaker_failure_threshold and upstreams.breaker_open_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        let urls = &self.upstreams.urls;
+        for (name, url) in [
+            ("met", &urls.met),
+            ("openweathermap", &urls.openweathermap),
+            ("yr", &urls.yr),
+            ("frost", &urls.frost),
+        ] {
+            match reqwest::Url::parse(url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => problems.push(format!(
+                    "upstreams.urls.{name} must be an http or https URL, got '{url}'"
+                )),
+            }
+        }
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            match reqwest::Url::parse(endpoint) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => problems.push(format!(
+                    "telemetry.otlp_endpoint must be an http or https URL, got '{endpoint}'"
+                )),
+            }
+        }
+        if self.health.probe_interval_secs == 0 || self.health.probe_timeout_secs == 0 {
+            problems.push(
+                "health.probe_interval_secs and health.probe_timeout_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        for name in &self.health.critical {
+            if Upstream::from_name(name).is_none() {
+                problems.push(format!(
+                    "health.critical must contain met, openweathermap, yr or frost, got '{name}'"
+                ));
+            }
+        }
+        let streams = &self.streams;
+        if streams.lightning_poll_secs == 0
+            || streams.topic_poll_secs == 0
+            || streams.keep_alive_secs == 0
+        {
+            problems.push(
+                "streams.lightning_poll_secs, streams.topic_poll_secs and streams.keep_alive_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        let webhooks = &self.webhooks;
+        if webhooks.max_attempts == 0 || webhooks.timeout_secs == 0 {
+            problems.push(
+                "webhooks.max_attempts and webhooks.timeout_secs must be greater than 0"
+                    .to_string(),
+            );
+        }
+        if webhooks.retry_base_delay_ms > webhooks.retry_max_delay_ms {
+            problems.push(
+                "webhooks.retry_base_delay_ms must not be greater than webhooks.retry_max_delay_ms"
+                    .to_string(),
+            );
+        }
+        for origin in &self.cors_origins {
+            let valid = origin == "*"
+                || (HeaderValue::from_str(origin).is_ok()
+                    && reqwest::Url::parse(origin).is_ok_and(|url| url.has_host()));
+            if !valid {
+                problems.push(format!(
+                    "cors_origins must contain origins like https://example.com or *, got '{origin}'"
+                ));
+            }
+        }
+        for (name, quota) in &self.quotas {
+            if Upstream::from_name(name).is_none() {
+                problems.push(format!(
+                    "quotas must be keyed by met, openweathermap, yr or frost, got '{name}'"
+                ));
+            }
+            if quota.per_minute == Some(0) || quota.per_day == Some(0) {
+                problems.push(format!(
+                    "quotas.{name}.per_minute and quotas.{name}.per_day must be greater than 0"
+                ));
+            }
+            if !(quota.degrade_at > 0.0 && quota.degrade_at <= 1.0) {
+                problems.push(format!(
+                    "quotas.{name}.degrade_at must be greater than 0 and at most 1"
+                ));
+            }
+        }
+        if let Some(rate_limit) = &self.rate_limit {
+            if rate_limit.requests == 0 || rate_limit.per_secs == 0 {
+                problems.push(
+                    "rate_limit.requests and rate_limit.per_secs must be greater than 0"
+                        .to_string(),
+                );
+            }
+        }
+        let mut names = HashSet::new();
+        for key in &self.auth.api_keys {
+            if key.name.trim().is_empty() || !names.insert(&key.name) {
+                problems.push(format!(
+                    "auth.api_keys must have unique, non-empty names, got '{}'",
+                    key.name
+                ));
+            }
+            if key.sha256.len() != 64 || !key.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+                problems.push(format!(
+                    "auth.api_keys.{}.sha256 must be 64 hex digits",
+                    key.name
+                ));
+            }
+            if let Some(rate_limit) = &key.rate_limit {
+                if rate_limit.requests == 0 || rate_limit.per_secs == 0 {
+                    problems.push(format!(
+                        "auth.api_keys.{}.rate_limit.requests and per_secs must be greater than 0",
+                        key.name
+                    ));
+                }
+            }
+        }
+        if self.auth.require_api_key && self.auth.api_keys.is_empty() {
+            problems
+                .push("auth.require_api_key needs at least one key in auth.api_keys".to_string());
+        }
+        for (version, deprecation) in &self.deprecations {
+            if !API_VERSIONS.contains(&version.as_str()) {
+                problems.push(format!(
+                    "deprecations must be keyed by unversioned or v1, got '{version}'"
+                ));
+            }
+            if deprecation
+                .sunset
+                .is_some_and(|sunset| sunset <= deprecation.since)
+            {
+                problems.push(format!(
+                    "deprecations.{version}.sunset must be later than since"
+                ));
+            }
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        bail!("Invalid configuration:\n  - {}", problems.join("\n  - "))
+    }
+
+    /// Names the changed settings that are only applied on startup.
+    pub fn changes_requiring_restart(&self, other: &Config) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+        if self.host != other.host {
+            changes.push("host");
+        }
+        if self.log_level != other.log_level {
+            changes.push("log_level");
+        }
+        if self.telemetry != other.telemetry {
+            changes.push("telemetry");
+        }
+        if self.caches != other.caches {
+            changes.push("caches");
+        }
+        if self.upstreams != other.upstreams {
+            changes.push("upstreams");
+        }
+        changes
+    }
+
+    pub fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.cors_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.as_bytes() == origin.as_bytes())
+    }
+}
+
+fn read_file(file: &Path) -> anyhow::Result<Value> {
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read config file {}", file.display()))?;
+    let value = match file.extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(anyhow::Error::from),
+        Some("yaml" | "yml") => serde_yaml_ng::from_str(&content).map_err(anyhow::Error::from),
+        _ => bail!(
+            "Config file {} must end in .toml, .yaml or .yml",
+            file.display()
+        ),
+    };
+    value.with_context(|| format!("Failed to parse config file {}", file.display()))
+}
+
+/// Recursively replaces the values in `base` with those in `overrides`.
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Sets `WICTK_A__B=value` at `a.b`. Values replacing strings are kept as
+/// strings, others are parsed as JSON, e.g. `true`, `600` or `["*"]`.
+fn apply_env(
+    value: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> anyhow::Result<()> {
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path = path.to_lowercase();
+        let keys: Vec<&str> = path.split("__").collect();
+        let (last, parents) = keys.split_last().expect("split returns at least one key");
+        let mut target = &mut *value;
+        for key in parents {
+            target = target
+                .as_object_mut()
+                .with_context(|| format!("{name} does not name a configuration key"))?
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Default::default()));
+        }
+        let object = target
+            .as_object_mut()
+            .with_context(|| format!("{name} does not name a configuration key"))?;
+        let parsed = match object.get(*last) {
+            Some(Value::String(_)) => Value::String(raw),
+            _ => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+        };
+        object.insert(last.to_string(), parsed);
+    }
+    Ok(())
+}
+
+/// Configuration shared with the request handlers, replaced when it is reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct SharedConfig(Arc<RwLock<Config>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(config)))
+    }
+
+    pub fn get(&self) -> Config {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn providers(&self) -> ProvidersConfig {
+        self.0.read().unwrap().providers.clone()
+    }
+
+    pub fn streams(&self) -> StreamsConfig {
+        self.0.read().unwrap().streams.clone()
+    }
+
+    pub fn webhooks(&self) -> WebhooksConfig {
+        self.0.read().unwrap().webhooks.clone()
+    }
+
+    pub fn quota(&self, upstream: Upstream) -> Option<QuotaConfig> {
+        self.0.read().unwrap().quotas.get(upstream.name()).cloned()
+    }
+
+    /// Names the configured API key whose hash is `sha256`.
+    pub fn api_key_name(&self, sha256: &str) -> Option<String> {
+        let config = self.0.read().unwrap();
+        config.auth.key(sha256).map(|key| key.name.clone())
+    }
+
+    pub fn requires_api_key(&self) -> bool {
+        self.0.read().unwrap().auth.require_api_key
+    }
+
+    pub fn trusted_proxy_hops(&self) -> usize {
+        self.0.read().unwrap().auth.trusted_proxy_hops
+    }
+
+    /// The rate limit of requests with the API key named `key`, or without a key.
+    pub fn rate_limit(&self, key: Option<&str>) -> Option<RateLimitConfig> {
+        let config = self.0.read().unwrap();
+        key.and_then(|name| config.auth.api_keys.iter().find(|key| key.name == name))
+            .and_then(|key| key.rate_limit.clone())
+            .or_else(|| config.rate_limit.clone())
+    }
+
+    pub fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.0.read().unwrap().allows_origin(origin)
+    }
+
+    pub fn deprecation(&self, version: &str) -> Option<DeprecationConfig> {
+        self.0.read().unwrap().deprecations.get(version).cloned()
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.0.write().unwrap() = config;
+    }
+
+    /// Replaces the configuration with `config`, keeping the running values of
+    /// the settings only applied on startup. Returns the names of those that
+    /// differ, see [`Config::changes_requiring_restart`].
+    pub fn reload(&self, mut config: Config) -> Vec<&'static str> {
+        let mut running = self.0.write().unwrap();
+        let changes = running.changes_requiring_restart(&config);
+        config.host = running.host.clone();
+        config.log_level = running.log_level;
+        config.telemetry = running.telemetry.clone();
+        config.caches = running.caches.clone();
+        config.upstreams = running.upstreams.clone();
+        *running = config;
+        changes
+    }
+}
+
+/// Reloads the configuration with `load` on every SIGHUP. Providers and CORS
+/// origins apply immediately, settings only applied on startup keep their
+/// running values and their changes are logged as needing a restart.
+/// An invalid configuration is rejected and the current one kept.
+#[cfg(unix)]
+pub async fn reload_on_sighup<F>(shared: SharedConfig, load: F) -> anyhow::Result<()>
+where
+    F: Fn() -> anyhow::Result<Config>,
+{
+    use tokio::signal::unix::{SignalKind, signal};
+    use tracing::{error, info, warn};
+
+    let mut hangups = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+    while hangups.recv().await.is_some() {
+        let config = match load() {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Keeping the current configuration: {:#}", err);
+                continue;
+            }
+        };
+        let changes = shared.reload(config);
+        if !changes.is_empty() {
+            warn!(
+                "Changes to {} are applied on the next restart",
+                changes.join(", ")
+            );
+        }
+        info!("Reloaded configuration");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn write(name: &str, content: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("wictk-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn file_overrides_defaults() {
+        let path = write(
+            "overrides.toml",
+            r#"
+            cors_origins = ["https://wictk.example"]
+
+            [caches.nowcast]
+            capacity = 100
+            ttl_secs = 60
+            max_staleness_secs = 600
+
+            [providers]
+            frost = false
+            "#,
+        );
+        let config = Config::load(Some(&path)).unwrap();
+
+        assert_eq!(
+            config.caches.nowcast,
+            CacheConfig {
+                capacity: 100,
+                ttl_secs: 60,
+                max_staleness_secs: 600
+            }
+        );
+        assert_eq!(config.caches.alert, CacheConfig::new(1));
+        assert_eq!(config.cors_origins, vec!["https://wictk.example"]);
+        assert!(!config.providers.frost);
+        assert!(config.providers.met);
+    }
+
+    #[test]
+    fn yaml_and_toml_are_equivalent() {
+        let toml = write(
+            "equivalent.toml",
+            "log_level = \"debug\"\n[upstreams]\ntimeout_secs = 3\n[rate_limit]\nrequests = 10\nper_secs = 1\n",
+        );
+        let yaml = write(
+            "equivalent.yaml",
+            "log_level: debug\nupstreams:\n  timeout_secs: 3\nrate_limit:\n  requests: 10\n  per_secs: 1\n",
+        );
+
+        let config = Config::load(Some(&toml)).unwrap();
+        assert_eq!(config, Config::load(Some(&yaml)).unwrap());
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.upstreams.timeout_secs, 3);
+        assert_eq!(
+            config.rate_limit,
+            Some(RateLimitConfig {
+                requests: 10,
+                per_secs: 1
+            })
+        );
+    }
+
+    #[test]
+    fn env_overrides_file_values() {
+        let mut value = serde_json::to_value(Config::default()).unwrap();
+        merge(
+            &mut value,
+            serde_json::json!({"host": "127.0.0.1:8080", "caches": {"nowcast": {"capacity": 50}}}),
+        );
+        apply_env(
+            &mut value,
+            vars(&[
+                ("WICTK_CACHES__NOWCAST__CAPACITY", "75"),
+                ("WICTK_PROVIDERS__YR", "false"),
+                ("WICTK_UPSTREAMS__URLS__MET", "http://localhost:8080"),
+                ("WICTK_CORS_ORIGINS", r#"["*"]"#),
+                ("WICTK_HOST", "1234"),
+                ("HOST", "ignored"),
+            ]),
+        )
+        .unwrap();
+        let config: Config = serde_json::from_value(value).unwrap();
+
+        assert_eq!(config.caches.nowcast.capacity, 75);
+        assert!(!config.providers.yr);
+        assert_eq!(config.upstreams.urls.met, "http://localhost:8080");
+        assert_eq!(config.cors_origins, vec!["*"]);
+        assert_eq!(config.host, "1234");
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let path = write("typo.toml", "[caches.nowcast]\ncapacty = 10\n");
+        let err = Config::load(Some(&path)).unwrap_err();
+
+        assert!(
+            format!("{err:#}").contains("unknown field `capacty`"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn unsupported_file_extension() {
+        let path = write("config.json", "{}");
+        let err = Config::load(Some(&path)).unwrap_err();
+
+        assert!(err.to_string().contains("must end in .toml, .yaml or .yml"));
+    }
+
+    #[test]
+    fn validation_lists_every_problem() {
+        let mut config = Config::default();
+        config.caches.lightning.capacity = 0;
+        config.upstreams.urls.yr = "ftp://yr.no".to_string();
+        config.upstreams.breaker_failure_threshold = 0;
+        config.telemetry.otlp_endpoint = Some("localhost:4318".to_string());
+        config.health.critical = vec!["ntfy".to_string()];
+        config.streams.keep_alive_secs = 0;
+        config.webhooks.max_attempts = 0;
+        config.cors_origins = vec!["not an origin".to_string()];
+        config.rate_limit = Some(RateLimitConfig {
+            requests: 0,
+            per_secs: 1,
+        });
+
+        let err = config.validate().unwrap_err().to_string();
+
+        assert_eq!(
+            err,
+            "Invalid configuration:\n  \
+             - caches.lightning.capacity must be greater than 0\n  \
+             - upstreams.breaker_failure_threshold and upstreams.breaker_open_secs must be greater than 0\n  \
+             - upstreams.urls.yr must be an http or https URL, got 'ftp://yr.no'\n  \
+             - telemetry.otlp_endpoint must be an http or https URL, got 'localhost:4318'\n  \
+             - health.critical must contain met, openweathermap, yr or frost, got 'ntfy'\n  \
+             - streams.lightning_poll_secs, streams.topic_poll_secs and streams.keep_alive_secs must be greater than 0\n  \
+             - webhooks.max_attempts and webhooks.timeout_secs must be greater than 0\n  \
+             - cors_origins must contain origins like https://example.com or *, got 'not an origin'\n  \
+             - rate_limit.requests and rate_limit.per_secs must be greater than 0"
+        );
+    }
+
+    #[test]
+    fn api_keys() {
+        let key = |name: &str, sha256: &str| ApiKeyConfig {
+            name: name.to_string(),
+            sha256: sha256.to_string(),
+            rate_limit: None,
+        };
+        let hash = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let mut config = Config::default();
+        config.auth.api_keys = vec![key("ios", hash), key("ios", "abc")];
+
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Invalid configuration:\n  \
+             - auth.api_keys must have unique, non-empty names, got 'ios'\n  \
+             - auth.api_keys.ios.sha256 must be 64 hex digits"
+        );
+
+        config.auth.api_keys[1] = ApiKeyConfig {
+            rate_limit: Some(RateLimitConfig {
+                requests: 5,
+                per_secs: 1,
+            }),
+            ..key("android", &hash.to_lowercase().replace("ba78", "0000"))
+        };
+        config.rate_limit = Some(RateLimitConfig {
+            requests: 1,
+            per_secs: 1,
+        });
+        config.validate().unwrap();
+        let shared = SharedConfig::new(config);
+        assert_eq!(
+            shared.api_key_name(&hash.to_lowercase()).as_deref(),
+            Some("ios")
+        );
+        assert_eq!(shared.rate_limit(Some("android")).unwrap().requests, 5);
+        assert_eq!(shared.rate_limit(Some("ios")).unwrap().requests, 1);
+        assert_eq!(shared.rate_limit(None).unwrap().requests, 1);
+    }
+
+    #[test]
+    fn quotas() {
+        let mut config = Config::default();
+        config.quotas.insert(
+            "owm".to_string(),
+            QuotaConfig {
+                per_minute: Some(60),
+                ..QuotaConfig::default()
+            },
+        );
+        config.quotas.insert(
+            "openweathermap".to_string(),
+            QuotaConfig {
+                per_minute: Some(0),
+                per_day: Some(1000),
+                degrade_at: 1.5,
+            },
+        );
+
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Invalid configuration:\n  \
+             - quotas.openweathermap.per_minute and quotas.openweathermap.per_day must be greater than 0\n  \
+             - quotas.openweathermap.degrade_at must be greater than 0 and at most 1\n  \
+             - quotas must be keyed by met, openweathermap, yr or frost, got 'owm'"
+        );
+
+        config.quotas.remove("owm");
+        config
+            .quotas
+            .insert("openweathermap".to_string(), QuotaConfig::default());
+        config.validate().unwrap();
+        let shared = SharedConfig::new(config);
+        assert_eq!(
+            shared.quota(Upstream::OpenWeatherMap).unwrap().degrade_at,
+            0.9
+        );
+        assert_eq!(shared.quota(Upstream::Met), None);
+    }
+
+    #[test]
+    fn deprecations() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [deprecations.unversioned]
+            since = "2026-10-01T00:00:00Z"
+            sunset = "2027-04-01T00:00:00Z"
+
+            [deprecations.v0]
+            since = "2026-10-01T00:00:00Z"
+            sunset = "2026-01-01T00:00:00Z"
+            "#,
+        )
+        .unwrap();
+
+        let err = config.validate().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "Invalid configuration:\n  \
+             - deprecations must be keyed by unversioned or v1, got 'v0'\n  \
+             - deprecations.v0.sunset must be later than since"
+        );
+
+        config.deprecations.remove("v0");
+        config.validate().unwrap();
+        let shared = SharedConfig::new(config);
+        assert_eq!(
+            shared.deprecation("unversioned").unwrap().sunset,
+            Some("2027-04-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(shared.deprecation("v1"), None);
+    }
+
+    #[test]
+    fn upstream_policies() {
+        let mut config = Config::default();
+        config.upstreams.timeouts.insert("yr".to_string(), 30);
+        config.upstreams.max_retries = 1;
+
+        let yr = config.upstreams.policy(Upstream::Yr);
+        let met = config.upstreams.policy(Upstream::Met);
+
+        assert_eq!(yr.timeout, Duration::from_secs(30));
+        assert_eq!(met.timeout, Duration::from_secs(10));
+        assert_eq!(met.max_retries, 1);
+        assert_eq!(
+            config
+                .upstreams
+                .client(reqwest::Client::new())
+                .policy(Upstream::Yr),
+            &yr
+        );
+    }
+
+    #[test]
+    fn changes_requiring_restart() {
+        let old = Config::default();
+        let mut new = old.clone();
+        new.providers.met = false;
+        new.cors_origins = vec!["*".to_string()];
+        new.health.probe_interval_secs = 60;
+        assert!(old.changes_requiring_restart(&new).is_empty());
+
+        new.caches.nowcast.capacity = 1;
+        new.host = "127.0.0.1:1".to_string();
+        new.telemetry.log_format = LogFormat::Json;
+        assert_eq!(
+            old.changes_requiring_restart(&new),
+            vec!["host", "telemetry", "caches"]
+        );
+    }
+
+    #[test]
+    fn reload_keeps_settings_requiring_restart() {
+        let shared = SharedConfig::new(Config::default());
+        let mut new = Config::default();
+        new.providers.met = false;
+        new.caches.nowcast.ttl_secs = 1;
+
+        assert_eq!(shared.reload(new.clone()), vec!["caches"]);
+        assert_eq!(shared.reload(new), vec!["caches"]);
+
+        let config = shared.get();
+        assert!(!config.providers.met);
+        assert_eq!(config.caches, Config::default().caches);
+    }
+
+    #[test]
+    fn allows_configured_origins() {
+        let mut config = Config::default();
+        let origin = HeaderValue::from_static("https://wictk.example");
+        assert!(!config.allows_origin(&origin));
+
+        config.cors_origins = vec!["https://wictk.example".to_string()];
+        assert!(config.allows_origin(&origin));
+        assert!(!config.allows_origin(&HeaderValue::from_static("https://other.example")));
+
+        config.cors_origins = vec!["*".to_string()];
+        assert!(config.allows_origin(&HeaderValue::from_static("https://other.example")));
+    }
+}
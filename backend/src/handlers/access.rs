@@ -0,0 +1,575 @@
+//! Identifies API clients by their API key or IP address, and limits how often
+//! each client may call the API.
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    extract::{ConnectInfo, Query, Request},
+    http::{HeaderName, HeaderValue, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use metrics::counter;
+use moka::sync::Cache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::config::{RateLimitConfig, SharedConfig};
+
+use super::error::ApplicationError;
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Browsers cannot set headers on WebSocket upgrades, so they may send the
+/// API key in this query parameter instead
+const API_KEY_PARAMETER: &str = "api_key";
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// The least recently used buckets are evicted beyond this many clients
+const MAX_BUCKETS: u64 = 10_000;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Who made a request, added to the request extensions by [`ApiKeyLayer`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ApiClient {
+    /// Name of the API key sent with the request
+    Key(String),
+    Ip(IpAddr),
+    /// No key was sent and the peer address is not known
+    Unknown,
+}
+
+impl ApiClient {
+    fn key(&self) -> Option<&str> {
+        match self {
+            ApiClient::Key(name) => Some(name),
+            ApiClient::Ip(_) | ApiClient::Unknown => None,
+        }
+    }
+
+    /// Label in the usage metrics, IP addresses are not used to keep the number of series bounded
+    fn metric_label(&self) -> String {
+        self.key().unwrap_or("anonymous").to_string()
+    }
+}
+
+/// Hex encoded SHA-256 hash of `key`, as configured in `auth.api_keys`.
+pub fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct ApiKeyQuery {
+    api_key: Option<String>,
+}
+
+/// The key in the `X-API-Key` header, or in the `api_key` query parameter of
+/// a WebSocket upgrade.
+fn sent_api_key(request: &Request) -> Option<String> {
+    if let Some(key) = request.headers().get(API_KEY_HEADER) {
+        return Some(key.to_str().unwrap_or_default().to_string());
+    }
+    let upgrade = request.headers().get(header::UPGRADE)?;
+    if !upgrade.as_bytes().eq_ignore_ascii_case(b"websocket") {
+        return None;
+    }
+    Query::<ApiKeyQuery>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(query)| query.api_key)
+}
+
+/// `uri` with the value of its `api_key` query parameter hidden, for logging.
+pub fn redact_api_key(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((API_KEY_PARAMETER, _)) => format!("{API_KEY_PARAMETER}=redacted"),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", uri.path(), query.join("&"))
+}
+
+/// Only hashes are compared, so response times reveal nothing about the keys.
+fn authenticate(config: &SharedConfig, request: &Request) -> Result<ApiClient, ApplicationError> {
+    match sent_api_key(request) {
+        Some(key) => {
+            let hash = hash_api_key(&key);
+            config
+                .api_key_name(&hash)
+                .map(ApiClient::Key)
+                .ok_or_else(|| ApplicationError::new("Invalid API key", StatusCode::UNAUTHORIZED))
+        }
+        None if config.requires_api_key() => Err(ApplicationError::new(
+            "Missing API key, send it in the X-API-Key header, or the api_key query parameter of WebSocket upgrades",
+            StatusCode::UNAUTHORIZED,
+        )),
+        None => Ok(client_ip(config.trusted_proxy_hops(), request)
+            .map_or(ApiClient::Unknown, ApiClient::Ip)),
+    }
+}
+
+/// The peer address, or with `trusted_proxy_hops` the address the outermost
+/// trusted proxy added to `X-Forwarded-For`. Entries before it can be sent by
+/// the client and are ignored.
+fn client_ip(trusted_proxy_hops: usize, request: &Request) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    if trusted_proxy_hops == 0 {
+        return peer;
+    }
+    let forwarded: Vec<&str> = request
+        .headers()
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    forwarded
+        .len()
+        .checked_sub(trusted_proxy_hops)
+        .or((!forwarded.is_empty()).then_some(0))
+        .and_then(|index| forwarded[index].parse().ok())
+        .or(peer)
+}
+
+/// Rejects requests with an unknown API key, or without one when keys are
+/// required, and adds the [`ApiClient`] to the others. When keys are required
+/// responses are marked `private`, so shared caches don't hand them to
+/// clients without a key.
+#[derive(Debug, Clone)]
+pub struct ApiKeyLayer {
+    config: SharedConfig,
+}
+
+impl ApiKeyLayer {
+    pub fn new(config: SharedConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl<S> Layer<S> for ApiKeyLayer {
+    type Service = ApiKeyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiKeyService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKeyService<S> {
+    inner: S,
+    config: SharedConfig,
+}
+
+impl<S> Service<Request> for ApiKeyService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        match authenticate(&self.config, &request) {
+            Ok(client) => {
+                counter!("api_requests_total", "key" => client.metric_label()).increment(1);
+                request.extensions_mut().insert(client);
+                let response = self.inner.call(request);
+                if !self.config.requires_api_key() {
+                    return Box::pin(response);
+                }
+                Box::pin(async move {
+                    let mut response = response.await?;
+                    make_private(&mut response);
+                    Ok(response)
+                })
+            }
+            Err(err) => {
+                counter!("api_unauthorized_requests_total").increment(1);
+                Box::pin(async move { Ok(err.into_response()) })
+            }
+        }
+    }
+}
+
+/// Turns `Cache-Control: public` into `private`, keeping the max age.
+fn make_private(response: &mut Response) {
+    let headers = response.headers_mut();
+    let Some(cache_control) = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("public"))
+        .and_then(|rest| HeaderValue::from_str(&format!("private{rest}")).ok())
+    else {
+        return;
+    };
+    headers.insert(header::CACHE_CONTROL, cache_control);
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimitConfig, now: Instant) -> Self {
+        Self {
+            tokens: limit.requests as f64,
+            updated_at: now,
+        }
+    }
+
+    /// Takes a token, or returns how long it takes until one is available.
+    fn take(&mut self, limit: &RateLimitConfig, now: Instant) -> Result<(), Duration> {
+        let capacity = limit.requests as f64;
+        let per_sec = capacity / limit.per_secs as f64;
+        let refilled = now.duration_since(self.updated_at).as_secs_f64() * per_sec;
+        self.tokens = (self.tokens + refilled).min(capacity);
+        self.updated_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+    }
+}
+
+/// Answers `429 Too Many Requests` with `Retry-After` once an [`ApiClient`]
+/// has used up its token bucket. The limits are read from the configuration
+/// on every request, so reloading it applies new limits immediately.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    config: SharedConfig,
+    buckets: Cache<ApiClient, Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            config,
+            buckets: Cache::new(MAX_BUCKETS),
+        }
+    }
+
+    fn check(&self, client: &ApiClient) -> Result<(), Duration> {
+        let Some(limit) = self.config.rate_limit(client.key()) else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let bucket = self
+            .buckets
+            .get_with_by_ref(client, || Arc::new(Mutex::new(Bucket::full(&limit, now))));
+        let result = bucket.lock().unwrap().take(&limit, now);
+        result
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limiter: RateLimitLayer,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let client = request
+            .extensions()
+            .get::<ApiClient>()
+            .cloned()
+            .unwrap_or(ApiClient::Unknown);
+        match self.limiter.check(&client) {
+            Ok(()) => Box::pin(self.inner.call(request)),
+            Err(wait) => {
+                warn!("Rate limited requests from {:?}", client);
+                counter!("api_rate_limited_requests_total", "key" => client.metric_label())
+                    .increment(1);
+                let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                let err = ApplicationError::new(
+                    "Too many requests, retry after the time in the Retry-After header",
+                    StatusCode::TOO_MANY_REQUESTS,
+                )
+                .with_retry_after(retry_after);
+                Box::pin(async move { Ok(err.into_response()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+    use wictk_core::Endpoints;
+
+    use super::*;
+    use crate::config::{ApiKeyConfig, AuthConfig, Config};
+    use crate::handlers::test_utils::{create_test_app_with_config, make_request_sending_headers};
+
+    const KEY: &str = "mobile-secret";
+
+    fn config(require_api_key: bool) -> Config {
+        Config {
+            auth: AuthConfig {
+                require_api_key,
+                api_keys: vec![ApiKeyConfig {
+                    name: "mobile".to_string(),
+                    sha256: hash_api_key(KEY),
+                    rate_limit: Some(RateLimitConfig {
+                        requests: 2,
+                        per_secs: 60,
+                    }),
+                }],
+                ..Default::default()
+            },
+            rate_limit: Some(RateLimitConfig {
+                requests: 1,
+                per_secs: 60,
+            }),
+            ..Default::default()
+        }
+    }
+
+    async fn from_ip(app: axum::Router, ip: [u8; 4]) -> StatusCode {
+        let request = Request::builder()
+            .uri("/api/nowcasts")
+            .extension(ConnectInfo(SocketAddr::from((ip, 4000))))
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[test]
+    fn hashes_keys_as_hex() {
+        assert_eq!(
+            hash_api_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn reads_the_key_of_websocket_upgrades_from_the_query() {
+        let request = |upgrade: Option<&str>| {
+            let mut request = Request::builder().uri("/api/v1/ws?api_key=a%2Bb&x=1");
+            if let Some(upgrade) = upgrade {
+                request = request.header(header::UPGRADE, upgrade);
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        assert_eq!(
+            sent_api_key(&request(Some("websocket"))).as_deref(),
+            Some("a+b")
+        );
+        assert_eq!(
+            sent_api_key(&request(Some("WebSocket"))).as_deref(),
+            Some("a+b")
+        );
+        assert_eq!(sent_api_key(&request(None)), None);
+        assert_eq!(
+            redact_api_key(request(None).uri()),
+            "/api/v1/ws?api_key=redacted&x=1"
+        );
+        assert_eq!(
+            redact_api_key(&Uri::from_static("/api/v1/ws")),
+            "/api/v1/ws"
+        );
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limit = RateLimitConfig {
+            requests: 2,
+            per_secs: 10,
+        };
+        let start = Instant::now();
+        let mut bucket = Bucket::full(&limit, start);
+
+        assert_eq!(bucket.take(&limit, start), Ok(()));
+        assert_eq!(bucket.take(&limit, start), Ok(()));
+        assert_eq!(bucket.take(&limit, start), Err(Duration::from_secs(5)));
+        assert_eq!(bucket.take(&limit, start + Duration::from_secs(5)), Ok(()));
+    }
+
+    #[test]
+    fn reads_the_client_from_trusted_proxies() {
+        let request = |forwarded: &str| {
+            Request::builder()
+                .header("x-forwarded-for", forwarded)
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 9], 4000))))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        assert_eq!(client_ip(0, &request("203.0.113.7")), ip("10.0.0.9"));
+        assert_eq!(
+            client_ip(1, &request("198.51.100.1, 203.0.113.7")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            client_ip(2, &request("198.51.100.1, 203.0.113.7, 10.0.0.8")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(client_ip(1, &request("not an address")), ip("10.0.0.9"));
+    }
+
+    #[tokio::test]
+    async fn limits_forwarded_clients_behind_trusted_proxies() {
+        let mut config = config(false);
+        config.auth.trusted_proxy_hops = 1;
+        let app = create_test_app_with_config(Endpoints::default(), config);
+        let from = |forwarded: &'static str| {
+            let request = Request::builder()
+                .uri("/api/nowcasts")
+                .header("x-forwarded-for", forwarded)
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 9], 4000))))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(from("203.0.113.7").await, StatusCode::BAD_REQUEST);
+        assert_eq!(from("203.0.113.7").await, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(from("203.0.113.8").await, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_missing_keys() {
+        let app = create_test_app_with_config(Endpoints::default(), config(true));
+
+        let (status, _, _) =
+            make_request_sending_headers(app.clone(), "/api/nowcasts", &[("x-api-key", "wrong")])
+                .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _, _) = make_request_sending_headers(app.clone(), "/api/nowcasts", &[]).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Status endpoints stay open
+        let (status, _, _) = make_request_sending_headers(app, "/status/ping", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn limits_each_key_with_its_own_limit() {
+        let app = create_test_app_with_config(Endpoints::default(), config(true));
+        let with_key =
+            || make_request_sending_headers(app.clone(), "/api/nowcasts", &[("x-api-key", KEY)]);
+
+        // Missing location parameters, but the requests got through
+        assert_eq!(with_key().await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(with_key().await.0, StatusCode::BAD_REQUEST);
+        let (status, headers, _) = with_key().await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(headers[header::RETRY_AFTER], "30");
+    }
+
+    #[test]
+    fn marks_cache_control_private() {
+        let mut response = Response::builder()
+            .header(header::CACHE_CONTROL, "public, max-age=60")
+            .body(Body::empty())
+            .unwrap();
+        make_private(&mut response);
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "private, max-age=60"
+        );
+    }
+
+    #[tokio::test]
+    async fn keyed_responses_are_private() {
+        let mut server = mockito::Server::new_async().await;
+        let _alerts = server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .with_status(200)
+            .with_body(r#"{"features":[],"type":"FeatureCollection"}"#)
+            .create_async()
+            .await;
+        let cache_control = |require_api_key| {
+            let app = create_test_app_with_config(
+                Endpoints::with_base_url(&server.url()),
+                config(require_api_key),
+            );
+            async move {
+                let (status, headers, _) =
+                    make_request_sending_headers(app, "/api/alerts", &[("x-api-key", KEY)]).await;
+                assert_eq!(status, StatusCode::OK);
+                headers[header::CACHE_CONTROL].to_str().unwrap().to_string()
+            }
+        };
+
+        assert!(cache_control(true).await.starts_with("private, max-age="));
+        assert!(cache_control(false).await.starts_with("public, max-age="));
+    }
+
+    #[tokio::test]
+    async fn limits_anonymous_clients_per_ip() {
+        let app = create_test_app_with_config(Endpoints::default(), config(false));
+
+        assert_eq!(
+            from_ip(app.clone(), [10, 0, 0, 1]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            from_ip(app.clone(), [10, 0, 0, 1]).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(from_ip(app, [10, 0, 0, 2]).await, StatusCode::BAD_REQUEST);
+    }
+}
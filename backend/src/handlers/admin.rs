@@ -0,0 +1,199 @@
+use axum::{
+    Json,
+    extract::{Path, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info, instrument};
+
+use crate::AppState;
+
+use super::error::{ApplicationError, ProblemDetails};
+
+/// Compares in constant time, so the token cannot be guessed from response times.
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Only lets requests with `Authorization: Bearer <admin token>` through.
+pub async fn require_admin_token(
+    State(app_state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApplicationError> {
+    let Some(admin_token) = &app_state.admin_token else {
+        return Err(ApplicationError::new(
+            "The admin API is disabled, no admin token is configured",
+            StatusCode::FORBIDDEN,
+        ));
+    };
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token_matches(admin_token.expose_secret(), token));
+    if !authorized {
+        return Err(ApplicationError::new(
+            "Missing or invalid admin token",
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/watch",
+    responses(
+        (status = 200, description = "Locations kept cached by the prefetcher", body = Vec<String>),
+        (status = 401, description = "Missing or invalid admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "No admin token is configured", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "admin"
+)]
+#[instrument]
+pub async fn watched_locations(State(app_state): State<AppState>) -> Json<Vec<String>> {
+    Json(app_state.watch_list.locations())
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/watch/{location}",
+    params(("location" = String, Path, description = "Location name (e.g., \"Oslo\")")),
+    responses(
+        (status = 201, description = "Location added to the watch list"),
+        (status = 204, description = "Location was already watched"),
+        (status = 400, description = "Location name is empty", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "No admin token is configured", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "admin"
+)]
+#[instrument]
+pub async fn watch_location(
+    State(app_state): State<AppState>,
+    Path(location): Path<String>,
+) -> Result<StatusCode, ApplicationError> {
+    let location = location.trim();
+    if location.is_empty() {
+        return Err(ApplicationError::new(
+            "Location name must not be empty",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    if app_state.watch_list.add(location) {
+        info!("Watching {}", location);
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/watch/{location}",
+    params(("location" = String, Path, description = "Location name (e.g., \"Oslo\")")),
+    responses(
+        (status = 204, description = "Location removed from the watch list"),
+        (status = 401, description = "Missing or invalid admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "No admin token is configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location is not watched", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "admin"
+)]
+#[instrument]
+pub async fn unwatch_location(
+    State(app_state): State<AppState>,
+    Path(location): Path<String>,
+) -> Result<StatusCode, ApplicationError> {
+    if !app_state.watch_list.remove(&location) {
+        return Err(ApplicationError::new(
+            &format!("{location} is not watched"),
+            StatusCode::NOT_FOUND,
+        ));
+    }
+    info!("Stopped watching {}", location);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::token_matches;
+    use crate::handlers::test_utils::{
+        create_test_app, create_test_app_with_admin_token, make_authorized_request, make_request,
+    };
+    use axum::http::StatusCode;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secret", "secreT"));
+        assert!(!token_matches("secret", "secret2"));
+        assert!(!token_matches("secret", ""));
+    }
+
+    #[tokio::test]
+    async fn test_watch_list_add_and_remove() {
+        let app = create_test_app_with_admin_token("secret");
+
+        let (status, _) =
+            make_authorized_request(app.clone(), "PUT", "/admin/watch/Oslo", "secret").await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) =
+            make_authorized_request(app.clone(), "PUT", "/admin/watch/Oslo", "secret").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) =
+            make_authorized_request(app.clone(), "PUT", "/admin/watch/Bergen", "secret").await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) =
+            make_authorized_request(app.clone(), "DELETE", "/admin/watch/Oslo", "secret").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) =
+            make_authorized_request(app.clone(), "DELETE", "/admin/watch/Oslo", "secret").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = make_authorized_request(app, "GET", "/admin/watch", "secret").await;
+        assert_eq!(status, StatusCode::OK);
+        let locations: Vec<String> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(locations, vec!["Bergen"]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_rejects_empty_location() {
+        let app = create_test_app_with_admin_token("secret");
+
+        let (status, _) =
+            make_authorized_request(app.clone(), "PUT", "/admin/watch/%20%20", "secret").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = make_authorized_request(app, "GET", "/admin/watch", "secret").await;
+        let locations: Vec<String> = serde_json::from_slice(&body).unwrap();
+        assert!(locations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_token() {
+        let app = create_test_app_with_admin_token("secret");
+
+        let (status, _) = make_request(app.clone(), "/admin/watch").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = make_authorized_request(app, "GET", "/admin/watch", "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_disabled_without_token() {
+        let app = create_test_app();
+
+        let (status, _) = make_authorized_request(app, "GET", "/admin/watch", "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -1,17 +1,20 @@
-use crate::AppState;
+use crate::{
+    AppState,
+    cache::{CacheInfo, ConditionalFetch},
+};
 use axum::{
     Json,
     extract::{Query, State},
 };
+use chrono::{DateTime, Utc};
 use geo::{Point, Polygon};
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument};
 use utoipa::{IntoParams, ToSchema};
-use wictk_core::{Alert, Area, Coordinates, MetAlert};
+use wictk_core::{Alert, Area, Conditional, Coordinates, MetAlert, Upstream, WictkError};
 
 use super::{
-    error::ApplicationError,
+    error::{ApplicationError, ProblemDetails},
     nowcasts::{LocationQuery, find_location},
 };
 
@@ -86,13 +89,70 @@ fn alert_contains_location(alert: &Alert, location: &Coordinates) -> bool {
     }
 }
 
+const ALERTS_KEY: &str = "met_alerts";
+
+/// Fetches the alerts, sending `If-Modified-Since` when revalidating cached ones.
+fn fetch_alerts(
+    app_state: &AppState,
+) -> impl FnOnce(Option<DateTime<Utc>>) -> ConditionalFetch<Alerts> + Send + 'static {
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let history = app_state.history.clone();
+    move |since| {
+        Box::pin(async move {
+            let result = MetAlert::fetch_if_modified(&client, &endpoints, since)
+                .await
+                .inspect_err(|err| error!("Error fetching alerts: {}", err))?;
+            if let (Some(history), Conditional::Modified(alerts, _)) = (history, &result) {
+                history.record_alerts(alerts).await;
+            }
+            Ok(result)
+        })
+    }
+}
+
+/// Revalidates the cached alerts once Met.no's `Expires` time has passed.
+pub(crate) async fn prefetch_alerts(app_state: &AppState) -> Result<(), WictkError> {
+    app_state.config.providers().check(Upstream::Met)?;
+    app_state
+        .alert_cache
+        .prefetch_conditional(ALERTS_KEY, fetch_alerts(app_state))
+        .await
+}
+
+/// The alerts covering `location`, for the WebSocket subscriptions.
+pub(crate) async fn alerts_at(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<Alerts, WictkError> {
+    app_state.config.providers().check(Upstream::Met)?;
+    let (all_alerts, _) = app_state
+        .alert_cache
+        .get_or_fetch_conditional(ALERTS_KEY, fetch_alerts(app_state))
+        .await?;
+    Ok(all_alerts
+        .into_iter()
+        .filter(|alert| alert_contains_location(alert, location))
+        .collect())
+}
+
 #[utoipa::path(
     get,
-    path = "/api/alerts",
+    path = "/api/v1/alerts",
     params(AlertQuery),
     responses(
-        (status = 200, description = "List of weather alerts", body = Vec<Alert>),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 200, description = "List of weather alerts", body = Vec<Alert>, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - invalid coordinates", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "alerts"
 )]
@@ -100,41 +160,22 @@ fn alert_contains_location(alert: &Alert, location: &Coordinates) -> bool {
 pub async fn alerts(
     State(app_state): State<AppState>,
     Query(alert_query): Query<AlertQuery>,
-) -> Result<Json<Vec<Alert>>, ApplicationError> {
-    let all_alerts = match app_state.alert_cache.get("met_alerts").await {
-        Some(alerts) => alerts.clone(),
-        None => {
-            let alerts = MetAlert::fetch(app_state.client.clone())
-                .await
-                .map_err(|err| {
-                    error!("Error fetching alerts: {}", err);
-                    ApplicationError::new(
-                        "Failed to get Met.no alerts",
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    )
-                })?;
-            app_state
-                .alert_cache
-                .insert("met_alerts".to_string(), alerts.clone())
-                .await;
-            alerts
-        }
-    };
+) -> Result<(CacheInfo, Json<Vec<Alert>>), ApplicationError> {
+    app_state.config.providers().check(Upstream::Met)?;
+    let (all_alerts, cache_info) = app_state
+        .alert_cache
+        .get_or_fetch_conditional(ALERTS_KEY, fetch_alerts(&app_state))
+        .await?;
 
     // If no location query is provided, return all alerts
     let filtered_alerts = match alert_query.into_location_query() {
         Some(location_query) => {
-            let location = find_location(
-                location_query,
-                &app_state.client,
-                &app_state.location_cache,
-                &app_state.openweathermap_apikey,
-            )
-            .await
-            .map_err(|err| {
-                error!("Error finding location: {:?}", err);
-                ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
+            let location = find_location(location_query, &app_state)
+                .await
+                .map_err(|err| {
+                    error!("Error finding location: {:?}", err);
+                    ApplicationError::from(err)
+                })?;
 
             // Filter alerts to only those that contain the specified location
             all_alerts
@@ -145,34 +186,162 @@ pub async fn alerts(
         None => all_alerts,
     };
 
-    Ok(Json(filtered_alerts))
+    Ok((cache_info, Json(filtered_alerts)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::handlers::test_utils::{create_test_app, make_request};
+    use crate::handlers::test_utils::{
+        create_replay_test_app, create_test_app_with_endpoints, make_request,
+        make_request_with_headers,
+    };
     use axum::http::StatusCode;
     use geo::Point;
     use pretty_assertions::assert_eq;
+    use wictk_core::Endpoints;
+
+    const MET_ALERTS: &str = r#"{"features":[{"geometry":{"coordinates":[[[10.0,63.0],[11.0,63.0],[11.0,64.0],[10.0,64.0],[10.0,63.0]]],"type":"Polygon"},"properties":{"certainty":"Likely","description":"Kraftige vindkast","event":"wind","severity":"Severe","title":"Vind, oransje nivå"},"type":"Feature","when":{"interval":["2025-01-20T12:00:00+00:00","2025-01-21T12:00:00+00:00"]}}],"type":"FeatureCollection"}"#;
+
+    async fn alerts_server() -> mockito::ServerGuard {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(MET_ALERTS)
+            .create_async()
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_alerts_from_endpoint() {
+        let server = alerts_server().await;
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/alerts").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let alerts: Vec<Alert> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(alerts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_alerts_revalidated_with_if_modified_since() {
+        let mut server = mockito::Server::new_async().await;
+        let _expired = server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .match_header("if-modified-since", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("expires", &wictk_core::format_http_date(&Utc::now()))
+            .with_header("last-modified", "Mon, 20 Jan 2025 11:55:00 GMT")
+            .with_body(MET_ALERTS)
+            .create_async()
+            .await;
+        let not_modified = server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .match_header("if-modified-since", "Mon, 20 Jan 2025 11:55:00 GMT")
+            .with_status(304)
+            .with_header("expires", "Fri, 01 Jan 2100 00:00:00 GMT")
+            .expect(1)
+            .create_async()
+            .await;
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+
+        let (_, headers, _) = make_request_with_headers(app.clone(), "/api/alerts").await;
+        assert_eq!(headers["x-cache"], "MISS");
+        let (_, headers, body) = make_request_with_headers(app.clone(), "/api/alerts").await;
+        assert_eq!(headers["x-cache"], "STALE");
+        assert_eq!(
+            serde_json::from_slice::<Vec<Alert>>(&body).unwrap().len(),
+            1
+        );
+
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let (status, headers, body) = make_request_with_headers(app, "/api/alerts").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-cache"], "HIT");
+        assert_eq!(
+            serde_json::from_slice::<Vec<Alert>>(&body).unwrap().len(),
+            1
+        );
+        not_modified.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_alerts_filtered_by_coordinates() {
+        let server = alerts_server().await;
+        let endpoints = Endpoints::with_base_url(&server.url());
+
+        let app = create_test_app_with_endpoints(endpoints.clone());
+        let (status, body) = make_request(app, "/api/alerts?lat=63.5&lon=10.5").await;
+        assert_eq!(status, StatusCode::OK);
+        let alerts: Vec<Alert> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(alerts.len(), 1);
+
+        let app = create_test_app_with_endpoints(endpoints);
+        let (status, body) = make_request(app, "/api/alerts?lat=59.9&lon=10.7").await;
+        assert_eq!(status, StatusCode::OK);
+        let alerts: Vec<Alert> = serde_json::from_slice(&body).unwrap();
+        assert!(alerts.is_empty());
+    }
 
     #[tokio::test]
     async fn test_alerts_endpoint() {
-        let app = create_test_app();
-        let (status, _body) = make_request(app, "/api/alerts").await;
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/alerts").await;
 
-        // This might fail due to external API dependency, but should not crash
-        // We're testing the endpoint structure, not the actual Met.no API
-        assert!(status == StatusCode::OK || status == StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status, StatusCode::OK);
+        let alerts: Vec<Alert> = serde_json::from_slice(&body).unwrap();
+        let titles: Vec<&str> = alerts
+            .iter()
+            .map(|alert| match alert {
+                Alert::Met(alert) => alert.title.as_str(),
+                Alert::Nve => "",
+            })
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Vind, oransje nivå, Trøndelag",
+                "Glatte veier, gult nivå, Oslo"
+            ]
+        );
     }
 
     #[tokio::test]
     async fn test_alerts_with_location() {
-        let app = create_test_app();
-        let (status, _body) = make_request(app, "/api/alerts?location=Oslo").await;
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/alerts?location=Oslo").await;
 
-        // External API dependency - test endpoint structure
-        assert!(status == StatusCode::OK || status == StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([{
+                "Met": {
+                    "title": "Glatte veier, gult nivå, Oslo",
+                    "severity": "Yellow",
+                    "description": "Glatte veier på grunn av snø.",
+                    "certainty": "Observed",
+                    "event": "icing",
+                    "duration": {
+                        "from": "2025-01-20T06:00:00Z",
+                        "until": "2025-01-20T18:00:00Z"
+                    },
+                    "area": {
+                        "Single": [
+                            {"x": 10.5, "y": 59.7},
+                            {"x": 11.0, "y": 59.7},
+                            {"x": 11.0, "y": 60.1},
+                            {"x": 10.5, "y": 60.1},
+                            {"x": 10.5, "y": 59.7}
+                        ]
+                    }
+                }
+            }])
+        );
     }
 
     #[test]
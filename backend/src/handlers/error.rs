@@ -4,13 +4,35 @@ use std::{
     fmt::{Display, Formatter},
 };
 
-use axum::http::StatusCode;
+use axum::Json;
+use axum::http::{HeaderValue, StatusCode, header};
 use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use wictk_core::WictkError;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Error body as described in RFC 7807
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ProblemDetails {
+    /// URI identifying the problem type, `about:blank` when only the status applies
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Short summary of the problem type
+    pub title: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Explanation specific to this occurrence of the problem
+    pub detail: String,
+}
 
 #[derive(Debug)]
 pub struct ApplicationError {
     message: String,
     status_code: StatusCode,
+    /// Seconds the client should wait before retrying
+    retry_after: Option<u64>,
 }
 
 impl Display for ApplicationError {
@@ -26,12 +48,147 @@ impl ApplicationError {
         Self {
             message: message.into(),
             status_code,
+            retry_after: None,
+        }
+    }
+
+    /// Sends `Retry-After` with the response.
+    pub fn with_retry_after(self, secs: u64) -> Self {
+        Self {
+            retry_after: Some(secs),
+            ..self
+        }
+    }
+
+    /// The body sent for this error.
+    pub fn problem(&self) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: self
+                .status_code
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+            status: self.status_code.as_u16(),
+            detail: self.message.clone(),
+        }
+    }
+}
+
+impl From<WictkError> for ApplicationError {
+    fn from(err: WictkError) -> Self {
+        let status_code = match &err {
+            WictkError::InvalidInput { .. } => StatusCode::BAD_REQUEST,
+            WictkError::NotFound { .. } => StatusCode::NOT_FOUND,
+            WictkError::UpstreamRateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            WictkError::ParseError { .. } => StatusCode::BAD_GATEWAY,
+            WictkError::UpstreamUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            WictkError::UpstreamTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+        };
+        let retry_after = match &err {
+            WictkError::UpstreamRateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        };
+        Self {
+            message: err.to_string(),
+            status_code,
+            retry_after,
         }
     }
 }
 
 impl IntoResponse for ApplicationError {
     fn into_response(self) -> Response {
-        (self.status_code, self.message.to_string()).into_response()
+        let mut response = (self.status_code, Json(self.problem())).into_response();
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+        );
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use wictk_core::Upstream;
+
+    use super::*;
+
+    async fn problem(err: ApplicationError) -> (StatusCode, Response, ProblemDetails) {
+        let response = err.into_response();
+        let status = response.status();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        let problem = serde_json::from_slice(&body).unwrap();
+        (status, Response::from_parts(parts, ().into()), problem)
+    }
+
+    #[tokio::test]
+    async fn problem_details_body() {
+        let (status, response, problem) = problem(ApplicationError::new(
+            "Missing location",
+            StatusCode::BAD_REQUEST,
+        ))
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            PROBLEM_CONTENT_TYPE
+        );
+        assert_eq!(
+            problem,
+            ProblemDetails {
+                problem_type: "about:blank".to_string(),
+                title: "Bad Request".to_string(),
+                status: 400,
+                detail: "Missing location".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn maps_wictk_errors_to_statuses() {
+        let cases = [
+            (WictkError::invalid_input("bad"), StatusCode::BAD_REQUEST),
+            (WictkError::not_found("missing"), StatusCode::NOT_FOUND),
+            (WictkError::parse("dt"), StatusCode::BAD_GATEWAY),
+            (
+                WictkError::UpstreamUnavailable {
+                    upstream: Upstream::Met,
+                    reason: "request failed".to_string(),
+                },
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                WictkError::UpstreamTimeout {
+                    upstream: Upstream::Frost,
+                },
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+        ];
+        for (err, expected) in cases {
+            let (status, _, problem) = problem(err.into()).await;
+            assert_eq!(status, expected);
+            assert_eq!(problem.status, expected.as_u16());
+        }
+    }
+
+    #[tokio::test]
+    async fn rate_limited_sets_retry_after() {
+        let err = WictkError::UpstreamRateLimited {
+            upstream: Upstream::OpenWeatherMap,
+            retry_after: Some(60),
+        };
+        let (status, response, problem) = problem(err.into()).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        assert_eq!(problem.detail, "OpenWeatherMap is rate limiting requests");
     }
 }
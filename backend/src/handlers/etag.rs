@@ -0,0 +1,124 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::Request,
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use super::error::ApplicationError;
+
+/// Adds an `ETag` computed from the body to successful `GET` and `HEAD`
+/// responses, and answers `304 Not Modified` without a body when it matches
+/// `If-None-Match`. Event streams never end, so they are passed through.
+pub async fn etag(request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::GET | Method::HEAD) {
+        return next.run(request).await;
+    }
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK || is_event_stream(&response) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            return ApplicationError::new(
+                &format!("Failed to read response body: {err}"),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response();
+        }
+    };
+    let etag = etag_of(&bytes);
+
+    let not_modified = if_none_match.is_some_and(|value| matches(&value, &etag));
+    parts.headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&etag).expect("hex digits are a valid header value"),
+    );
+    if not_modified {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_TYPE);
+        parts.headers.remove(header::CONTENT_LENGTH);
+        return Response::from_parts(parts, Body::empty());
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+/// The first 128 bits of the SHA-256 of the body, which stays the same across
+/// builds and replicas.
+fn etag_of(body: &[u8]) -> String {
+    let hex: String = Sha256::digest(body)[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("\"{hex}\"")
+}
+
+fn is_event_stream(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"text/event-stream"))
+}
+
+/// Weak comparison, as `If-None-Match` requires.
+fn matches(if_none_match: &HeaderValue, etag: &str) -> bool {
+    let Ok(if_none_match) = if_none_match.to_str() else {
+        return false;
+    };
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn send(method: Method) -> Response {
+        let app = Router::new()
+            .route("/", get(|| async { "hello" }).post(|| async { "hello" }))
+            .layer(middleware::from_fn(etag));
+        let request = Request::builder()
+            .method(method)
+            .uri("/")
+            .header(header::IF_NONE_MATCH, "*")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    #[test]
+    fn etag_is_a_stable_digest() {
+        assert_eq!(etag_of(b"hello"), "\"2cf24dba5fb0a30e26e83b2ac5b9e29e\"");
+    }
+
+    #[tokio::test]
+    async fn only_get_and_head_are_tagged() {
+        let response = send(Method::GET).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let response = send(Method::POST).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(header::ETAG));
+    }
+
+    #[test]
+    fn matches_lists_and_weak_tags() {
+        let etag = "\"0123456789abcdef\"";
+        let value = |value: &'static str| HeaderValue::from_static(value);
+
+        assert!(matches(&value("\"0123456789abcdef\""), etag));
+        assert!(matches(&value("\"other\", W/\"0123456789abcdef\""), etag));
+        assert!(matches(&value("*"), etag));
+        assert!(!matches(&value("\"other\""), etag));
+    }
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{AppState, history::NowcastBucket};
+
+use super::{
+    error::{ApplicationError, ProblemDetails},
+    nowcasts::{LocationParams, location_from_params},
+};
+
+/// Upper limit on buckets per request, to keep responses bounded
+const MAX_BUCKETS: u64 = 10_000;
+
+/// Query parameters for the nowcast history
+#[derive(Debug, Serialize, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Location name (e.g., "Oslo")
+    pub location: Option<String>,
+    /// Latitude coordinate
+    pub lat: Option<String>,
+    /// Longitude coordinate
+    pub lon: Option<String>,
+    /// Start of the series in RFC 3339 (default: 24 hours before `to`)
+    pub from: Option<DateTime<Utc>>,
+    /// End of the series in RFC 3339 (default: now)
+    pub to: Option<DateTime<Utc>>,
+    /// Bucket size as a number followed by s, m, h or d (default: 1h)
+    pub interval: Option<String>,
+}
+
+/// Parses intervals like `90s`, `15m`, `1h` and `7d`.
+fn parse_interval(interval: &str) -> Option<Duration> {
+    let split = interval.len().checked_sub(1)?;
+    let (amount, unit) = interval.split_at_checked(split)?;
+    let amount: u64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/history/nowcasts",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Recorded nowcasts aggregated per time bucket, empty buckets are left out", body = Vec<NowcastBucket>),
+        (status = 400, description = "Bad request - missing or invalid parameters", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No history database is configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "The history could not be read or the location lookup failed", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "history"
+)]
+#[instrument]
+pub async fn nowcast_history(
+    State(app_state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<NowcastBucket>>, ApplicationError> {
+    let history = app_state.history.clone().ok_or_else(|| {
+        ApplicationError::new(
+            "History is unavailable, no history database is configured",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+    })?;
+
+    let interval = parse_interval(query.interval.as_deref().unwrap_or("1h")).ok_or_else(|| {
+        ApplicationError::new(
+            "Invalid interval. Use a number followed by s, m, h or d, e.g. 15m",
+            StatusCode::BAD_REQUEST,
+        )
+    })?;
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or(to - chrono::Duration::days(1));
+    if from >= to {
+        return Err(ApplicationError::new(
+            "'from' must be before 'to'",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    if (to - from).num_seconds() as u64 / interval.as_secs() > MAX_BUCKETS {
+        return Err(ApplicationError::new(
+            &format!(
+                "Too many buckets, use a larger interval or a shorter range (max {MAX_BUCKETS})"
+            ),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let params = LocationParams {
+        location: query.location,
+        lat: query.lat,
+        lon: query.lon,
+    };
+    let location = location_from_params(&app_state, params).await?;
+
+    let buckets = history
+        .nowcast_series(&location, from, to, interval)
+        .await
+        .map_err(|err| {
+            error!("Error reading nowcast history: {:?}", err);
+            ApplicationError::new(
+                "Could not read nowcast history",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })?;
+
+    Ok(Json(buckets))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse_interval;
+    use crate::handlers::test_utils::{
+        create_test_app, create_test_app_with_history, make_request,
+    };
+    use crate::history::{History, NowcastBucket};
+    use axum::http::StatusCode;
+    use wictk_core::Endpoints;
+
+    const MET_NOWCAST: &str = r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[10.4034,63.4308,0]},"properties":{"meta":{"updated_at":"2023-08-14T18:16:07Z"},"timeseries":[{"time":"2023-08-14T18:15:00Z","data":{"instant":{"details":{"air_temperature":17.7,"relative_humidity":80.5,"wind_from_direction":294.4,"wind_speed":2.7,"wind_speed_of_gust":6.1}},"next_1_hours":{"summary":{"symbol_code":"cloudy"},"details":{"precipitation_amount":0.0}}}}]}}"#;
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_interval("15m"), Some(Duration::from_secs(900)));
+        assert_eq!(parse_interval("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_interval("7d"), Some(Duration::from_secs(604800)));
+        for invalid in ["", "h", "0h", "-1h", "1w", "1.5h", "1hh"] {
+            assert_eq!(parse_interval(invalid), None, "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nowcast_history_records_fetched_nowcasts() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .expect(1)
+            .create_async()
+            .await;
+        let app = create_test_app_with_history(
+            Endpoints::with_base_url(&server.url()),
+            History::in_memory().unwrap(),
+        );
+
+        let (status, _) =
+            make_request(app.clone(), "/api/met/nowcasts?lat=63.4308&lon=10.4034").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = make_request(
+            app,
+            "/api/history/nowcasts?lat=63.4308&lon=10.4034&from=2023-08-14T00:00:00Z&to=2023-08-15T00:00:00Z&interval=6h",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let buckets: Vec<NowcastBucket> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].start.to_rfc3339(), "2023-08-14T18:00:00+00:00");
+        assert_eq!(buckets[0].samples, 1);
+        assert_eq!(buckets[0].air_temperature.mean as f32, 17.7);
+    }
+
+    #[tokio::test]
+    async fn test_nowcast_history_invalid_params() {
+        let app = create_test_app_with_history(Endpoints::default(), History::in_memory().unwrap());
+        for uri in [
+            "/api/history/nowcasts",
+            "/api/history/nowcasts?lat=63.43&lon=10.40&interval=1w",
+            "/api/history/nowcasts?lat=63.43&lon=10.40&from=2024-01-02T00:00:00Z&to=2024-01-01T00:00:00Z",
+            "/api/history/nowcasts?lat=63.43&lon=10.40&from=2000-01-01T00:00:00Z&interval=1m",
+        ] {
+            let (status, _) = make_request(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nowcast_history_without_database() {
+        let app = create_test_app();
+        let (status, _) = make_request(app, "/api/history/nowcasts?lat=63.43&lon=10.40").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
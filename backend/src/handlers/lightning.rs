@@ -1,21 +1,31 @@
+use std::{convert::Infallible, future::Future};
+
 use axum::{
     Json,
     extract::{Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
 };
 use geo::{Distance, Haversine, Point};
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use tracing::{error, instrument};
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+};
+use tracing::{error, instrument, warn};
 use utoipa::{IntoParams, ToSchema};
-use wictk_core::Lightning;
+use wictk_core::{Coordinates, Lightning, Upstream, WictkError};
 
-use crate::AppState;
+use crate::{AppState, cache::CacheInfo};
 
 use super::{
-    error::ApplicationError,
+    error::{ApplicationError, ProblemDetails},
     nowcasts::{LocationQuery, find_location},
 };
 
+/// Radius of the strikes near a location when none is given
+pub(crate) const DEFAULT_RADIUS_KM: f64 = 50.0;
+
 #[derive(Debug, Serialize, Deserialize, Default, ToSchema, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct LightningQuery {
@@ -44,13 +54,109 @@ impl LightningQuery {
     }
 }
 
+const LIGHTNING_KEY: &str = "recent_lightning";
+
+fn fetch_lightning(
+    app_state: &AppState,
+) -> impl Future<Output = Result<Vec<Lightning>, WictkError>> + Send + 'static {
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let history = app_state.history.clone();
+    async move {
+        let strikes = Lightning::fetch(&client, &endpoints)
+            .await
+            .inspect_err(|err| error!("Error fetching lightning data: {:?}", err))?;
+        if let Some(history) = history {
+            history.record_lightning(&strikes).await;
+        }
+        Ok(strikes)
+    }
+}
+
+/// Refreshes the cached lightning strikes, even if they are still fresh.
+pub(crate) async fn prefetch_lightning(app_state: &AppState) -> Result<(), WictkError> {
+    app_state.config.providers().check(Upstream::Yr)?;
+    app_state
+        .lightning_cache
+        .prefetch(LIGHTNING_KEY, fetch_lightning(app_state))
+        .await
+}
+
+/// Refreshes the cached lightning strikes and returns them, for the stream poller.
+pub(crate) async fn poll_lightning(app_state: &AppState) -> Result<Vec<Lightning>, WictkError> {
+    prefetch_lightning(app_state).await?;
+    let (strikes, _) = app_state
+        .lightning_cache
+        .get_or_fetch(LIGHTNING_KEY, fetch_lightning(app_state))
+        .await?;
+    Ok(strikes)
+}
+
+/// The area of strikes to return, `None` for all of them.
+pub(super) async fn find_area(
+    query: LightningQuery,
+    app_state: &AppState,
+) -> Result<Option<Area>, ApplicationError> {
+    let (location_query, radius_km) = query.into_location_query();
+    if radius_km.is_some_and(|radius_km| !radius_km.is_finite() || radius_km <= 0.0) {
+        return Err(ApplicationError::new(
+            "'radius_km' must be greater than 0",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    let Some(location_query) = location_query else {
+        return Ok(None);
+    };
+    let location_coords = find_location(location_query, app_state)
+        .await
+        .map_err(|err| {
+            error!("Error finding location: {:?}", err);
+            ApplicationError::from(err)
+        })?;
+    Ok(Some(Area::around(
+        &location_coords,
+        radius_km.unwrap_or(DEFAULT_RADIUS_KM),
+    )))
+}
+
+/// Strikes within a radius of a location
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Area {
+    center: Point,
+    radius_meters: f64,
+}
+
+impl Area {
+    pub(crate) fn around(location: &Coordinates, radius_km: f64) -> Self {
+        Self {
+            center: Point::new(location.lon as f64, location.lat as f64),
+            radius_meters: radius_km * 1000.0,
+        }
+    }
+
+    pub(crate) fn contains(&self, lightning: &Lightning) -> bool {
+        let lightning_point = Point::new(lightning.location.x(), lightning.location.y());
+        Haversine.distance(self.center, lightning_point) <= self.radius_meters
+    }
+}
+
 #[utoipa::path(
     get,
-    path = "/api/recent_lightning",
+    path = "/api/v1/recent_lightning",
     params(LightningQuery),
     responses(
-        (status = 200, description = "List of recent lightning strikes", body = Vec<Lightning>),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 200, description = "List of recent lightning strikes", body = Vec<Lightning>, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - invalid coordinates or radius", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "lightning"
 )]
@@ -58,81 +164,254 @@ impl LightningQuery {
 pub async fn get_recent_lightning(
     app_state: State<AppState>,
     Query(query): Query<LightningQuery>,
-) -> Result<Json<Vec<Lightning>>, ApplicationError> {
-    // Get the lightning data first
-    let lightning_data = match app_state.lightning_cache.get("recent_lightning").await {
-        Some(lightning) => lightning,
-        None => {
-            let lightning = Lightning::find_ligntning(
-                &app_state.client,
-                "https://www.yr.no/api/v0/lightning-events?fromHours=24",
-            )
-            .await
-            .map_err(|err| {
-                error!("Error fetching lightning data: {:?}", err);
-                ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
-            app_state
-                .lightning_cache
-                .insert("recent_lightning".to_string(), lightning.clone())
-                .await;
-            lightning
-        }
-    };
-
-    // Convert query to location query and radius
-    let (location_query, radius_km) = query.into_location_query();
+) -> Result<(CacheInfo, Json<Vec<Lightning>>), ApplicationError> {
+    app_state.config.providers().check(Upstream::Yr)?;
+    // Validate the query before the lightning data is fetched
+    let area = find_area(query, &app_state).await?;
+    let (lightning_data, cache_info) = app_state
+        .lightning_cache
+        .get_or_fetch(LIGHTNING_KEY, fetch_lightning(&app_state))
+        .await?;
 
     // If no location is provided, return all lightning data
-    let Some(location_query) = location_query else {
-        return Ok(Json(lightning_data));
+    let Some(area) = area else {
+        return Ok((cache_info, Json(lightning_data)));
     };
 
-    // Find the location coordinates
-    let location_coords = find_location(
-        location_query,
-        &app_state.client,
-        &app_state.location_cache,
-        &app_state.openweathermap_apikey,
-    )
-    .await
-    .map_err(|err| {
-        error!("Error finding location: {:?}", err);
-        ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-    })?;
-
-    // Convert to geo::Point for distance calculations
-    let query_point = Point::new(location_coords.lon as f64, location_coords.lat as f64);
-
-    // Default radius to 50km if not specified
-    let radius_meters = radius_km.unwrap_or(50.0) * 1000.0;
-
     // Filter lightning strikes within the specified radius
     let filtered_lightning: Vec<Lightning> = lightning_data
         .into_iter()
-        .filter(|lightning| {
-            let lightning_point = Point::new(lightning.location.x(), lightning.location.y());
-            let distance = Haversine.distance(query_point, lightning_point);
-            distance <= radius_meters
-        })
+        .filter(|lightning| area.contains(lightning))
         .collect();
 
-    Ok(Json(filtered_lightning))
+    Ok((cache_info, Json(filtered_lightning)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/lightning/stream",
+    params(LightningQuery),
+    responses(
+        (status = 200, description = "Server-Sent Events stream with a `lightning` event per new strike, its data is the strike as JSON", body = Lightning, content_type = "text/event-stream"),
+        (status = 400, description = "Bad request - invalid coordinates or radius", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "The lightning upstream is disabled", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "lightning"
+)]
+#[instrument]
+pub async fn lightning_stream(
+    app_state: State<AppState>,
+    Query(query): Query<LightningQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApplicationError> {
+    app_state.config.providers().check(Upstream::Yr)?;
+    let area = find_area(query, &app_state).await?;
+
+    let strikes = BroadcastStream::new(app_state.lightning_feed.subscribe(&app_state));
+    let events = strikes.filter_map(move |strike| match strike {
+        Ok(strike) if area.is_none_or(|area| area.contains(&strike)) => Some(Ok(Event::default()
+            .event("lightning")
+            .json_data(&strike)
+            .expect("strikes serialize to JSON"))),
+        Ok(_) => None,
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            warn!(
+                "Lightning stream subscriber fell behind, skipped {} strikes",
+                missed
+            );
+            None
+        }
+    });
+    let keep_alive = KeepAlive::new().interval(app_state.config.streams().keep_alive());
+    Ok(Sse::new(events).keep_alive(keep_alive))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::handlers::test_utils::{create_test_app, make_request};
+    use crate::config::{Config, StreamsConfig};
+    use crate::handlers::test_utils::{
+        create_replay_test_app, create_test_app, create_test_app_with_config,
+        create_test_app_with_endpoints, make_request, serve,
+    };
     use axum::http::StatusCode;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+    use wictk_client::{Location, WictkClient};
+    use wictk_core::Endpoints;
+
+    #[tokio::test]
+    async fn test_recent_lightning_filtered_by_radius() {
+        let mut server = mockito::Server::new_async().await;
+        let mock_response = serde_json::json!({
+            "historicalData": "[[1700000000,63.4308,10.4034,1],[1700000060,59.9139,10.7522,2]]"
+        });
+        server
+            .mock("GET", "/api/v0/lightning-events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_response.to_string())
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(
+            app,
+            "/api/recent_lightning?lat=63.4308&lon=10.4034&radius_km=10",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let lightning: Vec<Lightning> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(lightning.len(), 1);
+        assert_eq!(lightning[0].magic_value, 1);
+    }
+
+    #[tokio::test]
+    async fn test_recent_lightning_rejects_invalid_radius_without_fetching() {
+        let mut server = mockito::Server::new_async().await;
+        let lightning = server
+            .mock("GET", "/api/v0/lightning-events")
+            .match_query(mockito::Matcher::Any)
+            .expect(0)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, _body) = make_request(
+            app,
+            "/api/recent_lightning?lat=63.4308&lon=10.4034&radius_km=0",
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        lightning.assert_async().await;
+    }
 
     #[tokio::test]
     async fn test_recent_lightning_endpoint() {
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/recent_lightning").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"location": {"x": 10.4187, "y": 63.4412}, "time": "2025-01-20T11:00:00Z", "magic_value": 12},
+                {"location": {"x": 10.7553, "y": 59.9201}, "time": "2025-01-20T11:10:00Z", "magic_value": 3},
+                {"location": {"x": 6.1549, "y": 62.0123}, "time": "2025-01-20T11:20:00Z", "magic_value": 7}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recent_lightning_near_location() {
+        let app = create_replay_test_app().await;
+        let (status, body) =
+            make_request(app, "/api/recent_lightning?location=Trondheim&radius_km=25").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {"location": {"x": 10.4187, "y": 63.4412}, "time": "2025-01-20T11:00:00Z", "magic_value": 12}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lightning_stream_sends_new_strikes_nearby() {
+        let mut server = mockito::Server::new_async().await;
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        server
+            .mock("GET", "/api/v0/lightning-events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body_from_request(move |_| {
+                // Oslo and then Trondheim strike after the first poll
+                let strikes = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    "[[1700000000,63.4308,10.4034,1]]"
+                } else {
+                    "[[1700000000,63.4308,10.4034,1],[1700000060,59.9139,10.7522,2],[1700000120,63.4308,10.4034,3]]"
+                };
+                serde_json::json!({ "historicalData": strikes })
+                    .to_string()
+                    .into()
+            })
+            .create_async()
+            .await;
+        let config = Config {
+            streams: StreamsConfig {
+                lightning_poll_secs: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::with_base_url(&server.url()), config);
+        let client = WictkClient::new(reqwest::Client::new(), &serve(app).await).unwrap();
+
+        let trondheim = Location::coordinates(63.4308, 10.4034);
+        let (mut near, mut everywhere) = (
+            client
+                .lightning_stream(Some(&trondheim), Some(10.0))
+                .await
+                .unwrap(),
+            client.lightning_stream(None, None).await.unwrap(),
+        );
+        let next = |strike: Option<Result<Lightning, _>>| strike.unwrap().unwrap().magic_value;
+        let timeout = Duration::from_secs(10);
+
+        assert_eq!(
+            next(tokio::time::timeout(timeout, near.next()).await.unwrap()),
+            3
+        );
+        assert_eq!(
+            next(
+                tokio::time::timeout(timeout, everywhere.next())
+                    .await
+                    .unwrap()
+            ),
+            2
+        );
+        assert_eq!(
+            next(
+                tokio::time::timeout(timeout, everywhere.next())
+                    .await
+                    .unwrap()
+            ),
+            3
+        );
+        // One poller serves both subscribers
+        assert!(polls.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_lightning_stream_rejects_invalid_coordinates() {
         let app = create_test_app();
-        let (status, _body) = make_request(app, "/api/recent_lightning").await;
+        let (status, _body) = make_request(app, "/api/lightning/stream?lat=north&lon=10.4").await;
 
-        // External API dependency - test endpoint structure
-        assert!(status == StatusCode::OK || status == StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_lightning_stream_rejects_invalid_radius() {
+        for radius_km in ["0", "-5", "NaN", "inf"] {
+            let app = create_test_app();
+            let uri = format!("/api/lightning/stream?lat=63.4&lon=10.4&radius_km={radius_km}");
+            let (status, _body) = make_request(app, &uri).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{radius_km}");
+        }
     }
 
     #[test]
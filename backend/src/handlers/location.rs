@@ -2,54 +2,51 @@ use axum::{
     Json,
     extract::{Query, State},
 };
-use moka::future::Cache;
-use redact::Secret;
-use reqwest::StatusCode;
 use tracing::debug;
-use wictk_core::{City, OpenWeatherMapLocation};
+use wictk_core::{City, OpenWeatherMapLocation, Upstream, WictkError};
 
 use crate::AppState;
 
-use super::error::ApplicationError;
+use super::error::{ApplicationError, ProblemDetails};
 
 pub async fn lookup_location(
-    client: &reqwest::Client,
+    app_state: &AppState,
     location: &str,
-    loc_cache: &Cache<String, OpenWeatherMapLocation>,
-    apikey: &Secret<String>,
-) -> Result<OpenWeatherMapLocation, ApplicationError> {
-    match loc_cache.get(location).await {
-        Some(location) => Ok(location),
-        None => {
-            let locations = OpenWeatherMapLocation::fetch(client, location, apikey)
-                .await
-                .ok_or_else(|| {
-                    tracing::error!("Failed to get location data from OpenWeatherMap");
-                    ApplicationError::new(
-                        "Failed to get location data from OpenWeatherMap",
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                    )
-                })?;
-            let location = locations.first().cloned().ok_or_else(|| {
-                tracing::error!("No location found for {}", location);
-                ApplicationError::new("No location found", StatusCode::NOT_FOUND)
+) -> Result<OpenWeatherMapLocation, WictkError> {
+    app_state
+        .config
+        .providers()
+        .check(Upstream::OpenWeatherMap)?;
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let query = location.to_string();
+    let apikey = app_state.openweathermap_apikey.clone();
+    let fetch = async move {
+        let locations = OpenWeatherMapLocation::fetch(&client, &endpoints, &query, &apikey)
+            .await
+            .inspect_err(|err| {
+                tracing::error!("Failed to get location data from OpenWeatherMap: {}", err);
             })?;
-            loc_cache
-                .insert(location.name.clone(), location.clone())
-                .await;
-            Ok(location)
-        }
-    }
+        locations.first().cloned().ok_or_else(|| {
+            tracing::error!("No location found for {}", query);
+            WictkError::not_found(&format!("No location found for {query}"))
+        })
+    };
+    let (location, _) = app_state
+        .location_cache
+        .get_or_fetch(location, fetch)
+        .await?;
+    Ok(location)
 }
 
 #[utoipa::path(
     get,
-    path = "/api/geocoding",
+    path = "/api/v1/geocoding",
     params(City),
     responses(
         (status = 200, description = "List of matching locations", body = Vec<OpenWeatherMapLocation>),
-        (status = 404, description = "No location found"),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 429, description = "OpenWeatherMap is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "OpenWeatherMap is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "geocoding"
 )]
@@ -57,18 +54,20 @@ pub async fn geocoding(
     State(app_state): State<AppState>,
     Query(query): Query<City>,
 ) -> Result<Json<Vec<OpenWeatherMapLocation>>, ApplicationError> {
+    app_state
+        .config
+        .providers()
+        .check(Upstream::OpenWeatherMap)?;
     let res = OpenWeatherMapLocation::fetch(
         &app_state.client,
+        &app_state.endpoints,
         &query.location,
         &app_state.openweathermap_apikey,
     )
     .await
-    .ok_or_else(|| {
-        tracing::error!("Failed to get geocoding data from OpenWeatherMap");
-        ApplicationError::new(
-            "Failed to get geocoding data from OpenWeatherMap",
-            StatusCode::INTERNAL_SERVER_ERROR,
-        )
+    .map_err(|err| {
+        tracing::error!("Failed to get geocoding data from OpenWeatherMap: {}", err);
+        ApplicationError::from(err)
     })?;
     debug!("Returning {:?} for {:?}", &res, &query);
     Ok(Json(res))
@@ -76,8 +75,30 @@ pub async fn geocoding(
 
 #[cfg(test)]
 mod tests {
-    use crate::handlers::test_utils::{create_test_app, make_request};
+    use crate::handlers::test_utils::{
+        create_test_app, create_test_app_with_endpoints, make_request,
+    };
     use axum::http::StatusCode;
+    use wictk_core::{Endpoints, OpenWeatherMapLocation};
+
+    #[tokio::test]
+    async fn test_geocoding_from_endpoint() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::UrlEncoded("q".into(), "Oslo".into()))
+            .with_status(200)
+            .with_body(r#"[{"name":"Oslo","lat":59.9133,"lon":10.7389,"country":"NO"}]"#)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/geocoding?location=Oslo").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let locations: Vec<OpenWeatherMapLocation> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(locations[0].name, "Oslo");
+    }
 
     #[tokio::test]
     async fn test_geocoding_missing_params() {
@@ -1,53 +1,94 @@
 use crate::AppState;
 use axum::{
     Json, Router,
-    extract::{Request, State},
+    extract::{MatchedPath, Request, State},
+    http::{Method, header},
     middleware::{self, Next},
     response::Response,
-    routing::get,
+    routing::{MethodRouter, get, post, put},
 };
-use lightning::get_recent_lightning;
-use metrics::histogram;
+use lightning::{get_recent_lightning, lightning_stream};
+use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::PrometheusHandle;
-use nowcasts::{nowcast_met, nowcast_openweathermap, nowcasts};
+use nowcasts::{nowcast_met, nowcast_openweathermap, nowcasts, nowcasts_batch};
 use tokio::time::Instant;
 use tower::ServiceBuilder;
-use tracing::{info, instrument};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{Instrument, field, info, info_span, instrument};
 use utoipa::OpenApi;
+use utoipa_swagger_ui::{Config as SwaggerConfig, SwaggerUi};
 use wictk_core::{
-    Alert, Area, City, Coordinates, CoordinatesAsString, Lightning, MetAlert, MetNowcast, Nowcast,
-    OpenWeatherMapLocation, OpenWeatherNowcast, Severity, TimeDuration,
+    Alert, Area, City, Coordinates, CoordinatesAsString, FrostObservation, FrostStation, Lightning,
+    MetAlert, MetNowcast, Nowcast, OpenWeatherMapLocation, OpenWeatherNowcast, Severity,
+    TimeDuration, telemetry,
 };
 
 use self::{
+    access::{API_KEY_HEADER, ApiKeyLayer, RateLimitLayer, redact_api_key},
+    admin::{require_admin_token, unwatch_location, watch_location, watched_locations},
     alerts::alerts,
+    etag::etag,
+    history::nowcast_history,
     location::geocoding,
-    status::{health, ping},
+    observations::observations,
+    status::{health, ping, ready},
+    versioning::deprecation,
+    webhooks::{delete_webhook, get_webhook, register_webhook, webhook_deliveries},
+    websocket::websocket,
 };
 
+mod access;
+mod admin;
 mod alerts;
 mod error;
+mod etag;
+mod history;
 mod lightning;
 mod location;
 mod nowcasts;
+mod observations;
 mod status;
+mod versioning;
+mod webhooks;
+mod websocket;
 
 #[cfg(test)]
 mod test_utils;
 
 pub use alerts::Alerts;
+pub(crate) use alerts::{alerts_at, prefetch_alerts};
+pub(crate) use lightning::{Area as LightningArea, poll_lightning, prefetch_lightning};
+pub(crate) use nowcasts::{
+    LocationQuery, combined_nowcasts, find_location, prefetch_met_nowcast,
+    prefetch_openweathermap_nowcast,
+};
+pub use observations::StationObservations;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         status::ping,
         status::health,
+        status::ready,
         alerts::alerts,
         nowcasts::nowcast_met,
         nowcasts::nowcast_openweathermap,
         nowcasts::nowcasts,
+        nowcasts::nowcasts_batch,
+        history::nowcast_history,
         location::geocoding,
         lightning::get_recent_lightning,
+        lightning::lightning_stream,
+        observations::observations,
+        websocket::websocket,
+        webhooks::register_webhook,
+        webhooks::get_webhook,
+        webhooks::delete_webhook,
+        webhooks::webhook_deliveries,
+        admin::watched_locations,
+        admin::watch_location,
+        admin::unwatch_location,
+        metrics,
         openapi,
     ),
     components(
@@ -65,10 +106,33 @@ pub use alerts::Alerts;
             CoordinatesAsString,
             City,
             OpenWeatherMapLocation,
+            FrostStation,
+            FrostObservation,
+            observations::StationObservations,
             nowcasts::LocationQuery,
             nowcasts::LocationParams,
+            nowcasts::NowcastBatchRequest,
+            nowcasts::NowcastBatchItem,
             alerts::AlertQuery,
             lightning::LightningQuery,
+            history::HistoryQuery,
+            websocket::ClientMessage,
+            websocket::Subscription,
+            websocket::SubscriptionTopic,
+            websocket::ServerMessage,
+            webhooks::WebhookRequest,
+            crate::webhooks::Webhook,
+            crate::webhooks::WebhookEvent,
+            crate::webhooks::Delivery,
+            crate::history::NowcastBucket,
+            crate::history::Aggregate,
+            crate::health::HealthReport,
+            crate::health::DependencyHealth,
+            crate::health::DependencyStatus,
+            crate::health::ServiceStatus,
+            crate::quota::QuotaUsage,
+            crate::health::ReadinessReport,
+            error::ProblemDetails,
         )
     ),
     tags(
@@ -77,32 +141,82 @@ pub use alerts::Alerts;
         (name = "alerts", description = "Weather alert endpoints"),
         (name = "geocoding", description = "Geocoding endpoints"),
         (name = "lightning", description = "Lightning data endpoints"),
+        (name = "observations", description = "Weather station observation endpoints"),
+        (name = "history", description = "Recorded weather history endpoints"),
+        (name = "subscriptions", description = "Live updates over WebSocket and webhooks"),
+        (name = "admin", description = "Administration endpoints, require the admin token"),
         (name = "documentation", description = "API documentation endpoints"),
     ),
     info(
         title = "WICTK Weather API",
-        description = "Weather Information and Climate Toolkit API",
-        version = "0.20.1"
+        description = "Weather Information and Climate Toolkit API, version 1.\n\n\
+            `/api` is an alias of `/api/v1`, the latest version. A deprecated version sends \
+            `Deprecation` and `Sunset` headers, and answers `410 Gone` after its sunset.",
+        version = "1.0.0"
     )
 )]
 pub struct ApiDoc;
 
-#[instrument]
+/// Counts a request as in flight until dropped, also when the client disconnects
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        gauge!("http_requests_in_flight").increment(1.0);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        gauge!("http_requests_in_flight").decrement(1.0);
+    }
+}
+
+/// Records the requests per route and status. Routes are labelled with their
+/// template, e.g. `/admin/watch/{location}`, so query strings and path
+/// parameters do not create new time series.
+///
+/// Each request gets a span, continuing the trace of a client that sent a
+/// `traceparent` header.
 pub async fn profile_endpoint(request: Request, next: Next) -> Response {
     let method = request.method().clone().to_string();
-    let uri = request.uri().clone().to_string();
+    let uri = redact_api_key(request.uri());
+    let path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{method} {path}"),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = %path,
+        http.response.status_code = field::Empty,
+    );
+    telemetry::continue_trace(&span, request.headers());
 
     info!("Handling {} at {}", method, uri);
 
+    let in_flight = InFlight::start();
     let now = Instant::now();
 
-    let response = next.run(request).await;
+    let response = next.run(request).instrument(span.clone()).await;
 
     let elapsed = now.elapsed();
+    drop(in_flight);
+    span.record("http.response.status_code", response.status().as_u16());
 
-    let labels = [("method", method.clone()), ("uri", uri.clone())];
+    let labels = [
+        ("method", method.clone()),
+        ("path", path),
+        ("status", response.status().as_str().to_string()),
+    ];
 
-    histogram!("handler", &labels).record(elapsed);
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(elapsed);
 
     info!(
         "Finished handling {} at {}, used {} ms",
@@ -113,27 +227,112 @@ pub async fn profile_endpoint(request: Request, next: Next) -> Response {
     response
 }
 
+/// Paths and their handlers, listed apart from the routers so the tests can
+/// check that each of them is in the OpenAPI document
+type Routes<S> = Vec<(&'static str, MethodRouter<S>)>;
+
+/// Served under `/api/v1` and the `/api` alias
+fn api_routes() -> Routes<AppState> {
+    vec![
+        ("/alerts", get(alerts)),
+        ("/owm/nowcasts", get(nowcast_openweathermap)),
+        ("/met/nowcasts", get(nowcast_met)),
+        ("/nowcasts", get(nowcasts)),
+        ("/nowcasts/batch", post(nowcasts_batch)),
+        ("/geocoding", get(geocoding)),
+        ("/recent_lightning", get(get_recent_lightning)),
+        ("/lightning/stream", get(lightning_stream)),
+        ("/observations", get(observations)),
+        ("/history/nowcasts", get(nowcast_history)),
+        ("/ws", get(websocket)),
+        ("/subscriptions", post(register_webhook)),
+        (
+            "/subscriptions/{id}",
+            get(get_webhook).delete(delete_webhook),
+        ),
+        ("/subscriptions/{id}/deliveries", get(webhook_deliveries)),
+    ]
+}
+
+/// Served under `/admin`
+fn admin_routes() -> Routes<AppState> {
+    vec![
+        ("/watch", get(watched_locations)),
+        (
+            "/watch/{location}",
+            put(watch_location).delete(unwatch_location),
+        ),
+    ]
+}
+
+/// Served under `/status`
+fn status_routes() -> Routes<AppState> {
+    vec![
+        ("/ping", get(ping)),
+        ("/health", get(health)),
+        ("/ready", get(ready)),
+    ]
+}
+
+fn root_routes() -> Routes<PrometheusHandle> {
+    vec![("/metrics", get(metrics)), ("/openapi.json", get(openapi))]
+}
+
+fn router<S: Clone + Send + Sync + 'static>(routes: Routes<S>) -> Router<S> {
+    routes
+        .into_iter()
+        .fold(Router::new(), |router, (path, route)| {
+            router.route(path, route)
+        })
+}
+
 pub fn setup_router(app_state: AppState, metrics_handler: PrometheusHandle) -> Router {
-    let api = Router::new()
-        .route("/alerts", get(alerts))
-        .route("/owm/nowcasts", get(nowcast_openweathermap))
-        .route("/met/nowcasts", get(nowcast_met))
-        .route("/nowcasts", get(nowcasts))
-        .route("/geocoding", get(geocoding))
-        .route("/recent_lightning", get(get_recent_lightning))
-        .with_state(app_state);
-
-    let status = Router::new()
-        .route("/ping", get(ping))
-        .route("/health", get(health));
-
-    Router::new()
-        .route("/metrics", get(metrics))
-        .route("/openapi", get(openapi))
+    let api = router(api_routes())
+        .route_layer(middleware::from_fn(etag))
+        .with_state(app_state.clone());
+    let api = api.layer(
+        ServiceBuilder::new()
+            .layer(ApiKeyLayer::new(app_state.config.clone()))
+            .layer(RateLimitLayer::new(app_state.config.clone())),
+    );
+    // Clones share the rate limits, so the alias does not double them
+    let versioned = |version: &'static str| {
+        api.clone().layer(middleware::from_fn_with_state(
+            (app_state.config.clone(), version),
+            deprecation,
+        ))
+    };
+    let (v1, unversioned) = (versioned("v1"), versioned("unversioned"));
+
+    let admin = router(admin_routes())
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_admin_token,
+        ))
+        .with_state(app_state.clone());
+
+    let status = router(status_routes()).with_state(app_state.clone());
+
+    let config = app_state.config.clone();
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, API_KEY_HEADER])
+        .allow_origin(AllowOrigin::predicate(move |origin, _| {
+            config.allows_origin(origin)
+        }));
+
+    router(root_routes())
         .with_state(metrics_handler)
+        .merge(SwaggerUi::new("/docs").config(SwaggerConfig::from("/openapi.json")))
         .nest("/status", status)
-        .nest("/api", api)
-        .layer(ServiceBuilder::new().layer(middleware::from_fn(profile_endpoint)))
+        .nest("/api/v1", v1)
+        .nest("/api", unversioned)
+        .nest("/admin", admin)
+        .layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(profile_endpoint))
+                .layer(cors),
+        )
 }
 
 #[utoipa::path(
@@ -148,6 +347,14 @@ async fn openapi() -> Json<utoipa::openapi::OpenApi> {
     Json(ApiDoc::openapi())
 }
 
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Prometheus metrics in the text exposition format", body = String, content_type = "text/plain")
+    ),
+    tag = "status"
+)]
 #[instrument]
 async fn metrics(State(handle): State<PrometheusHandle>) -> String {
     handle.render()
@@ -155,12 +362,25 @@ async fn metrics(State(handle): State<PrometheusHandle>) -> String {
 
 #[cfg(test)]
 mod tests {
-    use crate::handlers::test_utils::{create_test_app, make_request, make_request_with_method};
-    use axum::http::StatusCode;
+
+    use crate::config::Config;
+    use crate::handlers::test_utils::{
+        create_replay_test_app, create_test_app, create_test_app_with_config, make_request,
+        make_request_with_headers, make_request_with_method, serve,
+    };
+    use axum::body::Body;
+    use axum::http::{Method, Request, StatusCode, header};
     use axum::{extract::Query, http::Uri};
-    use wictk_core::{City, CoordinatesAsString};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use tower::ServiceExt;
+    use wictk_core::{City, CoordinatesAsString, Endpoints};
 
+    use super::{
+        ApiDoc, admin_routes, api_routes, root_routes, status_routes, versioning::LATEST_VERSION,
+    };
     use crate::handlers::nowcasts::LocationQuery;
+    use utoipa::OpenApi;
+    use wictk_client::{Location, WictkClient};
 
     #[tokio::test]
     async fn test_metrics_endpoint() {
@@ -173,6 +393,191 @@ mod tests {
         // The important thing is that it responds with 200 OK
     }
 
+    #[tokio::test]
+    async fn test_metrics_are_labelled_by_route() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+        let _recorder = metrics::set_default_local_recorder(&recorder);
+        let app = create_replay_test_app().await;
+
+        make_request(app.clone(), "/api/nowcasts?location=Trondheim").await;
+        make_request(app.clone(), "/api/nowcasts?location=Trondheim").await;
+        make_request(app, "/api/invalid?location=Trondheim").await;
+
+        let metrics = handle.render();
+        for expected in [
+            r#"http_requests_total{method="GET",path="/api/nowcasts",status="200"} 2"#,
+            r#"http_requests_total{method="GET",path="unmatched",status="404"} 1"#,
+            "http_requests_in_flight 0",
+            r#"upstream_requests_total{upstream="openweathermap",status="200"} 2"#,
+            r#"cache_requests_total{cache="nowcast",status="miss"} 2"#,
+            r#"cache_requests_total{cache="nowcast",status="hit"} 2"#,
+            r#"cache_requests_total{cache="location",status="hit"} 1"#,
+        ] {
+            assert!(
+                metrics.contains(expected),
+                "{expected} missing in\n{metrics}"
+            );
+        }
+        assert!(!metrics.contains("Trondheim"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_configured_origins() {
+        let config = Config {
+            cors_origins: vec!["https://wictk.example".to_string()],
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::default(), config);
+
+        for (origin, allowed) in [
+            ("https://wictk.example", true),
+            ("https://other.example", false),
+        ] {
+            let request = Request::builder()
+                .uri("/status/ping")
+                .header(header::ORIGIN, origin)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(
+                response
+                    .headers()
+                    .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                    .is_some(),
+                allowed,
+                "{origin}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_json_posts() {
+        let config = Config {
+            cors_origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::default(), config);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/nowcasts/batch")
+            .header(header::ORIGIN, "https://wictk.example")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type,x-api-key",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let headers = response.headers();
+        assert!(headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        let allowed = |name| headers[name].to_str().unwrap().to_lowercase();
+        assert!(allowed(header::ACCESS_CONTROL_ALLOW_METHODS).contains("post"));
+        assert!(allowed(header::ACCESS_CONTROL_ALLOW_HEADERS).contains("content-type"));
+        assert!(allowed(header::ACCESS_CONTROL_ALLOW_HEADERS).contains("x-api-key"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_allows_deleting_webhooks() {
+        let config = Config {
+            cors_origins: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::default(), config);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/subscriptions/0123456789abcdef")
+            .header(header::ORIGIN, "https://wictk.example")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-api-key")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        let headers = response.headers();
+        let allowed = |name| headers[name].to_str().unwrap().to_lowercase();
+        assert!(allowed(header::ACCESS_CONTROL_ALLOW_METHODS).contains("delete"));
+        assert!(allowed(header::ACCESS_CONTROL_ALLOW_HEADERS).contains("x-api-key"));
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let spec = ApiDoc::openapi();
+        let paths = api_routes()
+            .into_iter()
+            .map(|(path, _)| format!("/api/{LATEST_VERSION}{path}"))
+            .chain(
+                admin_routes()
+                    .into_iter()
+                    .map(|(path, _)| format!("/admin{path}")),
+            )
+            .chain(
+                status_routes()
+                    .into_iter()
+                    .map(|(path, _)| format!("/status{path}")),
+            )
+            .chain(root_routes().into_iter().map(|(path, _)| path.to_string()));
+
+        let undocumented: Vec<String> = paths
+            .filter(|path| !spec.paths.paths.contains_key(path))
+            .collect();
+        assert!(
+            undocumented.is_empty(),
+            "Missing from the OpenAPI document: {undocumented:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serves_api_docs() {
+        let app = create_test_app();
+        let (status, body) = make_request(app.clone(), "/openapi.json").await;
+        assert_eq!(status, StatusCode::OK);
+        let spec: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(spec["paths"]["/api/v1/nowcasts"].is_object());
+
+        let (status, headers, body) = make_request_with_headers(app, "/docs/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            headers[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html")
+        );
+        assert!(String::from_utf8(body).unwrap().contains("swagger-ui"));
+    }
+
+    /// Calls the backend through `wictk_client`, so the two cannot drift apart.
+    #[tokio::test]
+    async fn test_client_contract() {
+        let app = create_replay_test_app().await;
+        let client = WictkClient::new(reqwest::Client::new(), &serve(app).await).unwrap();
+        let trondheim = Location::city("Trondheim");
+
+        client.ping().await.unwrap();
+        client.health().await.unwrap();
+        assert!(!client.nowcasts(&trondheim).await.unwrap().is_empty());
+
+        let batch = client
+            .nowcasts_batch(&[trondheim.clone(), trondheim])
+            .await
+            .unwrap();
+        assert_eq!(batch.len(), 2);
+        for item in batch {
+            assert!(!item.into_result().unwrap().is_empty());
+        }
+
+        let err = client.watched_locations().await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::FORBIDDEN));
+        assert_eq!(
+            err.to_string(),
+            "The admin API is disabled, no admin token is configured"
+        );
+    }
+
     #[tokio::test]
     async fn test_invalid_endpoint() {
         let app = create_test_app();
@@ -201,7 +606,7 @@ mod tests {
         assert_eq!(status, StatusCode::OK);
 
         let body_str = String::from_utf8(body).unwrap();
-        assert!(body_str.contains("handler"));
+        assert!(body_str.contains(r#"path="/status/ping""#));
     }
 
     #[test]
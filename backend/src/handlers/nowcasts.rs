@@ -1,28 +1,38 @@
+use std::{future::Future, sync::Arc};
+
 use axum::{
     Json,
     extract::{Query, State},
 };
-use moka::future::Cache;
-use redact::Secret;
-use reqwest::{Client, StatusCode};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
 use tracing::error;
-use tracing::instrument;
+use tracing::{Instrument, Span, instrument};
 use utoipa::{IntoParams, ToSchema};
 use wictk_core::{
-    City, Coordinates, CoordinatesAsString, MetNowcast, Nowcast, OpenWeatherMapLocation,
-    OpenWeatherNowcast,
+    City, Conditional, Coordinates, CoordinatesAsString, MetNowcast, Nowcast, OpenWeatherNowcast,
+    Upstream, WictkError,
 };
 
-use crate::AppState;
+use crate::{
+    AppState,
+    cache::{CacheInfo, ConditionalFetch},
+};
 
-use super::{error::ApplicationError, location::lookup_location};
+use super::{
+    error::{ApplicationError, ProblemDetails},
+    location::lookup_location,
+};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(untagged)]
 pub enum LocationQuery {
     Location(City),
     Coordinates(CoordinatesAsString),
+    /// Coordinates as numbers, as sent in JSON bodies
+    Point(Coordinates),
 }
 
 /// Query parameters for location-based endpoints
@@ -51,34 +61,57 @@ impl LocationParams {
 
 pub async fn find_location(
     location_query: LocationQuery,
-    client: &Client,
-    location_cache: &Cache<String, OpenWeatherMapLocation>,
-    apikey: &Secret<String>,
-) -> anyhow::Result<Coordinates> {
+    app_state: &AppState,
+) -> Result<Coordinates, WictkError> {
     match location_query {
-        LocationQuery::Location(location) => {
-            let location =
-                lookup_location(client, &location.location, location_cache, apikey).await;
-            match location {
-                Ok(location) => Ok(location.location),
-                Err(err) => Err(err.into()),
-            }
-        }
-        LocationQuery::Coordinates(cords_as_string) => {
-            let cords = cords_as_string.try_into()?;
-            Ok(cords)
-        }
+        LocationQuery::Location(location) => lookup_location(app_state, &location.location)
+            .await
+            .map(|location| location.location),
+        LocationQuery::Coordinates(cords_as_string) => cords_as_string.try_into(),
+        LocationQuery::Point(coordinates) => Ok(coordinates),
     }
 }
 
+/// Most locations accepted in one batch request
+const MAX_BATCH_LOCATIONS: usize = 50;
+/// Locations of a batch request fetched at the same time
+const BATCH_CONCURRENCY: usize = 4;
+
+/// Locations to fetch nowcasts for
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NowcastBatchRequest {
+    /// City names as `{"location": "Oslo"}`, or coordinates as `{"lat": 59.91, "lon": 10.75}`
+    pub locations: Vec<LocationQuery>,
+}
+
+/// Nowcasts for one location of a batch, or why they could not be fetched
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NowcastBatchItem {
+    /// The location as requested
+    pub location: LocationQuery,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nowcasts: Option<Vec<Nowcast>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ProblemDetails>,
+}
+
 #[utoipa::path(
     get,
-    path = "/api/met/nowcasts",
+    path = "/api/v1/met/nowcasts",
     params(LocationParams),
     responses(
-        (status = 200, description = "Weather nowcast from Met.no", body = Nowcast),
-        (status = 400, description = "Bad request - missing or invalid parameters"),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 200, description = "Weather nowcast from Met.no", body = Nowcast, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - missing or invalid parameters", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "nowcasts"
 )]
@@ -86,56 +119,29 @@ pub async fn find_location(
 pub async fn nowcast_met(
     app_state: State<AppState>,
     Query(params): Query<LocationParams>,
-) -> Result<Json<Nowcast>, ApplicationError> {
-    let location_query = params.into_location_query().ok_or_else(|| {
-        ApplicationError::new(
-            "Missing location parameter. Provide 'location' or 'lat' and 'lon'",
-            StatusCode::BAD_REQUEST,
-        )
-    })?;
-
-    let location = find_location(
-        location_query,
-        &app_state.client,
-        &app_state.location_cache,
-        &app_state.openweathermap_apikey,
-    )
-    .await
-    .map_err(|err| {
-        error!("Error finding location: {:?}", err);
-        ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-    })?;
-
-    match app_state
-        .nowcast_cache
-        .get(&format!("met_{location}"))
-        .await
-    {
-        Some(nowcast) => return Ok(Json(nowcast)),
-        None => {
-            let nowcast = MetNowcast::fetch(&app_state.client, &location)
-                .await
-                .map_err(|err| {
-                    error!("Error fetching Met.no nowcast: {:?}", err);
-                    ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-                })?;
-            app_state
-                .nowcast_cache
-                .insert(format!("met_{location}"), nowcast.clone())
-                .await;
-            Ok(Json(nowcast))
-        }
-    }
+) -> Result<(CacheInfo, Json<Nowcast>), ApplicationError> {
+    let location = location_from_params(&app_state, params).await?;
+    let (nowcast, cache_info) = met_nowcast(&app_state, &location).await?;
+    Ok((cache_info, Json(nowcast)))
 }
 
 #[utoipa::path(
     get,
-    path = "/api/owm/nowcasts",
+    path = "/api/v1/owm/nowcasts",
     params(LocationParams),
     responses(
-        (status = 200, description = "Weather nowcast from OpenWeatherMap", body = Nowcast),
-        (status = 400, description = "Bad request - missing or invalid parameters"),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 200, description = "Weather nowcast from OpenWeatherMap", body = Nowcast, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - missing or invalid parameters", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "nowcasts"
 )]
@@ -143,60 +149,29 @@ pub async fn nowcast_met(
 pub async fn nowcast_openweathermap(
     app_state: State<AppState>,
     Query(params): Query<LocationParams>,
-) -> Result<Json<Nowcast>, ApplicationError> {
-    let location_query = params.into_location_query().ok_or_else(|| {
-        ApplicationError::new(
-            "Missing location parameter. Provide 'location' or 'lat' and 'lon'",
-            StatusCode::BAD_REQUEST,
-        )
-    })?;
-
-    let location = find_location(
-        location_query,
-        &app_state.client,
-        &app_state.location_cache,
-        &app_state.openweathermap_apikey,
-    )
-    .await
-    .map_err(|err| {
-        error!("Error finding location: {:?}", err);
-        ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-    })?;
-
-    match app_state
-        .nowcast_cache
-        .get(&format!("open_{location}"))
-        .await
-    {
-        Some(nowcast) => return Ok(Json(nowcast)),
-        None => {
-            let nowcast = OpenWeatherNowcast::fetch(
-                &app_state.client,
-                &location,
-                &app_state.openweathermap_apikey,
-            )
-            .await
-            .map_err(|err| {
-                error!("Error fetching from OpenWeatherMap.com nowcast: {:?}", err);
-                ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-            })?;
-            app_state
-                .nowcast_cache
-                .insert(format!("open_{location}"), nowcast.clone())
-                .await;
-            Ok(Json(nowcast))
-        }
-    }
+) -> Result<(CacheInfo, Json<Nowcast>), ApplicationError> {
+    let location = location_from_params(&app_state, params).await?;
+    let (nowcast, cache_info) = openweathermap_nowcast(&app_state, &location).await?;
+    Ok((cache_info, Json(nowcast)))
 }
 
 #[utoipa::path(
     get,
-    path = "/api/nowcasts",
+    path = "/api/v1/nowcasts",
     params(LocationParams),
     responses(
-        (status = 200, description = "Weather nowcasts from both Met.no and OpenWeatherMap", body = Vec<Nowcast>),
-        (status = 400, description = "Bad request - missing or invalid parameters"),
-        (status = 500, description = "Internal server error", body = String)
+        (status = 200, description = "Weather nowcasts from both Met.no and OpenWeatherMap", body = Vec<Nowcast>, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - missing or invalid parameters", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is unavailable, too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
     ),
     tag = "nowcasts"
 )]
@@ -204,7 +179,115 @@ pub async fn nowcast_openweathermap(
 pub async fn nowcasts(
     app_state: State<AppState>,
     Query(params): Query<LocationParams>,
-) -> Result<Json<Vec<Nowcast>>, ApplicationError> {
+) -> Result<(CacheInfo, Json<Vec<Nowcast>>), ApplicationError> {
+    let location = location_from_params(&app_state, params).await?;
+    let (nowcasts, cache_info) = combined_nowcasts(&app_state, &location).await?;
+    Ok((cache_info, Json(nowcasts)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/nowcasts/batch",
+    request_body = NowcastBatchRequest,
+    responses(
+        (status = 200, description = "Nowcasts from Met.no and OpenWeatherMap per location, in the order requested. Locations that failed have an `error` instead", body = Vec<NowcastBatchItem>),
+        (status = 400, description = "Bad request - no locations, or more than 50", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "nowcasts"
+)]
+#[instrument(skip(request), fields(locations = request.locations.len()))]
+pub async fn nowcasts_batch(
+    State(app_state): State<AppState>,
+    Json(request): Json<NowcastBatchRequest>,
+) -> Result<Json<Vec<NowcastBatchItem>>, ApplicationError> {
+    if request.locations.is_empty() || request.locations.len() > MAX_BATCH_LOCATIONS {
+        return Err(ApplicationError::new(
+            &format!("Provide between 1 and {MAX_BATCH_LOCATIONS} locations"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let permits = Arc::new(Semaphore::new(BATCH_CONCURRENCY));
+    let mut fetches = JoinSet::new();
+    for (index, location) in request.locations.into_iter().enumerate() {
+        let app_state = app_state.clone();
+        let permits = permits.clone();
+        fetches.spawn(
+            async move {
+                let _permit = permits.acquire_owned().await;
+                let result = batch_nowcasts(&app_state, location.clone()).await;
+                let item = match result {
+                    Ok(nowcasts) => NowcastBatchItem {
+                        location,
+                        nowcasts: Some(nowcasts),
+                        error: None,
+                    },
+                    Err(err) => NowcastBatchItem {
+                        location,
+                        nowcasts: None,
+                        error: Some(err.problem()),
+                    },
+                };
+                (index, item)
+            }
+            .instrument(Span::current()),
+        );
+    }
+    let mut items = fetches.join_all().await;
+    items.sort_by_key(|(index, _)| *index);
+    Ok(Json(items.into_iter().map(|(_, item)| item).collect()))
+}
+
+async fn batch_nowcasts(
+    app_state: &AppState,
+    location: LocationQuery,
+) -> Result<Vec<Nowcast>, ApplicationError> {
+    let location = find_location(location, app_state).await?;
+    let (nowcasts, _) = combined_nowcasts(app_state, &location).await?;
+    Ok(nowcasts)
+}
+
+/// Nowcasts from every enabled provider for `location`.
+pub(crate) async fn combined_nowcasts(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<(Vec<Nowcast>, CacheInfo), ApplicationError> {
+    let providers = app_state.config.providers();
+    let met = async {
+        match providers.met {
+            true => met_nowcast(app_state, location).await.map(Some),
+            false => Ok(None),
+        }
+    };
+    let open = async {
+        match providers.openweathermap {
+            true => match openweathermap_nowcast(app_state, location).await {
+                // Leave OpenWeatherMap out near its quota while Met.no can answer
+                Err(WictkError::UpstreamRateLimited { .. }) if providers.met => Ok(None),
+                result => result.map(Some),
+            },
+            false => Ok(None),
+        }
+    };
+    let (met, open) = tokio::try_join!(met, open)?;
+    let (nowcasts, infos): (Vec<Nowcast>, Vec<CacheInfo>) = met.into_iter().chain(open).unzip();
+    let cache_info = infos
+        .into_iter()
+        .reduce(CacheInfo::combine)
+        .ok_or_else(|| {
+            ApplicationError::new(
+                "No nowcast provider is enabled",
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+        })?;
+    Ok((nowcasts, cache_info))
+}
+
+/// Resolves the location of a nowcast request.
+pub(super) async fn location_from_params(
+    app_state: &AppState,
+    params: LocationParams,
+) -> Result<Coordinates, ApplicationError> {
     let location_query = params.into_location_query().ok_or_else(|| {
         ApplicationError::new(
             "Missing location parameter. Provide 'location' or 'lat' and 'lon'",
@@ -212,76 +295,552 @@ pub async fn nowcasts(
         )
     })?;
 
-    let location = find_location(
-        location_query,
-        &app_state.client,
-        &app_state.location_cache,
-        &app_state.openweathermap_apikey,
-    )
-    .await
-    .map_err(|err| {
-        error!("Error finding location: {:?}", err);
-        ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-    })?;
+    find_location(location_query, app_state)
+        .await
+        .map_err(|err| {
+            error!("Error finding location: {:?}", err);
+            ApplicationError::from(err)
+        })
+}
 
-    let open_nowcast_fut = async {
-        match app_state
-            .nowcast_cache
-            .get(&format!("open_{location}"))
-            .await
-        {
-            Some(nowcast) => Ok(nowcast),
-            None => {
-                let open_nowcast = OpenWeatherNowcast::fetch(
-                    &app_state.client,
-                    &location,
-                    &app_state.openweathermap_apikey,
-                )
+async fn met_nowcast(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<(Nowcast, CacheInfo), WictkError> {
+    app_state.config.providers().check(Upstream::Met)?;
+    app_state
+        .nowcast_cache
+        .get_or_fetch_conditional(&format!("met_{location}"), fetch_met(app_state, location))
+        .await
+}
+
+async fn openweathermap_nowcast(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<(Nowcast, CacheInfo), WictkError> {
+    app_state
+        .config
+        .providers()
+        .check(Upstream::OpenWeatherMap)?;
+    app_state
+        .nowcast_cache
+        .get_or_fetch(
+            &format!("open_{location}"),
+            fetch_openweathermap(app_state, location),
+        )
+        .await
+}
+
+/// Revalidates the cached Met.no nowcast for `location` once Met.no's
+/// `Expires` time has passed.
+pub(crate) async fn prefetch_met_nowcast(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<(), WictkError> {
+    app_state.config.providers().check(Upstream::Met)?;
+    app_state
+        .nowcast_cache
+        .prefetch_conditional(&format!("met_{location}"), fetch_met(app_state, location))
+        .await
+}
+
+/// Refreshes the cached OpenWeatherMap nowcast for `location`, even if it is still fresh.
+pub(crate) async fn prefetch_openweathermap_nowcast(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> Result<(), WictkError> {
+    app_state
+        .config
+        .providers()
+        .check(Upstream::OpenWeatherMap)?;
+    app_state
+        .nowcast_cache
+        .prefetch(
+            &format!("open_{location}"),
+            fetch_openweathermap(app_state, location),
+        )
+        .await
+}
+
+/// Fetches the Met.no nowcast, sending `If-Modified-Since` when revalidating a cached one.
+fn fetch_met(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> impl FnOnce(Option<DateTime<Utc>>) -> ConditionalFetch<Nowcast> + Send + 'static {
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let coordinates = location.clone();
+    let history = app_state.history.clone();
+    move |since| {
+        Box::pin(async move {
+            let result = MetNowcast::fetch_if_modified(&client, &endpoints, &coordinates, since)
                 .await
-                .map_err(|err| {
-                    error!("Error fetching from OpenWeatherMap.com nowcast: {:?}", err);
-                    ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-                })?;
-                app_state
-                    .nowcast_cache
-                    .insert(format!("open_{location}"), open_nowcast.clone())
-                    .await;
-                Ok(open_nowcast)
+                .inspect_err(|err| error!("Error fetching Met.no nowcast: {:?}", err))?;
+            if let (Some(history), Conditional::Modified(nowcast, _)) = (history, &result) {
+                history.record_nowcast(&coordinates, nowcast).await;
             }
-        }
-    };
+            Ok(result)
+        })
+    }
+}
 
-    let met_nowcast_fut = async {
-        match app_state
-            .nowcast_cache
-            .get(&format!("met_{location}"))
+fn fetch_openweathermap(
+    app_state: &AppState,
+    location: &Coordinates,
+) -> impl Future<Output = Result<Nowcast, WictkError>> + Send + 'static {
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let coordinates = location.clone();
+    let apikey = app_state.openweathermap_apikey.clone();
+    let history = app_state.history.clone();
+    async move {
+        let nowcast = OpenWeatherNowcast::fetch(&client, &endpoints, &coordinates, &apikey)
             .await
-        {
-            Some(nowcast) => Ok(nowcast),
-            None => {
-                let met_nowcast = MetNowcast::fetch(&app_state.client, &location)
-                    .await
-                    .map_err(|err| {
-                        error!("Error fetching Met.no nowcast: {:?}", err);
-                        ApplicationError::new(&err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
-                    })?;
-                app_state
-                    .nowcast_cache
-                    .insert(format!("met_{location}"), met_nowcast.clone())
-                    .await;
-                Ok(met_nowcast)
-            }
+            .inspect_err(|err| {
+                error!("Error fetching from OpenWeatherMap.com nowcast: {:?}", err)
+            })?;
+        if let Some(history) = history {
+            history.record_nowcast(&coordinates, &nowcast).await;
         }
-    };
-
-    let (open_nowcast, met_nowcast) = tokio::try_join!(open_nowcast_fut, met_nowcast_fut)?;
-    Ok(Json(vec![met_nowcast, open_nowcast]))
+        Ok(nowcast)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::handlers::test_utils::{create_test_app, make_request};
+    use crate::config::{Config, QuotaConfig};
+    use crate::handlers::error::ProblemDetails;
+    use crate::handlers::test_utils::{
+        create_replay_test_app, create_test_app, create_test_app_with_config,
+        create_test_app_with_endpoints, make_json_request, make_request,
+        make_request_sending_headers, make_request_with_headers,
+    };
     use axum::http::StatusCode;
+    use wictk_core::{Coordinates, Endpoints, Nowcast};
+
+    use super::{LocationQuery, NowcastBatchItem};
+
+    const MET_NOWCAST: &str = r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[10.4034,63.4308,0]},"properties":{"meta":{"updated_at":"2023-08-14T18:16:07Z"},"timeseries":[{"time":"2023-08-14T18:15:00Z","data":{"instant":{"details":{"air_temperature":17.7,"relative_humidity":80.5,"wind_from_direction":294.4,"wind_speed":2.7,"wind_speed_of_gust":6.1}},"next_1_hours":{"summary":{"symbol_code":"cloudy"},"details":{"precipitation_amount":0.0}}}}]}}"#;
+
+    const OPENWEATHER_NOWCAST: &str = r#"{"coord":{"lon":10.3951,"lat":63.4305},"weather":[{"main":"Clouds","description":"overcast clouds"}],"main":{"temp":15.21,"feels_like":15.19,"pressure":1014,"humidity":92},"visibility":10000,"wind":{"speed":0.89,"deg":270},"clouds":{"all":99},"dt":1692185222,"sys":{"country":"NO"},"name":"Trondheim"}"#;
+
+    const GEOCODING: &str = r#"[{"name":"Trondheim","lat":63.4305,"lon":10.3951,"country":"NO"}]"#;
+
+    #[tokio::test]
+    async fn test_met_nowcast_with_coordinates() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(MET_NOWCAST)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/met/nowcasts?lat=63.4308&lon=10.4034").await;
+
+        mock.assert_async().await;
+        assert_eq!(status, StatusCode::OK);
+        let nowcast: Nowcast = serde_json::from_slice(&body).unwrap();
+        match nowcast {
+            Nowcast::Met(met) => assert_eq!(met.air_temperature, 17.7),
+            Nowcast::OpenWeather(_) => panic!("Expected Met nowcast"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_with_location() {
+        let mut server = mockito::Server::new_async().await;
+        let geocoding = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::UrlEncoded("q".into(), "Trondheim".into()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(GEOCODING)
+            .create_async()
+            .await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .create_async()
+            .await;
+        let _openweather = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(OPENWEATHER_NOWCAST)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/nowcasts?location=Trondheim").await;
+
+        geocoding.assert_async().await;
+        assert_eq!(status, StatusCode::OK);
+        let nowcasts: Vec<Nowcast> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(nowcasts.len(), 2);
+        assert!(matches!(nowcasts[0], Nowcast::Met(_)));
+        assert!(matches!(nowcasts[1], Nowcast::OpenWeather(_)));
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_batch() {
+        let mut server = mockito::Server::new_async().await;
+        let _trondheim = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::UrlEncoded("q".into(), "Trondheim".into()))
+            .with_status(200)
+            .with_body(GEOCODING)
+            .create_async()
+            .await;
+        let _nowhere = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::UrlEncoded("q".into(), "Nowhere".into()))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .create_async()
+            .await;
+        let _openweather = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(OPENWEATHER_NOWCAST)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_json_request(
+            app,
+            "POST",
+            "/api/nowcasts/batch",
+            r#"{"locations": [
+                {"location": "Trondheim"},
+                {"location": "Nowhere"},
+                {"lat": 59.9139, "lon": 10.7522},
+                {"lat": "north", "lon": "10.7522"}
+            ]}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let items: Vec<NowcastBatchItem> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(items.len(), 4);
+        assert!(
+            matches!(&items[0].location, LocationQuery::Location(city) if city.location == "Trondheim")
+        );
+        assert_eq!(items[0].nowcasts.as_ref().map(Vec::len), Some(2));
+        assert_eq!(
+            items[1].error.as_ref().map(|problem| problem.status),
+            Some(404)
+        );
+        assert!(items[1].nowcasts.is_none());
+        assert_eq!(
+            items[2].location,
+            LocationQuery::Point(Coordinates::new(10.7522, 59.9139))
+        );
+        assert_eq!(items[2].nowcasts.as_ref().map(Vec::len), Some(2));
+        assert_eq!(
+            items[3].error.as_ref().map(|problem| problem.status),
+            Some(400)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_batch_size_is_limited() {
+        let app = create_test_app();
+        let (status, body) = make_json_request(
+            app.clone(),
+            "POST",
+            "/api/nowcasts/batch",
+            r#"{"locations": []}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.detail, "Provide between 1 and 50 locations");
+
+        let locations = vec![r#"{"location": "Oslo"}"#; 51].join(",");
+        let (status, _) = make_json_request(
+            app,
+            "POST",
+            "/api/nowcasts/batch",
+            &format!(r#"{{"locations": [{locations}]}}"#),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_nowcast_cache_headers_and_coalescing() {
+        let mut server = mockito::Server::new_async().await;
+        let met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let uri = "/api/met/nowcasts?lat=63.4308&lon=10.4034";
+        let (first, second) = tokio::join!(
+            make_request_with_headers(app.clone(), uri),
+            make_request_with_headers(app.clone(), uri)
+        );
+        for (status, headers, _body) in [first, second] {
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers["x-cache"], "MISS");
+            assert_eq!(headers["age"], "0");
+        }
+
+        let (status, headers, _body) = make_request_with_headers(app, uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-cache"], "HIT");
+        met.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_conditional_get() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .create_async()
+            .await;
+        let _owm = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(OPENWEATHER_NOWCAST)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let uri = "/api/nowcasts?lat=63.4308&lon=10.4034";
+        let (status, headers, _body) = make_request_with_headers(app.clone(), uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let max_age = headers["cache-control"]
+            .to_str()
+            .unwrap()
+            .strip_prefix("public, max-age=")
+            .and_then(|max_age| max_age.parse::<u64>().ok());
+        assert!(matches!(max_age, Some(295..=300)));
+        assert!(headers.contains_key("last-modified"));
+        let etag = headers["etag"].to_str().unwrap().to_string();
+
+        let (status, headers, body) =
+            make_request_sending_headers(app.clone(), uri, &[("if-none-match", &etag)]).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers["etag"], etag.as_str());
+        assert_eq!(headers["x-cache"], "HIT");
+        assert!(body.is_empty());
+
+        let (status, _headers, body) =
+            make_request_sending_headers(app, uri, &[("if-none-match", "\"outdated\"")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_upstream_failure() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(500)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/met/nowcasts?lat=63.4308&lon=10.4034").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem.detail,
+            "Met.no is unavailable: responded with 500 Internal Server Error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_upstream_rate_limited() {
+        let mut server = mockito::Server::new_async().await;
+        let _owm = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("retry-after", "30")
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/owm/nowcasts?lat=63.4308&lon=10.4034").await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.status, 429);
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_fail_fast_while_upstream_keeps_failing() {
+        let mut server = mockito::Server::new_async().await;
+        let met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        let mut config = Config::default();
+        config.upstreams.max_retries = 0;
+        config.upstreams.breaker_failure_threshold = 1;
+        let app = create_test_app_with_config(Endpoints::with_base_url(&server.url()), config);
+
+        let (status, _) =
+            make_request(app.clone(), "/api/met/nowcasts?lat=63.4308&lon=10.4034").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, body) = make_request(app, "/api/met/nowcasts?lat=59.9139&lon=10.7522").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem.detail,
+            "Met.no is unavailable: circuit breaker is open after repeated failures"
+        );
+        met.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_near_openweathermap_quota() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(MET_NOWCAST)
+            .create_async()
+            .await;
+        let owm = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(OPENWEATHER_NOWCAST)
+            .expect(1)
+            .create_async()
+            .await;
+        let mut config = Config::default();
+        config.quotas.insert(
+            "openweathermap".to_string(),
+            QuotaConfig {
+                per_minute: Some(1),
+                ..QuotaConfig::default()
+            },
+        );
+        let app = create_test_app_with_config(Endpoints::with_base_url(&server.url()), config);
+        let trondheim = "/api/nowcasts?lat=63.4308&lon=10.4034";
+        let oslo = "/api/nowcasts?lat=59.9139&lon=10.7522";
+
+        let (status, body) = make_request(app.clone(), trondheim).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<Vec<Nowcast>>(&body).unwrap().len(),
+            2
+        );
+
+        // OpenWeatherMap is only served from the cache, and left out without it
+        let (status, body) = make_request(app.clone(), trondheim).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_slice::<Vec<Nowcast>>(&body).unwrap().len(),
+            2
+        );
+        let (status, body) = make_request(app.clone(), oslo).await;
+        assert_eq!(status, StatusCode::OK);
+        let nowcasts: Vec<Nowcast> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(nowcasts.len(), 1);
+        assert!(matches!(nowcasts[0], Nowcast::Met(_)));
+
+        let (status, headers, _body) =
+            make_request_with_headers(app, "/api/owm/nowcasts?lat=59.9139&lon=10.7522").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert!(headers.contains_key("retry-after"));
+        owm.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_with_disabled_provider() {
+        let mut server = mockito::Server::new_async().await;
+        let owm = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(OPENWEATHER_NOWCAST)
+            .create_async()
+            .await;
+        let mut config = Config::default();
+        config.providers.met = false;
+        let app = create_test_app_with_config(Endpoints::with_base_url(&server.url()), config);
+
+        let (status, body) =
+            make_request(app.clone(), "/api/met/nowcasts?lat=63.4308&lon=10.4034").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            problem.detail,
+            "Met.no is unavailable: disabled by configuration"
+        );
+
+        let (status, body) = make_request(app, "/api/nowcasts?lat=63.4308&lon=10.4034").await;
+        assert_eq!(status, StatusCode::OK);
+        let nowcasts: Vec<Nowcast> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(nowcasts.len(), 1);
+        owm.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_without_enabled_providers() {
+        let mut config = Config::default();
+        config.providers.met = false;
+        config.providers.openweathermap = false;
+        let app = create_test_app_with_config(Endpoints::default(), config);
+
+        let (status, _) = make_request(app, "/api/nowcasts?lat=63.4308&lon=10.4034").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_invalid_coordinates() {
+        let app = create_test_app();
+        let (status, body) = make_request(app, "/api/nowcasts?lat=north&lon=10.4034").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.detail, "Could not parse latitude: north");
+    }
+
+    #[tokio::test]
+    async fn test_nowcasts_unknown_location() {
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/nowcasts?location=Nowhere").await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert_eq!(problem.detail, "No location found for Nowhere");
+    }
 
     #[tokio::test]
     async fn test_nowcasts_missing_params() {
@@ -293,7 +852,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_query_parameter_parsing() {
-        let app = create_test_app();
+        let app = create_replay_test_app().await;
 
         // Test various query parameter formats
         let test_cases = vec![
@@ -305,17 +864,63 @@ mod tests {
 
         for test_case in test_cases {
             let (status, _body) = make_request(app.clone(), test_case).await;
-            // Should not return 400 Bad Request for valid query parameters
-            assert_ne!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(status, StatusCode::OK, "{test_case}");
         }
     }
 
+    #[tokio::test]
+    async fn test_nowcasts_replayed() {
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/nowcasts?location=Trondheim").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!([
+                {
+                    "met": {
+                        "time": "2025-01-20T12:05:00Z",
+                        "location": {"lon": 10.3951, "lat": 63.4305},
+                        "description": "cloudy",
+                        "air_temperature": -3.4,
+                        "relative_humidity": 86.1,
+                        "precipitation_rate": 0.0,
+                        "precipitation_amount": 0.0,
+                        "wind_speed": 4.2,
+                        "wind_speed_gust": 7.9,
+                        "wind_from_direction": 152.3
+                    }
+                },
+                {
+                    "open_weather": {
+                        "dt": "2025-01-20T12:05:00Z",
+                        "name": "Trondheim",
+                        "country": "NO",
+                        "lon": 10.3951,
+                        "lat": 63.4305,
+                        "main": "Clouds",
+                        "desc": "overcast clouds",
+                        "clouds": 99,
+                        "wind_speed": 4.6,
+                        "wind_deg": 160,
+                        "visibility": 10000,
+                        "temp": -3.0,
+                        "feels_like": -7.5,
+                        "humidity": 87,
+                        "pressure": 1004
+                    }
+                }
+            ])
+        );
+    }
+
     #[tokio::test]
     async fn test_invalid_query_parameters() {
-        let app = create_test_app();
+        let app = create_replay_test_app().await;
 
         // Test invalid coordinate formats
         let (status, _body) = make_request(app, "/api/nowcasts?lat=invalid&lon=10.74609").await;
-        assert!(status == StatusCode::BAD_REQUEST || status == StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
     }
 }
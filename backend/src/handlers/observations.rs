@@ -0,0 +1,202 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+};
+use redact::Secret;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use tracing::{error, instrument};
+use utoipa::ToSchema;
+use wictk_core::{
+    Coordinates, Endpoints, FrostObservation, FrostStation, Upstream, UpstreamClient, WictkError,
+};
+
+use crate::{AppState, cache::CacheInfo};
+
+use super::{
+    error::{ApplicationError, ProblemDetails},
+    nowcasts::{LocationParams, find_location},
+};
+
+/// Number of nearby stations to consider, as the closest one may not report data
+const NEAREST_STATIONS: u32 = 5;
+
+/// The latest observations from the closest station that reported data
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StationObservations {
+    pub station: FrostStation,
+    pub observations: Vec<FrostObservation>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/observations",
+    params(LocationParams),
+    responses(
+        (status = 200, description = "Latest observations from the nearest weather station", body = StationObservations, headers(
+            ("X-Cache" = String, description = "HIT, STALE or MISS"),
+            ("Age" = u64, description = "Seconds since the data was fetched from the upstream"),
+            ("Last-Modified" = String, description = "When the data was fetched from the upstream"),
+            ("Cache-Control" = String, description = "max-age is the time left until the cached data is stale"),
+            ("ETag" = String, description = "Hash of the response body")
+        )),
+        (status = 304, description = "Not modified, the response matches `If-None-Match`"),
+        (status = 400, description = "Bad request - missing or invalid parameters", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location or station with recent observations not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Upstream is rate limiting requests", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Frost client id is not configured or an upstream is unavailable", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = "5XX", description = "Upstream is too slow or sent an invalid response", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "observations"
+)]
+#[instrument]
+pub async fn observations(
+    State(app_state): State<AppState>,
+    Query(params): Query<LocationParams>,
+) -> Result<(CacheInfo, Json<StationObservations>), ApplicationError> {
+    let location_query = params.into_location_query().ok_or_else(|| {
+        ApplicationError::new(
+            "Missing location parameter. Provide 'location' or 'lat' and 'lon'",
+            StatusCode::BAD_REQUEST,
+        )
+    })?;
+
+    app_state.config.providers().check(Upstream::Frost)?;
+    let client_id = app_state.frost_client_id.clone().ok_or_else(|| {
+        ApplicationError::new(
+            "Observations are unavailable, no Frost client id is configured",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+    })?;
+
+    let location = find_location(location_query, &app_state)
+        .await
+        .map_err(|err| {
+            error!("Error finding location: {:?}", err);
+            ApplicationError::from(err)
+        })?;
+
+    let client = app_state.client.clone();
+    let endpoints = app_state.endpoints.clone();
+    let coordinates = location.clone();
+    let fetch =
+        async move { nearest_observations(&client, &endpoints, &coordinates, &client_id).await };
+    let (observations, cache_info) = app_state
+        .observation_cache
+        .get_or_fetch(&format!("frost_{location}"), fetch)
+        .await?;
+
+    Ok((cache_info, Json(observations)))
+}
+
+/// Fetches the latest observations from the closest station that has any.
+async fn nearest_observations(
+    client: &UpstreamClient,
+    endpoints: &Endpoints,
+    location: &Coordinates,
+    client_id: &Secret<String>,
+) -> Result<StationObservations, WictkError> {
+    let stations = FrostStation::nearest(client, endpoints, location, NEAREST_STATIONS, client_id)
+        .await
+        .inspect_err(|err| error!("Error finding Frost stations: {}", err))?;
+
+    let station_ids: Vec<String> = stations.iter().map(|station| station.id.clone()).collect();
+    let all_observations = FrostObservation::latest(client, endpoints, &station_ids, client_id)
+        .await
+        .inspect_err(|err| error!("Error fetching Frost observations: {}", err))?;
+
+    // Stations are ordered by distance, so the first one with data is the closest
+    stations
+        .into_iter()
+        .map(|station| {
+            let observations = all_observations
+                .iter()
+                .filter(|observation| observation.station_id == station.id)
+                .cloned()
+                .collect();
+            StationObservations {
+                station,
+                observations,
+            }
+        })
+        .find(|station_observations| !station_observations.observations.is_empty())
+        .ok_or_else(|| WictkError::not_found("No station with recent observations found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StationObservations;
+    use crate::handlers::test_utils::{
+        create_replay_test_app, create_test_app, create_test_app_with_endpoints, make_request,
+    };
+    use axum::http::StatusCode;
+    use wictk_core::Endpoints;
+
+    #[tokio::test]
+    async fn test_observations_replayed() {
+        let app = create_replay_test_app().await;
+        let (status, body) = make_request(app, "/api/observations?location=Trondheim").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "station": {
+                    "id": "SN68860",
+                    "name": "TRONDHEIM - VOLL",
+                    "location": {"lon": 10.4533, "lat": 63.4107},
+                    "masl": 127.0,
+                    "distance": 3.54
+                },
+                "observations": [
+                    {"station_id": "SN68860", "time": "2025-01-20T12:00:00Z", "element": "air_temperature", "value": -3.8, "unit": "degC"},
+                    {"station_id": "SN68860", "time": "2025-01-20T12:00:00Z", "element": "wind_speed", "value": 4.4, "unit": "m/s"}
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_observations_from_nearest_station_with_data() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/sources/v0.jsonld")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"data":[{"id":"SN1","name":"CLOSEST","geometry":{"coordinates":[10.40,63.43]},"distance":0.5},{"id":"SN2","name":"SECOND","geometry":{"coordinates":[10.45,63.41]},"distance":2.7}]}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/observations/v0.jsonld")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"data":[{"sourceId":"SN2:0","referenceTime":"2025-01-20T12:00:00.000Z","observations":[{"elementId":"air_temperature","value":-3.4,"unit":"degC"}]}]}"#)
+            .create_async()
+            .await;
+
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
+        let (status, body) = make_request(app, "/api/observations?lat=63.4308&lon=10.4034").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let observations: StationObservations = serde_json::from_slice(&body).unwrap();
+        assert_eq!(observations.station.id, "SN2");
+        assert_eq!(observations.observations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_observations_missing_params() {
+        let app = create_test_app();
+        let (status, _body) = make_request(app, "/api/observations").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_observations_without_client_id() {
+        let app = create_test_app();
+        let (status, _body) = make_request(app, "/api/observations?lat=63.4308&lon=10.4034").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
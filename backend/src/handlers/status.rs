@@ -1,3 +1,10 @@
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::{
+    AppState,
+    health::{HealthReport, ReadinessReport, ServiceStatus, readiness},
+};
+
 #[utoipa::path(
     get,
     path = "/status/ping",
@@ -15,19 +22,48 @@ pub async fn ping() -> &'static str {
     get,
     path = "/status/health",
     responses(
-        (status = 200, description = "Service is healthy", body = String)
+        (status = 200, description = "All critical upstreams are reachable, status is degraded if another one is down or close to its quota", body = HealthReport),
+        (status = 503, description = "A critical upstream is down", body = HealthReport)
     ),
     tag = "status"
 )]
-pub async fn health() -> &'static str {
+pub async fn health(State(app_state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
     tracing::info!("GET /status/health");
-    "OK"
+    let report = app_state.health.check(&app_state).await;
+    let status = match report.status {
+        ServiceStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+        ServiceStatus::Ok | ServiceStatus::Degraded => StatusCode::OK,
+    };
+    (status, Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/status/ready",
+    responses(
+        (status = 200, description = "Ready to serve requests", body = ReadinessReport),
+        (status = 503, description = "The history database or Redis is unreachable", body = ReadinessReport)
+    ),
+    tag = "status"
+)]
+pub async fn ready(State(app_state): State<AppState>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = readiness(&app_state).await;
+    let status = match report.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::handlers::test_utils::{create_test_app, make_request};
+    use crate::handlers::test_utils::{
+        create_test_app, create_test_app_with_endpoints, create_test_app_with_history, make_request,
+    };
+    use crate::health::{DependencyStatus, HealthReport, ReadinessReport, ServiceStatus};
+    use crate::history::History;
     use axum::http::StatusCode;
+    use wictk_core::Endpoints;
 
     #[tokio::test]
     async fn test_ping_endpoint() {
@@ -40,10 +76,62 @@ mod tests {
 
     #[tokio::test]
     async fn test_health_endpoint() {
-        let app = create_test_app();
+        let mut server = mockito::Server::new_async().await;
+        let _met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/status")
+            .with_status(200)
+            .create_async()
+            .await;
+        let _owm = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+        let _yr = server
+            .mock("GET", "/api/v0/lightning-events")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .create_async()
+            .await;
+        let _frost = server
+            .mock("GET", "/sources/v0.jsonld")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .create_async()
+            .await;
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url(&server.url()));
         let (status, body) = make_request(app, "/status/health").await;
 
         assert_eq!(status, StatusCode::OK);
-        assert_eq!(&body[..], b"OK");
+        let report: HealthReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.status, ServiceStatus::Ok);
+        assert_eq!(report.dependencies.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_health_endpoint_with_critical_upstream_down() {
+        let app = create_test_app_with_endpoints(Endpoints::with_base_url("http://127.0.0.1:1"));
+        let (status, body) = make_request(app, "/status/health").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let report: HealthReport = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report.status, ServiceStatus::Down);
+        assert_eq!(
+            report.dependencies["met"].last_error.as_deref(),
+            Some("Met.no is unavailable: request failed")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ready_endpoint() {
+        let app = create_test_app_with_history(Endpoints::default(), History::in_memory().unwrap());
+        let (status, body) = make_request(app, "/status/ready").await;
+
+        assert_eq!(status, StatusCode::OK);
+        let report: ReadinessReport = serde_json::from_slice(&body).unwrap();
+        assert!(report.ready);
+        assert_eq!(report.dependencies["history"], DependencyStatus::Up);
     }
 }
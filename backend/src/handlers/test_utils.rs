@@ -1,13 +1,16 @@
-use crate::{AppState, handlers::setup_router};
+use crate::{
+    AppState, config::Config, handlers::setup_router, history::History, webhooks::Webhooks,
+};
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    http::{HeaderMap, Request, StatusCode},
 };
 use http_body_util::BodyExt;
 use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
 use once_cell::sync::Lazy;
 use std::sync::Mutex;
 use tower::ServiceExt;
+use wictk_core::Endpoints;
 
 static METRICS_HANDLE: Lazy<Mutex<Option<PrometheusHandle>>> = Lazy::new(|| Mutex::new(None));
 
@@ -26,10 +29,117 @@ pub fn create_test_app() -> axum::Router {
     let metrics_handler = get_metrics_handle();
 
     let client = reqwest::Client::new();
-    let app_state = AppState::new(client, "test_api_key".to_string());
+    let app_state = AppState::new(
+        client,
+        "test_api_key".to_string(),
+        None,
+        Endpoints::default(),
+    );
+    setup_router(app_state, metrics_handler)
+}
+
+/// Creates a test app serving the recorded upstream responses in `fixtures/replay`.
+pub async fn create_replay_test_app() -> axum::Router {
+    let dir = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/replay");
+    let endpoints = crate::replay::start(dir, false, Endpoints::default(), reqwest::Client::new())
+        .await
+        .expect("failed to start replay server");
+    create_test_app_with_endpoints(endpoints)
+}
+
+/// Creates a test app like [`create_test_app_with_endpoints`] that records to `history`.
+pub fn create_test_app_with_history(endpoints: Endpoints, history: History) -> axum::Router {
+    let metrics_handler = get_metrics_handle();
+
+    let client = reqwest::Client::new();
+    let app_state = AppState::new(
+        client,
+        "test_api_key".to_string(),
+        Some("test_client_id".to_string()),
+        endpoints,
+    )
+    .with_history(history);
+    setup_router(app_state, metrics_handler)
+}
+
+/// Creates a test app with the admin API enabled for `admin_token`.
+pub fn create_test_app_with_admin_token(admin_token: &str) -> axum::Router {
+    let metrics_handler = get_metrics_handle();
+
+    let client = reqwest::Client::new();
+    let app_state = AppState::new(
+        client,
+        "test_api_key".to_string(),
+        None,
+        Endpoints::default(),
+    )
+    .with_admin_token(admin_token.to_string());
+    setup_router(app_state, metrics_handler)
+}
+
+/// Creates a test app like [`create_test_app_with_endpoints`] configured by `config`.
+pub fn create_test_app_with_config(endpoints: Endpoints, config: Config) -> axum::Router {
+    let metrics_handler = get_metrics_handle();
+
+    let client = reqwest::Client::new();
+    let app_state = AppState::from_config(
+        client,
+        "test_api_key".to_string(),
+        Some("test_client_id".to_string()),
+        endpoints,
+        config,
+    );
+    setup_router(app_state, metrics_handler)
+}
+
+/// Creates a test app like [`create_test_app_with_config`] with the webhook
+/// endpoints enabled.
+pub fn create_test_app_with_webhooks(endpoints: Endpoints, config: Config) -> axum::Router {
+    let metrics_handler = get_metrics_handle();
+
+    let client = reqwest::Client::new();
+    let app_state = AppState::from_config(
+        client,
+        "test_api_key".to_string(),
+        Some("test_client_id".to_string()),
+        endpoints,
+        config,
+    );
+    let webhooks = Webhooks::in_memory(app_state.config.clone()).unwrap();
+    setup_router(app_state.with_webhooks(webhooks), metrics_handler)
+}
+
+/// Creates a test app where all upstream requests go to `endpoints`,
+/// typically a mockito server.
+pub fn create_test_app_with_endpoints(endpoints: Endpoints) -> axum::Router {
+    let metrics_handler = get_metrics_handle();
+
+    let client = reqwest::Client::new();
+    let app_state = AppState::new(
+        client,
+        "test_api_key".to_string(),
+        Some("test_client_id".to_string()),
+        endpoints,
+    );
     setup_router(app_state, metrics_handler)
 }
 
+/// Serves `app` on a local port like `main` does and returns its base URL,
+/// for tests going through a real HTTP client.
+pub async fn serve(app: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    format!("http://{addr}")
+}
+
 pub async fn make_request(app: axum::Router, uri: &str) -> (StatusCode, Vec<u8>) {
     let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
 
@@ -40,6 +150,40 @@ pub async fn make_request(app: axum::Router, uri: &str) -> (StatusCode, Vec<u8>)
     (status, body.to_vec())
 }
 
+pub async fn make_request_with_headers(
+    app: axum::Router,
+    uri: &str,
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, headers, body.to_vec())
+}
+
+/// Sends a GET request with `request_headers`, returning the response headers too.
+pub async fn make_request_sending_headers(
+    app: axum::Router,
+    uri: &str,
+    request_headers: &[(&str, &str)],
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut request = Request::builder().uri(uri);
+    for (name, value) in request_headers {
+        request = request.header(*name, *value);
+    }
+    let request = request.body(Body::empty()).unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, headers, body.to_vec())
+}
+
 pub async fn make_request_with_method(
     app: axum::Router,
     method: &str,
@@ -57,3 +201,70 @@ pub async fn make_request_with_method(
 
     (status, body.to_vec())
 }
+
+pub async fn make_json_request(
+    app: axum::Router,
+    method: &str,
+    uri: &str,
+    json: &str,
+) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(json.to_string()))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, body.to_vec())
+}
+
+pub async fn make_authorized_request(
+    app: axum::Router,
+    method: &str,
+    uri: &str,
+    token: &str,
+) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("authorization", format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, body.to_vec())
+}
+
+/// Sends a request with `key` in the `X-API-Key` header, and `json` as its body if given.
+pub async fn make_request_with_api_key(
+    app: axum::Router,
+    method: &str,
+    uri: &str,
+    key: &str,
+    json: Option<&str>,
+) -> (StatusCode, Vec<u8>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", key);
+    let request = match json {
+        Some(json) => request
+            .header("content-type", "application/json")
+            .body(Body::from(json.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, body.to_vec())
+}
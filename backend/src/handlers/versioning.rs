@@ -0,0 +1,138 @@
+//! The API is served under `/api/v1`, with `/api` as an alias of the latest
+//! version. Versions configured in `deprecations` announce that they are going
+//! away, and are retired at their sunset.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use wictk_core::format_http_date;
+
+use crate::config::SharedConfig;
+
+use super::error::ApplicationError;
+
+/// The version `/api` is an alias of
+pub const LATEST_VERSION: &str = "v1";
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Adds `Deprecation` and `Sunset` headers to the responses of a deprecated
+/// API `version`, and answers `410 Gone` once it is past its sunset. The
+/// unversioned alias also links to the same route of the latest version.
+pub async fn deprecation(
+    State((config, version)): State<(SharedConfig, &'static str)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(deprecation) = config.deprecation(version) else {
+        return next.run(request).await;
+    };
+    let successor = (version == "unversioned")
+        .then(|| format!("/api/{LATEST_VERSION}{}", request.uri().path()));
+
+    let mut response = match deprecation.sunset {
+        Some(sunset) if sunset <= Utc::now() => ApplicationError::new(
+            &format!(
+                "This API version was retired on {}",
+                format_http_date(&sunset)
+            ),
+            StatusCode::GONE,
+        )
+        .into_response(),
+        _ => next.run(request).await,
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        DEPRECATION,
+        HeaderValue::from_str(&format!("@{}", deprecation.since.timestamp()))
+            .expect("a timestamp is a valid header value"),
+    );
+    if let Some(sunset) = deprecation.sunset {
+        headers.insert(
+            SUNSET,
+            HeaderValue::from_str(&format_http_date(&sunset))
+                .expect("an HTTP date is a valid header value"),
+        );
+    }
+    if let Some(link) = successor.and_then(|path| {
+        HeaderValue::from_str(&format!("<{path}>; rel=\"successor-version\"")).ok()
+    }) {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use chrono::{Duration, Utc};
+    use wictk_core::Endpoints;
+
+    use crate::config::{Config, DeprecationConfig};
+    use crate::handlers::error::ProblemDetails;
+    use crate::handlers::test_utils::{create_test_app_with_config, make_request_with_headers};
+
+    fn deprecated_alias(sunset: Option<chrono::DateTime<Utc>>) -> axum::Router {
+        let mut config = Config::default();
+        config.deprecations.insert(
+            "unversioned".to_string(),
+            DeprecationConfig {
+                since: "2026-10-01T00:00:00Z".parse().unwrap(),
+                sunset,
+            },
+        );
+        create_test_app_with_config(Endpoints::default(), config)
+    }
+
+    #[tokio::test]
+    async fn serves_both_versions_without_deprecations() {
+        let app = create_test_app_with_config(Endpoints::default(), Config::default());
+        for uri in ["/api/v1/nowcasts", "/api/nowcasts"] {
+            let (status, headers, _) = make_request_with_headers(app.clone(), uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(!headers.contains_key("deprecation"));
+        }
+    }
+
+    #[tokio::test]
+    async fn announces_deprecated_versions() {
+        let sunset = "2100-01-01T00:00:00Z".parse().unwrap();
+        let app = deprecated_alias(Some(sunset));
+
+        let (_, headers, _) = make_request_with_headers(app.clone(), "/api/nowcasts").await;
+        assert_eq!(headers["deprecation"], "@1790812800");
+        assert_eq!(headers["sunset"], "Fri, 01 Jan 2100 00:00:00 GMT");
+        assert_eq!(
+            headers["link"],
+            "</api/v1/nowcasts>; rel=\"successor-version\""
+        );
+
+        let (_, headers, _) = make_request_with_headers(app, "/api/v1/nowcasts").await;
+        assert!(!headers.contains_key("deprecation"));
+    }
+
+    #[tokio::test]
+    async fn retires_versions_after_their_sunset() {
+        let app = deprecated_alias(Some(Utc::now() - Duration::days(1)));
+
+        let (status, headers, body) =
+            make_request_with_headers(app.clone(), "/api/nowcasts?location=Oslo").await;
+        assert_eq!(status, StatusCode::GONE);
+        assert!(headers.contains_key("sunset"));
+        let problem: ProblemDetails = serde_json::from_slice(&body).unwrap();
+        assert!(
+            problem
+                .detail
+                .starts_with("This API version was retired on")
+        );
+
+        let (status, _, _) = make_request_with_headers(app, "/api/v1/nowcasts").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,566 @@
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{SubsecRound, Utc};
+use redact::Secret;
+use serde::Deserialize;
+use tracing::{error, instrument};
+use utoipa::ToSchema;
+use wictk_core::{Severity, Upstream};
+
+use crate::{
+    AppState,
+    webhooks::{Delivery, RegisterError, Webhook, WebhookEvent, Webhooks, check_url, random_id},
+};
+
+use super::{
+    access::ApiClient,
+    error::{ApplicationError, ProblemDetails},
+    lightning::DEFAULT_RADIUS_KM,
+    nowcasts::{LocationQuery, find_location},
+};
+
+/// Shortest secret accepted, so signatures cannot be forged by guessing it
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// A webhook to register
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct WebhookRequest {
+    /// City name as `"location": "Oslo"`, or coordinates as `"lat": 59.91, "lon": 10.75`
+    #[serde(flatten)]
+    pub location: LocationQuery,
+    pub events: Vec<WebhookEvent>,
+    /// Alerts below this severity are not sent (default: Yellow)
+    pub min_severity: Option<Severity>,
+    /// Radius in kilometers of the lightning events (default: 50)
+    pub radius_km: Option<f64>,
+    /// http or https URL the events are POSTed to
+    pub url: String,
+    /// Key of the `X-Wictk-Signature` HMAC, at least 16 characters
+    pub secret: String,
+}
+
+fn webhooks(app_state: &AppState) -> Result<Webhooks, ApplicationError> {
+    app_state.webhooks.clone().ok_or_else(|| {
+        ApplicationError::new(
+            "Webhooks are unavailable, no webhook database is configured",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+    })
+}
+
+/// Webhooks make requests on behalf of whoever registers them, so they are
+/// only for clients with an API key, even when the rest of the API is open.
+/// Returns the name of the key.
+fn require_api_key(client: &ApiClient) -> Result<&str, ApplicationError> {
+    match client {
+        ApiClient::Key(name) => Ok(name),
+        ApiClient::Ip(_) | ApiClient::Unknown => Err(ApplicationError::new(
+            "Webhooks need an API key, send it in the X-API-Key header",
+            StatusCode::UNAUTHORIZED,
+        )),
+    }
+}
+
+fn store_error(err: rusqlite::Error) -> ApplicationError {
+    error!("Error accessing the webhook database: {:?}", err);
+    ApplicationError::new(
+        "Could not access the webhooks",
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+fn not_found(id: &str) -> ApplicationError {
+    ApplicationError::new(&format!("No webhook '{id}'"), StatusCode::NOT_FOUND)
+}
+
+fn validate(request: &WebhookRequest) -> Result<(), ApplicationError> {
+    let bad_request = |message: &str| Err(ApplicationError::new(message, StatusCode::BAD_REQUEST));
+    if request.events.is_empty() {
+        return bad_request("'events' must contain alert, lightning or both");
+    }
+    match reqwest::Url::parse(&request.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => return bad_request("'url' must be an http or https URL"),
+    }
+    if request.secret.chars().count() < MIN_SECRET_LENGTH {
+        return bad_request(&format!(
+            "'secret' must be at least {MIN_SECRET_LENGTH} characters"
+        ));
+    }
+    if request
+        .radius_km
+        .is_some_and(|radius_km| !radius_km.is_finite() || radius_km <= 0.0)
+    {
+        return bad_request("'radius_km' must be greater than 0");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    request_body = WebhookRequest,
+    responses(
+        (status = 201, description = "Webhook registered. Its events are POSTed as JSON signed with `X-Wictk-Signature`", body = Webhook),
+        (status = 400, description = "Bad request - invalid webhook, or its URL is not a public address", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Missing or invalid API key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "The API key has registered the most webhooks allowed", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Location not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No webhook database is configured, a provider of the events is disabled, or too many locations are polled", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "subscriptions"
+)]
+#[instrument(skip(request))]
+pub async fn register_webhook(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    Json(request): Json<WebhookRequest>,
+) -> Result<(StatusCode, Json<Webhook>), ApplicationError> {
+    let owner = require_api_key(&client)?;
+    let webhooks = webhooks(&app_state)?;
+    validate(&request)?;
+    let allow_private = app_state.config.webhooks().allow_private_urls;
+    if let Err(err) = check_url(&request.url, allow_private).await {
+        return Err(ApplicationError::new(
+            &format!("'url' is not allowed: {err}"),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    for event in &request.events {
+        let upstream = match event {
+            WebhookEvent::Alert => Upstream::Met,
+            WebhookEvent::Lightning => Upstream::Yr,
+        };
+        app_state.config.providers().check(upstream)?;
+    }
+    let location = find_location(request.location, &app_state)
+        .await
+        .map_err(|err| {
+            error!("Error finding location: {:?}", err);
+            ApplicationError::from(err)
+        })?;
+
+    let mut events = Vec::new();
+    for event in request.events {
+        if !events.contains(&event) {
+            events.push(event);
+        }
+    }
+    let webhook = Webhook {
+        id: random_id(),
+        owner: owner.to_string(),
+        url: request.url,
+        secret: Secret::new(request.secret),
+        location,
+        events,
+        min_severity: request.min_severity.unwrap_or(Severity::Yellow),
+        radius_km: request.radius_km.unwrap_or(DEFAULT_RADIUS_KM),
+        // Stored in whole seconds
+        created_at: Utc::now().trunc_subsecs(0),
+    };
+    match webhooks.register(&app_state, webhook.clone()).await {
+        Ok(()) => Ok((StatusCode::CREATED, Json(webhook))),
+        Err(RegisterError::TooManyTopics) => Err(ApplicationError::new(
+            "Too many locations are polled, try again later",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )),
+        Err(RegisterError::TooManyWebhooks(max_per_key)) => Err(ApplicationError::new(
+            &format!("An API key can register at most {max_per_key} webhooks, delete one first"),
+            StatusCode::FORBIDDEN,
+        )),
+        Err(RegisterError::Store(err)) => Err(store_error(err)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/{id}",
+    params(("id" = String, Path, description = "ID returned when the webhook was registered")),
+    responses(
+        (status = 200, description = "The webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid API key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No webhook database is configured", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "subscriptions"
+)]
+#[instrument]
+pub async fn get_webhook(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    Path(id): Path<String>,
+) -> Result<Json<Webhook>, ApplicationError> {
+    require_api_key(&client)?;
+    let webhook = webhooks(&app_state)?
+        .get(&id)
+        .await
+        .map_err(store_error)?
+        .ok_or_else(|| not_found(&id))?;
+    Ok(Json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/subscriptions/{id}",
+    params(("id" = String, Path, description = "ID returned when the webhook was registered")),
+    responses(
+        (status = 204, description = "Webhook and its delivery log deleted"),
+        (status = 401, description = "Missing or invalid API key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No webhook database is configured", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "subscriptions"
+)]
+#[instrument]
+pub async fn delete_webhook(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApplicationError> {
+    require_api_key(&client)?;
+    if !webhooks(&app_state)?
+        .remove(&id)
+        .await
+        .map_err(store_error)?
+    {
+        return Err(not_found(&id));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/subscriptions/{id}/deliveries",
+    params(("id" = String, Path, description = "ID returned when the webhook was registered")),
+    responses(
+        (status = 200, description = "The last 100 delivery attempts, newest first", body = Vec<Delivery>),
+        (status = 401, description = "Missing or invalid API key", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such webhook", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "No webhook database is configured", body = ProblemDetails, content_type = "application/problem+json")
+    ),
+    tag = "subscriptions"
+)]
+#[instrument]
+pub async fn webhook_deliveries(
+    State(app_state): State<AppState>,
+    Extension(client): Extension<ApiClient>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Delivery>>, ApplicationError> {
+    require_api_key(&client)?;
+    let webhooks = webhooks(&app_state)?;
+    if webhooks.get(&id).await.map_err(store_error)?.is_none() {
+        return Err(not_found(&id));
+    }
+    let deliveries = webhooks.deliveries(&id).await.map_err(store_error)?;
+    Ok(Json(deliveries))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use axum::{Router, http::HeaderMap, routing::post};
+    use pretty_assertions::assert_eq;
+    use serde_json::{Value, json};
+    use tokio::sync::mpsc;
+    use wictk_client::{Location, NewWebhook, WictkClient};
+    use wictk_core::Endpoints;
+
+    use crate::{
+        config::{ApiKeyConfig, AuthConfig, Config, WebhooksConfig},
+        handlers::{
+            access::hash_api_key,
+            test_utils::{
+                create_test_app_with_config, create_test_app_with_webhooks, make_json_request,
+                make_request_with_api_key, make_request_with_method, serve,
+            },
+        },
+        webhooks::sign,
+    };
+
+    use super::*;
+
+    const MET_ALERTS: &str = r#"{"features":[{"geometry":{"coordinates":[[[10.0,63.0],[11.0,63.0],[11.0,64.0],[10.0,64.0],[10.0,63.0]]],"type":"Polygon"},"properties":{"certainty":"Likely","description":"Kraftige vindkast","event":"wind","severity":"Severe","title":"Vind, oransje nivå"},"type":"Feature","when":{"interval":["2025-01-20T12:00:00+00:00","2025-01-21T12:00:00+00:00"]}}],"type":"FeatureCollection"}"#;
+    const SECRET: &str = "a secret of 16+ chars";
+    const KEY: &str = "webhook key";
+
+    /// Accepts [`KEY`], and webhooks on localhost if `allow_private_urls`
+    fn config(allow_private_urls: bool) -> Config {
+        Config {
+            webhooks: WebhooksConfig {
+                retry_base_delay_ms: 10,
+                allow_private_urls,
+                ..Default::default()
+            },
+            auth: AuthConfig {
+                api_keys: vec![ApiKeyConfig {
+                    name: "hooks".to_string(),
+                    sha256: hash_api_key(KEY),
+                    rate_limit: None,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Serves a webhook that fails its first request, returning its URL and the requests it got
+    async fn receiver() -> (String, mpsc::UnboundedReceiver<(HeaderMap, String)>) {
+        let (sender, requests) = mpsc::unbounded_channel();
+        let received = Arc::new(AtomicUsize::new(0));
+        let hook = post(move |headers: HeaderMap, body: String| async move {
+            sender.send((headers, body)).unwrap();
+            if received.fetch_add(1, Ordering::SeqCst) == 0 {
+                StatusCode::INTERNAL_SERVER_ERROR
+            } else {
+                StatusCode::NO_CONTENT
+            }
+        });
+        let base_url = serve(Router::new().route("/hook", hook)).await;
+        (format!("{base_url}/hook"), requests)
+    }
+
+    #[tokio::test]
+    async fn test_webhook_sent_signed_alerts_with_retries() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(MET_ALERTS)
+            .create_async()
+            .await;
+        // The receiver is on localhost
+        let app =
+            create_test_app_with_webhooks(Endpoints::with_base_url(&server.url()), config(true));
+        let (url, mut requests) = receiver().await;
+
+        let request = json!({
+            "lat": 63.5, "lon": 10.5, "events": ["alert"], "min_severity": "Orange",
+            "url": url, "secret": SECRET
+        });
+        let (status, body) = make_request_with_api_key(
+            app.clone(),
+            "POST",
+            "/api/v1/subscriptions",
+            KEY,
+            Some(&request.to_string()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let webhook: Value = serde_json::from_slice(&body).unwrap();
+        let id = webhook["id"].as_str().unwrap().to_string();
+        assert_eq!(webhook["events"], json!(["alert"]));
+        assert_eq!(webhook["radius_km"], 50.0);
+        assert!(webhook.get("secret").is_none());
+
+        let mut bodies = Vec::new();
+        for _ in 0..2 {
+            let (headers, body) = tokio::time::timeout(Duration::from_secs(10), requests.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let timestamp = headers["x-wictk-timestamp"].to_str().unwrap();
+            assert_eq!(
+                headers["x-wictk-signature"],
+                sign(SECRET, timestamp, &body).as_str()
+            );
+            assert_eq!(headers["x-wictk-event"], "alert");
+            bodies.push(body);
+        }
+        assert_eq!(bodies[0], bodies[1], "a retry sends the same event");
+        let event: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(event["webhook_id"], id.as_str());
+        assert_eq!(event["event"], "alert");
+        assert_eq!(event["data"]["Met"]["title"], "Vind, oransje nivå");
+
+        // The delivery is logged after the response was read
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (status, body) = make_request_with_api_key(
+            app.clone(),
+            "GET",
+            &format!("/api/v1/subscriptions/{id}/deliveries"),
+            KEY,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let deliveries: Vec<Delivery> = serde_json::from_slice(&body).unwrap();
+        let attempts: Vec<_> = deliveries
+            .iter()
+            .map(|delivery| (delivery.attempt, delivery.status))
+            .collect();
+        assert_eq!(attempts, vec![(2, Some(204)), (1, Some(500))]);
+        assert_eq!(deliveries[0].delivery_id, event["delivery_id"]);
+
+        let path = format!("/api/v1/subscriptions/{id}");
+        let (status, _) = make_request_with_api_key(app.clone(), "DELETE", &path, KEY, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = make_request_with_api_key(app, "GET", &path, KEY, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_client_round_trip() {
+        // Upstream requests of the delivery task fail, nothing is delivered
+        let server = mockito::Server::new_async().await;
+        let app =
+            create_test_app_with_webhooks(Endpoints::with_base_url(&server.url()), config(true));
+        let client = WictkClient::new(reqwest::Client::new(), &serve(app).await)
+            .unwrap()
+            .with_api_key(KEY);
+
+        let webhook = client
+            .register_webhook(&NewWebhook {
+                location: Location::coordinates(63.43, 10.39),
+                events: vec![wictk_client::WebhookEvent::Lightning],
+                min_severity: None,
+                radius_km: Some(10.0),
+                url: "http://127.0.0.1:9/hook".to_string(),
+                secret: Secret::new(SECRET.to_string()),
+            })
+            .await
+            .unwrap();
+        assert_eq!(webhook.radius_km, 10.0);
+        assert_eq!(webhook.min_severity, Severity::Yellow);
+        assert_eq!(client.webhook(&webhook.id).await.unwrap(), webhook);
+        assert!(
+            client
+                .webhook_deliveries(&webhook.id)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        client.delete_webhook(&webhook.id).await.unwrap();
+        let err = client.webhook(&webhook.id).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_rejects_invalid_requests() {
+        let app = create_test_app_with_webhooks(Endpoints::default(), config(false));
+        let valid = json!({
+            "lat": 63.5, "lon": 10.5, "events": ["alert", "lightning"],
+            "url": "https://example.com/hook", "secret": SECRET
+        });
+        let invalid = [
+            ("events", json!([])),
+            ("url", json!("ftp://example.com/hook")),
+            ("url", json!("http://169.254.169.254/latest/meta-data")),
+            ("url", json!("http://localhost:3000/hook")),
+            ("secret", json!("short")),
+            ("radius_km", json!(0)),
+        ];
+        for (field, value) in invalid {
+            let mut request = valid.clone();
+            request[field] = value;
+            let (status, body) = make_request_with_api_key(
+                app.clone(),
+                "POST",
+                "/api/v1/subscriptions",
+                KEY,
+                Some(&request.to_string()),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{field}");
+            let problem: Value = serde_json::from_slice(&body).unwrap();
+            assert!(
+                problem["detail"].as_str().unwrap().contains(field),
+                "{problem}"
+            );
+        }
+        let (status, _) = make_request_with_api_key(
+            app,
+            "GET",
+            "/api/v1/subscriptions/unknown/deliveries",
+            KEY,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_webhook_registrations_are_limited() {
+        let server = mockito::Server::new_async().await;
+        let mut config = config(true);
+        config.streams.max_topics = 0;
+        config.webhooks.max_per_key = 1;
+        let app = create_test_app_with_webhooks(Endpoints::with_base_url(&server.url()), config);
+        let register = |events: Value| {
+            let request = json!({
+                "lat": 63.5, "lon": 10.5, "events": events,
+                "url": "http://127.0.0.1:9/hook", "secret": SECRET
+            });
+            let app = app.clone();
+            async move {
+                make_request_with_api_key(
+                    app,
+                    "POST",
+                    "/api/v1/subscriptions",
+                    KEY,
+                    Some(&request.to_string()),
+                )
+                .await
+            }
+        };
+
+        // The alerts would need a poller, none are left
+        let (status, _) = register(json!(["alert", "lightning"])).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let (status, body) = register(json!(["lightning"])).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = register(json!(["lightning"])).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let webhook: Value = serde_json::from_slice(&body).unwrap();
+        let path = format!("/api/v1/subscriptions/{}", webhook["id"].as_str().unwrap());
+        let (status, _) = make_request_with_api_key(app.clone(), "DELETE", &path, KEY, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = register(json!(["lightning"])).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_need_an_api_key() {
+        let app = create_test_app_with_webhooks(Endpoints::default(), config(false));
+        let request = json!({
+            "lat": 63.5, "lon": 10.5, "events": ["alert"],
+            "url": "https://example.com/hook", "secret": SECRET
+        });
+        let (status, _) = make_json_request(
+            app.clone(),
+            "POST",
+            "/api/v1/subscriptions",
+            &request.to_string(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        for (method, path) in [
+            ("GET", "/api/v1/subscriptions/unknown"),
+            ("DELETE", "/api/v1/subscriptions/unknown"),
+            ("GET", "/api/v1/subscriptions/unknown/deliveries"),
+        ] {
+            let (status, _) = make_request_with_method(app.clone(), method, path).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{method} {path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_unavailable_without_database() {
+        let app = create_test_app_with_config(Endpoints::default(), config(false));
+        let (status, _) =
+            make_request_with_api_key(app, "GET", "/api/v1/subscriptions/unknown", KEY, None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
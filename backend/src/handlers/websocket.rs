@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::Response,
+};
+use metrics::gauge;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    task::AbortHandle,
+    time::Instant,
+};
+use tracing::{debug, instrument};
+use utoipa::ToSchema;
+use wictk_core::{City, CoordinatesAsString, Upstream};
+
+use crate::{
+    AppState,
+    topics::{Topic, TopicKind},
+};
+
+use super::{
+    error::{ApplicationError, ProblemDetails},
+    lightning::{LightningQuery, find_area},
+    nowcasts::{LocationQuery, find_location},
+};
+
+/// Most subscriptions a connection may hold at once
+const MAX_SUBSCRIPTIONS: usize = 16;
+/// Messages waiting for a slow client before the subscriptions wait too
+const OUTGOING_CAPACITY: usize = 32;
+
+/// What a subscription receives
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionTopic {
+    /// The alerts covering the location, sent on subscribing and when they change
+    Alerts,
+    /// The nowcasts of all providers for the location, sent on subscribing and when they change
+    Nowcast,
+    /// Each new lightning strike within `radius_km` of the location, or anywhere without one
+    Lightning,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct Subscription {
+    /// Chosen by the client to tell its subscriptions apart
+    pub id: String,
+    pub topic: SubscriptionTopic,
+    /// Location name (e.g., "Oslo")
+    pub location: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    /// Radius of lightning subscriptions, 50 km by default
+    pub radius_km: Option<f64>,
+}
+
+/// A message sent by the client
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Subscribe(Subscription),
+    Unsubscribe { id: String },
+}
+
+/// A message sent to the client
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Subscribed {
+        id: String,
+    },
+    Unsubscribed {
+        id: String,
+    },
+    /// Data of a subscription: alerts, nowcasts or a lightning strike
+    Event {
+        id: String,
+        topic: SubscriptionTopic,
+        data: Value,
+    },
+    /// Lightning strikes left out because the client did not keep up
+    Lagged {
+        id: String,
+        missed: u64,
+    },
+    /// A message was invalid or a subscription failed, which ends it
+    Error {
+        id: Option<String>,
+        error: ProblemDetails,
+    },
+}
+
+impl From<ServerMessage> for Message {
+    fn from(message: ServerMessage) -> Self {
+        let text = serde_json::to_string(&message).expect("messages serialize to JSON");
+        Message::Text(text.into())
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/ws",
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol. The client sends `ClientMessage`s as JSON text and receives `ServerMessage`s, and must answer pings"),
+        (status = 400, description = "Not a WebSocket handshake")
+    ),
+    tag = "subscriptions"
+)]
+#[instrument(skip(upgrade))]
+pub async fn websocket(upgrade: WebSocketUpgrade, State(app_state): State<AppState>) -> Response {
+    upgrade.on_upgrade(move |socket| handle_socket(socket, app_state))
+}
+
+/// Reads the client's messages, writes the subscriptions' messages and pings
+/// the client until it disconnects or stops answering.
+async fn handle_socket(mut socket: WebSocket, app_state: AppState) {
+    gauge!("websocket_connections").increment(1);
+    let keep_alive = app_state.config.streams().keep_alive();
+    let (out, mut outgoing) = mpsc::channel(OUTGOING_CAPACITY);
+    let mut subscriptions: HashMap<String, AbortHandle> = HashMap::new();
+    let mut heartbeat = tokio::time::interval_at(Instant::now() + keep_alive, keep_alive);
+    let mut last_heard = Instant::now();
+
+    loop {
+        let reply = tokio::select! {
+            message = socket.recv() => {
+                let Some(Ok(message)) = message else { break };
+                last_heard = Instant::now();
+                match message {
+                    Message::Text(text) => {
+                        handle_message(&app_state, &text, &mut subscriptions, &out)
+                    }
+                    Message::Close(_) => break,
+                    // Pings are answered by axum, pongs only show the client is alive
+                    _ => None,
+                }
+            }
+            Some(message) = outgoing.recv() => Some(message),
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() > keep_alive * 2 {
+                    debug!("Closing a WebSocket connection that stopped answering pings");
+                    break;
+                }
+                if socket.send(Message::Ping(Default::default())).await.is_err() {
+                    break;
+                }
+                None
+            }
+        };
+        if let Some(reply) = reply {
+            if socket.send(reply.into()).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    for subscription in subscriptions.values() {
+        subscription.abort();
+    }
+    gauge!("websocket_connections").decrement(1);
+}
+
+/// Starts or ends a subscription, returning the reply to send right away.
+fn handle_message(
+    app_state: &AppState,
+    text: &str,
+    subscriptions: &mut HashMap<String, AbortHandle>,
+    out: &mpsc::Sender<ServerMessage>,
+) -> Option<ServerMessage> {
+    let error = |id: Option<String>, message: &str, status_code| ServerMessage::Error {
+        id,
+        error: ApplicationError::new(message, status_code).problem(),
+    };
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return Some(error(
+                None,
+                &format!("Invalid message: {err}"),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    subscriptions.retain(|_, subscription| !subscription.is_finished());
+    match message {
+        ClientMessage::Subscribe(subscription) => {
+            let id = subscription.id.clone();
+            if subscriptions.contains_key(&id) {
+                return Some(error(
+                    Some(id.clone()),
+                    &format!("Already subscribed as '{id}'"),
+                    StatusCode::CONFLICT,
+                ));
+            }
+            if subscriptions.len() >= MAX_SUBSCRIPTIONS {
+                return Some(error(
+                    Some(id),
+                    &format!("At most {MAX_SUBSCRIPTIONS} subscriptions per connection"),
+                    StatusCode::TOO_MANY_REQUESTS,
+                ));
+            }
+            let task = tokio::spawn(subscribe(app_state.clone(), subscription, out.clone()));
+            subscriptions.insert(id, task.abort_handle());
+            None
+        }
+        ClientMessage::Unsubscribe { id } => match subscriptions.remove(&id) {
+            Some(subscription) => {
+                subscription.abort();
+                Some(ServerMessage::Unsubscribed { id })
+            }
+            None => Some(error(
+                Some(id.clone()),
+                &format!("No subscription '{id}'"),
+                StatusCode::NOT_FOUND,
+            )),
+        },
+    }
+}
+
+/// Sends the events of `subscription` until it is aborted, or an error
+/// message if it cannot be served.
+async fn subscribe(
+    app_state: AppState,
+    subscription: Subscription,
+    out: mpsc::Sender<ServerMessage>,
+) {
+    let id = subscription.id.clone();
+    let result = match subscription.topic {
+        SubscriptionTopic::Alerts => {
+            forward_topic(&app_state, TopicKind::Alerts, subscription, &out).await
+        }
+        SubscriptionTopic::Nowcast => {
+            forward_topic(&app_state, TopicKind::Nowcast, subscription, &out).await
+        }
+        SubscriptionTopic::Lightning => forward_lightning(&app_state, subscription, &out).await,
+    };
+    if let Err(err) = result {
+        let _ = out
+            .send(ServerMessage::Error {
+                id: Some(id),
+                error: err.problem(),
+            })
+            .await;
+    }
+}
+
+/// Sends the data of an alerts or nowcast topic now and whenever it changes.
+/// Returns once the connection is closed.
+async fn forward_topic(
+    app_state: &AppState,
+    kind: TopicKind,
+    subscription: Subscription,
+    out: &mpsc::Sender<ServerMessage>,
+) -> Result<(), ApplicationError> {
+    let (id, topic) = (subscription.id.clone(), subscription.topic);
+    // Nowcasts come from whichever providers are enabled
+    if kind == TopicKind::Alerts {
+        app_state.config.providers().check(Upstream::Met)?;
+    }
+    let Some(location_query) = location_query(subscription) else {
+        return Err(ApplicationError::new(
+            "Alerts and nowcast subscriptions need a location, or lat and lon",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+    let location = find_location(location_query, app_state).await?;
+    let Some(mut data) = app_state
+        .topics
+        .subscribe(app_state, Topic { kind, location })
+    else {
+        return Err(ApplicationError::new(
+            "Too many locations are subscribed to, try again later",
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    };
+    if out
+        .send(ServerMessage::Subscribed { id: id.clone() })
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+    loop {
+        let current = data.borrow_and_update().clone();
+        if let Some(current) = current {
+            let event = ServerMessage::Event {
+                id: id.clone(),
+                topic,
+                data: current,
+            };
+            if out.send(event).await.is_err() {
+                return Ok(());
+            }
+        }
+        if data.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends each new strike within the subscription's area. Returns once the
+/// connection is closed.
+async fn forward_lightning(
+    app_state: &AppState,
+    subscription: Subscription,
+    out: &mpsc::Sender<ServerMessage>,
+) -> Result<(), ApplicationError> {
+    app_state.config.providers().check(Upstream::Yr)?;
+    let id = subscription.id.clone();
+    let query = LightningQuery {
+        location: subscription.location,
+        lat: subscription.lat.map(|lat| lat.to_string()),
+        lon: subscription.lon.map(|lon| lon.to_string()),
+        radius_km: subscription.radius_km,
+    };
+    let area = find_area(query, app_state).await?;
+    let mut strikes = app_state.lightning_feed.subscribe(app_state);
+    if out
+        .send(ServerMessage::Subscribed { id: id.clone() })
+        .await
+        .is_err()
+    {
+        return Ok(());
+    }
+    loop {
+        let message = match strikes.recv().await {
+            Ok(strike) if area.is_none_or(|area| area.contains(&strike)) => ServerMessage::Event {
+                id: id.clone(),
+                topic: SubscriptionTopic::Lightning,
+                data: serde_json::to_value(strike).expect("strikes serialize to JSON"),
+            },
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => ServerMessage::Lagged {
+                id: id.clone(),
+                missed,
+            },
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        if out.send(message).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn location_query(subscription: Subscription) -> Option<LocationQuery> {
+    match (subscription.location, subscription.lat, subscription.lon) {
+        (Some(location), _, _) => Some(LocationQuery::Location(City { location })),
+        (None, Some(lat), Some(lon)) => Some(LocationQuery::Coordinates(CoordinatesAsString {
+            lat: lat.to_string(),
+            lon: lon.to_string(),
+        })),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::{SinkExt, StreamExt};
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite};
+    use wictk_core::Endpoints;
+
+    use crate::{
+        config::{ApiKeyConfig, AuthConfig, Config, StreamsConfig},
+        handlers::{
+            access::hash_api_key,
+            test_utils::{create_replay_test_app, create_test_app_with_config, serve},
+        },
+    };
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn connect(app: axum::Router) -> Client {
+        let base_url = serve(app).await.replace("http://", "ws://");
+        let (socket, _) = connect_async(format!("{base_url}/api/v1/ws"))
+            .await
+            .unwrap();
+        socket
+    }
+
+    async fn send(socket: &mut Client, message: serde_json::Value) {
+        let text = message.to_string();
+        socket
+            .send(tungstenite::Message::Text(text.into()))
+            .await
+            .unwrap();
+    }
+
+    /// The next JSON message, skipping pings
+    async fn receive(socket: &mut Client) -> serde_json::Value {
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            if let tungstenite::Message::Text(text) = message {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_websocket_sends_alerts_and_nowcasts() {
+        let mut socket = connect(create_replay_test_app().await).await;
+        send(
+            &mut socket,
+            json!({"type": "subscribe", "id": "weather", "topic": "nowcast", "location": "Trondheim"}),
+        )
+        .await;
+        send(
+            &mut socket,
+            json!({"type": "subscribe", "id": "alerts", "topic": "alerts", "lat": 63.43, "lon": 10.39}),
+        )
+        .await;
+
+        let mut events = std::collections::HashMap::new();
+        let mut subscribed = Vec::new();
+        while events.len() < 2 {
+            let message = receive(&mut socket).await;
+            let id = message["id"].as_str().unwrap().to_string();
+            match message["type"].as_str().unwrap() {
+                "subscribed" => subscribed.push(id),
+                "event" => {
+                    assert!(subscribed.contains(&id), "{id} got an event first");
+                    events.insert(id, message);
+                }
+                other => panic!("unexpected {other} message: {message}"),
+            }
+        }
+        assert_eq!(events["weather"]["topic"], "nowcast");
+        let nowcasts = events["weather"]["data"].as_array().unwrap();
+        let lat = nowcasts[0]["met"]["location"]["lat"].as_f64().unwrap();
+        assert!((lat - 63.4305).abs() < 1e-4, "{lat}");
+        assert_eq!(events["alerts"]["topic"], "alerts");
+        assert!(events["alerts"]["data"].is_array());
+
+        send(&mut socket, json!({"type": "unsubscribe", "id": "weather"})).await;
+        assert_eq!(
+            receive(&mut socket).await,
+            json!({"type": "unsubscribed", "id": "weather"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_websocket_rejects_invalid_messages() {
+        let mut socket = connect(create_replay_test_app().await).await;
+
+        send(&mut socket, json!({"type": "subscribe", "id": "x"})).await;
+        let message = receive(&mut socket).await;
+        assert_eq!(message["type"], "error");
+        assert_eq!(message["id"], serde_json::Value::Null);
+        assert_eq!(message["error"]["status"], 400);
+
+        send(
+            &mut socket,
+            json!({"type": "subscribe", "id": "nowhere", "topic": "nowcast"}),
+        )
+        .await;
+        let message = receive(&mut socket).await;
+        assert_eq!(message["id"], "nowhere");
+        assert_eq!(message["error"]["status"], 400);
+
+        send(&mut socket, json!({"type": "unsubscribe", "id": "unknown"})).await;
+        let message = receive(&mut socket).await;
+        assert_eq!(message["id"], "unknown");
+        assert_eq!(message["error"]["status"], 404);
+
+        let subscribe = json!({"type": "subscribe", "id": "twice", "topic": "nowcast", "location": "Trondheim"});
+        send(&mut socket, subscribe.clone()).await;
+        send(&mut socket, subscribe).await;
+        let mut statuses = Vec::new();
+        while statuses.len() < 3 {
+            let message = receive(&mut socket).await;
+            statuses.push(match message["type"].as_str().unwrap() {
+                "error" => message["error"]["status"].as_u64().unwrap(),
+                _ => 0,
+            });
+        }
+        assert!(statuses.contains(&409), "{statuses:?}");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_shares_nearby_topics_up_to_the_limit() {
+        let server = mockito::Server::new_async().await;
+        let config = Config {
+            streams: StreamsConfig {
+                max_topics: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::with_base_url(&server.url()), config);
+        let mut socket = connect(app).await;
+
+        for (id, lat, lon) in [
+            ("here", 63.431, 10.392),
+            ("nearby", 63.434, 10.394),
+            ("oslo", 59.91, 10.75),
+        ] {
+            send(
+                &mut socket,
+                json!({"type": "subscribe", "id": id, "topic": "nowcast", "lat": lat, "lon": lon}),
+            )
+            .await;
+        }
+
+        let mut replies = std::collections::HashMap::new();
+        while replies.len() < 3 {
+            let message = receive(&mut socket).await;
+            let reply = match message["type"].as_str().unwrap() {
+                "error" => message["error"]["status"].as_u64().unwrap(),
+                _ => 0,
+            };
+            replies.insert(message["id"].as_str().unwrap().to_string(), reply);
+        }
+        assert_eq!(replies["here"], 0);
+        assert_eq!(replies["nearby"], 0);
+        assert_eq!(replies["oslo"], 503);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_accepts_the_api_key_in_the_query() {
+        let config = Config {
+            auth: AuthConfig {
+                require_api_key: true,
+                api_keys: vec![ApiKeyConfig {
+                    name: "browser".to_string(),
+                    sha256: hash_api_key("browser-secret"),
+                    rate_limit: None,
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::default(), config);
+        let base_url = serve(app).await.replace("http://", "ws://");
+
+        let err = connect_async(format!("{base_url}/api/v1/ws"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, tungstenite::Error::Http(response) if response.status() == 401),
+            "{err:?}"
+        );
+        let err = connect_async(format!("{base_url}/api/v1/ws?api_key=wrong"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, tungstenite::Error::Http(response) if response.status() == 401),
+            "{err:?}"
+        );
+
+        let (mut socket, _) = connect_async(format!("{base_url}/api/v1/ws?api_key=browser-secret"))
+            .await
+            .unwrap();
+        send(&mut socket, json!({"type": "unsubscribe", "id": "none"})).await;
+        assert_eq!(receive(&mut socket).await["error"]["status"], 404);
+    }
+
+    #[tokio::test]
+    async fn test_websocket_closes_silent_connections() {
+        let config = Config {
+            streams: StreamsConfig {
+                keep_alive_secs: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let app = create_test_app_with_config(Endpoints::default(), config);
+        let base_url = serve(app).await.replace("http://", "ws://");
+        let (socket, _) = connect_async(format!("{base_url}/api/v1/ws"))
+            .await
+            .unwrap();
+        // Reading is what answers pings, so a client that does not read goes silent
+        let (_sink, mut stream) = socket.split();
+        tokio::time::sleep(Duration::from_secs(4)).await;
+
+        let mut pings = 0;
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(message)) = stream.next().await {
+                match message {
+                    tungstenite::Message::Ping(_) => pings += 1,
+                    tungstenite::Message::Close(_) => return true,
+                    _ => {}
+                }
+            }
+            true
+        })
+        .await
+        .unwrap();
+        assert!(closed);
+        assert!(pings >= 1, "got {pings} pings");
+    }
+}
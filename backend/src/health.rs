@@ -0,0 +1,390 @@
+//! Probes the upstreams for `/status/health` and the local dependencies for
+//! `/status/ready`.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Mutex, task::JoinSet, time::Instant};
+use tracing::warn;
+use utoipa::ToSchema;
+use wictk_core::{Upstream, WictkError};
+
+use crate::{AppState, quota::QuotaUsage};
+
+/// Status of a single dependency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+    /// The provider is disabled in the configuration and not probed
+    Disabled,
+    /// Credentials needed to call the dependency are not configured
+    Unconfigured,
+    /// Close to its quota, so it is not probed and only served from the cache
+    Limited,
+}
+
+/// Result of the latest probe of a dependency
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DependencyHealth {
+    pub status: DependencyStatus,
+    /// Whether the service is down while this dependency is down
+    pub critical: bool,
+    /// Milliseconds the probe took, missing if it was not probed
+    pub latency_ms: Option<u64>,
+    /// The most recent failure, kept after the dependency recovers
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Calls made to the upstream, up to date even when the report is reused
+    pub quota: QuotaUsage,
+}
+
+/// Overall status, `down` when a critical dependency is down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HealthReport {
+    pub status: ServiceStatus,
+    /// When the dependencies were probed, reports are reused for a while
+    pub checked_at: DateTime<Utc>,
+    pub dependencies: BTreeMap<String, DependencyHealth>,
+}
+
+#[derive(Debug, Default)]
+struct ProbeState {
+    latest: Option<(Instant, HealthReport)>,
+    last_errors: HashMap<Upstream, (String, DateTime<Utc>)>,
+}
+
+/// Runs the upstream probes at most once per probe interval, concurrent
+/// health checks wait for and share the same run.
+#[derive(Debug, Clone, Default)]
+pub struct HealthChecker {
+    state: Arc<Mutex<ProbeState>>,
+}
+
+impl HealthChecker {
+    pub async fn check(&self, app_state: &AppState) -> HealthReport {
+        let config = app_state.config.get();
+        let mut state = self.state.lock().await;
+        if let Some((probed_at, report)) = &state.latest {
+            if probed_at.elapsed() < config.health.probe_interval() {
+                let mut report = report.clone();
+                for upstream in Upstream::ALL {
+                    if let Some(dependency) = report.dependencies.get_mut(upstream.name()) {
+                        dependency.quota = app_state.quota.usage(upstream);
+                    }
+                }
+                return report;
+            }
+        }
+
+        let mut probes = JoinSet::new();
+        for upstream in Upstream::ALL {
+            let app_state = app_state.clone();
+            let timeout = config.health.probe_timeout();
+            probes.spawn(async move {
+                let started = Instant::now();
+                let result = probe(&app_state, upstream, timeout).await;
+                (upstream, result, started.elapsed())
+            });
+        }
+
+        let mut dependencies = BTreeMap::new();
+        for (upstream, result, latency) in probes.join_all().await {
+            let status = match result {
+                Probe::Up => DependencyStatus::Up,
+                Probe::Disabled => DependencyStatus::Disabled,
+                Probe::Unconfigured => DependencyStatus::Unconfigured,
+                Probe::Limited => DependencyStatus::Limited,
+                Probe::Down(err) => {
+                    warn!("Health probe of {} failed: {}", upstream, err);
+                    state
+                        .last_errors
+                        .insert(upstream, (err.to_string(), Utc::now()));
+                    DependencyStatus::Down
+                }
+            };
+            let probed = matches!(status, DependencyStatus::Up | DependencyStatus::Down);
+            let last_error = state.last_errors.get(&upstream).cloned();
+            dependencies.insert(
+                upstream.name().to_string(),
+                DependencyHealth {
+                    status,
+                    critical: config.health.is_critical(upstream),
+                    latency_ms: probed.then_some(latency.as_millis() as u64),
+                    last_error_at: last_error.as_ref().map(|(_, at)| *at),
+                    last_error: last_error.map(|(error, _)| error),
+                    quota: app_state.quota.usage(upstream),
+                },
+            );
+        }
+
+        let report = HealthReport {
+            status: service_status(&dependencies),
+            checked_at: Utc::now(),
+            dependencies,
+        };
+        state.latest = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+/// A limited upstream only degrades the service, it is still served from the cache.
+fn service_status(dependencies: &BTreeMap<String, DependencyHealth>) -> ServiceStatus {
+    let down = dependencies
+        .values()
+        .filter(|dependency| dependency.status == DependencyStatus::Down);
+    let limited = dependencies
+        .values()
+        .any(|dependency| dependency.status == DependencyStatus::Limited);
+    match down.map(|dependency| dependency.critical).max() {
+        Some(true) => ServiceStatus::Down,
+        Some(false) => ServiceStatus::Degraded,
+        None if limited => ServiceStatus::Degraded,
+        None => ServiceStatus::Ok,
+    }
+}
+
+enum Probe {
+    Up,
+    Down(WictkError),
+    Disabled,
+    Unconfigured,
+    Limited,
+}
+
+/// Makes the cheapest request that shows the upstream works with our credentials.
+async fn probe(app_state: &AppState, upstream: Upstream, timeout: Duration) -> Probe {
+    if !app_state.config.providers().enabled(upstream) {
+        return Probe::Disabled;
+    }
+    let client = app_state.client.inner();
+    let endpoints = &app_state.endpoints;
+    let request = match upstream {
+        Upstream::Met => client.get(format!("{}/weatherapi/nowcast/2.0/status", endpoints.met)),
+        Upstream::OpenWeatherMap => client
+            .get(format!("{}/geo/1.0/direct", endpoints.openweathermap))
+            .query(&[("q", "Oslo"), ("limit", "1")])
+            .query(&[("appid", app_state.openweathermap_apikey.expose_secret())]),
+        Upstream::Yr => client
+            .get(format!("{}/api/v0/lightning-events", endpoints.yr))
+            .query(&[("fromHours", "1")]),
+        Upstream::Frost => {
+            let Some(client_id) = &app_state.frost_client_id else {
+                return Probe::Unconfigured;
+            };
+            client
+                .get(format!("{}/sources/v0.jsonld", endpoints.frost))
+                .basic_auth(client_id.expose_secret(), None::<&str>)
+                .query(&[("ids", "SN18700")])
+        }
+    };
+    if app_state.quota.try_record(upstream, "probe").is_err() {
+        return Probe::Limited;
+    }
+    let result = match request.timeout(timeout).send().await {
+        Ok(response) => WictkError::check_status(upstream, response).map(|_| ()),
+        Err(err) => Err(WictkError::request(upstream, &err)),
+    };
+    match result {
+        Ok(()) => Probe::Up,
+        Err(err) => Probe::Down(err),
+    }
+}
+
+/// Whether this replica can serve requests, only checking its local dependencies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    /// Status of the history database and Redis, when configured
+    pub dependencies: BTreeMap<String, DependencyStatus>,
+}
+
+/// Checks the history database and Redis. Upstream outages affect every
+/// replica alike, so they do not make a replica unready.
+pub async fn readiness(app_state: &AppState) -> ReadinessReport {
+    let mut dependencies = BTreeMap::new();
+    if let Some(history) = &app_state.history {
+        dependencies.insert("history".to_string(), status(history.ping().await));
+    }
+    if let Some(redis) = &app_state.redis {
+        dependencies.insert("redis".to_string(), status(redis.ping().await));
+    }
+    ReadinessReport {
+        ready: dependencies
+            .values()
+            .all(|status| *status == DependencyStatus::Up),
+        dependencies,
+    }
+}
+
+fn status<E: std::fmt::Display>(result: Result<(), E>) -> DependencyStatus {
+    match result {
+        Ok(()) => DependencyStatus::Up,
+        Err(err) => {
+            warn!("Readiness check failed: {}", err);
+            DependencyStatus::Down
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use wictk_core::Endpoints;
+
+    use super::*;
+    use crate::config::{Config, QuotaConfig};
+
+    async fn mock(server: &mut mockito::ServerGuard, path: &str, status: usize) -> mockito::Mock {
+        server
+            .mock("GET", path)
+            .match_query(mockito::Matcher::Any)
+            .with_status(status)
+            .with_body("{}")
+            .create_async()
+            .await
+    }
+
+    fn app_state(server: &mockito::ServerGuard, config: Config) -> AppState {
+        AppState::from_config(
+            reqwest::Client::new(),
+            "test_api_key".to_string(),
+            None,
+            Endpoints::with_base_url(&server.url()),
+            config,
+        )
+    }
+
+    #[tokio::test]
+    async fn reports_each_upstream() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = mock(&mut server, "/weatherapi/nowcast/2.0/status", 200).await;
+        let _owm = mock(&mut server, "/geo/1.0/direct", 200).await;
+        let _yr = mock(&mut server, "/api/v0/lightning-events", 500).await;
+        let app_state = app_state(&server, Config::default());
+
+        let report = HealthChecker::default().check(&app_state).await;
+
+        assert_eq!(report.status, ServiceStatus::Degraded);
+        let status = |name: &str| report.dependencies[name].status;
+        assert_eq!(status("met"), DependencyStatus::Up);
+        assert_eq!(status("openweathermap"), DependencyStatus::Up);
+        assert_eq!(status("yr"), DependencyStatus::Down);
+        assert_eq!(status("frost"), DependencyStatus::Unconfigured);
+        assert_eq!(
+            report.dependencies["yr"].last_error.as_deref(),
+            Some("Yr is unavailable: responded with 500 Internal Server Error")
+        );
+        assert!(report.dependencies["met"].latency_ms.is_some());
+        assert_eq!(report.dependencies["frost"].latency_ms, None);
+    }
+
+    #[tokio::test]
+    async fn critical_upstream_down_and_disabled_providers() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = mock(&mut server, "/weatherapi/nowcast/2.0/status", 200).await;
+        let _owm = mock(&mut server, "/geo/1.0/direct", 401).await;
+        let mut config = Config::default();
+        config.providers.yr = false;
+        let app_state = app_state(&server, config);
+
+        let report = HealthChecker::default().check(&app_state).await;
+
+        assert_eq!(report.status, ServiceStatus::Down);
+        assert!(report.dependencies["openweathermap"].critical);
+        assert_eq!(report.dependencies["yr"].status, DependencyStatus::Disabled);
+    }
+
+    #[tokio::test]
+    async fn reuses_reports_within_probe_interval() {
+        let mut server = mockito::Server::new_async().await;
+        let met = server
+            .mock("GET", "/weatherapi/nowcast/2.0/status")
+            .with_status(200)
+            .expect(1)
+            .create_async()
+            .await;
+        let app_state = app_state(&server, Config::default());
+        let checker = HealthChecker::default();
+
+        let (first, second) = tokio::join!(checker.check(&app_state), checker.check(&app_state));
+
+        assert_eq!(first, second);
+        met.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn stops_probing_near_quota() {
+        let mut server = mockito::Server::new_async().await;
+        let _met = mock(&mut server, "/weatherapi/nowcast/2.0/status", 200).await;
+        let owm = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("[]")
+            .expect(1)
+            .create_async()
+            .await;
+        let mut config = Config::default();
+        config.health.probe_interval_secs = 0;
+        config.providers.yr = false;
+        config.quotas.insert(
+            "openweathermap".to_string(),
+            QuotaConfig {
+                per_minute: Some(1),
+                ..QuotaConfig::default()
+            },
+        );
+        let app_state = app_state(&server, config);
+        let checker = HealthChecker::default();
+
+        let first = checker.check(&app_state).await;
+        assert_eq!(first.status, ServiceStatus::Ok);
+        assert_eq!(
+            first.dependencies["openweathermap"].quota.calls_last_minute,
+            1
+        );
+
+        let second = checker.check(&app_state).await;
+        let owm_health = &second.dependencies["openweathermap"];
+        assert_eq!(second.status, ServiceStatus::Degraded);
+        assert_eq!(owm_health.status, DependencyStatus::Limited);
+        assert_eq!(owm_health.latency_ms, None);
+        assert!(owm_health.quota.degraded);
+        owm.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn keeps_last_error_after_recovery() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = mock(&mut server, "/weatherapi/nowcast/2.0/status", 503).await;
+        let mut config = Config::default();
+        config.health.probe_interval_secs = 0;
+        let app_state = app_state(&server, config);
+        let checker = HealthChecker::default();
+
+        checker.check(&app_state).await;
+        failing.remove_async().await;
+        let _met = mock(&mut server, "/weatherapi/nowcast/2.0/status", 200).await;
+        let report = checker.check(&app_state).await;
+
+        let met = &report.dependencies["met"];
+        assert_eq!(met.status, DependencyStatus::Up);
+        assert!(met.last_error.is_some());
+        assert!(met.last_error_at.is_some());
+    }
+}
@@ -0,0 +1,469 @@
+//! SQLite store recording every nowcast, alert and lightning strike fetched from the upstreams.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+use wictk_core::{Alert, Coordinates, Lightning, Nowcast};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS nowcasts (
+    provider TEXT NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    time INTEGER NOT NULL,
+    air_temperature REAL NOT NULL,
+    relative_humidity REAL NOT NULL,
+    wind_speed REAL NOT NULL,
+    precipitation_rate REAL,
+    data TEXT NOT NULL,
+    UNIQUE (provider, lat, lon, time)
+);
+CREATE INDEX IF NOT EXISTS nowcasts_location_time ON nowcasts (lat, lon, time);
+CREATE TABLE IF NOT EXISTS alerts (
+    recorded_at INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    event TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    data TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS lightning (
+    time INTEGER NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    magic_value INTEGER NOT NULL,
+    UNIQUE (time, lat, lon)
+);
+";
+
+/// Minimum, maximum and mean of a value within a bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Aggregate {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Aggregated nowcasts from all providers within one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NowcastBucket {
+    /// Start of the bucket, inclusive
+    pub start: DateTime<Utc>,
+    /// End of the bucket, exclusive
+    pub end: DateTime<Utc>,
+    /// Number of nowcasts in the bucket
+    pub samples: u64,
+    pub air_temperature: Aggregate,
+    pub relative_humidity: Aggregate,
+    pub wind_speed: Aggregate,
+    /// Only reported by Met.no, missing when no Met.no nowcast is in the bucket
+    pub precipitation_rate: Option<Aggregate>,
+}
+
+/// Handle to the history database, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct History {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl History {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap()))
+            .await
+            .expect("history task panicked")
+    }
+
+    /// Checks that the database answers, for the readiness probe.
+    pub async fn ping(&self) -> rusqlite::Result<()> {
+        self.with_connection(|connection| connection.query_row("SELECT 1", [], |_| Ok(())))
+            .await
+    }
+
+    /// Records a nowcast requested for `location`. Nowcasts already recorded are ignored.
+    pub async fn record_nowcast(&self, location: &Coordinates, nowcast: &Nowcast) {
+        let (provider, time, temperature, humidity, wind_speed, precipitation_rate) = match nowcast
+        {
+            Nowcast::Met(met) => (
+                "met",
+                met.time,
+                met.air_temperature,
+                met.relative_humidity,
+                met.wind_speed,
+                Some(met.precipitation_rate),
+            ),
+            Nowcast::OpenWeather(open) => (
+                "openweathermap",
+                open.dt,
+                open.temp,
+                open.humidity as f32,
+                open.wind_speed,
+                None,
+            ),
+        };
+        let (lat, lon) = (location.lat, location.lon);
+        let data = serde_json::to_string(nowcast).unwrap_or_default();
+        let result = self
+            .with_connection(move |connection| {
+                connection.execute(
+                    "INSERT OR IGNORE INTO nowcasts VALUES (?1, ROUND(?2, 2), ROUND(?3, 2), ?4, ?5, ?6, ?7, ?8, ?9)",
+                    params![
+                        provider,
+                        lat,
+                        lon,
+                        time.timestamp(),
+                        temperature,
+                        humidity,
+                        wind_speed,
+                        precipitation_rate,
+                        data
+                    ],
+                )
+            })
+            .await;
+        if let Err(err) = result {
+            warn!("Failed to record {} nowcast: {}", provider, err);
+        }
+    }
+
+    /// Records alerts not seen before.
+    pub async fn record_alerts(&self, alerts: &[Alert]) {
+        let rows: Vec<_> = alerts
+            .iter()
+            .filter_map(|alert| match alert {
+                Alert::Met(met) => Some((
+                    met.title.clone(),
+                    met.event.clone(),
+                    format!("{:?}", met.severity),
+                    serde_json::to_string(alert).ok()?,
+                )),
+                Alert::Nve => None,
+            })
+            .collect();
+        let recorded_at = Utc::now().timestamp();
+        let result = self
+            .with_connection(move |connection| {
+                let transaction = connection.transaction()?;
+                {
+                    let mut insert = transaction
+                        .prepare("INSERT OR IGNORE INTO alerts VALUES (?1, ?2, ?3, ?4, ?5)")?;
+                    for (title, event, severity, data) in rows {
+                        insert.execute(params![recorded_at, title, event, severity, data])?;
+                    }
+                }
+                transaction.commit()
+            })
+            .await;
+        if let Err(err) = result {
+            warn!("Failed to record alerts: {}", err);
+        }
+    }
+
+    /// Records lightning strikes not seen before.
+    pub async fn record_lightning(&self, strikes: &[Lightning]) {
+        let rows: Vec<_> = strikes
+            .iter()
+            .map(|strike| {
+                (
+                    strike.time.timestamp(),
+                    strike.location.y(),
+                    strike.location.x(),
+                    strike.magic_value,
+                )
+            })
+            .collect();
+        let result = self
+            .with_connection(move |connection| {
+                let transaction = connection.transaction()?;
+                {
+                    let mut insert = transaction
+                        .prepare("INSERT OR IGNORE INTO lightning VALUES (?1, ?2, ?3, ?4)")?;
+                    for (time, lat, lon, magic_value) in rows {
+                        insert.execute(params![time, lat, lon, magic_value])?;
+                    }
+                }
+                transaction.commit()
+            })
+            .await;
+        if let Err(err) = result {
+            warn!("Failed to record lightning: {}", err);
+        }
+    }
+
+    /// Aggregates the nowcasts recorded for `location` between `from` and `to`
+    /// into buckets of `interval`, aligned to the Unix epoch. Empty buckets are left out.
+    pub async fn nowcast_series(
+        &self,
+        location: &Coordinates,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        interval: Duration,
+    ) -> anyhow::Result<Vec<NowcastBucket>> {
+        let (lat, lon) = (location.lat, location.lon);
+        let interval = interval.as_secs() as i64;
+        let buckets = self
+            .with_connection(move |connection| {
+                let mut query = connection.prepare(
+                    "SELECT time / ?1 * ?1 AS bucket, COUNT(*),
+                        MIN(air_temperature), MAX(air_temperature), AVG(air_temperature),
+                        MIN(relative_humidity), MAX(relative_humidity), AVG(relative_humidity),
+                        MIN(wind_speed), MAX(wind_speed), AVG(wind_speed),
+                        MIN(precipitation_rate), MAX(precipitation_rate), AVG(precipitation_rate)
+                    FROM nowcasts
+                    WHERE lat = ROUND(?2, 2) AND lon = ROUND(?3, 2) AND time >= ?4 AND time < ?5
+                    GROUP BY bucket
+                    ORDER BY bucket",
+                )?;
+                let rows = query.query_map(
+                    params![interval, lat, lon, from.timestamp(), to.timestamp()],
+                    |row| {
+                        let aggregate = |first: usize| -> rusqlite::Result<Aggregate> {
+                            Ok(Aggregate {
+                                min: row.get(first)?,
+                                max: row.get(first + 1)?,
+                                mean: row.get(first + 2)?,
+                            })
+                        };
+                        let start: i64 = row.get(0)?;
+                        Ok(NowcastBucket {
+                            start: DateTime::from_timestamp(start, 0).unwrap_or_default(),
+                            end: DateTime::from_timestamp(start + interval, 0).unwrap_or_default(),
+                            samples: row.get::<_, i64>(1)? as u64,
+                            air_temperature: aggregate(2)?,
+                            relative_humidity: aggregate(5)?,
+                            wind_speed: aggregate(8)?,
+                            precipitation_rate: aggregate(11).ok(),
+                        })
+                    },
+                )?;
+                rows.collect()
+            })
+            .await?;
+        Ok(buckets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use geo::Point;
+    use pretty_assertions::assert_eq;
+    use wictk_core::{MetNowcast, OpenWeatherNowcast};
+
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, hour, minute, 0).unwrap()
+    }
+
+    fn met(time: DateTime<Utc>, air_temperature: f32) -> Nowcast {
+        Nowcast::Met(MetNowcast {
+            time,
+            location: Coordinates::new(10.40, 63.43),
+            description: "cloudy".to_string(),
+            air_temperature,
+            relative_humidity: 80.0,
+            precipitation_rate: 0.5,
+            precipitation_amount: 0.0,
+            wind_speed: 2.0,
+            wind_speed_gust: 4.0,
+            wind_from_direction: 270.0,
+        })
+    }
+
+    fn open(time: DateTime<Utc>, temp: f32) -> Nowcast {
+        Nowcast::OpenWeather(OpenWeatherNowcast {
+            dt: time,
+            name: "Trondheim".to_string(),
+            country: "NO".to_string(),
+            lon: 10.39,
+            lat: 63.43,
+            main: "Clouds".to_string(),
+            desc: "overcast clouds".to_string(),
+            clouds: 99,
+            wind_speed: 4.0,
+            wind_deg: 270,
+            visibility: 10000,
+            temp,
+            feels_like: temp,
+            humidity: 90,
+            pressure: 1014,
+        })
+    }
+
+    fn count(history: &History, table: &str) -> i64 {
+        history
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    const TRONDHEIM: Coordinates = Coordinates {
+        lon: 10.3951,
+        lat: 63.4305,
+    };
+
+    #[tokio::test]
+    async fn aggregates_nowcasts_per_bucket() {
+        let history = History::in_memory().unwrap();
+        history
+            .record_nowcast(&TRONDHEIM, &met(at(10, 0), 10.0))
+            .await;
+        history
+            .record_nowcast(&TRONDHEIM, &met(at(10, 30), 14.0))
+            .await;
+        history
+            .record_nowcast(&TRONDHEIM, &open(at(10, 45), 12.0))
+            .await;
+        history
+            .record_nowcast(&TRONDHEIM, &open(at(11, 15), 16.0))
+            .await;
+
+        let buckets = history
+            .nowcast_series(&TRONDHEIM, at(0, 0), at(23, 0), Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            buckets,
+            vec![
+                NowcastBucket {
+                    start: at(10, 0),
+                    end: at(11, 0),
+                    samples: 3,
+                    air_temperature: Aggregate {
+                        min: 10.0,
+                        max: 14.0,
+                        mean: 12.0
+                    },
+                    relative_humidity: Aggregate {
+                        min: 80.0,
+                        max: 90.0,
+                        mean: 250.0 / 3.0
+                    },
+                    wind_speed: Aggregate {
+                        min: 2.0,
+                        max: 4.0,
+                        mean: 8.0 / 3.0
+                    },
+                    precipitation_rate: Some(Aggregate {
+                        min: 0.5,
+                        max: 0.5,
+                        mean: 0.5
+                    }),
+                },
+                NowcastBucket {
+                    start: at(11, 0),
+                    end: at(12, 0),
+                    samples: 1,
+                    air_temperature: Aggregate {
+                        min: 16.0,
+                        max: 16.0,
+                        mean: 16.0
+                    },
+                    relative_humidity: Aggregate {
+                        min: 90.0,
+                        max: 90.0,
+                        mean: 90.0
+                    },
+                    wind_speed: Aggregate {
+                        min: 4.0,
+                        max: 4.0,
+                        mean: 4.0
+                    },
+                    precipitation_rate: None,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn series_is_limited_to_location_and_range() {
+        let history = History::in_memory().unwrap();
+        history
+            .record_nowcast(&TRONDHEIM, &met(at(10, 0), 10.0))
+            .await;
+        history
+            .record_nowcast(&Coordinates::new(10.75, 59.91), &met(at(10, 0), 20.0))
+            .await;
+        history
+            .record_nowcast(&TRONDHEIM, &met(at(12, 0), 30.0))
+            .await;
+
+        let buckets = history
+            .nowcast_series(
+                &Coordinates::new(10.3958, 63.4312),
+                at(9, 0),
+                at(12, 0),
+                Duration::from_secs(86400),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].samples, 1);
+        assert_eq!(buckets[0].air_temperature.max, 10.0);
+    }
+
+    #[tokio::test]
+    async fn ignores_duplicates() {
+        let history = History::in_memory().unwrap();
+        let strike = Lightning::new(Point::new(10.4, 63.4), at(10, 0), 1);
+        let alert: Alert = serde_json::from_value(serde_json::json!({"Met": {
+            "title": "Gale warning",
+            "severity": "Yellow",
+            "description": "Strong wind",
+            "certainty": "Likely",
+            "event": "gale",
+            "duration": {"from": "2024-06-01T10:00:00Z", "until": "2024-06-01T18:00:00Z"},
+            "area": {"Single": []}
+        }}))
+        .unwrap();
+
+        for _ in 0..2 {
+            history
+                .record_nowcast(&TRONDHEIM, &met(at(10, 0), 10.0))
+                .await;
+            history
+                .record_lightning(std::slice::from_ref(&strike))
+                .await;
+            history.record_alerts(&[alert.clone(), Alert::Nve]).await;
+        }
+
+        assert_eq!(count(&history, "nowcasts"), 1);
+        assert_eq!(count(&history, "lightning"), 1);
+        assert_eq!(count(&history, "alerts"), 1);
+    }
+}
@@ -0,0 +1,141 @@
+//! Polls the lightning upstream once for all stream subscribers and
+//! broadcasts the strikes that were not seen in the previous poll.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use metrics::counter;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+use wictk_core::Lightning;
+
+use crate::{AppState, handlers::poll_lightning};
+
+/// Strikes a subscriber may fall behind by before it misses some
+const CAPACITY: usize = 1024;
+
+/// New lightning strikes, shared by the stream handlers.
+#[derive(Debug, Clone)]
+pub struct LightningFeed {
+    sender: broadcast::Sender<Lightning>,
+    /// Whether the poller runs. Only changed with the lock held, so a
+    /// subscriber arriving as the poller stops starts a new one.
+    polling: Arc<Mutex<bool>>,
+}
+
+impl Default for LightningFeed {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+            polling: Arc::new(Mutex::new(false)),
+        }
+    }
+}
+
+impl LightningFeed {
+    /// Receives the strikes found from now on, starting the poller if this
+    /// is the only subscriber.
+    pub fn subscribe(&self, app_state: &AppState) -> broadcast::Receiver<Lightning> {
+        let mut polling = self.polling.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        if !*polling {
+            *polling = true;
+            tokio::spawn(poll(app_state.clone(), self.clone()));
+        }
+        receiver
+    }
+}
+
+/// Polls until nobody is subscribed. The first poll only records what is
+/// already known, so subscribers are not sent the last 24 hours of strikes.
+async fn poll(app_state: AppState, feed: LightningFeed) {
+    debug!("Started polling lightning for the stream subscribers");
+    let mut seen: Option<HashSet<StrikeId>> = None;
+    loop {
+        match poll_lightning(&app_state).await {
+            Ok(strikes) => {
+                if let Some(seen) = &seen {
+                    let new = new_strikes(seen, &strikes);
+                    counter!("lightning_stream_strikes_total").increment(new.len() as u64);
+                    for strike in new {
+                        // Fails only when everyone unsubscribed, which is checked below
+                        let _ = feed.sender.send(strike);
+                    }
+                }
+                seen = Some(strikes.iter().map(StrikeId::of).collect());
+            }
+            Err(err) => warn!("Failed to poll lightning for the stream: {}", err),
+        }
+        tokio::time::sleep(app_state.config.streams().lightning_poll_interval()).await;
+
+        let mut polling = feed.polling.lock().unwrap();
+        if feed.sender.receiver_count() == 0 {
+            *polling = false;
+            debug!("Stopped polling lightning, nobody is subscribed");
+            return;
+        }
+    }
+}
+
+/// Identifies a strike, as strikes have no ID of their own
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct StrikeId {
+    time: DateTime<Utc>,
+    x: u64,
+    y: u64,
+    magic_value: u8,
+}
+
+impl StrikeId {
+    fn of(strike: &Lightning) -> Self {
+        Self {
+            time: strike.time,
+            x: strike.location.x().to_bits(),
+            y: strike.location.y().to_bits(),
+            magic_value: strike.magic_value,
+        }
+    }
+}
+
+/// The strikes not in `seen`, oldest first.
+fn new_strikes(seen: &HashSet<StrikeId>, strikes: &[Lightning]) -> Vec<Lightning> {
+    let mut new: Vec<Lightning> = strikes
+        .iter()
+        .filter(|strike| !seen.contains(&StrikeId::of(strike)))
+        .cloned()
+        .collect();
+    new.sort_by_key(|strike| strike.time);
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use geo::Point;
+
+    use super::*;
+
+    fn strike(minute: u32, magic_value: u8) -> Lightning {
+        Lightning::new(
+            Point::new(10.4, 63.4),
+            Utc.with_ymd_and_hms(2025, 1, 20, 11, minute, 0).unwrap(),
+            magic_value,
+        )
+    }
+
+    #[test]
+    fn finds_strikes_not_seen_before() {
+        let seen: HashSet<StrikeId> = [strike(0, 1), strike(5, 2)]
+            .iter()
+            .map(StrikeId::of)
+            .collect();
+
+        let new = new_strikes(&seen, &[strike(10, 4), strike(0, 1), strike(5, 3)]);
+
+        let new: Vec<(u8, _)> = new.iter().map(|s| (s.magic_value, s.time)).collect();
+        assert_eq!(new, vec![(3, strike(5, 3).time), (4, strike(10, 4).time)]);
+    }
+}
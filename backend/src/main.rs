@@ -1,94 +1,295 @@
+mod cache;
+mod config;
 pub mod handlers;
+mod health;
+mod history;
+mod lightning_feed;
+mod prefetch;
+mod quota;
+mod replay;
+mod topics;
+mod webhooks;
 
 use axum::serve;
 use anyhow::Context;
 use clap::Parser;
-use handlers::Alerts;
+use handlers::{Alerts, StationObservations};
 use metrics_exporter_prometheus::PrometheusBuilder;
-use moka::future::{Cache, CacheBuilder};
 use redact::Secret;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
-use wictk_core::{Lightning, Nowcast, OpenWeatherMapLocation};
+use tracing::{Level, error, info};
+use wictk_core::telemetry::{self, LogFormat};
+use wictk_core::{Endpoints, Lightning, Nowcast, OpenWeatherMapLocation, UpstreamClient};
 
+use crate::cache::{RedisCache, SwrCache};
+use crate::config::{Config, LogLevel, SharedConfig};
 use crate::handlers::setup_router;
-
-#[derive(Debug, Clone)]
-enum LogLevel {
-    Trace,
-    Debug,
-    Info,
-    Warn,
-    Error,
-}
-
-impl std::str::FromStr for LogLevel {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "trace" => Ok(LogLevel::Trace),
-            "debug" => Ok(LogLevel::Debug),
-            "info" => Ok(LogLevel::Info),
-            "warn" => Ok(LogLevel::Warn),
-            "error" => Ok(LogLevel::Error),
-            _ => Err("unknown log level".to_string()),
-        }
-    }
-}
+use crate::health::HealthChecker;
+use crate::history::History;
+use crate::lightning_feed::LightningFeed;
+use crate::prefetch::WatchList;
+use crate::quota::QuotaTracker;
+use crate::topics::Topics;
+use crate::webhooks::Webhooks;
 
 #[derive(Debug, Clone, Parser)]
 pub struct Opts {
-    #[arg(short, long, default_value = "0.0.0.0:3000")]
-    host: String,
+    /// TOML or YAML configuration file, see the README for the available settings
+    #[arg(long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
 
+    #[arg(short, long)]
+    host: Option<String>,
+
+    /// Required unless the OpenWeatherMap provider is disabled
     #[arg(short, long, env = "OPENWEATHERMAPAPIKEY")]
-    apikey: String,
+    apikey: Option<String>,
+
+    #[arg(short, long)]
+    log_level: Option<LogLevel>,
+
+    /// Log as text or as one JSON object per line
+    #[arg(long, env = "LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// OTLP/HTTP collector to export traces to, e.g. http://otel-collector:4318
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    #[arg(long, env = "FROST_CLIENT_ID")]
+    frost_client_id: Option<String>,
+
+    /// Base URL of the MET Norway API
+    #[arg(long, env = "MET_URL")]
+    met_url: Option<String>,
+
+    /// Base URL of the OpenWeatherMap API
+    #[arg(long, env = "OPENWEATHERMAP_URL")]
+    openweathermap_url: Option<String>,
+
+    /// Base URL of the yr.no API
+    #[arg(long, env = "YR_URL")]
+    yr_url: Option<String>,
+
+    /// Base URL of the MET Norway Frost API
+    #[arg(long, env = "FROST_URL")]
+    frost_url: Option<String>,
 
-    #[arg(short, long, default_value = "info")]
-    log_level: LogLevel,
+    /// Serve upstream responses recorded in this directory instead of calling the upstreams
+    #[arg(long, env = "REPLAY_DIR")]
+    replay_dir: Option<PathBuf>,
+
+    /// Forward requests missing from the replay directory upstream and record the responses
+    #[arg(long, env = "RECORD", requires = "replay_dir")]
+    record: bool,
+
+    /// Redis URL to use as a cache shared between replicas, e.g. redis://redis:6379
+    #[arg(long, env = "REDIS_URL")]
+    redis_url: Option<String>,
+
+    /// SQLite database to record fetched weather data in, enables the history endpoints
+    #[arg(long, env = "HISTORY_DB")]
+    history_db: Option<PathBuf>,
+
+    /// SQLite database to store the registered webhooks in, enables the webhook endpoints
+    #[arg(long, env = "WEBHOOKS_DB")]
+    webhooks_db: Option<PathBuf>,
+
+    /// Location to keep cached by refreshing it in the background, can be repeated
+    #[arg(long = "watch", env = "WATCH_LOCATIONS", value_delimiter = ';')]
+    watch_locations: Vec<String>,
+
+    /// Seconds between refreshes of the watched locations, below the cache TTL
+    #[arg(
+        long,
+        env = "PREFETCH_INTERVAL",
+        default_value_t = 240,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    prefetch_interval: u64,
+
+    /// Bearer token for the admin API, which is disabled when unset
+    #[arg(long, env = "ADMIN_TOKEN")]
+    admin_token: Option<String>,
 }
 
-impl From<LogLevel> for Level {
-    fn from(log_level: LogLevel) -> Self {
-        match log_level {
-            LogLevel::Trace => Level::TRACE,
-            LogLevel::Debug => Level::DEBUG,
-            LogLevel::Info => Level::INFO,
-            LogLevel::Warn => Level::WARN,
-            LogLevel::Error => Level::ERROR,
+impl Opts {
+    /// Loads the configuration file and `WICTK_` variables, overridden by the flags.
+    fn config(&self) -> anyhow::Result<Config> {
+        let mut config = Config::load(self.config.as_deref())?;
+        if let Some(host) = &self.host {
+            config.host = host.clone();
+        }
+        if let Some(log_level) = self.log_level {
+            config.log_level = log_level;
         }
+        if let Some(log_format) = self.log_format {
+            config.telemetry.log_format = log_format;
+        }
+        if let Some(otlp_endpoint) = &self.otlp_endpoint {
+            config.telemetry.otlp_endpoint = Some(otlp_endpoint.clone());
+        }
+        let urls = &mut config.upstreams.urls;
+        for (url, flag) in [
+            (&mut urls.met, &self.met_url),
+            (&mut urls.openweathermap, &self.openweathermap_url),
+            (&mut urls.yr, &self.yr_url),
+            (&mut urls.frost, &self.frost_url),
+        ] {
+            if let Some(flag) = flag {
+                *url = flag.clone();
+            }
+            *url = url.trim_end_matches('/').to_string();
+        }
+        if config.providers.openweathermap && self.apikey.is_none() {
+            anyhow::bail!(
+                "An OpenWeatherMap API key is required, set OPENWEATHERMAPAPIKEY or disable providers.openweathermap"
+            );
+        }
+        config.validate()?;
+        Ok(config)
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub openweathermap_apikey: Secret<String>,
-    pub client: reqwest::Client,
-    pub alert_cache: Cache<String, Alerts>,
-    pub location_cache: Cache<String, OpenWeatherMapLocation>,
-    pub nowcast_cache: Cache<String, Nowcast>,
-    pub lightning_cache: Cache<String, Vec<Lightning>>,
+    pub frost_client_id: Option<Secret<String>>,
+    pub client: UpstreamClient,
+    pub endpoints: Endpoints,
+    pub config: SharedConfig,
+    pub alert_cache: SwrCache<Alerts>,
+    pub location_cache: SwrCache<OpenWeatherMapLocation>,
+    pub nowcast_cache: SwrCache<Nowcast>,
+    pub lightning_cache: SwrCache<Vec<Lightning>>,
+    pub observation_cache: SwrCache<StationObservations>,
+    pub history: Option<History>,
+    pub redis: Option<RedisCache>,
+    pub health: HealthChecker,
+    pub quota: QuotaTracker,
+    pub watch_list: WatchList,
+    pub lightning_feed: LightningFeed,
+    pub topics: Topics,
+    pub webhooks: Option<Webhooks>,
+    pub admin_token: Option<Secret<String>>,
 }
 
 impl AppState {
-    pub fn new(client: reqwest::Client, apikey: String) -> Self {
+    pub fn new(
+        client: reqwest::Client,
+        apikey: String,
+        frost_client_id: Option<String>,
+        endpoints: Endpoints,
+    ) -> Self {
+        Self::from_config(
+            client,
+            apikey,
+            frost_client_id,
+            endpoints,
+            Config::default(),
+        )
+    }
+
+    /// Creates the state with caches sized and upstream requests retried as in `config`.
+    pub fn from_config(
+        client: reqwest::Client,
+        apikey: String,
+        frost_client_id: Option<String>,
+        endpoints: Endpoints,
+        config: Config,
+    ) -> Self {
+        let caches = &config.caches;
+        let shared = SharedConfig::new(config.clone());
+        let quota = QuotaTracker::new(shared.clone());
         Self {
             openweathermap_apikey: Secret::new(apikey),
-            client,
-            alert_cache: CacheBuilder::new(1)
-                .time_to_live(std::time::Duration::from_secs(60 * 5))
-                .build(),
-            location_cache: CacheBuilder::new(20)
-                .time_to_live(std::time::Duration::from_secs(60 * 5))
-                .build(),
-            nowcast_cache: CacheBuilder::new(20)
-                .time_to_live(std::time::Duration::from_secs(60 * 5))
-                .build(),
-            lightning_cache: CacheBuilder::new(1)
-                .time_to_live(std::time::Duration::from_secs(60 * 5))
-                .build(),
+            frost_client_id: frost_client_id.map(Secret::new),
+            client: config
+                .upstreams
+                .client(client)
+                .with_guard(Arc::new(quota.clone())),
+            endpoints,
+            alert_cache: SwrCache::new(
+                "alert",
+                caches.alert.capacity,
+                caches.alert.ttl(),
+                caches.alert.max_staleness(),
+            ),
+            location_cache: SwrCache::new(
+                "location",
+                caches.location.capacity,
+                caches.location.ttl(),
+                caches.location.max_staleness(),
+            ),
+            nowcast_cache: SwrCache::new(
+                "nowcast",
+                caches.nowcast.capacity,
+                caches.nowcast.ttl(),
+                caches.nowcast.max_staleness(),
+            ),
+            lightning_cache: SwrCache::new(
+                "lightning",
+                caches.lightning.capacity,
+                caches.lightning.ttl(),
+                caches.lightning.max_staleness(),
+            ),
+            observation_cache: SwrCache::new(
+                "observation",
+                caches.observation.capacity,
+                caches.observation.ttl(),
+                caches.observation.max_staleness(),
+            ),
+            config: shared,
+            history: None,
+            redis: None,
+            health: HealthChecker::default(),
+            quota,
+            watch_list: WatchList::default(),
+            lightning_feed: LightningFeed::default(),
+            topics: Topics::default(),
+            webhooks: None,
+            admin_token: None,
+        }
+    }
+
+    /// Uses `redis` as a second level behind the in-memory caches.
+    pub fn with_redis(self, redis: &RedisCache) -> Self {
+        Self {
+            alert_cache: self.alert_cache.with_redis(redis, "alert"),
+            location_cache: self.location_cache.with_redis(redis, "location"),
+            nowcast_cache: self.nowcast_cache.with_redis(redis, "nowcast"),
+            lightning_cache: self.lightning_cache.with_redis(redis, "lightning"),
+            observation_cache: self.observation_cache.with_redis(redis, "observation"),
+            redis: Some(redis.clone()),
+            ..self
+        }
+    }
+
+    /// Enables the admin API for requests with `token` as bearer token.
+    pub fn with_admin_token(self, token: String) -> Self {
+        Self {
+            admin_token: Some(Secret::new(token)),
+            ..self
+        }
+    }
+
+    /// Records everything fetched from the upstreams in `history`.
+    pub fn with_history(self, history: History) -> Self {
+        Self {
+            history: Some(history),
+            ..self
+        }
+    }
+
+    /// Enables the webhook endpoints, storing the webhooks in `webhooks`.
+    pub fn with_webhooks(self, webhooks: Webhooks) -> Self {
+        Self {
+            webhooks: Some(webhooks),
+            ..self
         }
     }
 }
@@ -96,12 +297,16 @@ impl AppState {
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let opts = Opts::parse();
+    let config = opts.config()?;
 
-    let level: Level = opts.log_level.into();
+    let level: Level = config.log_level.into();
 
-    let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
-
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let _telemetry = telemetry::init(
+        env!("CARGO_PKG_NAME"),
+        level,
+        config.telemetry.log_format,
+        config.telemetry.otlp_endpoint.as_deref(),
+    )?;
     let metrics_handler = PrometheusBuilder::new()
         .install_recorder()
         .expect("failed to install recorder/exporter");
@@ -114,19 +319,80 @@ async fn main() -> Result<(), anyhow::Error> {
         " ",
         env!("CARGO_PKG_HOMEPAGE"),
     );
-    let client = client_builder.user_agent(APP_USER_AGENT).build().unwrap();
+    let client = client_builder
+        .user_agent(APP_USER_AGENT)
+        .timeout(Duration::from_secs(config.upstreams.timeout_secs))
+        .connect_timeout(Duration::from_secs(config.upstreams.connect_timeout_secs))
+        .build()
+        .unwrap();
 
-    let app_state = AppState::new(client, opts.apikey);
+    let endpoints = match opts.replay_dir.clone() {
+        Some(replay_dir) => {
+            let urls = config.upstreams.urls.clone();
+            replay::start(replay_dir, opts.record, urls, client.clone()).await?
+        }
+        None => config.upstreams.urls.clone(),
+    };
+    let host = config.host.clone();
+    let mut app_state = AppState::from_config(
+        client,
+        opts.apikey.clone().unwrap_or_default(),
+        opts.frost_client_id.clone(),
+        endpoints,
+        config,
+    );
+    if let Some(redis_url) = &opts.redis_url {
+        let redis = RedisCache::connect(redis_url).await?;
+        info!("Using Redis as shared cache");
+        app_state = app_state.with_redis(&redis);
+    }
+    if let Some(history_db) = &opts.history_db {
+        let history = History::open(history_db).with_context(|| {
+            format!("Failed to open history database {}", history_db.display())
+        })?;
+        info!("Recording history in {}", history_db.display());
+        app_state = app_state.with_history(history);
+    }
+    if let Some(webhooks_db) = &opts.webhooks_db {
+        let webhooks = Webhooks::open(webhooks_db, app_state.config.clone()).with_context(|| {
+            format!("Failed to open webhook database {}", webhooks_db.display())
+        })?;
+        app_state = app_state.with_webhooks(webhooks);
+    }
+
+    if let Some(admin_token) = opts.admin_token.clone() {
+        app_state = app_state.with_admin_token(admin_token);
+    }
+    app_state.watch_list = WatchList::new(opts.watch_locations.clone());
+    tokio::spawn(prefetch::run(
+        app_state.clone(),
+        Duration::from_secs(opts.prefetch_interval),
+    ));
+    if let Some(webhooks) = &app_state.webhooks {
+        webhooks.start(&app_state).await?;
+    }
+
+    #[cfg(unix)]
+    {
+        let shared = app_state.config.clone();
+        tokio::spawn(async move {
+            if let Err(err) = config::reload_on_sighup(shared, || opts.config()).await {
+                error!("Configuration reloading stopped: {:#}", err);
+            }
+        });
+    }
 
     let app = setup_router(app_state, metrics_handler);
 
-    let host = opts.host;
     let listener = TcpListener::bind(&host)
         .await
         .with_context(|| format!("Failed to bind TCP listener on {host}"))?;
-    serve(listener, app)
-        .await
-        .with_context(|| format!("HTTP server failed while serving on {host}"))?;
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .with_context(|| format!("HTTP server failed while serving on {host}"))?;
 
     Ok(())
 }
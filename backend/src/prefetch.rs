@@ -0,0 +1,226 @@
+//! Keeps the caches warm for a watch list of locations, so requests for them
+//! are served from the cache instead of waiting for the upstreams.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use metrics::{counter, gauge};
+use tokio::{task::JoinSet, time::MissedTickBehavior};
+use tracing::{debug, warn};
+use wictk_core::{City, WictkError};
+
+use crate::{
+    AppState,
+    handlers::{
+        LocationQuery, find_location, prefetch_alerts, prefetch_lightning, prefetch_met_nowcast,
+        prefetch_openweathermap_nowcast,
+    },
+};
+
+/// Location names to keep cached, shared between the prefetcher and the admin API.
+#[derive(Debug, Clone, Default)]
+pub struct WatchList {
+    locations: Arc<RwLock<BTreeSet<String>>>,
+}
+
+impl WatchList {
+    pub fn new(locations: impl IntoIterator<Item = String>) -> Self {
+        let watch_list = Self::default();
+        for location in locations {
+            watch_list.add(&location);
+        }
+        watch_list
+    }
+
+    /// Adds `location`, returning false if it was already watched or is empty.
+    pub fn add(&self, location: &str) -> bool {
+        let location = location.trim();
+        if location.is_empty() {
+            return false;
+        }
+        let mut locations = self.locations.write().unwrap();
+        let added = locations.insert(location.to_string());
+        gauge!("prefetch_watched_locations").set(locations.len() as f64);
+        added
+    }
+
+    /// Removes `location`, returning false if it was not watched.
+    pub fn remove(&self, location: &str) -> bool {
+        let mut locations = self.locations.write().unwrap();
+        let removed = locations.remove(location.trim());
+        gauge!("prefetch_watched_locations").set(locations.len() as f64);
+        removed
+    }
+
+    pub fn locations(&self) -> Vec<String> {
+        self.locations.read().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Refreshes the watched locations every `interval`, which should be shorter
+/// than the cache TTL so entries are replaced before they go stale.
+pub async fn run(app_state: AppState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        refresh(&app_state).await;
+    }
+}
+
+/// Refreshes alerts and lightning, and the nowcasts of every watched location.
+pub async fn refresh(app_state: &AppState) {
+    let locations = app_state.watch_list.locations();
+    if locations.is_empty() {
+        return;
+    }
+    debug!("Prefetching {} watched locations", locations.len());
+
+    let mut refreshes = JoinSet::new();
+    let state = app_state.clone();
+    refreshes.spawn(async move { record("alerts", prefetch_alerts(&state).await) });
+    let state = app_state.clone();
+    refreshes.spawn(async move { record("lightning", prefetch_lightning(&state).await) });
+    for location in locations {
+        let state = app_state.clone();
+        refreshes.spawn(async move { refresh_location(&state, location).await });
+    }
+    refreshes.join_all().await;
+}
+
+async fn refresh_location(app_state: &AppState, location: String) {
+    let coordinates = match find_location(
+        LocationQuery::Location(City {
+            location: location.clone(),
+        }),
+        app_state,
+    )
+    .await
+    {
+        Ok(coordinates) => coordinates,
+        Err(err) => {
+            record("location", Err(err));
+            return;
+        }
+    };
+    let (met, openweathermap) = tokio::join!(
+        prefetch_met_nowcast(app_state, &coordinates),
+        prefetch_openweathermap_nowcast(app_state, &coordinates)
+    );
+    record("met_nowcast", met);
+    record("openweathermap_nowcast", openweathermap);
+}
+
+fn record(kind: &'static str, result: Result<(), WictkError>) {
+    let outcome = match &result {
+        Ok(()) => "success",
+        Err(err) => {
+            warn!("Failed to prefetch {}: {}", kind, err);
+            "failure"
+        }
+    };
+    counter!("prefetch_refreshes_total", "kind" => kind, "outcome" => outcome).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use wictk_core::Endpoints;
+
+    use super::*;
+    use crate::cache::CacheStatus;
+
+    const MET_NOWCAST: &str = r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[10.4034,63.4308,0]},"properties":{"meta":{"updated_at":"2023-08-14T18:16:07Z"},"timeseries":[{"time":"2023-08-14T18:15:00Z","data":{"instant":{"details":{"air_temperature":17.7,"relative_humidity":80.5,"wind_from_direction":294.4,"wind_speed":2.7,"wind_speed_of_gust":6.1}},"next_1_hours":{"summary":{"symbol_code":"cloudy"},"details":{"precipitation_amount":0.0}}}}]}}"#;
+
+    const OPENWEATHER_NOWCAST: &str = r#"{"coord":{"lon":10.3951,"lat":63.4305},"weather":[{"main":"Clouds","description":"overcast clouds"}],"main":{"temp":15.21,"feels_like":15.19,"pressure":1014,"humidity":92},"visibility":10000,"wind":{"speed":0.89,"deg":270},"clouds":{"all":99},"dt":1692185222,"sys":{"country":"NO"},"name":"Trondheim"}"#;
+
+    const GEOCODING: &str = r#"[{"name":"Trondheim","lat":63.4305,"lon":10.3951,"country":"NO"}]"#;
+
+    #[test]
+    fn watch_list_add_and_remove() {
+        let watch_list = WatchList::new(["Oslo".to_string(), " Bergen ".to_string()]);
+
+        assert!(!watch_list.add("Oslo"));
+        assert!(watch_list.add("Trondheim"));
+        assert!(watch_list.remove("Bergen"));
+        assert!(!watch_list.remove("Bergen"));
+        assert_eq!(watch_list.locations(), vec!["Oslo", "Trondheim"]);
+    }
+
+    #[test]
+    fn watch_list_skips_empty_names() {
+        let watch_list = WatchList::new(["Oslo", "", " "].map(String::from));
+
+        assert!(!watch_list.add("  "));
+        assert_eq!(watch_list.locations(), vec!["Oslo"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_warms_caches_for_watched_locations() {
+        let mut server = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for (path, body) in [
+            ("/geo/1.0/direct", GEOCODING),
+            ("/weatherapi/nowcast/2.0/complete", MET_NOWCAST),
+            ("/data/2.5/weather", OPENWEATHER_NOWCAST),
+            (
+                "/weatherapi/metalerts/2.0/current.json",
+                r#"{"features":[]}"#,
+            ),
+            ("/api/v0/lightning-events", r#"{"historicalData":"[]"}"#),
+        ] {
+            mocks.push(
+                server
+                    .mock("GET", path)
+                    .match_query(mockito::Matcher::Any)
+                    .with_status(200)
+                    .with_body(body)
+                    .create_async()
+                    .await,
+            );
+        }
+        let mut app_state = AppState::new(
+            reqwest::Client::new(),
+            "test_api_key".to_string(),
+            None,
+            Endpoints::with_base_url(&server.url()),
+        );
+        app_state.watch_list = WatchList::new(["Trondheim".to_string()]);
+
+        refresh(&app_state).await;
+
+        for mock in &mocks {
+            mock.assert_async().await;
+        }
+        let (_, info) = app_state
+            .nowcast_cache
+            .get_or_fetch("met_10.3951,63.4305", async {
+                Err(WictkError::not_found("not prefetched"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(info.status, CacheStatus::Hit);
+    }
+
+    #[tokio::test]
+    async fn refresh_without_watched_locations_does_nothing() {
+        let app_state = AppState::new(
+            reqwest::Client::new(),
+            "test_api_key".to_string(),
+            None,
+            Endpoints::with_base_url("http://127.0.0.1:1"),
+        );
+
+        refresh(&app_state).await;
+
+        let (_, info) = app_state
+            .alert_cache
+            .get_or_fetch("met_alerts", async { Ok(Vec::new()) })
+            .await
+            .unwrap();
+        assert_eq!(info.status, CacheStatus::Miss);
+    }
+}
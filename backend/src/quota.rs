@@ -0,0 +1,317 @@
+//! Counts the calls made to each upstream over rolling windows, so paid
+//! upstreams are only used from the cache once their quota is nearly used up.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use metrics::{counter, gauge};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use wictk_core::{CallGuard, Upstream, WictkError};
+
+use crate::config::{QuotaConfig, SharedConfig};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Call counts in buckets of `bucket` length, kept for `span`
+#[derive(Debug)]
+struct RollingWindow {
+    bucket: Duration,
+    span: Duration,
+    /// Start and count of each bucket, oldest first
+    counts: VecDeque<(Instant, u64)>,
+}
+
+impl RollingWindow {
+    fn new(bucket: Duration, span: Duration) -> Self {
+        Self {
+            bucket,
+            span,
+            counts: VecDeque::new(),
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((start, _)) = self.counts.front() {
+            if *start + self.span > now {
+                break;
+            }
+            self.counts.pop_front();
+        }
+    }
+
+    fn add(&mut self, now: Instant) {
+        self.expire(now);
+        match self.counts.back_mut() {
+            Some((start, count)) if now < *start + self.bucket => *count += 1,
+            _ => self.counts.push_back((now, 1)),
+        }
+    }
+
+    fn total(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        self.counts.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Time until the oldest bucket leaves the window
+    fn next_expiry(&self, now: Instant) -> Duration {
+        self.counts
+            .front()
+            .map(|(start, _)| (*start + self.span).saturating_duration_since(now))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+struct Usage {
+    minute: RollingWindow,
+    day: RollingWindow,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Self {
+            minute: RollingWindow::new(Duration::from_secs(1), MINUTE),
+            day: RollingWindow::new(MINUTE, DAY),
+        }
+    }
+}
+
+/// Calls made to an upstream and its configured limits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct QuotaUsage {
+    pub calls_last_minute: u64,
+    pub calls_last_day: u64,
+    pub limit_per_minute: Option<u64>,
+    pub limit_per_day: Option<u64>,
+    /// Whether the upstream is only used from the cache until usage drops
+    pub degraded: bool,
+}
+
+/// Tracks the calls to every upstream. Cheap to clone, clones share the counts.
+#[derive(Debug, Clone)]
+pub struct QuotaTracker {
+    config: SharedConfig,
+    usage: Arc<Mutex<HashMap<Upstream, Usage>>>,
+}
+
+impl QuotaTracker {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            config,
+            usage: Arc::default(),
+        }
+    }
+
+    /// Counts a call of kind `call`, e.g. `geocoding` or `weather`, unless
+    /// `upstream` is close to one of its limits. It then fails with
+    /// [`WictkError::UpstreamRateLimited`] and the call is not counted.
+    pub fn try_record(&self, upstream: Upstream, call: &'static str) -> Result<(), WictkError> {
+        let quota = self.config.quota(upstream).unwrap_or_default();
+        let now = Instant::now();
+        // Checked and counted under one lock, so concurrent calls cannot
+        // all pass the check before any of them is counted
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(upstream).or_default();
+        let (measured, recovers_in) = measure(&quota, usage, now);
+        gauge!("upstream_quota_degraded", "upstream" => upstream.name())
+            .set(if measured.degraded { 1.0 } else { 0.0 });
+        if let Some(wait) = recovers_in {
+            return Err(WictkError::UpstreamRateLimited {
+                upstream,
+                retry_after: Some(wait.as_secs().max(1)),
+            });
+        }
+        counter!("upstream_calls_total", "upstream" => upstream.name(), "call" => call)
+            .increment(1);
+        usage.minute.add(now);
+        usage.day.add(now);
+        gauge!("upstream_calls_last_minute", "upstream" => upstream.name())
+            .set(usage.minute.total(now) as f64);
+        gauge!("upstream_calls_last_day", "upstream" => upstream.name())
+            .set(usage.day.total(now) as f64);
+        Ok(())
+    }
+
+    pub fn usage(&self, upstream: Upstream) -> QuotaUsage {
+        let quota = self.config.quota(upstream).unwrap_or_default();
+        let mut usage = self.usage.lock().unwrap();
+        measure(&quota, usage.entry(upstream).or_default(), Instant::now()).0
+    }
+}
+
+/// Usage of an upstream with `quota`, and when a degraded upstream may be called again.
+fn measure(quota: &QuotaConfig, usage: &mut Usage, now: Instant) -> (QuotaUsage, Option<Duration>) {
+    let calls_last_minute = usage.minute.total(now);
+    let calls_last_day = usage.day.total(now);
+    let near = |calls: u64, limit: Option<u64>| {
+        limit.is_some_and(|limit| calls as f64 >= limit as f64 * quota.degrade_at)
+    };
+    let minute_near = near(calls_last_minute, quota.per_minute);
+    let day_near = near(calls_last_day, quota.per_day);
+    let recovers_in = match (minute_near, day_near) {
+        (_, true) => Some(usage.day.next_expiry(now)),
+        (true, false) => Some(usage.minute.next_expiry(now)),
+        (false, false) => None,
+    };
+    let usage = QuotaUsage {
+        calls_last_minute,
+        calls_last_day,
+        limit_per_minute: quota.per_minute,
+        limit_per_day: quota.per_day,
+        degraded: minute_near || day_near,
+    };
+    (usage, recovers_in)
+}
+
+/// Names the kind of call for the `call` label of `upstream_calls_total`.
+fn call_kind(upstream: Upstream, url: &Url) -> &'static str {
+    let path = url.path();
+    match upstream {
+        Upstream::OpenWeatherMap if path.starts_with("/geo/") => "geocoding",
+        Upstream::OpenWeatherMap => "weather",
+        Upstream::Met if path.contains("metalerts") => "alerts",
+        Upstream::Met => "nowcast",
+        Upstream::Yr => "lightning",
+        Upstream::Frost if path.starts_with("/sources/") => "stations",
+        Upstream::Frost => "observations",
+    }
+}
+
+/// Stops calls to an upstream close to its limits, so requests are answered
+/// from the cache or fail with `429 Too Many Requests`.
+impl CallGuard for QuotaTracker {
+    fn admit(&self, upstream: Upstream, url: &Url) -> Result<(), WictkError> {
+        self.try_record(upstream, call_kind(upstream, url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    use super::*;
+
+    fn tracker(quota: QuotaConfig) -> QuotaTracker {
+        let mut config = Config::default();
+        config.quotas.insert("openweathermap".to_string(), quota);
+        QuotaTracker::new(SharedConfig::new(config))
+    }
+
+    #[test]
+    fn rolling_window_forgets_old_calls() {
+        let mut window = RollingWindow::new(Duration::from_secs(1), MINUTE);
+        let start = Instant::now();
+        window.add(start);
+        window.add(start + Duration::from_millis(500));
+        window.add(start + Duration::from_secs(30));
+
+        assert_eq!(window.counts.len(), 2);
+        assert_eq!(window.total(start + Duration::from_secs(59)), 3);
+        assert_eq!(
+            window.next_expiry(start + Duration::from_secs(59)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(window.total(start + Duration::from_secs(60)), 1);
+        assert_eq!(window.total(start + Duration::from_secs(90)), 0);
+    }
+
+    #[test]
+    fn degrades_near_the_limit() {
+        let quota = tracker(QuotaConfig {
+            per_minute: Some(10),
+            per_day: Some(100),
+            degrade_at: 0.5,
+        });
+
+        for _ in 0..4 {
+            assert!(
+                quota
+                    .try_record(Upstream::OpenWeatherMap, "weather")
+                    .is_ok()
+            );
+        }
+        assert!(
+            quota
+                .try_record(Upstream::OpenWeatherMap, "geocoding")
+                .is_ok()
+        );
+
+        let err = quota
+            .try_record(Upstream::OpenWeatherMap, "weather")
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WictkError::UpstreamRateLimited {
+                upstream: Upstream::OpenWeatherMap,
+                retry_after: Some(59 | 60),
+            }
+        ));
+        assert_eq!(
+            quota.usage(Upstream::OpenWeatherMap),
+            QuotaUsage {
+                calls_last_minute: 5,
+                calls_last_day: 5,
+                limit_per_minute: Some(10),
+                limit_per_day: Some(100),
+                degraded: true,
+            }
+        );
+        // Upstreams without a quota are only counted
+        assert!(quota.try_record(Upstream::Met, "nowcast").is_ok());
+    }
+
+    #[test]
+    fn concurrent_calls_do_not_exceed_the_limit() {
+        let quota = tracker(QuotaConfig {
+            per_minute: Some(10),
+            per_day: None,
+            degrade_at: 1.0,
+        });
+
+        let admitted = std::thread::scope(|scope| {
+            let calls: Vec<_> = (0..50)
+                .map(|_| scope.spawn(|| quota.try_record(Upstream::OpenWeatherMap, "weather")))
+                .collect();
+            calls
+                .into_iter()
+                .filter_map(|call| call.join().unwrap().ok())
+                .count()
+        });
+
+        assert_eq!(admitted, 10);
+        assert_eq!(quota.usage(Upstream::OpenWeatherMap).calls_last_minute, 10);
+    }
+
+    #[test]
+    fn names_calls() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        assert_eq!(
+            call_kind(
+                Upstream::OpenWeatherMap,
+                &url("https://api.openweathermap.org/geo/1.0/direct?q=Oslo")
+            ),
+            "geocoding"
+        );
+        assert_eq!(
+            call_kind(
+                Upstream::OpenWeatherMap,
+                &url("https://api.openweathermap.org/data/2.5/weather")
+            ),
+            "weather"
+        );
+        assert_eq!(
+            call_kind(
+                Upstream::Met,
+                &url("https://api.met.no/weatherapi/metalerts/2.0/current.json")
+            ),
+            "alerts"
+        );
+    }
+}
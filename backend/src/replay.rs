@@ -0,0 +1,271 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+    routing::any,
+};
+use tokio::{net::TcpListener, sync::Mutex};
+use tracing::{error, info, warn};
+use wictk_core::{
+    Endpoints, Upstream,
+    replay::{Cassette, Interaction, normalize_query},
+};
+
+/// Serves recorded upstream responses from cassettes in a directory.
+///
+/// When recording, requests without a recorded response are forwarded to the
+/// real upstream and the response is added to the cassette.
+#[derive(Clone)]
+struct ReplayState {
+    record: bool,
+    upstreams: Endpoints,
+    client: reqwest::Client,
+    cassettes: Arc<Mutex<HashMap<Upstream, Cassette>>>,
+}
+
+/// Starts a replay server on a local port and returns endpoints pointing at it.
+pub async fn start(
+    dir: PathBuf,
+    record: bool,
+    upstreams: Endpoints,
+    client: reqwest::Client,
+) -> anyhow::Result<Endpoints> {
+    let cassettes = Upstream::ALL
+        .into_iter()
+        .map(|upstream| {
+            Cassette::load(&dir, upstream)
+                .map(|cassette| (upstream, cassette))
+                .with_context(|| {
+                    format!(
+                        "Failed to load {} cassette from {}",
+                        upstream.name(),
+                        dir.display()
+                    )
+                })
+        })
+        .collect::<anyhow::Result<HashMap<Upstream, Cassette>>>()?;
+
+    let state = ReplayState {
+        record,
+        upstreams,
+        client,
+        cassettes: Arc::new(Mutex::new(cassettes)),
+    };
+    let app = Router::new()
+        .route("/{upstream}/{*path}", any(replay))
+        .with_state(state);
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("Failed to bind replay server")?;
+    let addr = listener
+        .local_addr()
+        .context("Failed to get replay server address")?;
+    tokio::spawn(async move {
+        if let Err(err) = axum::serve(listener, app).await {
+            error!("Replay server failed: {}", err);
+        }
+    });
+
+    info!(
+        "Replaying upstream responses from {} on {} (recording: {})",
+        dir.display(),
+        addr,
+        record
+    );
+    Ok(Endpoints::with_upstream_prefixes(&format!("http://{addr}")))
+}
+
+async fn replay(
+    State(state): State<ReplayState>,
+    Path((upstream, path)): Path<(String, String)>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let Some(upstream) = Upstream::from_name(&upstream) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Unknown upstream {upstream}"),
+        )
+            .into_response();
+    };
+    let path = format!("/{path}");
+    let raw_query = uri.query().unwrap_or_default();
+    let query = normalize_query(raw_query);
+
+    let mut cassettes = state.cassettes.lock().await;
+    let Some(cassette) = cassettes.get_mut(&upstream) else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    if let Some(interaction) = cassette.find(method.as_str(), &path, &query) {
+        return interaction_response(interaction);
+    }
+
+    if !state.record {
+        warn!(
+            "No recorded {} response for {} {}?{}",
+            upstream.name(),
+            method,
+            path,
+            raw_query
+        );
+        return (
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "No recorded {} response for {} {}",
+                upstream.name(),
+                method,
+                path
+            ),
+        )
+            .into_response();
+    }
+
+    let interaction = match forward(&state, upstream, &method, &path, raw_query, &headers).await {
+        Ok(interaction) => interaction,
+        Err(err) => {
+            error!("Failed to record {} response: {:?}", upstream.name(), err);
+            return (StatusCode::BAD_GATEWAY, err.to_string()).into_response();
+        }
+    };
+    let response = interaction_response(&interaction);
+    cassette.record(interaction);
+    if let Err(err) = cassette.save() {
+        error!("Failed to save {} cassette: {}", upstream.name(), err);
+    }
+    response
+}
+
+async fn forward(
+    state: &ReplayState,
+    upstream: Upstream,
+    method: &Method,
+    path: &str,
+    raw_query: &str,
+    headers: &HeaderMap,
+) -> anyhow::Result<Interaction> {
+    let mut url = format!("{}{}", upstream.base_url(&state.upstreams), path);
+    if !raw_query.is_empty() {
+        url = format!("{url}?{raw_query}");
+    }
+    let mut request = state.client.request(method.clone(), &url);
+    if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+        request = request.header(header::AUTHORIZATION, authorization);
+    }
+    let response = request
+        .send()
+        .await
+        .with_context(|| format!("Request to {} failed", upstream.name()))?;
+    let status = response.status().as_u16();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+    let body = response
+        .text()
+        .await
+        .with_context(|| format!("Failed to read response from {}", upstream.name()))?;
+    Ok(Interaction {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: normalize_query(raw_query),
+        status,
+        body: Interaction::body_from_text(content_type.as_deref(), &body),
+        content_type,
+    })
+}
+
+fn interaction_response(interaction: &Interaction) -> Response {
+    let status = StatusCode::from_u16(interaction.status).unwrap_or(StatusCode::OK);
+    let mut response = Response::builder().status(status);
+    if let Some(content_type) = &interaction.content_type {
+        response = response.header(header::CONTENT_TYPE, content_type);
+    }
+    response
+        .body(Body::from(interaction.body_text()))
+        .unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("wictk_{name}_{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_response() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/replay");
+        let client = reqwest::Client::new();
+        let endpoints = start(dir, false, Endpoints::default(), client.clone())
+            .await
+            .unwrap();
+
+        let response = client
+            .get(format!(
+                "{}/weatherapi/metalerts/2.0/current.json",
+                endpoints.met
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = client
+            .get(format!("{}/not/recorded", endpoints.met))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn records_missing_responses() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"name":"Trondheim"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let dir = temp_dir("record");
+        let client = reqwest::Client::new();
+        let endpoints = start(
+            dir.clone(),
+            true,
+            Endpoints::with_base_url(&server.url()),
+            client.clone(),
+        )
+        .await
+        .unwrap();
+
+        let url = format!(
+            "{}/data/2.5/weather?lat=63.4&lon=10.4&appid=secret",
+            endpoints.openweathermap
+        );
+        for _ in 0..2 {
+            let body = client.get(&url).send().await.unwrap().text().await.unwrap();
+            assert_eq!(body, r#"{"name":"Trondheim"}"#);
+        }
+        mock.assert_async().await;
+
+        let recorded = std::fs::read_to_string(dir.join("openweathermap.json")).unwrap();
+        assert!(recorded.contains("Trondheim"));
+        assert!(!recorded.contains("secret"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+//! Alerts and nowcasts that WebSocket clients subscribe to. Each topic is
+//! polled once however many clients subscribe to it, and its subscribers
+//! only see the latest data, so slow clients skip changes instead of piling
+//! them up. Coordinates are rounded to [`DECIMALS`] decimals, so nearby
+//! subscribers share a topic.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::Value;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+use wictk_core::Coordinates;
+
+use crate::{
+    AppState,
+    handlers::{alerts_at, combined_nowcasts},
+};
+
+/// Decimals of the coordinates of a topic, about a kilometer apart
+const DECIMALS: i32 = 2;
+
+/// The kind of data of a topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopicKind {
+    Alerts,
+    Nowcast,
+}
+
+/// Data for a location
+#[derive(Debug, Clone)]
+pub struct Topic {
+    pub kind: TopicKind,
+    pub location: Coordinates,
+}
+
+/// The kind and coordinates of a topic as bits, as floats cannot be map keys
+type TopicKey = (TopicKind, u32, u32);
+
+impl Topic {
+    /// The topic at the coordinates rounded to [`DECIMALS`] decimals
+    fn rounded(self) -> Self {
+        let scale = 10f32.powi(DECIMALS);
+        let round = |degrees: f32| (degrees * scale).round() / scale;
+        Self {
+            location: Coordinates {
+                lat: round(self.location.lat),
+                lon: round(self.location.lon),
+            },
+            ..self
+        }
+    }
+
+    fn key(&self) -> TopicKey {
+        (
+            self.kind,
+            self.location.lat.to_bits(),
+            self.location.lon.to_bits(),
+        )
+    }
+
+    async fn fetch(&self, app_state: &AppState) -> anyhow::Result<Value> {
+        let value = match self.kind {
+            TopicKind::Alerts => serde_json::to_value(alerts_at(app_state, &self.location).await?),
+            TopicKind::Nowcast => {
+                let (nowcasts, _) = combined_nowcasts(app_state, &self.location).await?;
+                serde_json::to_value(nowcasts)
+            }
+        };
+        Ok(value?)
+    }
+}
+
+/// The latest data of a topic, `None` until it has been fetched
+pub type TopicReceiver = watch::Receiver<Option<Value>>;
+
+/// The polled topics, shared by the WebSocket connections.
+#[derive(Debug, Clone, Default)]
+pub struct Topics {
+    /// Removed by the poller with the lock held once nobody is subscribed,
+    /// so a subscriber arriving as the poller stops starts a new one
+    senders: Arc<Mutex<HashMap<TopicKey, watch::Sender<Option<Value>>>>>,
+}
+
+impl Topics {
+    /// Receives the data of `topic`, starting its poller if this is the only
+    /// subscriber. `None` if it would be polled and `streams.max_topics`
+    /// topics are polled already.
+    pub fn subscribe(&self, app_state: &AppState, topic: Topic) -> Option<TopicReceiver> {
+        let topic = topic.rounded();
+        let mut senders = self.senders.lock().unwrap();
+        if let Some(sender) = senders.get(&topic.key()) {
+            return Some(sender.subscribe());
+        }
+        if senders.len() >= app_state.config.streams().max_topics {
+            warn!("Not polling {:?}, too many topics are polled", topic);
+            return None;
+        }
+        let (sender, receiver) = watch::channel(None);
+        senders.insert(topic.key(), sender.clone());
+        tokio::spawn(poll(app_state.clone(), self.clone(), topic, sender));
+        Some(receiver)
+    }
+}
+
+async fn poll(
+    app_state: AppState,
+    topics: Topics,
+    topic: Topic,
+    sender: watch::Sender<Option<Value>>,
+) {
+    debug!("Started polling {:?}", topic);
+    loop {
+        match topic.fetch(&app_state).await {
+            Ok(value) => {
+                sender.send_if_modified(|current| {
+                    let changed = current.as_ref() != Some(&value);
+                    if changed {
+                        *current = Some(value);
+                    }
+                    changed
+                });
+            }
+            Err(err) => warn!("Failed to poll {:?}: {}", topic, err),
+        }
+        tokio::time::sleep(app_state.config.streams().topic_poll_interval()).await;
+
+        let mut senders = topics.senders.lock().unwrap();
+        if sender.receiver_count() == 0 {
+            senders.remove(&topic.key());
+            debug!("Stopped polling {:?}, nobody is subscribed", topic);
+            return;
+        }
+    }
+}
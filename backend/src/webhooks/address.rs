@@ -0,0 +1,182 @@
+//! Keeps webhooks from reaching the internal network. Their hosts have to
+//! resolve to public addresses when they are registered, before each delivery
+//! and when the delivery connects, so a host cannot be re-pointed at an
+//! internal address after it was checked.
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{Context, bail};
+use reqwest::{
+    Client, Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+
+use crate::config::SharedConfig;
+
+/// Whether `ip` is reachable from the internet. Loopback, private, link-local
+/// (including the cloud metadata endpoints), unique local and reserved
+/// addresses are not.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || shared
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                let unique_local = (first & 0xfe00) == 0xfc00;
+                let link_local = (first & 0xffc0) == 0xfe80;
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || unique_local
+                    || link_local)
+            }
+        },
+    }
+}
+
+/// The addresses `host` resolves to, an error if there are none or any of
+/// them is not public and `allow_private` is not set.
+async fn resolve(host: &str, port: u16, allow_private: bool) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("{host} could not be resolved"))?
+        .collect();
+    if addrs.is_empty() {
+        bail!("{host} could not be resolved");
+    }
+    check_addrs(host, &addrs, allow_private)?;
+    Ok(addrs)
+}
+
+fn check_addrs(host: &str, addrs: &[SocketAddr], allow_private: bool) -> anyhow::Result<()> {
+    match addrs.iter().find(|addr| !is_public(addr.ip())) {
+        Some(addr) if !allow_private => {
+            bail!("{host} is {}, which is not a public address", addr.ip())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that the host of the webhook `url` only resolves to public
+/// addresses, unless `allow_private` is set.
+pub async fn check_url(url: &str, allow_private: bool) -> anyhow::Result<()> {
+    let url = Url::parse(url)?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let host = url.host_str().context("the URL has no host")?;
+    // IPv6 hosts are in brackets, IPv4 ones are normalized by the parser
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => check_addrs(host, &[SocketAddr::new(ip, port)], allow_private),
+        Err(_) => resolve(host, port, allow_private).await.map(|_| ()),
+    }
+}
+
+/// Resolves like [`check_url`], with `webhooks.allow_private_urls` as it is
+/// when connecting.
+#[derive(Debug, Clone)]
+struct PublicResolver {
+    config: SharedConfig,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allow_private = self.config.webhooks().allow_private_urls;
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            // The port is filled in from the URL
+            let addrs = resolve(&host, 0, allow_private).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Client for the deliveries. It only connects to public addresses, and does
+/// not follow redirects or use proxies, as they could lead anywhere.
+pub fn client(config: SharedConfig) -> Client {
+    Client::builder()
+        .dns_resolver(PublicResolver { config })
+        .redirect(redirect::Policy::none())
+        .no_proxy()
+        .build()
+        .expect("the webhook client is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:10.0.0.1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "1.1.1.1",
+            "151.101.2.132",
+            "2606:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_urls_of_internal_hosts() {
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:3000/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::ffff:10.0.0.1]/hook",
+            "https://[fe80::1]/hook",
+        ] {
+            assert!(check_url(url, false).await.is_err(), "{url}");
+            assert!(check_url(url, true).await.is_ok(), "{url}");
+        }
+        assert!(check_url("https://1.1.1.1/hook", false).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn client_does_not_connect_to_internal_hosts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://localhost:{}/hook",
+            listener.local_addr().unwrap().port()
+        );
+        let client = client(SharedConfig::new(Config::default()));
+
+        let err = client.post(&url).send().await.unwrap_err();
+
+        assert!(err.is_connect(), "{err:?}");
+    }
+}
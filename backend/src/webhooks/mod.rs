@@ -0,0 +1,560 @@
+//! Webhooks registered through the API, which are sent the new and updated
+//! alerts and the lightning strikes near their location. Each webhook is served
+//! by a task of its own, fed by the same pollers as the WebSocket subscriptions.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, KeyInit, Mac};
+use metrics::counter;
+use redact::Secret;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+    task::AbortHandle,
+};
+use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+use wictk_core::{Alert, Coordinates, Lightning, Severity};
+
+use crate::{
+    AppState,
+    config::SharedConfig,
+    handlers::LightningArea,
+    topics::{Topic, TopicKind, TopicReceiver},
+};
+
+mod address;
+mod store;
+
+pub use address::check_url;
+use store::WebhookStore;
+
+/// Events waiting to be delivered to a webhook, newer ones are dropped until
+/// it catches up
+const MAX_QUEUED_EVENTS: usize = 100;
+
+type QueuedEvent = (WebhookEvent, Value);
+
+/// What a webhook is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Each alert covering the location when it is issued or updated
+    Alert,
+    /// The strikes within the radius of the location found by a lightning poll
+    Lightning,
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::Alert => "alert",
+            WebhookEvent::Lightning => "lightning",
+        }
+    }
+}
+
+/// A registered webhook. Its secret is never returned.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Webhook {
+    /// Identifies the webhook, only known to whoever registered it
+    pub id: String,
+    /// Name of the API key that registered it
+    #[serde(skip)]
+    pub owner: String,
+    /// Where the events are POSTed
+    pub url: String,
+    /// Key of the `X-Wictk-Signature` HMAC
+    #[serde(skip)]
+    pub secret: Secret<String>,
+    pub location: Coordinates,
+    pub events: Vec<WebhookEvent>,
+    /// Alerts below this severity are not sent
+    pub min_severity: Severity,
+    /// Radius of the lightning events
+    pub radius_km: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One attempt to deliver an event to a webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Delivery {
+    /// Shared by the attempts to deliver the same event, sent as `X-Wictk-Delivery`
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    /// Starts at 1
+    pub attempt: u32,
+    pub time: DateTime<Utc>,
+    /// The status the webhook answered, missing when it could not be reached
+    pub status: Option<u16>,
+    /// Why the attempt failed, missing when it succeeded
+    pub error: Option<String>,
+}
+
+/// Why a webhook was not registered
+#[derive(Debug)]
+pub enum RegisterError {
+    /// Its alerts would need a new poller while `streams.max_topics` topics are polled
+    TooManyTopics,
+    /// Its API key has `webhooks.max_per_key` webhooks already
+    TooManyWebhooks(usize),
+    Store(rusqlite::Error),
+}
+
+impl From<rusqlite::Error> for RegisterError {
+    fn from(err: rusqlite::Error) -> Self {
+        RegisterError::Store(err)
+    }
+}
+
+/// The registered webhooks and their delivery tasks, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Webhooks {
+    store: WebhookStore,
+    /// Only connects to public addresses, see [`address`]
+    client: reqwest::Client,
+    /// Keyed by webhook ID
+    tasks: Arc<Mutex<HashMap<String, AbortHandle>>>,
+}
+
+impl Webhooks {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    pub fn open(path: &Path, config: SharedConfig) -> anyhow::Result<Self> {
+        Ok(Self::new(WebhookStore::open(path)?, config))
+    }
+
+    #[cfg(test)]
+    pub fn in_memory(config: SharedConfig) -> anyhow::Result<Self> {
+        Ok(Self::new(WebhookStore::in_memory()?, config))
+    }
+
+    fn new(store: WebhookStore, config: SharedConfig) -> Self {
+        Self {
+            store,
+            client: address::client(config),
+            tasks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Serves the webhooks registered before a restart. The alerts that are
+    /// current on startup are taken as already sent.
+    pub async fn start(&self, app_state: &AppState) -> anyhow::Result<()> {
+        let webhooks = self.store.all().await?;
+        info!("Delivering events to {} webhooks", webhooks.len());
+        for webhook in webhooks {
+            let alerts = if webhook.events.contains(&WebhookEvent::Alert) {
+                let alerts = subscribe_alerts(app_state, &webhook);
+                if alerts.is_none() {
+                    error!(
+                        "Not sending alerts to webhook {}, too many topics are polled",
+                        webhook.id
+                    );
+                }
+                alerts
+            } else {
+                None
+            };
+            self.spawn(app_state, webhook, alerts, false);
+        }
+        Ok(())
+    }
+
+    /// Stores `webhook` and starts serving it, beginning with the current
+    /// alerts. It is refused if its alerts cannot be polled, or its owner has
+    /// `webhooks.max_per_key` webhooks already.
+    pub async fn register(
+        &self,
+        app_state: &AppState,
+        webhook: Webhook,
+    ) -> Result<(), RegisterError> {
+        // Subscribed before storing, so the webhook is sure to get its alerts
+        let alerts = if webhook.events.contains(&WebhookEvent::Alert) {
+            Some(subscribe_alerts(app_state, &webhook).ok_or(RegisterError::TooManyTopics)?)
+        } else {
+            None
+        };
+        let max_per_key = app_state.config.webhooks().max_per_key;
+        if !self.store.insert(&webhook, max_per_key).await? {
+            return Err(RegisterError::TooManyWebhooks(max_per_key));
+        }
+        info!(
+            "Registered webhook {} of {} for {}",
+            webhook.id, webhook.owner, webhook.url
+        );
+        self.spawn(app_state, webhook, alerts, true);
+        Ok(())
+    }
+
+    pub async fn get(&self, id: &str) -> rusqlite::Result<Option<Webhook>> {
+        self.store.get(id).await
+    }
+
+    /// Stops serving the webhook and deletes it, returns whether it existed.
+    pub async fn remove(&self, id: &str) -> rusqlite::Result<bool> {
+        if let Some(task) = self.tasks.lock().unwrap().remove(id) {
+            task.abort();
+        }
+        self.store.delete(id).await
+    }
+
+    pub async fn deliveries(&self, id: &str) -> rusqlite::Result<Vec<Delivery>> {
+        self.store.deliveries(id).await
+    }
+
+    fn spawn(
+        &self,
+        app_state: &AppState,
+        webhook: Webhook,
+        alerts: Option<TopicReceiver>,
+        send_current: bool,
+    ) {
+        let id = webhook.id.clone();
+        let task = tokio::spawn(serve(
+            app_state.clone(),
+            self.store.clone(),
+            self.client.clone(),
+            webhook,
+            alerts,
+            send_current,
+        ));
+        self.tasks.lock().unwrap().insert(id, task.abort_handle());
+    }
+}
+
+/// A random ID that cannot be guessed, as it is all that is needed to manage a webhook.
+pub fn random_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes).expect("the OS provides random bytes");
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Receives the alerts at the location of `webhook`, `None` if they would need
+/// a new poller and `streams.max_topics` topics are polled already.
+fn subscribe_alerts(app_state: &AppState, webhook: &Webhook) -> Option<TopicReceiver> {
+    let topic = Topic {
+        kind: TopicKind::Alerts,
+        location: webhook.location.clone(),
+    };
+    let mut receiver = app_state.topics.subscribe(app_state, topic)?;
+    // The alerts already known are news to this webhook
+    receiver.mark_changed();
+    Some(receiver)
+}
+
+/// Sends the events `webhook` subscribed to until it is removed. They are
+/// queued for delivery, so a slow webhook does not hold up the receivers and
+/// make them skip strikes while it is retried.
+async fn serve(
+    app_state: AppState,
+    store: WebhookStore,
+    client: reqwest::Client,
+    webhook: Webhook,
+    alerts: Option<TopicReceiver>,
+    send_current: bool,
+) {
+    let (queue, queued) = mpsc::channel(MAX_QUEUED_EVENTS);
+    // Both run in this task, so removing the webhook stops the deliveries too
+    tokio::join!(
+        queue_events(&app_state, &webhook, alerts, send_current, queue),
+        deliver_queued(&app_state, &store, &client, &webhook, queued),
+    );
+}
+
+/// Queues the `alerts` and the other events `webhook` subscribed to until
+/// there are no more.
+async fn queue_events(
+    app_state: &AppState,
+    webhook: &Webhook,
+    mut alerts: Option<TopicReceiver>,
+    send_current: bool,
+    queue: mpsc::Sender<QueuedEvent>,
+) {
+    let mut strikes = webhook
+        .events
+        .contains(&WebhookEvent::Lightning)
+        .then(|| app_state.lightning_feed.subscribe(app_state));
+    let area = LightningArea::around(&webhook.location, webhook.radius_km);
+    // None until the first alerts are known, unless they are to be sent
+    let mut sent_alerts: Option<HashSet<String>> = send_current.then(HashSet::new);
+
+    loop {
+        tokio::select! {
+            Some(current) = next_alerts(&mut alerts) => {
+                for alert in new_alerts(&mut sent_alerts, current, webhook.min_severity) {
+                    enqueue(&queue, &webhook.id, WebhookEvent::Alert, alert);
+                }
+            }
+            Some(nearby) = next_strikes(&mut strikes, &area) => {
+                if !nearby.is_empty() {
+                    let data = json!({ "strikes": nearby });
+                    enqueue(&queue, &webhook.id, WebhookEvent::Lightning, data);
+                }
+            }
+            else => return,
+        }
+    }
+}
+
+/// Drops the event if the webhook is [`MAX_QUEUED_EVENTS`] events behind.
+fn enqueue(queue: &mpsc::Sender<QueuedEvent>, webhook_id: &str, event: WebhookEvent, data: Value) {
+    if queue.try_send((event, data)).is_err() {
+        warn!(
+            "Webhook {} is {} events behind, dropped a {} event",
+            webhook_id,
+            MAX_QUEUED_EVENTS,
+            event.name()
+        );
+        counter!("webhook_deliveries_total", "event" => event.name(), "result" => "dropped")
+            .increment(1);
+    }
+}
+
+async fn deliver_queued(
+    app_state: &AppState,
+    store: &WebhookStore,
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    mut queued: mpsc::Receiver<QueuedEvent>,
+) {
+    while let Some((event, data)) = queued.recv().await {
+        deliver(app_state, store, client, webhook, event, data).await;
+    }
+}
+
+/// The alerts when first known and then whenever they change, `None` once
+/// there are no more.
+async fn next_alerts(alerts: &mut Option<TopicReceiver>) -> Option<Value> {
+    let receiver = alerts.as_mut()?;
+    loop {
+        if receiver.changed().await.is_err() {
+            *alerts = None;
+            return None;
+        }
+        if let Some(current) = receiver.borrow_and_update().clone() {
+            return Some(current);
+        }
+    }
+}
+
+/// The strikes within `area` among those found by the next poll, `None`
+/// once there are no more.
+async fn next_strikes(
+    strikes: &mut Option<broadcast::Receiver<Lightning>>,
+    area: &LightningArea,
+) -> Option<Vec<Lightning>> {
+    let receiver = strikes.as_mut()?;
+    let mut nearby = Vec::new();
+    match receiver.recv().await {
+        Ok(strike) => nearby.push(strike),
+        Err(RecvError::Lagged(missed)) => {
+            warn!("Webhook fell behind, skipped {} strikes", missed);
+        }
+        Err(RecvError::Closed) => {
+            *strikes = None;
+            return None;
+        }
+    }
+    // A poll broadcasts its strikes at once, so they are sent together
+    while let Ok(strike) = receiver.try_recv() {
+        nearby.push(strike);
+    }
+    nearby.retain(|strike| area.contains(strike));
+    Some(nearby)
+}
+
+/// The alerts at least as severe as `min_severity` that are not in `sent`,
+/// after which `sent` holds the current ones. Updated alerts differ from the
+/// sent ones and are sent again.
+fn new_alerts(
+    sent: &mut Option<HashSet<String>>,
+    current: Value,
+    min_severity: Severity,
+) -> Vec<Value> {
+    let alerts: Vec<Alert> = serde_json::from_value(current).unwrap_or_default();
+    let current: Vec<(String, Value)> = alerts
+        .into_iter()
+        .filter(|alert| matches!(alert, Alert::Met(met) if met.severity >= min_severity))
+        .filter_map(|alert| {
+            let value = serde_json::to_value(alert).ok()?;
+            Some((value.to_string(), value))
+        })
+        .collect();
+    let new = match sent {
+        Some(sent) => current
+            .iter()
+            .filter(|(key, _)| !sent.contains(key))
+            .map(|(_, alert)| alert.clone())
+            .collect(),
+        None => Vec::new(),
+    };
+    *sent = Some(current.into_iter().map(|(key, _)| key).collect());
+    new
+}
+
+/// POSTs an event to the webhook, retrying with backoff until it answers with
+/// a 2xx status or runs out of attempts. Every attempt is logged, including
+/// those refused as the host no longer resolves to public addresses.
+async fn deliver(
+    app_state: &AppState,
+    store: &WebhookStore,
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: WebhookEvent,
+    data: Value,
+) {
+    let config = app_state.config.webhooks();
+    let delivery_id = random_id();
+    let body = json!({
+        "delivery_id": delivery_id,
+        "webhook_id": webhook.id,
+        "event": event,
+        "time": Utc::now(),
+        "data": data,
+    })
+    .to_string();
+
+    for attempt in 1..=config.max_attempts {
+        let timestamp = Utc::now().timestamp().to_string();
+        let refused = check_url(&webhook.url, config.allow_private_urls)
+            .await
+            .err();
+        let request = client
+            .post(&webhook.url)
+            .timeout(config.timeout())
+            .header("content-type", "application/json")
+            .header("x-wictk-event", event.name())
+            .header("x-wictk-delivery", &delivery_id)
+            .header("x-wictk-timestamp", &timestamp)
+            .header(
+                "x-wictk-signature",
+                sign(webhook.secret.expose_secret(), &timestamp, &body),
+            )
+            .body(body.clone());
+        let response = match refused {
+            Some(err) => Err(format!("Refused: {err}")),
+            None => request.send().await.map_err(|err| err.to_string()),
+        };
+        let (status, error) = match response {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => (
+                Some(response.status()),
+                Some(format!("Answered {}", response.status())),
+            ),
+            Err(err) => (None, Some(err)),
+        };
+        let delivered = error.is_none();
+        let logged = Delivery {
+            delivery_id: delivery_id.clone(),
+            event,
+            attempt,
+            time: Utc::now(),
+            status: status.map(|status| status.as_u16()),
+            error,
+        };
+        if let Err(err) = store.record_delivery(&webhook.id, &logged).await {
+            warn!("Failed to log delivery to webhook {}: {}", webhook.id, err);
+        }
+        if delivered {
+            debug!("Delivered {} to webhook {}", event.name(), webhook.id);
+            counter!("webhook_deliveries_total", "event" => event.name(), "result" => "delivered")
+                .increment(1);
+            return;
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(config.retry_delay(attempt)).await;
+        }
+    }
+    warn!(
+        "Gave up delivering {} to webhook {} after {} attempts",
+        event.name(),
+        webhook.id,
+        config.max_attempts
+    );
+    counter!("webhook_deliveries_total", "event" => event.name(), "result" => "failed")
+        .increment(1);
+}
+
+/// `sha256=` and the hex encoded HMAC-SHA256 of `{timestamp}.{body}`, so
+/// receivers can check that an event is from us and reject replayed ones.
+pub fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let signature: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={signature}")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn alert(title: &str, severity: &str) -> Value {
+        json!({"Met": {
+            "title": title,
+            "severity": severity,
+            "description": "Kraftige vindkast",
+            "certainty": "Likely",
+            "event": "wind",
+            "duration": {"from": "2025-01-20T12:00:00Z", "until": "2025-01-21T12:00:00Z"},
+            "area": {"Single": [{"x": 10.0, "y": 63.0}]}
+        }})
+    }
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        // Python: hmac.new(b"secret", b"1700000000.{}", hashlib.sha256).hexdigest()
+        assert_eq!(
+            sign("secret", "1700000000", "{}"),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+
+    #[test]
+    fn finds_new_and_updated_alerts() {
+        let mut sent = None;
+        let current = json!([alert("Wind", "Orange"), alert("Snow", "Yellow")]);
+        assert!(new_alerts(&mut sent, current.clone(), Severity::Yellow).is_empty());
+
+        let updated = json!([alert("Wind", "Red"), alert("Snow", "Yellow")]);
+        let new = new_alerts(&mut sent, updated, Severity::Orange);
+        assert_eq!(new, vec![alert("Wind", "Red")]);
+
+        let mut sent = Some(HashSet::new());
+        let new = new_alerts(&mut sent, current, Severity::Orange);
+        assert_eq!(new, vec![alert("Wind", "Orange")]);
+    }
+
+    #[test]
+    fn drops_events_beyond_the_queue() {
+        let (queue, mut queued) = mpsc::channel(MAX_QUEUED_EVENTS);
+        for strikes in 0..=MAX_QUEUED_EVENTS {
+            enqueue(&queue, "hook", WebhookEvent::Lightning, json!(strikes));
+        }
+
+        let mut delivered = Vec::new();
+        while let Ok((_, data)) = queued.try_recv() {
+            delivered.push(data);
+        }
+        assert_eq!(delivered.len(), MAX_QUEUED_EVENTS);
+        assert_eq!(delivered[0], json!(0));
+    }
+}
@@ -0,0 +1,261 @@
+//! SQLite store of the registered webhooks and their delivery log.
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+
+use chrono::{DateTime, Utc};
+use redact::Secret;
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use wictk_core::Coordinates;
+
+use super::{Delivery, Webhook};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    lat REAL NOT NULL,
+    lon REAL NOT NULL,
+    events TEXT NOT NULL,
+    min_severity TEXT NOT NULL,
+    radius_km REAL NOT NULL,
+    created_at INTEGER NOT NULL,
+    owner TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS deliveries (
+    webhook_id TEXT NOT NULL,
+    delivery_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    time INTEGER NOT NULL,
+    status INTEGER,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS deliveries_webhook ON deliveries (webhook_id);
+";
+
+/// Attempts kept per webhook, older ones are deleted
+const DELIVERY_LOG_SIZE: u32 = 100;
+
+/// Handle to the webhook database, cheap to clone.
+#[derive(Debug, Clone)]
+pub struct WebhookStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl WebhookStore {
+    /// Opens the database at `path`, creating it and its tables if needed.
+    /// The database stays locked while it is open, as every process serving
+    /// it would deliver each event, so a second replica fails to start.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(Duration::ZERO)?;
+        connection
+            .execute_batch("PRAGMA locking_mode = EXCLUSIVE; BEGIN EXCLUSIVE; COMMIT;")
+            .context("The webhook database is in use, webhooks need a single replica")?;
+        Self::init(connection)
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(connection: Connection) -> anyhow::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn with_connection<T, F>(&self, f: F) -> rusqlite::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap()))
+            .await
+            .expect("webhook store task panicked")
+    }
+
+    /// Inserts `webhook` unless its owner has `max_per_owner` webhooks
+    /// already, returns whether it was inserted.
+    pub async fn insert(&self, webhook: &Webhook, max_per_owner: usize) -> rusqlite::Result<bool> {
+        let webhook = webhook.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let owned: i64 = transaction.query_row(
+                "SELECT COUNT(*) FROM webhooks WHERE owner = ?1",
+                [&webhook.owner],
+                |row| row.get(0),
+            )?;
+            if owned >= max_per_owner as i64 {
+                return Ok(false);
+            }
+            transaction.execute(
+                "INSERT INTO webhooks VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    webhook.id,
+                    webhook.url,
+                    webhook.secret.expose_secret(),
+                    webhook.location.lat,
+                    webhook.location.lon,
+                    serde_json::to_string(&webhook.events).unwrap_or_default(),
+                    serde_json::to_string(&webhook.min_severity).unwrap_or_default(),
+                    webhook.radius_km,
+                    webhook.created_at.timestamp(),
+                    webhook.owner,
+                ],
+            )?;
+            transaction.commit()?;
+            Ok(true)
+        })
+        .await
+    }
+
+    pub async fn get(&self, id: &str) -> rusqlite::Result<Option<Webhook>> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            connection
+                .query_row(
+                    "SELECT * FROM webhooks WHERE id = ?1",
+                    [id],
+                    webhook_from_row,
+                )
+                .optional()
+        })
+        .await
+    }
+
+    pub async fn all(&self) -> rusqlite::Result<Vec<Webhook>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare("SELECT * FROM webhooks ORDER BY created_at")?;
+            let webhooks = statement.query_map([], webhook_from_row)?;
+            webhooks.collect()
+        })
+        .await
+    }
+
+    /// Deletes the webhook and its delivery log, returns whether it existed.
+    pub async fn delete(&self, id: &str) -> rusqlite::Result<bool> {
+        let id = id.to_string();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let deleted = transaction.execute("DELETE FROM webhooks WHERE id = ?1", [&id])?;
+            transaction.execute("DELETE FROM deliveries WHERE webhook_id = ?1", [&id])?;
+            transaction.commit()?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    /// Logs a delivery attempt, dropping the oldest beyond the log size.
+    pub async fn record_delivery(
+        &self,
+        webhook_id: &str,
+        delivery: &Delivery,
+    ) -> rusqlite::Result<()> {
+        let (webhook_id, delivery) = (webhook_id.to_string(), delivery.clone());
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            transaction.execute(
+                "INSERT INTO deliveries VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    webhook_id,
+                    delivery.delivery_id,
+                    serde_json::to_string(&delivery.event).unwrap_or_default(),
+                    delivery.attempt,
+                    delivery.time.timestamp_millis(),
+                    delivery.status,
+                    delivery.error,
+                ],
+            )?;
+            transaction.execute(
+                "DELETE FROM deliveries WHERE webhook_id = ?1 AND rowid NOT IN (
+                    SELECT rowid FROM deliveries WHERE webhook_id = ?1 ORDER BY rowid DESC LIMIT ?2
+                )",
+                params![webhook_id, DELIVERY_LOG_SIZE],
+            )?;
+            transaction.commit()
+        })
+        .await
+    }
+
+    /// The logged delivery attempts of a webhook, newest first.
+    pub async fn deliveries(&self, webhook_id: &str) -> rusqlite::Result<Vec<Delivery>> {
+        let webhook_id = webhook_id.to_string();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT delivery_id, event, attempt, time, status, error FROM deliveries
+                 WHERE webhook_id = ?1 ORDER BY rowid DESC",
+            )?;
+            let deliveries = statement.query_map([webhook_id], |row| {
+                Ok(Delivery {
+                    delivery_id: row.get(0)?,
+                    event: json_column(row, 1)?,
+                    attempt: row.get(2)?,
+                    time: DateTime::from_timestamp_millis(row.get(3)?).unwrap_or_default(),
+                    status: row.get(4)?,
+                    error: row.get(5)?,
+                })
+            })?;
+            deliveries.collect()
+        })
+        .await
+    }
+}
+
+fn webhook_from_row(row: &Row) -> rusqlite::Result<Webhook> {
+    Ok(Webhook {
+        id: row.get("id")?,
+        owner: row.get("owner")?,
+        url: row.get("url")?,
+        secret: Secret::new(row.get("secret")?),
+        location: Coordinates {
+            lat: row.get("lat")?,
+            lon: row.get("lon")?,
+        },
+        events: json_column(row, "events")?,
+        min_severity: json_column(row, "min_severity")?,
+        radius_km: row.get("radius_km")?,
+        created_at: DateTime::<Utc>::from_timestamp(row.get("created_at")?, 0).unwrap_or_default(),
+    })
+}
+
+/// Reads a column holding JSON, as the events and severities are stored.
+fn json_column<T: serde::de::DeserializeOwned>(
+    row: &Row,
+    column: impl rusqlite::RowIndex,
+) -> rusqlite::Result<T> {
+    let text: String = row.get(column)?;
+    serde_json::from_str(&text).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhooks::random_id;
+
+    #[test]
+    fn only_one_process_opens_the_database() {
+        let path = std::env::temp_dir().join(format!("webhooks-{}.sqlite", random_id()));
+        let store = WebhookStore::open(&path).unwrap();
+
+        // Another connection stands in for a second replica
+        let err = WebhookStore::open(&path).unwrap_err();
+        assert!(err.to_string().contains("single replica"), "{err}");
+
+        drop(store);
+        assert!(WebhookStore::open(&path).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::Result;
 use clap::Parser;
+use redact::Secret;
 use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use wictk_core::telemetry::{self, LogFormat, Telemetry};
 
 #[derive(Debug, Clone)]
 pub enum LogLevel {
@@ -47,20 +48,63 @@ pub struct Opts {
     #[arg(short, long, default_value = "http://wictk.frikk.io/")]
     pub service_url: String,
 
+    /// API key for the WICTK backend, when it requires one
+    #[arg(long, env = "WICTK_API_KEY")]
+    pub api_key: Option<Secret<String>>,
+
     #[arg(short = 'r', long, default_value = "http://hemrs.frikk.io/")]
     pub hemrs_url: String,
 
     #[arg(long)]
     pub store_lightning: bool,
 
+    /// MQTT broker to publish the weather of each location to, e.g. mqtt://broker:1883
+    #[arg(long, env = "MQTT_URL")]
+    pub mqtt_url: Option<String>,
+
+    #[arg(long, env = "MQTT_USERNAME")]
+    pub mqtt_username: Option<String>,
+
+    #[arg(long, env = "MQTT_PASSWORD")]
+    pub mqtt_password: Option<Secret<String>>,
+
+    /// Topic prefix of the published states, `{prefix}/{location}/nowcast` and so on
+    #[arg(long, default_value = "wictk")]
+    pub mqtt_prefix: String,
+
+    /// Home Assistant's discovery prefix, where the sensor configs are published
+    #[arg(long, default_value = "homeassistant")]
+    pub mqtt_discovery_prefix: String,
+
+    /// Seconds to wait for the broker to acknowledge every message
+    #[arg(long, default_value_t = 30)]
+    pub mqtt_timeout_secs: u64,
+
+    /// Radius around each location to count lightning strikes within for MQTT
+    #[arg(long, default_value_t = 50.0)]
+    pub lightning_radius_km: f64,
+
     #[arg(long, default_value = "info")]
     pub log_level: LogLevel,
+
+    /// Log as text or as one JSON object per line
+    #[arg(long, default_value = "text", env = "LOG_FORMAT")]
+    pub log_format: LogFormat,
+
+    /// OTLP/HTTP collector to export traces to, e.g. http://otel-collector:4318
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
 }
 
-pub fn init_tracing(opts: &Opts) {
+/// Sets up logging and trace export, which is flushed when the result is dropped.
+pub fn init_tracing(opts: &Opts) -> Result<Telemetry> {
     let level: Level = opts.log_level.clone().into();
-    let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    Ok(telemetry::init(
+        env!("CARGO_PKG_NAME"),
+        level,
+        opts.log_format,
+        opts.otlp_endpoint.as_deref(),
+    )?)
 }
 
 #[cfg(test)]
@@ -175,6 +219,12 @@ mod tests {
         assert_eq!(opts.service_url, "http://wictk.frikk.io/");
         assert_eq!(opts.hemrs_url, "http://hemrs.frikk.io/");
         assert!(!opts.store_lightning);
+        assert_eq!(opts.mqtt_url, None);
+        assert_eq!(opts.mqtt_prefix, "wictk");
+        assert_eq!(opts.mqtt_discovery_prefix, "homeassistant");
+        assert_eq!(opts.mqtt_timeout_secs, 30);
+        assert_eq!(opts.lightning_radius_km, 50.0);
         assert!(matches!(opts.log_level, LogLevel::Info));
+        assert_eq!(opts.log_format, LogFormat::Text);
     }
 }
@@ -1,14 +1,17 @@
 use anyhow::Result;
 use clap::Parser;
 use device::{DeviceApi, DeviceClient};
+use mqtt::{MqttApi, MqttClient, Topics};
 use sensor::{SensorApi, SensorClient, SensorIds};
 use storage::{StorageApi, StorageClient};
 use weather::{WeatherApi, WeatherClient};
+use wictk_client::WictkClient;
 use wictk_core::{Lightning, Nowcast};
 
 mod cli;
 mod device;
 mod measurement;
+mod mqtt;
 mod sensor;
 mod storage;
 mod weather;
@@ -16,7 +19,7 @@ mod weather;
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = cli::Opts::parse();
-    cli::init_tracing(&opts);
+    let _telemetry = cli::init_tracing(&opts)?;
 
     tracing::info!("Starting client logger with configuration: {:?}", opts);
 
@@ -26,7 +29,40 @@ async fn main() -> Result<()> {
     let mut device_client = DeviceClient::new(client.clone());
     let mut sensor_client = SensorClient::new(client.clone());
     let storage_client = StorageClient::new(client.clone());
-    let weather_client = WeatherClient::new(client.clone());
+    let mut wictk_client = WictkClient::new(client.clone(), &opts.service_url)?;
+    if let Some(api_key) = &opts.api_key {
+        wictk_client = wictk_client.with_api_key(api_key.expose_secret());
+    }
+    let weather_client = WeatherClient::new(wictk_client);
+
+    // Publish to MQTT first, it doesn't depend on HEMRS being reachable
+    if let Some(mqtt_url) = &opts.mqtt_url {
+        let mqtt_client = MqttClient::new(
+            mqtt_url,
+            opts.mqtt_username.as_deref(),
+            opts.mqtt_password.as_ref(),
+        )?
+        .with_timeout(std::time::Duration::from_secs(opts.mqtt_timeout_secs));
+        let topics = Topics {
+            prefix: opts.mqtt_prefix.clone(),
+            discovery_prefix: opts.mqtt_discovery_prefix.clone(),
+        };
+
+        let mut messages = Vec::new();
+        for location in &opts.locations {
+            let state =
+                mqtt::location_state(&weather_client, location, opts.lightning_radius_km).await;
+            messages.extend(topics.messages(&state));
+        }
+
+        match mqtt_client.publish(&messages).await {
+            Ok(()) => tracing::info!(
+                "Published the weather of {} locations to MQTT",
+                opts.locations.len()
+            ),
+            Err(e) => tracing::error!("Failed to publish to MQTT: {:#}", e),
+        }
+    }
 
     // Setup sensors (global, not per location)
     let setup_start = std::time::Instant::now();
@@ -45,16 +81,29 @@ async fn main() -> Result<()> {
         setup_elapsed.as_secs_f64()
     );
 
+    // Fetch the nowcasts of all locations at once, one by one if the backend can't
+    let mut batch = match weather_client.get_nowcasts(&opts.locations).await {
+        Ok(results) => Some(results.into_iter()),
+        Err(e) => {
+            tracing::warn!(
+                "Batch nowcast request failed, fetching each location: {}",
+                e
+            );
+            None
+        }
+    };
+
     // Process each location
     for location in &opts.locations {
         tracing::info!("Processing location: {}", location);
 
         // Fetch nowcast data for this location
         let start_time = std::time::Instant::now();
-        let nowcasts = match weather_client
-            .get_nowcast(&opts.service_url, location)
-            .await
-        {
+        let fetched = match batch.as_mut().and_then(Iterator::next) {
+            Some(result) => result,
+            None => weather_client.get_nowcast(location).await,
+        };
+        let nowcasts = match fetched {
             Ok(data) => {
                 let elapsed = start_time.elapsed();
                 tracing::info!(
@@ -192,7 +241,7 @@ async fn main() -> Result<()> {
     let now = chrono::Utc::now();
     tracing::info!("Current time for lightning filtering: {}", now);
 
-    let lightnings = match weather_client.get_lightnings(&opts.service_url).await {
+    let lightnings = match weather_client.get_lightnings().await {
         Ok(data) => {
             tracing::info!("Successfully retrieved lightning data");
             data
@@ -0,0 +1,59 @@
+use anyhow::Result;
+
+use crate::weather::WeatherApi;
+
+pub mod mqtt_client;
+pub mod types;
+
+pub use mqtt_client::MqttClient;
+pub use types::{AlertState, LightningState, LocationState, Message, Topics, WeatherState};
+
+pub trait MqttApi {
+    /// Publishes the messages retained with QoS 1, returning once the broker
+    /// has acknowledged all of them.
+    async fn publish(&self, messages: &[Message]) -> Result<()>;
+}
+
+/// Fetches what is published about a location, leaving out the parts that
+/// could not be fetched so their retained messages stay as they were.
+pub async fn location_state(
+    weather: &impl WeatherApi,
+    location: &str,
+    lightning_radius_km: f64,
+) -> LocationState {
+    let weather_state = match weather.get_nowcast(location).await {
+        Ok(nowcasts) => WeatherState::from_nowcasts(&nowcasts),
+        Err(e) => {
+            tracing::error!("Failed to fetch nowcast data for {}: {}", location, e);
+            None
+        }
+    };
+    let alerts = match weather.get_alerts(location).await {
+        Ok(alerts) => Some(AlertState::from_alerts(&alerts)),
+        Err(e) => {
+            tracing::error!("Failed to fetch alerts for {}: {}", location, e);
+            None
+        }
+    };
+    let lightning = match weather
+        .get_nearby_lightnings(location, lightning_radius_km)
+        .await
+    {
+        Ok(lightnings) => Some(LightningState::new(
+            &lightnings,
+            lightning_radius_km,
+            chrono::Utc::now(),
+        )),
+        Err(e) => {
+            tracing::error!("Failed to fetch lightning data for {}: {}", location, e);
+            None
+        }
+    };
+
+    LocationState {
+        location: location.to_string(),
+        weather: weather_state,
+        alerts,
+        lightning,
+    }
+}
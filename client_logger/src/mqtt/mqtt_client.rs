@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use redact::Secret;
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, QoS};
+use tracing::instrument;
+
+use super::{types::Message, MqttApi};
+
+const DEFAULT_PORT: u16 = 1883;
+
+/// Discovery configs and alert descriptions go beyond rumqttc's 10 kB default.
+const MAX_PACKET_SIZE: usize = 256 * 1024;
+
+pub struct MqttClient {
+    options: MqttOptions,
+    timeout: Duration,
+}
+
+impl MqttClient {
+    /// Connects to brokers given as `mqtt://host[:port]`, over plain TCP.
+    pub fn new(
+        url: &str,
+        username: Option<&str>,
+        password: Option<&Secret<String>>,
+    ) -> Result<Self> {
+        let url = reqwest::Url::parse(url).context("Invalid MQTT URL")?;
+        if !matches!(url.scheme(), "mqtt" | "tcp") {
+            anyhow::bail!("Unsupported MQTT URL scheme: {}", url.scheme());
+        }
+        let host = url.host_str().context("MQTT URL has no host")?;
+
+        let client_id = format!("{}-{}", env!("CARGO_PKG_NAME"), std::process::id());
+        let mut options = MqttOptions::new(client_id, host, url.port().unwrap_or(DEFAULT_PORT));
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_clean_session(true)
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+        if let Some(username) = username {
+            let password = password.map(|p| p.expose_secret().as_str()).unwrap_or("");
+            options.set_credentials(username, password);
+        }
+
+        Ok(Self {
+            options,
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl MqttApi for MqttClient {
+    #[instrument(skip(self, messages), fields(messages = messages.len()))]
+    async fn publish(&self, messages: &[Message]) -> Result<()> {
+        // Room for every publish and the disconnect, so queueing never waits on the event loop
+        let (client, mut eventloop) = AsyncClient::new(self.options.clone(), messages.len() + 1);
+        for message in messages {
+            client
+                .publish(
+                    message.topic.as_str(),
+                    QoS::AtLeastOnce,
+                    true,
+                    message.payload.as_bytes(),
+                )
+                .await?;
+        }
+
+        let mut unacknowledged = messages.len();
+        let session = async {
+            while unacknowledged > 0 {
+                if let Event::Incoming(Packet::PubAck(_)) = eventloop.poll().await? {
+                    unacknowledged -= 1;
+                }
+            }
+            client.disconnect().await?;
+            while !matches!(
+                eventloop.poll().await?,
+                Event::Outgoing(Outgoing::Disconnect)
+            ) {}
+            anyhow::Ok(())
+        };
+        tokio::time::timeout(self.timeout, session)
+            .await
+            .context("Timed out publishing to the MQTT broker")?
+            .context("Failed to publish to the MQTT broker")?;
+
+        tracing::info!("Published {} MQTT messages", messages.len());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use rumqttc::{ConnAck, ConnectReturnCode, PubAck, Publish};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// A broker stand-in accepting one connection, acknowledging its publishes
+    /// until it disconnects and returning them.
+    async fn broker(acknowledge: bool) -> (String, JoinHandle<Vec<Publish>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("mqtt://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut read = BytesMut::new();
+            let mut publishes = Vec::new();
+            loop {
+                let packet = match Packet::read(&mut read, MAX_PACKET_SIZE) {
+                    Ok(packet) => packet,
+                    Err(rumqttc::Error::InsufficientBytes(_)) => {
+                        if socket.read_buf(&mut read).await.unwrap() == 0 {
+                            return publishes;
+                        }
+                        continue;
+                    }
+                    Err(err) => panic!("invalid packet: {err:?}"),
+                };
+
+                let mut write = BytesMut::new();
+                match packet {
+                    Packet::Connect(..) => {
+                        Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false))
+                            .write(&mut write, MAX_PACKET_SIZE)
+                            .unwrap();
+                    }
+                    Packet::Publish(publish) => {
+                        if acknowledge {
+                            Packet::PubAck(PubAck::new(publish.pkid))
+                                .write(&mut write, MAX_PACKET_SIZE)
+                                .unwrap();
+                        }
+                        publishes.push(publish);
+                    }
+                    Packet::Disconnect => return publishes,
+                    _ => {}
+                }
+                socket.write_all(&write).await.unwrap();
+            }
+        });
+
+        (url, handle)
+    }
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message {
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_publish_retained_messages() {
+        let (url, broker) = broker(true).await;
+        let client = MqttClient::new(&url, None, None).unwrap();
+        let messages = vec![
+            message(
+                "homeassistant/sensor/wictk_oslo/nowcast_temperature/config",
+                "{}",
+            ),
+            message("wictk/oslo/nowcast", r#"{"temperature":20.5}"#),
+        ];
+
+        client.publish(&messages).await.unwrap();
+
+        let publishes = broker.await.unwrap();
+        assert_eq!(publishes.len(), 2);
+        for (publish, message) in publishes.iter().zip(&messages) {
+            assert_eq!(publish.topic, message.topic);
+            assert_eq!(publish.payload, message.payload.as_bytes());
+            assert_eq!(publish.qos, QoS::AtLeastOnce);
+            assert!(publish.retain);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_time_out_without_acknowledgements() {
+        let (url, _broker) = broker(false).await;
+        let client = MqttClient::new(&url, None, None)
+            .unwrap()
+            .with_timeout(Duration::from_millis(200));
+
+        let result = client.publish(&[message("wictk/oslo/nowcast", "{}")]).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_fail_when_broker_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("mqtt://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let client = MqttClient::new(&url, None, None).unwrap();
+
+        let result = client.publish(&[message("wictk/oslo/nowcast", "{}")]).await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_reject_unsupported_urls() {
+        assert!(MqttClient::new("http://broker", None, None).is_err());
+        assert!(MqttClient::new("not a url", None, None).is_err());
+        assert!(MqttClient::new("mqtt://broker:1884", Some("user"), None).is_ok());
+    }
+}
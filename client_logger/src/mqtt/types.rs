@@ -0,0 +1,489 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use wictk_core::{Alert, Lightning, Nowcast, Severity};
+
+/// A retained message to publish.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+}
+
+/// The latest nowcast of a location with the fields both providers share,
+/// preferring MET and filling in what only OpenWeatherMap has.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WeatherState {
+    pub provider: &'static str,
+    pub time: DateTime<Utc>,
+    pub description: String,
+    pub temperature: f32,
+    pub humidity: f32,
+    pub wind_speed: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_gust: Option<f32>,
+    pub wind_direction: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub precipitation_rate: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pressure: Option<u32>,
+}
+
+impl WeatherState {
+    pub fn from_nowcasts(nowcasts: &[Nowcast]) -> Option<Self> {
+        let met = nowcasts.iter().find_map(|nowcast| match nowcast {
+            Nowcast::Met(met) => Some(met),
+            _ => None,
+        });
+        let open_weather = nowcasts.iter().find_map(|nowcast| match nowcast {
+            Nowcast::OpenWeather(open_weather) => Some(open_weather),
+            _ => None,
+        });
+        let pressure = open_weather.map(|open_weather| open_weather.pressure);
+
+        match (met, open_weather) {
+            (Some(met), _) => Some(Self {
+                provider: "met",
+                time: met.time,
+                description: met.description.clone(),
+                temperature: met.air_temperature,
+                humidity: met.relative_humidity,
+                wind_speed: met.wind_speed,
+                wind_gust: Some(met.wind_speed_gust),
+                wind_direction: met.wind_from_direction,
+                precipitation_rate: Some(met.precipitation_rate),
+                pressure,
+            }),
+            (None, Some(open_weather)) => Some(Self {
+                provider: "openweathermap",
+                time: open_weather.dt,
+                description: open_weather.desc.clone(),
+                temperature: open_weather.temp,
+                humidity: open_weather.humidity as f32,
+                wind_speed: open_weather.wind_speed,
+                wind_gust: None,
+                wind_direction: open_weather.wind_deg as f32,
+                precipitation_rate: None,
+                pressure,
+            }),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertSummary {
+    pub title: String,
+    pub event: String,
+    pub severity: Severity,
+    pub description: String,
+}
+
+/// The active alerts of a location, most severe first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertState {
+    pub count: usize,
+    pub highest_severity: Option<Severity>,
+    pub alerts: Vec<AlertSummary>,
+}
+
+impl AlertState {
+    pub fn from_alerts(alerts: &[Alert]) -> Self {
+        let mut alerts: Vec<AlertSummary> = alerts
+            .iter()
+            .filter_map(|alert| match alert {
+                Alert::Met(alert) => Some(AlertSummary {
+                    title: alert.title.clone(),
+                    event: alert.event.clone(),
+                    severity: alert.severity,
+                    description: alert.description.clone(),
+                }),
+                Alert::Nve => None,
+            })
+            .collect();
+        alerts.sort_by_key(|alert| std::cmp::Reverse(alert.severity));
+
+        Self {
+            count: alerts.len(),
+            highest_severity: alerts.first().map(|alert| alert.severity),
+            alerts,
+        }
+    }
+}
+
+/// Counts of the recent strikes within the radius of a location.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LightningState {
+    pub radius_km: f64,
+    pub count: usize,
+    pub last_hour: usize,
+    pub latest: Option<DateTime<Utc>>,
+}
+
+impl LightningState {
+    pub fn new(lightnings: &[Lightning], radius_km: f64, now: DateTime<Utc>) -> Self {
+        let hour_ago = now - Duration::hours(1);
+        Self {
+            radius_km,
+            count: lightnings.len(),
+            last_hour: lightnings
+                .iter()
+                .filter(|lightning| lightning.time > hour_ago)
+                .count(),
+            latest: lightnings.iter().map(|lightning| lightning.time).max(),
+        }
+    }
+}
+
+/// What is known about a location, a part left out if it could not be fetched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocationState {
+    pub location: String,
+    pub weather: Option<WeatherState>,
+    pub alerts: Option<AlertState>,
+    pub lightning: Option<LightningState>,
+}
+
+/// A Home Assistant sensor reading one field of a state topic.
+struct SensorKind {
+    field: &'static str,
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+}
+
+const fn sensor(
+    field: &'static str,
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+) -> SensorKind {
+    SensorKind {
+        field,
+        name,
+        unit,
+        device_class,
+    }
+}
+
+const NOWCAST_SENSORS: &[SensorKind] = &[
+    sensor(
+        "temperature",
+        "Temperature",
+        Some("°C"),
+        Some("temperature"),
+    ),
+    sensor("humidity", "Humidity", Some("%"), Some("humidity")),
+    sensor("wind_speed", "Wind speed", Some("m/s"), Some("wind_speed")),
+    sensor("wind_gust", "Wind gust", Some("m/s"), Some("wind_speed")),
+    sensor("wind_direction", "Wind direction", Some("°"), None),
+    sensor(
+        "precipitation_rate",
+        "Precipitation rate",
+        Some("mm/h"),
+        Some("precipitation_intensity"),
+    ),
+    sensor(
+        "pressure",
+        "Pressure",
+        Some("hPa"),
+        Some("atmospheric_pressure"),
+    ),
+    sensor("description", "Conditions", None, None),
+];
+
+const ALERT_SENSORS: &[SensorKind] = &[
+    sensor("count", "Active alerts", None, None),
+    sensor("highest_severity", "Alert severity", None, None),
+];
+
+const LIGHTNING_SENSORS: &[SensorKind] = &[
+    sensor("count", "Lightning strikes", Some("strikes"), None),
+    sensor(
+        "last_hour",
+        "Lightning strikes last hour",
+        Some("strikes"),
+        None,
+    ),
+];
+
+/// Lays out the topics, `{prefix}/{location}/{nowcast,alerts,lightning}` for
+/// the states and `{discovery_prefix}/sensor/wictk_{location}/…/config` for
+/// Home Assistant discovery.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    pub prefix: String,
+    pub discovery_prefix: String,
+}
+
+impl Topics {
+    /// The state messages of the location, each preceded by the discovery
+    /// configs of the fields it holds, so a provider's missing fields don't
+    /// show up as sensors without a value.
+    pub fn messages(&self, state: &LocationState) -> Vec<Message> {
+        let slug = slug(&state.location);
+        let parts = [
+            ("nowcast", to_value(&state.weather), NOWCAST_SENSORS),
+            ("alerts", to_value(&state.alerts), ALERT_SENSORS),
+            ("lightning", to_value(&state.lightning), LIGHTNING_SENSORS),
+        ];
+
+        let mut messages = Vec::new();
+        for (topic, value, sensors) in parts {
+            if value.is_null() {
+                continue;
+            }
+            let state_topic = format!("{}/{}/{}", self.prefix, slug, topic);
+            for sensor in sensors
+                .iter()
+                .filter(|sensor| value.get(sensor.field).is_some())
+            {
+                messages.push(Message {
+                    topic: format!(
+                        "{}/sensor/wictk_{}/{}_{}/config",
+                        self.discovery_prefix, slug, topic, sensor.field
+                    ),
+                    payload: discovery_config(state, &slug, topic, &state_topic, sensor)
+                        .to_string(),
+                });
+            }
+            messages.push(Message {
+                topic: state_topic,
+                payload: value.to_string(),
+            });
+        }
+        messages
+    }
+}
+
+fn to_value<T: Serialize>(state: &Option<T>) -> Value {
+    serde_json::to_value(state).unwrap_or(Value::Null)
+}
+
+fn discovery_config(
+    state: &LocationState,
+    slug: &str,
+    topic: &str,
+    state_topic: &str,
+    sensor: &SensorKind,
+) -> Value {
+    let mut config = json!({
+        "name": sensor.name,
+        "unique_id": format!("wictk_{}_{}_{}", slug, topic, sensor.field),
+        "state_topic": state_topic,
+        "value_template": format!("{{{{ value_json.{} }}}}", sensor.field),
+        "device": {
+            "identifiers": [format!("wictk_{}", slug)],
+            "name": format!("WICTK {}", state.location),
+            "manufacturer": "WICTK",
+            "model": env!("CARGO_PKG_NAME"),
+        },
+    });
+    if let Some(unit) = sensor.unit {
+        config["unit_of_measurement"] = unit.into();
+        config["state_class"] = "measurement".into();
+    }
+    if let Some(device_class) = sensor.device_class {
+        config["device_class"] = device_class.into();
+    }
+    config
+}
+
+/// Lowercases the location and replaces anything but ASCII letters and digits
+/// with underscores, as topic levels and Home Assistant ids need.
+pub fn slug(location: &str) -> String {
+    let mut slug = String::new();
+    for c in location.chars().flat_map(char::to_lowercase) {
+        match c {
+            'æ' => slug.push_str("ae"),
+            'ø' => slug.push('o'),
+            'å' => slug.push('a'),
+            c if c.is_ascii_alphanumeric() => slug.push(c),
+            _ if slug.is_empty() || slug.ends_with('_') => {}
+            _ => slug.push('_'),
+        }
+    }
+    slug.trim_end_matches('_').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::Point;
+    use wictk_core::{Coordinates, MetNowcast, OpenWeatherNowcast};
+
+    fn met_nowcast() -> Nowcast {
+        Nowcast::Met(MetNowcast {
+            time: "2025-08-11T12:00:00Z".parse().unwrap(),
+            location: Coordinates {
+                lat: 63.0,
+                lon: 10.0,
+            },
+            description: "Clear".to_string(),
+            air_temperature: 20.5,
+            relative_humidity: 65.0,
+            precipitation_rate: 0.0,
+            precipitation_amount: 0.0,
+            wind_speed: 5.2,
+            wind_speed_gust: 6.0,
+            wind_from_direction: 180.0,
+        })
+    }
+
+    fn open_weather_nowcast() -> Nowcast {
+        Nowcast::OpenWeather(OpenWeatherNowcast {
+            dt: "2025-08-11T13:00:00Z".parse().unwrap(),
+            name: "Trondheim".to_string(),
+            country: "NO".to_string(),
+            lon: 10.0,
+            lat: 63.0,
+            main: "Clouds".to_string(),
+            desc: "few clouds".to_string(),
+            clouds: 20,
+            wind_speed: 4.1,
+            wind_deg: 200,
+            visibility: 10000,
+            temp: 22.3,
+            feels_like: 23.0,
+            humidity: 70,
+            pressure: 1013,
+        })
+    }
+
+    fn alert(title: &str, severity: &str) -> Alert {
+        serde_json::from_value(json!({"Met": {
+            "title": title,
+            "severity": severity,
+            "description": "Kraftige vindkast",
+            "certainty": "Likely",
+            "event": "wind",
+            "duration": {"from": "2025-01-20T12:00:00Z", "until": "2025-01-21T12:00:00Z"},
+            "area": {"Single": [{"x": 10.0, "y": 63.0}]}
+        }}))
+        .unwrap()
+    }
+
+    fn topics() -> Topics {
+        Topics {
+            prefix: "wictk".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        }
+    }
+
+    #[test]
+    fn weather_state_prefers_met_and_fills_in_pressure() {
+        let state = WeatherState::from_nowcasts(&[open_weather_nowcast(), met_nowcast()]).unwrap();
+
+        assert_eq!(state.provider, "met");
+        assert_eq!(state.temperature, 20.5);
+        assert_eq!(state.wind_gust, Some(6.0));
+        assert_eq!(state.pressure, Some(1013));
+    }
+
+    #[test]
+    fn weather_state_falls_back_to_openweathermap() {
+        let state = WeatherState::from_nowcasts(&[open_weather_nowcast()]).unwrap();
+
+        assert_eq!(state.provider, "openweathermap");
+        assert_eq!(state.humidity, 70.0);
+        assert_eq!(state.wind_direction, 200.0);
+        assert_eq!(state.wind_gust, None);
+        assert!(WeatherState::from_nowcasts(&[]).is_none());
+    }
+
+    #[test]
+    fn alert_state_puts_the_most_severe_first() {
+        let state = AlertState::from_alerts(&[
+            alert("Vind, gult nivå", "Yellow"),
+            alert("Vind, oransje nivå", "Orange"),
+        ]);
+
+        assert_eq!(state.count, 2);
+        assert_eq!(state.highest_severity, Some(Severity::Orange));
+        assert_eq!(state.alerts[0].title, "Vind, oransje nivå");
+        assert_eq!(AlertState::from_alerts(&[]).highest_severity, None);
+    }
+
+    #[test]
+    fn lightning_state_counts_the_last_hour() {
+        let now: DateTime<Utc> = "2025-08-11T12:00:00Z".parse().unwrap();
+        let strike =
+            |minutes| Lightning::new(Point::new(10.0, 63.0), now - Duration::minutes(minutes), 0);
+        let state = LightningState::new(&[strike(5), strike(30), strike(90)], 50.0, now);
+
+        assert_eq!(state.count, 3);
+        assert_eq!(state.last_hour, 2);
+        assert_eq!(state.latest, Some(now - Duration::minutes(5)));
+    }
+
+    #[test]
+    fn slug_keeps_letters_and_digits() {
+        assert_eq!(slug("Trondheim"), "trondheim");
+        assert_eq!(slug("Mo i Rana"), "mo_i_rana");
+        assert_eq!(slug(" Ålesund, NO "), "alesund_no");
+        assert_eq!(slug("Tromsø"), "tromso");
+    }
+
+    #[test]
+    fn messages_skip_missing_fields_and_parts() {
+        let state = LocationState {
+            location: "Mo i Rana".to_string(),
+            weather: WeatherState::from_nowcasts(&[open_weather_nowcast()]),
+            alerts: None,
+            lightning: None,
+        };
+
+        let messages = topics().messages(&state);
+        let topics: Vec<&str> = messages.iter().map(|m| m.topic.as_str()).collect();
+
+        assert!(topics.contains(&"homeassistant/sensor/wictk_mo_i_rana/nowcast_temperature/config"));
+        assert!(topics.contains(&"homeassistant/sensor/wictk_mo_i_rana/nowcast_pressure/config"));
+        assert!(!topics.contains(&"homeassistant/sensor/wictk_mo_i_rana/nowcast_wind_gust/config"));
+        assert!(!topics.iter().any(|topic| topic.contains("alerts")));
+        assert_eq!(topics.last(), Some(&"wictk/mo_i_rana/nowcast"));
+    }
+
+    #[test]
+    fn discovery_config_points_at_the_state_topic() {
+        let state = LocationState {
+            location: "Trondheim".to_string(),
+            weather: WeatherState::from_nowcasts(&[met_nowcast()]),
+            alerts: Some(AlertState::from_alerts(&[alert("Vind", "Yellow")])),
+            lightning: None,
+        };
+
+        let messages = topics().messages(&state);
+        let config = messages
+            .iter()
+            .find(|m| m.topic == "homeassistant/sensor/wictk_trondheim/nowcast_temperature/config")
+            .unwrap();
+        let config: Value = serde_json::from_str(&config.payload).unwrap();
+
+        assert_eq!(
+            config,
+            json!({
+                "name": "Temperature",
+                "unique_id": "wictk_trondheim_nowcast_temperature",
+                "state_topic": "wictk/trondheim/nowcast",
+                "value_template": "{{ value_json.temperature }}",
+                "unit_of_measurement": "°C",
+                "state_class": "measurement",
+                "device_class": "temperature",
+                "device": {
+                    "identifiers": ["wictk_trondheim"],
+                    "name": "WICTK Trondheim",
+                    "manufacturer": "WICTK",
+                    "model": "client_logger",
+                },
+            })
+        );
+
+        let alerts = messages
+            .iter()
+            .find(|m| m.topic == "wictk/trondheim/alerts")
+            .unwrap();
+        let alerts: Value = serde_json::from_str(&alerts.payload).unwrap();
+        assert_eq!(alerts["count"], 1);
+        assert_eq!(alerts["highest_severity"], "Yellow");
+    }
+}
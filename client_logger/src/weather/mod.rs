@@ -5,11 +5,23 @@ pub mod weather_client;
 pub use weather_client::WeatherClient;
 
 pub trait WeatherApi {
-    async fn get_nowcast(
+    async fn get_nowcast(&self, location: &str) -> Result<Vec<wictk_core::Nowcast>>;
+
+    /// Fetches the nowcasts of every location in one request, returning a
+    /// result per location in the same order.
+    async fn get_nowcasts(
         &self,
-        url: &str,
-        location: &str,
-    ) -> Result<Vec<wictk_core::Nowcast>>;
+        locations: &[String],
+    ) -> Result<Vec<Result<Vec<wictk_core::Nowcast>>>>;
+
+    async fn get_lightnings(&self) -> Result<Vec<wictk_core::Lightning>>;
 
-    async fn get_lightnings(&self, url: &str) -> Result<Vec<wictk_core::Lightning>>;
+    async fn get_alerts(&self, location: &str) -> Result<Vec<wictk_core::Alert>>;
+
+    /// Fetches the recent lightning strikes within `radius_km` of the location.
+    async fn get_nearby_lightnings(
+        &self,
+        location: &str,
+        radius_km: f64,
+    ) -> Result<Vec<wictk_core::Lightning>>;
 }
@@ -1,97 +1,127 @@
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use tracing::instrument;
+use wictk_client::{Location, WictkClient};
 
 use super::WeatherApi;
 
 pub struct WeatherClient {
-    client: reqwest::Client,
+    client: WictkClient,
 }
 
 impl WeatherClient {
-    pub fn new(client: reqwest::Client) -> Self {
+    pub fn new(client: WictkClient) -> Self {
         Self { client }
     }
 }
 
 impl WeatherApi for WeatherClient {
-    #[instrument(skip(self), fields(url = %url, location = %location))]
-    async fn get_nowcast(
-        &self,
-        url: &str,
-        location: &str,
-    ) -> Result<Vec<wictk_core::Nowcast>> {
+    #[instrument(skip(self))]
+    async fn get_nowcast(&self, location: &str) -> Result<Vec<wictk_core::Nowcast>> {
         tracing::debug!("Fetching nowcast data");
-        let full_url = format!("{url}api/nowcasts?location={location}");
-        tracing::info!("Requesting nowcast data from: {}", full_url);
-
-        let response = self
+        let nowcasts = self
             .client
-            .get(&full_url)
-            .send()
+            .nowcasts(&Location::city(location))
             .await
             .context("Failed to fetch nowcast data")?;
+        tracing::info!("Successfully fetched {} nowcast records", nowcasts.len());
+        Ok(nowcasts)
+    }
 
-        tracing::debug!("Response status: {}", response.status());
-
-        if response.status().is_success() {
-            let nowcasts: Vec<wictk_core::Nowcast> = response
-                .json()
-                .await
-                .context("Failed to parse nowcast response")?;
-            tracing::info!("Successfully fetched {} nowcast records", nowcasts.len());
-            Ok(nowcasts)
-        } else {
-            tracing::error!("Failed to fetch nowcast data: HTTP {}", response.status());
-            bail!("HTTP error: {}", response.status())
+    #[instrument(skip(self, locations), fields(locations = locations.len()))]
+    async fn get_nowcasts(
+        &self,
+        locations: &[String],
+    ) -> Result<Vec<Result<Vec<wictk_core::Nowcast>>>> {
+        let locations: Vec<Location> = locations.iter().map(Location::city).collect();
+        let items = self
+            .client
+            .nowcasts_batch(&locations)
+            .await
+            .context("Failed to fetch nowcast data")?;
+        if items.len() != locations.len() {
+            anyhow::bail!(
+                "Expected nowcasts for {} locations, got {}",
+                locations.len(),
+                items.len()
+            )
         }
+        Ok(items
+            .into_iter()
+            .map(|item| {
+                item.into_result()
+                    .map_err(|problem| anyhow::anyhow!(problem.detail))
+            })
+            .collect())
     }
 
-    #[instrument(skip(self), fields(url = %url))]
-    async fn get_lightnings(&self, url: &str) -> Result<Vec<wictk_core::Lightning>> {
+    #[instrument(skip(self))]
+    async fn get_lightnings(&self) -> Result<Vec<wictk_core::Lightning>> {
         tracing::debug!("Fetching lightning data");
-        let full_url = format!("{url}api/recent_lightning");
-        tracing::info!("Requesting lightning data from: {}", full_url);
-
-        let response = self
+        let lightnings = self
             .client
-            .get(&full_url)
-            .send()
+            .recent_lightning(None, None)
             .await
             .context("Failed to fetch lightning data")?;
+        tracing::info!(
+            "Successfully fetched {} lightning records",
+            lightnings.len()
+        );
+        Ok(lightnings)
+    }
 
-        tracing::debug!("Response status: {}", response.status());
-
-        if response.status().is_success() {
-            let lightnings: Vec<wictk_core::Lightning> = response
-                .json()
-                .await
-                .context("Failed to parse lightning response")?;
-            tracing::info!(
-                "Successfully fetched {} lightning records",
-                lightnings.len()
-            );
-            Ok(lightnings)
-        } else {
-            tracing::error!("Failed to fetch lightning data: HTTP {}", response.status());
-            bail!("HTTP error: {}", response.status())
-        }
+    #[instrument(skip(self))]
+    async fn get_alerts(&self, location: &str) -> Result<Vec<wictk_core::Alert>> {
+        tracing::debug!("Fetching alerts");
+        let alerts = self
+            .client
+            .alerts(Some(&Location::city(location)))
+            .await
+            .context("Failed to fetch alerts")?;
+        tracing::info!("Successfully fetched {} alerts", alerts.len());
+        Ok(alerts)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_nearby_lightnings(
+        &self,
+        location: &str,
+        radius_km: f64,
+    ) -> Result<Vec<wictk_core::Lightning>> {
+        tracing::debug!("Fetching nearby lightning data");
+        let lightnings = self
+            .client
+            .recent_lightning(Some(&Location::city(location)), Some(radius_km))
+            .await
+            .context("Failed to fetch lightning data")?;
+        tracing::info!(
+            "Successfully fetched {} nearby lightning records",
+            lightnings.len()
+        );
+        Ok(lightnings)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wictk_client::RetryPolicy;
     use wictk_core::Nowcast;
 
-    fn make_client() -> WeatherClient {
-        WeatherClient::new(reqwest::Client::new())
+    fn make_client(server: &mockito::Server) -> WeatherClient {
+        let client = WictkClient::new(reqwest::Client::new(), &server.url())
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                max_retries: 0,
+                ..RetryPolicy::default()
+            });
+        WeatherClient::new(client)
     }
 
     #[tokio::test]
     async fn should_get_nowcast_successfully() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/nowcasts")
+            .mock("GET", "/api/v1/nowcasts")
             .match_query(mockito::Matcher::UrlEncoded(
                 "location".into(),
                 "Trondheim".into(),
@@ -119,10 +149,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_nowcast(&format!("{}/", server.url()), "Trondheim")
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_nowcast("Trondheim").await;
 
         assert!(result.is_ok());
         let nowcasts = result.unwrap();
@@ -145,7 +173,7 @@ mod tests {
     async fn should_handle_empty_nowcast_response() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/nowcasts")
+            .mock("GET", "/api/v1/nowcasts")
             .match_query(mockito::Matcher::UrlEncoded(
                 "location".into(),
                 "TestLocation".into(),
@@ -156,10 +184,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_nowcast(&format!("{}/", server.url()), "TestLocation")
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_nowcast("TestLocation").await;
 
         assert!(result.is_ok());
         let nowcasts = result.unwrap();
@@ -168,11 +194,69 @@ mod tests {
         mock.assert_async().await;
     }
 
+    #[tokio::test]
+    async fn should_get_nowcasts_in_one_batch() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/v1/nowcasts/batch")
+            .match_body(mockito::Matcher::Json(serde_json::json!({
+                "locations": [{"location": "Trondheim"}, {"location": "Nowhere"}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"[
+                {"location": {"location": "Trondheim"}, "nowcasts": []},
+                {
+                    "location": {"location": "Nowhere"},
+                    "error": {
+                        "type": "about:blank",
+                        "title": "Not Found",
+                        "status": 404,
+                        "detail": "Nowhere not found"
+                    }
+                }
+            ]"#,
+            )
+            .create_async()
+            .await;
+
+        let weather_client = make_client(&server);
+        let results = weather_client
+            .get_nowcasts(&["Trondheim".to_string(), "Nowhere".to_string()])
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].as_ref().is_ok_and(Vec::is_empty));
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "Nowhere not found"
+        );
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn should_fail_batch_on_server_error() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/v1/nowcasts/batch")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let weather_client = make_client(&server);
+        let result = weather_client.get_nowcasts(&["Oslo".to_string()]).await;
+
+        assert!(result.is_err());
+        mock.assert_async().await;
+    }
+
     #[tokio::test]
     async fn should_handle_nowcast_server_error() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/nowcasts")
+            .mock("GET", "/api/v1/nowcasts")
             .match_query(mockito::Matcher::UrlEncoded(
                 "location".into(),
                 "ErrorLocation".into(),
@@ -181,10 +265,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_nowcast(&format!("{}/", server.url()), "ErrorLocation")
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_nowcast("ErrorLocation").await;
 
         assert!(result.is_err());
         mock.assert_async().await;
@@ -194,7 +276,7 @@ mod tests {
     async fn should_get_lightnings_successfully() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/recent_lightning")
+            .mock("GET", "/api/v1/recent_lightning")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(
@@ -212,10 +294,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_lightnings(&format!("{}/", server.url()))
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_lightnings().await;
 
         assert!(result.is_ok());
         let lightnings = result.unwrap();
@@ -233,17 +313,15 @@ mod tests {
     async fn should_handle_empty_lightnings_response() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/recent_lightning")
+            .mock("GET", "/api/v1/recent_lightning")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body("[]")
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_lightnings(&format!("{}/", server.url()))
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_lightnings().await;
 
         assert!(result.is_ok());
         let lightnings = result.unwrap();
@@ -256,15 +334,13 @@ mod tests {
     async fn should_handle_lightnings_server_error() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/recent_lightning")
+            .mock("GET", "/api/v1/recent_lightning")
             .with_status(500)
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_lightnings(&format!("{}/", server.url()))
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_lightnings().await;
 
         assert!(result.is_err());
         mock.assert_async().await;
@@ -274,7 +350,7 @@ mod tests {
     async fn should_get_nowcast_with_multiple_types() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/nowcasts")
+            .mock("GET", "/api/v1/nowcasts")
             .match_query(mockito::Matcher::UrlEncoded(
                 "location".into(),
                 "Mixed".into(),
@@ -321,10 +397,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_nowcast(&format!("{}/", server.url()), "Mixed")
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_nowcast("Mixed").await;
 
         assert!(result.is_ok());
         let nowcasts = result.unwrap();
@@ -344,7 +418,7 @@ mod tests {
     async fn should_get_lightnings_with_multiple_entries() {
         let mut server = mockito::Server::new_async().await;
         let mock = server
-            .mock("GET", "/api/recent_lightning")
+            .mock("GET", "/api/v1/recent_lightning")
             .with_status(200)
             .with_header("content-type", "application/json")
             .with_body(
@@ -370,10 +444,8 @@ mod tests {
             .create_async()
             .await;
 
-        let weather_client = make_client();
-        let result = weather_client
-            .get_lightnings(&format!("{}/", server.url()))
-            .await;
+        let weather_client = make_client(&server);
+        let result = weather_client.get_lightnings().await;
 
         assert!(result.is_ok());
         let lightnings = result.unwrap();
@@ -389,4 +461,66 @@ mod tests {
 
         mock.assert_async().await;
     }
+
+    #[tokio::test]
+    async fn should_get_alerts_for_location() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v1/alerts")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "location".into(),
+                "Trondheim".into(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"[
+                {
+                    "Met": {
+                        "title": "Kraftige vindkast, gult nivå",
+                        "severity": "Yellow",
+                        "description": "Kraftige vindkast",
+                        "certainty": "Likely",
+                        "event": "wind",
+                        "duration": {"from": "2025-01-20T12:00:00Z", "until": "2025-01-21T12:00:00Z"},
+                        "area": {"Single": [{"x": 10.0, "y": 63.0}]}
+                    }
+                }
+            ]"#,
+            )
+            .create_async()
+            .await;
+
+        let weather_client = make_client(&server);
+        let alerts = weather_client.get_alerts("Trondheim").await.unwrap();
+
+        assert_eq!(alerts.len(), 1);
+        assert!(matches!(&alerts[0], wictk_core::Alert::Met(alert) if alert.event == "wind"));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn should_get_nearby_lightnings_within_radius() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v1/recent_lightning")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("location".into(), "Trondheim".into()),
+                mockito::Matcher::UrlEncoded("radius_km".into(), "25".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let weather_client = make_client(&server);
+        let lightnings = weather_client
+            .get_nearby_lightnings("Trondheim", 25.0)
+            .await
+            .unwrap();
+
+        assert!(lightnings.is_empty());
+        mock.assert_async().await;
+    }
 }
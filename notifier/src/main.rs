@@ -1,10 +1,11 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use reqwest::Client;
+use redact::Secret;
 use std::time::Duration;
-use tracing::Level;
-use tracing_subscriber::FmtSubscriber;
+use tracing::{Instrument, Level, info_span};
+use wictk_client::{Location, WictkClient};
 use wictk_core::Alert;
+use wictk_core::telemetry::{self, LogFormat};
 
 use crate::notifications::{Notifier, NtfyNotifier};
 mod alerts;
@@ -52,14 +53,13 @@ pub struct Opts {
     #[arg(short, long, default_value = "https://ntfy.frikk.io", env = "NTFY_URL")]
     ntfy_url: String,
 
-    /// WICTK API alerts endpoint
-    #[arg(
-        short,
-        long,
-        default_value = "http://wictk/api/alerts",
-        env = "WICTK_ALERTS_URL"
-    )]
-    alerts_url: String,
+    /// WICTK backend base URL
+    #[arg(short, long, default_value = "http://wictk", env = "WICTK_URL")]
+    wictk_url: String,
+
+    /// API key for the WICTK backend, when it requires one
+    #[arg(long, env = "WICTK_API_KEY")]
+    api_key: Option<Secret<String>>,
 
     /// Notification topic
     #[arg(short, long, default_value = "weather_alerts", env = "NTFY_TOPIC")]
@@ -69,6 +69,14 @@ pub struct Opts {
     #[arg(long, default_value = "info")]
     log_level: LogLevel,
 
+    /// Log as text or as one JSON object per line
+    #[arg(long, default_value = "text", env = "LOG_FORMAT")]
+    log_format: LogFormat,
+
+    /// OTLP/HTTP collector to export traces to, e.g. http://otel-collector:4318
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
     /// Sleep duration between checks (in seconds)
     #[arg(short, long, default_value = "120", env = "SLEEP_DURATION")]
     sleep: u64,
@@ -78,31 +86,36 @@ pub struct Opts {
     location: String,
 }
 
-pub async fn get_met_alerts(client: &Client, url: &str, location: &str) -> Result<Vec<Alert>> {
-    let resp = client
-        .get(format!("{url}?location={}", location))
-        .send()
+pub async fn get_met_alerts(client: &WictkClient, location: &str) -> Result<Vec<Alert>> {
+    client
+        .alerts(Some(&Location::city(location)))
         .await
-        .with_context(|| format!("Failed to fetch alerts for location '{location}' from {url}"))?;
-    let alerts: Vec<Alert> = resp
-        .json()
-        .await
-        .with_context(|| format!("Failed to parse alerts response for location '{location}' from {url}"))?;
-    Ok(alerts)
+        .with_context(|| format!("Failed to fetch alerts for location '{location}'"))
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts = Opts::parse();
     let level: Level = opts.log_level.clone().into();
-    let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
+    let _telemetry = telemetry::init(
+        env!("CARGO_PKG_NAME"),
+        level,
+        opts.log_format,
+        opts.otlp_endpoint.as_deref(),
+    )?;
     let client = reqwest::Client::new();
-    let mut alerter = NtfyNotifier::new(client.clone(), opts.ntfy_url.clone());
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let mut wictk = WictkClient::new(client.clone(), &opts.wictk_url)?;
+    if let Some(api_key) = &opts.api_key {
+        wictk = wictk.with_api_key(api_key.expose_secret());
+    }
+    let mut alerter = NtfyNotifier::new(client, opts.ntfy_url.clone());
 
     tracing::info!("Starting notifier with configuration: {:?}", opts);
     loop {
-        let alerts = get_met_alerts(&client, &opts.alerts_url, &opts.location).await?;
+        // One trace per check, continued by the backend
+        let alerts = get_met_alerts(&wictk, &opts.location)
+            .instrument(info_span!("check_alerts", location = %opts.location))
+            .await?;
         tracing::info!("Fetched {} alerts", alerts.len());
         for alert in alerts {
             match alert {
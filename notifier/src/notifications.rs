@@ -150,6 +150,8 @@ impl NtfyNotifier {
     }
 }
 
+// Predates clippy being enforced
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
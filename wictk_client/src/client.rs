@@ -0,0 +1,580 @@
+use std::time::Duration;
+
+use redact::Secret;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use tracing::{instrument, warn};
+use wictk_core::{
+    backoff, is_transient, retry_after, telemetry, Alert, Lightning, Nowcast,
+    OpenWeatherMapLocation,
+};
+
+use crate::{
+    ClientError, Delivery, HealthReport, HistoryRange, LightningStream, Location, NewWebhook,
+    NowcastBatchItem, NowcastBucket, ProblemDetails, ReadinessReport, StationObservations, Webhook,
+};
+
+/// Segments of the path the API routes are below
+const API: [&str; 2] = ["api", "v1"];
+
+/// How failed requests are retried. Every route but registering a webhook is
+/// safe to repeat, so the other requests are retried on connection failures,
+/// timeouts, `429 Too Many Requests` and `5xx` statuses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one
+    pub max_retries: u32,
+    /// Upper bound of the jittered delay before the first retry, doubled for each retry
+    pub base_delay: Duration,
+    /// Longest delay between attempts, longer `Retry-After` values are not waited for
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Client for every route of the backend. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct WictkClient {
+    http: Client,
+    /// Ends with a slash, so routes are joined below it
+    base: Url,
+    api_key: Option<Secret<String>>,
+    admin_token: Option<Secret<String>>,
+    retry: RetryPolicy,
+}
+
+impl WictkClient {
+    /// Client for the backend at `base_url`, e.g. `http://wictk` or
+    /// `https://example.com/weather/`.
+    pub fn new(http: Client, base_url: &str) -> Result<Self, ClientError> {
+        let invalid = || ClientError::InvalidUrl {
+            url: base_url.to_string(),
+        };
+        let mut base = Url::parse(base_url).map_err(|_| invalid())?;
+        if !matches!(base.scheme(), "http" | "https") || base.cannot_be_a_base() {
+            return Err(invalid());
+        }
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        Ok(Self {
+            http,
+            base,
+            api_key: None,
+            admin_token: None,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Sends `key` in the `X-API-Key` header of API requests.
+    pub fn with_api_key(self, key: impl Into<String>) -> Self {
+        Self {
+            api_key: Some(Secret::new(key.into())),
+            ..self
+        }
+    }
+
+    /// Sends `token` with the admin requests.
+    pub fn with_admin_token(self, token: impl Into<String>) -> Self {
+        Self {
+            admin_token: Some(Secret::new(token.into())),
+            ..self
+        }
+    }
+
+    pub fn with_retry_policy(self, retry: RetryPolicy) -> Self {
+        Self { retry, ..self }
+    }
+
+    /// `GET /status/ping`
+    pub async fn ping(&self) -> Result<(), ClientError> {
+        let response = self.send(self.request(Method::GET, &["status", "ping"]), true);
+        check(response.await?).await.map(|_| ())
+    }
+
+    /// `GET /status/health`, also returning the report when the service is down.
+    pub async fn health(&self) -> Result<HealthReport, ClientError> {
+        self.report(&["status", "health"]).await
+    }
+
+    /// `GET /status/ready`, also returning the report when the replica is not ready.
+    pub async fn ready(&self) -> Result<ReadinessReport, ClientError> {
+        self.report(&["status", "ready"]).await
+    }
+
+    /// `GET /api/v1/alerts`, for all of Norway without a location.
+    #[instrument(skip(self))]
+    pub async fn alerts(&self, location: Option<&Location>) -> Result<Vec<Alert>, ClientError> {
+        let query = location.map(Location::query).unwrap_or_default();
+        self.get_json(&["alerts"], &query).await
+    }
+
+    /// `GET /api/v1/nowcasts`, from every enabled provider.
+    #[instrument(skip(self))]
+    pub async fn nowcasts(&self, location: &Location) -> Result<Vec<Nowcast>, ClientError> {
+        self.get_json(&["nowcasts"], &location.query()).await
+    }
+
+    /// `GET /api/v1/met/nowcasts`
+    #[instrument(skip(self))]
+    pub async fn met_nowcast(&self, location: &Location) -> Result<Nowcast, ClientError> {
+        self.get_json(&["met", "nowcasts"], &location.query()).await
+    }
+
+    /// `GET /api/v1/owm/nowcasts`
+    #[instrument(skip(self))]
+    pub async fn openweathermap_nowcast(
+        &self,
+        location: &Location,
+    ) -> Result<Nowcast, ClientError> {
+        self.get_json(&["owm", "nowcasts"], &location.query()).await
+    }
+
+    /// `POST /api/v1/nowcasts/batch`, with an item per location in the same order.
+    #[instrument(skip(self, locations), fields(locations = locations.len()))]
+    pub async fn nowcasts_batch(
+        &self,
+        locations: &[Location],
+    ) -> Result<Vec<NowcastBatchItem>, ClientError> {
+        let body = serde_json::json!({ "locations": locations });
+        let request = self
+            .api_request(Method::POST, &["nowcasts", "batch"])
+            .json(&body);
+        json(self.send(request, true).await?).await
+    }
+
+    /// `GET /api/v1/geocoding`
+    #[instrument(skip(self))]
+    pub async fn geocoding(
+        &self,
+        location: &str,
+    ) -> Result<Vec<OpenWeatherMapLocation>, ClientError> {
+        self.get_json(&["geocoding"], &[("location", location.to_string())])
+            .await
+    }
+
+    /// `GET /api/v1/recent_lightning`, within `radius_km` of `location` if given.
+    #[instrument(skip(self))]
+    pub async fn recent_lightning(
+        &self,
+        location: Option<&Location>,
+        radius_km: Option<f64>,
+    ) -> Result<Vec<Lightning>, ClientError> {
+        let mut query = location.map(Location::query).unwrap_or_default();
+        if let Some(radius_km) = radius_km {
+            query.push(("radius_km", radius_km.to_string()));
+        }
+        self.get_json(&["recent_lightning"], &query).await
+    }
+
+    /// `GET /api/v1/lightning/stream`, the strikes found from now on within
+    /// `radius_km` of `location` if given. The stream is cut short by a
+    /// timeout set on the `reqwest::Client`.
+    #[instrument(skip(self))]
+    pub async fn lightning_stream(
+        &self,
+        location: Option<&Location>,
+        radius_km: Option<f64>,
+    ) -> Result<LightningStream, ClientError> {
+        let mut query = location.map(Location::query).unwrap_or_default();
+        if let Some(radius_km) = radius_km {
+            query.push(("radius_km", radius_km.to_string()));
+        }
+        let request = self
+            .api_request(Method::GET, &["lightning", "stream"])
+            .query(&query);
+        let response = check(self.send(request, true).await?).await?;
+        Ok(LightningStream::new(response))
+    }
+
+    /// `GET /api/v1/observations`
+    #[instrument(skip(self))]
+    pub async fn observations(
+        &self,
+        location: &Location,
+    ) -> Result<StationObservations, ClientError> {
+        self.get_json(&["observations"], &location.query()).await
+    }
+
+    /// `GET /api/v1/history/nowcasts`
+    #[instrument(skip(self))]
+    pub async fn nowcast_history(
+        &self,
+        location: &Location,
+        range: &HistoryRange,
+    ) -> Result<Vec<NowcastBucket>, ClientError> {
+        let mut query = location.query();
+        query.extend(range.query());
+        self.get_json(&["history", "nowcasts"], &query).await
+    }
+
+    /// `POST /api/v1/subscriptions`, never retried as it would register the
+    /// webhook twice.
+    #[instrument(skip(self, webhook), fields(url = %webhook.url))]
+    pub async fn register_webhook(&self, webhook: &NewWebhook) -> Result<Webhook, ClientError> {
+        let request = self
+            .api_request(Method::POST, &["subscriptions"])
+            .json(webhook);
+        json(self.send(request, false).await?).await
+    }
+
+    /// `GET /api/v1/subscriptions/{id}`
+    #[instrument(skip(self))]
+    pub async fn webhook(&self, id: &str) -> Result<Webhook, ClientError> {
+        self.get_json(&["subscriptions", id], &[]).await
+    }
+
+    /// `DELETE /api/v1/subscriptions/{id}`
+    #[instrument(skip(self))]
+    pub async fn delete_webhook(&self, id: &str) -> Result<(), ClientError> {
+        let request = self.api_request(Method::DELETE, &["subscriptions", id]);
+        check(self.send(request, true).await?).await.map(|_| ())
+    }
+
+    /// `GET /api/v1/subscriptions/{id}/deliveries`, newest first.
+    #[instrument(skip(self))]
+    pub async fn webhook_deliveries(&self, id: &str) -> Result<Vec<Delivery>, ClientError> {
+        self.get_json(&["subscriptions", id, "deliveries"], &[])
+            .await
+    }
+
+    /// `GET /admin/watch`
+    pub async fn watched_locations(&self) -> Result<Vec<String>, ClientError> {
+        let request = self.admin_request(Method::GET, None);
+        json(self.send(request, true).await?).await
+    }
+
+    /// `PUT /admin/watch/{location}`, returning whether it was not watched already.
+    #[instrument(skip(self))]
+    pub async fn watch_location(&self, location: &str) -> Result<bool, ClientError> {
+        let request = self.admin_request(Method::PUT, Some(location));
+        let response = check(self.send(request, true).await?).await?;
+        Ok(response.status() == StatusCode::CREATED)
+    }
+
+    /// `DELETE /admin/watch/{location}`
+    #[instrument(skip(self))]
+    pub async fn unwatch_location(&self, location: &str) -> Result<(), ClientError> {
+        let request = self.admin_request(Method::DELETE, Some(location));
+        check(self.send(request, true).await?).await.map(|_| ())
+    }
+
+    /// A request to the route made of `segments` below the base URL. Each
+    /// segment is percent-encoded as a whole, so ids containing `/` stay
+    /// within their segment.
+    fn request(&self, method: Method, segments: &[&str]) -> RequestBuilder {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("the base URL was checked to be a base")
+            .pop_if_empty()
+            .extend(segments);
+        let request = self.http.request(method, url);
+        match &self.api_key {
+            Some(key) => request.header("x-api-key", key.expose_secret()),
+            None => request,
+        }
+    }
+
+    /// A request to the route made of `route` below `/api/v1`.
+    fn api_request(&self, method: Method, route: &[&str]) -> RequestBuilder {
+        let segments: Vec<&str> = API.iter().chain(route).copied().collect();
+        self.request(method, &segments)
+    }
+
+    fn admin_request(&self, method: Method, location: Option<&str>) -> RequestBuilder {
+        let mut url = self.base.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .expect("the base URL was checked to be a base");
+            segments.pop_if_empty().extend(["admin", "watch"]);
+            if let Some(location) = location {
+                segments.push(location);
+            }
+        }
+        let request = self.http.request(method, url);
+        match &self.admin_token {
+            Some(token) => request.bearer_auth(token.expose_secret()),
+            None => request,
+        }
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        route: &[&str],
+        query: &[(&str, String)],
+    ) -> Result<T, ClientError> {
+        let request = self.api_request(Method::GET, route).query(query);
+        json(self.send(request, true).await?).await
+    }
+
+    /// Status reports answer `503 Service Unavailable` with a body, which is
+    /// not worth retrying.
+    async fn report<T: DeserializeOwned>(&self, route: &[&str]) -> Result<T, ClientError> {
+        let response = self.send(self.request(Method::GET, route), false).await?;
+        if response.status() == StatusCode::SERVICE_UNAVAILABLE {
+            return response.json().await.map_err(ClientError::Decode);
+        }
+        json(response).await
+    }
+
+    /// Sends `request` with the current trace, retrying transient failures
+    /// when `retry` is set. Error statuses are returned as responses.
+    async fn send(&self, request: RequestBuilder, retry: bool) -> Result<Response, ClientError> {
+        let request = telemetry::propagate(request)
+            .build()
+            .map_err(ClientError::Request)?;
+        let retries = if retry { self.retry.max_retries } else { 0 };
+
+        let mut attempt = 0;
+        loop {
+            let this_attempt = request
+                .try_clone()
+                .expect("requests have no streaming bodies");
+            let result = self.http.execute(this_attempt).await;
+            let delay = match &result {
+                Ok(response) if !is_transient(response.status()) => break result,
+                Ok(response) => retry_after(response).unwrap_or_else(|| self.backoff(attempt)),
+                Err(err) if err.is_connect() || err.is_timeout() => self.backoff(attempt),
+                Err(_) => break result,
+            };
+            if attempt >= retries || delay > self.retry.max_delay {
+                break result;
+            }
+            match &result {
+                Ok(response) => warn!(
+                    "{} {} responded with {}, retrying in {:?}",
+                    request.method(),
+                    request.url().path(),
+                    response.status(),
+                    delay
+                ),
+                Err(err) => warn!(
+                    "{} {} failed: {}, retrying in {:?}",
+                    request.method(),
+                    request.url().path(),
+                    err,
+                    delay
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+        .map_err(ClientError::Request)
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        backoff(self.retry.base_delay, self.retry.max_delay, attempt)
+    }
+}
+
+/// Passes successful responses through and turns error statuses into errors.
+async fn check(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after = retry_after(&response);
+    let problem = response.json::<ProblemDetails>().await.ok();
+    Err(ClientError::Api {
+        status,
+        problem,
+        retry_after,
+    })
+}
+
+async fn json<T: DeserializeOwned>(response: Response) -> Result<T, ClientError> {
+    check(response)
+        .await?
+        .json()
+        .await
+        .map_err(ClientError::Decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const PROBLEM: &str =
+        r#"{"type":"about:blank","title":"Not Found","status":404,"detail":"Nowhere not found"}"#;
+
+    fn client(server: &mockito::Server) -> WictkClient {
+        WictkClient::new(Client::new(), &server.url())
+            .unwrap()
+            .with_retry_policy(RetryPolicy {
+                base_delay: Duration::from_millis(1),
+                ..RetryPolicy::default()
+            })
+    }
+
+    #[test]
+    fn accepts_http_base_urls() {
+        for url in [
+            "http://wictk",
+            "http://wictk/",
+            "https://example.com/weather",
+        ] {
+            assert!(WictkClient::new(Client::new(), url).is_ok(), "{url}");
+        }
+        for url in ["wictk", "ftp://wictk", "mailto:wictk@example.com"] {
+            assert!(matches!(
+                WictkClient::new(Client::new(), url),
+                Err(ClientError::InvalidUrl { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn joins_routes_below_the_base_path() {
+        let client = WictkClient::new(Client::new(), "https://example.com/weather").unwrap();
+        let request = client
+            .api_request(Method::GET, &["nowcasts", "batch"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.url().as_str(),
+            "https://example.com/weather/api/v1/nowcasts/batch"
+        );
+    }
+
+    #[tokio::test]
+    async fn encodes_query_and_path() {
+        let mut server = mockito::Server::new_async().await;
+        let nowcasts = server
+            .mock("GET", "/api/v1/nowcasts")
+            .match_query(Matcher::UrlEncoded("location".into(), "Ås & Vestby".into()))
+            .with_body("[]")
+            .create_async()
+            .await;
+        let watch = server
+            .mock("PUT", "/admin/watch/S%C3%B8r%2FFron")
+            .match_header("authorization", "Bearer secret")
+            .with_status(201)
+            .create_async()
+            .await;
+
+        let client = client(&server).with_admin_token("secret");
+        let result = client.nowcasts(&Location::city("Ås & Vestby")).await;
+        assert!(result.unwrap().is_empty());
+        assert!(client.watch_location("Sør/Fron").await.unwrap());
+        nowcasts.assert_async().await;
+        watch.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn keeps_ids_in_one_segment() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("DELETE", "/api/v1/subscriptions/..%2F..%2Fadmin%2Fwatch")
+            .with_status(204)
+            .create_async()
+            .await;
+
+        client(&server)
+            .delete_webhook("../../admin/watch")
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn sends_the_api_key() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v1/recent_lightning")
+            .match_header("x-api-key", "key")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("lat".into(), "63.4".into()),
+                Matcher::UrlEncoded("lon".into(), "10.4".into()),
+                Matcher::UrlEncoded("radius_km".into(), "25".into()),
+            ]))
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let client = client(&server).with_api_key("key");
+        let lightning = client
+            .recent_lightning(Some(&Location::coordinates(63.4, 10.4)), Some(25.0))
+            .await
+            .unwrap();
+
+        assert!(lightning.is_empty());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn returns_problem_details() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/api/v1/observations")
+            .match_query(Matcher::Any)
+            .with_status(404)
+            .with_header("content-type", "application/problem+json")
+            .with_body(PROBLEM)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let err = client(&server)
+            .observations(&Location::city("Nowhere"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(err.to_string(), "Nowhere not found");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("GET", "/api/v1/alerts")
+            .with_status(503)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let err = client(&server).alerts(None).await.unwrap_err();
+
+        assert!(matches!(
+            err,
+            ClientError::Api {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                problem: None,
+                ..
+            }
+        ));
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn reads_reports_of_unavailable_services() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/status/ready")
+            .with_status(503)
+            .with_body(r#"{"ready":false,"dependencies":{"redis":"down"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let report = client(&server).ready().await.unwrap();
+
+        assert!(!report.ready);
+        assert_eq!(report.dependencies["redis"], crate::DependencyStatus::Down);
+        mock.assert_async().await;
+    }
+}
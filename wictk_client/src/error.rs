@@ -0,0 +1,65 @@
+use std::{fmt, time::Duration};
+
+use reqwest::StatusCode;
+
+use crate::ProblemDetails;
+
+/// Errors from calling the backend.
+#[derive(Debug)]
+pub enum ClientError {
+    /// The base URL is not an http or https URL
+    InvalidUrl { url: String },
+    /// The backend could not be reached or did not respond in time
+    Request(reqwest::Error),
+    /// The backend answered with an error status, once retries were exhausted
+    Api {
+        status: StatusCode,
+        /// The body describing the error, if the backend sent one
+        problem: Option<ProblemDetails>,
+        /// How long the backend asked to wait before retrying
+        retry_after: Option<Duration>,
+    },
+    /// The response was not the expected JSON
+    Decode(reqwest::Error),
+    /// A streamed event did not carry the expected JSON
+    InvalidEvent(serde_json::Error),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl { url } => {
+                write!(f, "'{url}' is not an http or https URL")
+            }
+            ClientError::Request(err) => write!(f, "Request to the backend failed: {err}"),
+            ClientError::Api {
+                problem: Some(problem),
+                ..
+            } => write!(f, "{}", problem.detail),
+            ClientError::Api { status, .. } => write!(f, "Backend responded with {status}"),
+            ClientError::Decode(err) => write!(f, "Could not parse the response: {err}"),
+            ClientError::InvalidEvent(err) => write!(f, "Could not parse the event: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Request(err) | ClientError::Decode(err) => Some(err),
+            ClientError::InvalidEvent(err) => Some(err),
+            ClientError::InvalidUrl { .. } | ClientError::Api { .. } => None,
+        }
+    }
+}
+
+impl ClientError {
+    /// The status the backend answered with, if it answered.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            ClientError::Request(err) | ClientError::Decode(err) => err.status(),
+            ClientError::InvalidUrl { .. } | ClientError::InvalidEvent(_) => None,
+        }
+    }
+}
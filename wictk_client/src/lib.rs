@@ -0,0 +1,16 @@
+//! Typed async client for the WICTK backend API.
+//!
+//! Calls `/api/v1`, so backend changes to unversioned routes do not break it.
+//! Query parameters and path segments are encoded, transient failures are
+//! retried and error responses become [`ClientError::Api`] with the backend's
+//! problem details.
+
+mod client;
+mod error;
+mod stream;
+mod types;
+
+pub use client::{RetryPolicy, WictkClient};
+pub use error::ClientError;
+pub use stream::LightningStream;
+pub use types::*;
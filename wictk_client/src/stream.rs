@@ -0,0 +1,74 @@
+use reqwest::Response;
+use wictk_core::Lightning;
+
+use crate::ClientError;
+
+/// New lightning strikes from `/api/v1/lightning/stream`, read one at a time
+/// with [`LightningStream::next`].
+#[derive(Debug)]
+pub struct LightningStream {
+    response: Response,
+    /// Received bytes not yet parsed into events
+    buffer: Vec<u8>,
+}
+
+impl LightningStream {
+    pub(crate) fn new(response: Response) -> Self {
+        Self {
+            response,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Waits for the next strike, `None` once the backend closed the stream.
+    pub async fn next(&mut self) -> Option<Result<Lightning, ClientError>> {
+        loop {
+            while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+                let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                if let Some(data) = lightning_data(&String::from_utf8_lossy(&block)) {
+                    return Some(serde_json::from_str(&data).map_err(ClientError::InvalidEvent));
+                }
+            }
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Ok(None) => return None,
+                Err(err) => return Some(Err(ClientError::Request(err))),
+            }
+        }
+    }
+}
+
+/// The data of a `lightning` event, `None` for other events and keep-alive comments.
+fn lightning_data(block: &str) -> Option<String> {
+    let mut event = None;
+    let mut data: Vec<&str> = Vec::new();
+    for line in block.lines() {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "event" => event = Some(value),
+            "data" => data.push(value),
+            _ => {}
+        }
+    }
+    (event == Some("lightning") && !data.is_empty()).then(|| data.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lightning_events() {
+        assert_eq!(
+            lightning_data("event: lightning\ndata: {\"magic_value\":1}\n\n"),
+            Some("{\"magic_value\":1}".to_string())
+        );
+        assert_eq!(
+            lightning_data("event:lightning\ndata:[1,\ndata:2]\n\n"),
+            Some("[1,\n2]".to_string())
+        );
+        assert_eq!(lightning_data(":\n\n"), None);
+        assert_eq!(lightning_data("event: other\ndata: {}\n\n"), None);
+    }
+}
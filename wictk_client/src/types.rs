@@ -0,0 +1,254 @@
+//! Requests and responses of the backend routes that are not weather data.
+//! Weather data uses the types from `wictk_core`.
+
+use std::{collections::BTreeMap, fmt};
+
+use chrono::{DateTime, Utc};
+use redact::Secret;
+use serde::{Deserialize, Serialize, Serializer};
+use wictk_core::{Coordinates, FrostObservation, FrostStation, Nowcast, Severity};
+
+/// Where to get weather data for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Location {
+    /// A place name such as "Oslo", looked up by the backend
+    City {
+        location: String,
+    },
+    Coordinates {
+        lat: f64,
+        lon: f64,
+    },
+}
+
+impl Location {
+    pub fn city(name: impl Into<String>) -> Self {
+        Location::City {
+            location: name.into(),
+        }
+    }
+
+    pub fn coordinates(lat: f64, lon: f64) -> Self {
+        Location::Coordinates { lat, lon }
+    }
+
+    /// The query parameters selecting this location.
+    pub(crate) fn query(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Location::City { location } => vec![("location", location.clone())],
+            Location::Coordinates { lat, lon } => {
+                vec![("lat", lat.to_string()), ("lon", lon.to_string())]
+            }
+        }
+    }
+}
+
+impl From<&str> for Location {
+    fn from(name: &str) -> Self {
+        Location::city(name)
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::City { location } => write!(f, "{location}"),
+            Location::Coordinates { lat, lon } => write!(f, "{lat},{lon}"),
+        }
+    }
+}
+
+/// Error body as described in RFC 7807
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+/// Nowcasts for one location of a batch, or why they could not be fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowcastBatchItem {
+    pub location: Location,
+    #[serde(default)]
+    pub nowcasts: Option<Vec<Nowcast>>,
+    #[serde(default)]
+    pub error: Option<ProblemDetails>,
+}
+
+impl NowcastBatchItem {
+    pub fn into_result(self) -> Result<Vec<Nowcast>, ProblemDetails> {
+        match (self.nowcasts, self.error) {
+            (Some(nowcasts), _) => Ok(nowcasts),
+            (None, Some(problem)) => Err(problem),
+            (None, None) => Err(ProblemDetails {
+                problem_type: "about:blank".to_string(),
+                title: "Bad Gateway".to_string(),
+                status: 502,
+                detail: format!("No nowcasts or error for {}", self.location),
+            }),
+        }
+    }
+}
+
+/// Latest observations from the weather station nearest a location
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StationObservations {
+    pub station: FrostStation,
+    pub observations: Vec<FrostObservation>,
+}
+
+/// Time range and bucket size of a history request, the backend defaults
+/// to the last 24 hours in buckets of an hour
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistoryRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// A number followed by s, m, h or d, e.g. `15m`
+    pub interval: Option<String>,
+}
+
+impl HistoryRange {
+    pub(crate) fn query(&self) -> Vec<(&'static str, String)> {
+        let mut query = Vec::new();
+        if let Some(from) = self.from {
+            query.push(("from", from.to_rfc3339()));
+        }
+        if let Some(to) = self.to {
+            query.push(("to", to.to_rfc3339()));
+        }
+        if let Some(interval) = &self.interval {
+            query.push(("interval", interval.clone()));
+        }
+        query
+    }
+}
+
+/// Minimum, maximum and mean of a value within a bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Aggregated nowcasts from all providers within one time bucket
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NowcastBucket {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub samples: u64,
+    pub air_temperature: Aggregate,
+    pub relative_humidity: Aggregate,
+    pub wind_speed: Aggregate,
+    pub precipitation_rate: Option<Aggregate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyStatus {
+    Up,
+    Down,
+    Disabled,
+    Unconfigured,
+    Limited,
+}
+
+/// Calls made to an upstream and its configured limits
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub calls_last_minute: u64,
+    pub calls_last_day: u64,
+    pub limit_per_minute: Option<u64>,
+    pub limit_per_day: Option<u64>,
+    pub degraded: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DependencyHealth {
+    pub status: DependencyStatus,
+    pub critical: bool,
+    pub latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub quota: QuotaUsage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: ServiceStatus,
+    pub checked_at: DateTime<Utc>,
+    pub dependencies: BTreeMap<String, DependencyHealth>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub dependencies: BTreeMap<String, DependencyStatus>,
+}
+
+/// What a webhook is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// Each alert covering the location when it is issued or updated
+    Alert,
+    /// The strikes within the radius of the location found by a lightning poll
+    Lightning,
+}
+
+/// A webhook to register, the backend defaults to alerts of any severity
+/// and lightning within 50 km
+#[derive(Debug, Clone, Serialize)]
+pub struct NewWebhook {
+    #[serde(flatten)]
+    pub location: Location,
+    pub events: Vec<WebhookEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_severity: Option<Severity>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius_km: Option<f64>,
+    /// Where the events are POSTed
+    pub url: String,
+    /// Key of the `X-Wictk-Signature` HMAC, at least 16 characters
+    #[serde(serialize_with = "expose")]
+    pub secret: Secret<String>,
+}
+
+fn expose<S: Serializer>(secret: &Secret<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(secret.expose_secret())
+}
+
+/// A registered webhook, without its secret
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub location: Coordinates,
+    pub events: Vec<WebhookEvent>,
+    pub min_severity: Severity,
+    pub radius_km: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One attempt to deliver an event to a webhook
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub delivery_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub time: DateTime<Utc>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
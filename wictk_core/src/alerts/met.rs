@@ -1,11 +1,16 @@
 use chrono::{DateTime, Utc};
 use geo::Point;
-use reqwest::Client;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use utoipa::ToSchema;
 
-use super::{Alert, AlertError, Severity};
+use crate::{
+    conditional::with_if_modified_since, CacheMetadata, Conditional, Endpoints, Upstream,
+    UpstreamClient, WictkError,
+};
+
+use super::{Alert, Severity};
 
 impl From<MetAlert> for Alert {
     fn from(met: MetAlert) -> Self {
@@ -59,7 +64,7 @@ fn polygon_to_points(polygon: &[Value]) -> Vec<Point> {
         .flat_map(|coords| {
             coords
                 .as_array()
-                .ok_or_else(|| AlertError::new("Failed to parse coordinates"))
+                .ok_or_else(|| WictkError::parse("coordinates"))
                 .map(|coords| {
                     coords
                         .iter()
@@ -72,18 +77,18 @@ fn polygon_to_points(polygon: &[Value]) -> Vec<Point> {
 }
 
 impl TryFrom<serde_json::Value> for MetAlert {
-    type Error = AlertError;
+    type Error = WictkError;
 
     fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
         let area_type = value["geometry"]["type"]
             .as_str()
-            .ok_or_else(|| AlertError::new("Failed to parse area type"))?;
+            .ok_or_else(|| WictkError::parse("area_type"))?;
         let area = match area_type {
             // this is on the format of [[lon, lat], [lon, lat], ...]
             "Polygon" => {
                 let polygon = value["geometry"]["coordinates"]
                     .as_array()
-                    .ok_or_else(|| AlertError::new("Failed to parse coordinates"))?;
+                    .ok_or_else(|| WictkError::parse("coordinates"))?;
                 let points = polygon_to_points(polygon);
                 Area::Single(points)
             }
@@ -91,19 +96,19 @@ impl TryFrom<serde_json::Value> for MetAlert {
                 // this is on the format of [[[lon, lat], [lon, lat], ...], [[lon, lat], [lon, lat], ...], ...]
                 let polygons = value["geometry"]["coordinates"]
                     .as_array()
-                    .ok_or_else(|| AlertError::new("Failed to parse coordinates"))?
+                    .ok_or_else(|| WictkError::parse("coordinates"))?
                     .iter()
                     .map(|polygon| {
                         polygon
                             .as_array()
-                            .ok_or_else(|| AlertError::new("Failed to parse polygon"))
+                            .ok_or_else(|| WictkError::parse("polygon"))
                             .map(|polygon| polygon_to_points(polygon))
                     })
-                    .collect::<Result<Vec<Vec<Point>>, AlertError>>()?;
+                    .collect::<Result<Vec<Vec<Point>>, WictkError>>()?;
                 Area::Multiple(polygons)
             }
             _ => {
-                return Err(AlertError::new("invalid area type"));
+                return Err(WictkError::parse("area_type"));
             }
         };
         let severity = match value["properties"]["severity"].as_str() {
@@ -111,36 +116,36 @@ impl TryFrom<serde_json::Value> for MetAlert {
             Some("Severe") => Severity::Orange,
             Some("Extreme") => Severity::Red,
             _ => {
-                return Err(AlertError::new("invalid severity"));
+                return Err(WictkError::parse("severity"));
             }
         };
         let title = value["properties"]["title"]
             .as_str()
-            .ok_or_else(|| AlertError::new("Failed to parse title"))?
+            .ok_or_else(|| WictkError::parse("title"))?
             .to_owned();
         let description = value["properties"]["description"]
             .as_str()
-            .ok_or_else(|| AlertError::new("Failed to parse description"))?
+            .ok_or_else(|| WictkError::parse("description"))?
             .to_owned();
         let certainty = value["properties"]["certainty"]
             .as_str()
-            .ok_or_else(|| AlertError::new("Failed to parse certainty"))?
+            .ok_or_else(|| WictkError::parse("certainty"))?
             .to_owned();
         let event = value["properties"]["event"]
             .as_str()
-            .ok_or_else(|| AlertError::new("Failed to parse event"))?
+            .ok_or_else(|| WictkError::parse("event"))?
             .to_owned();
         let duration = TimeDuration {
             from: value["when"]["interval"][0]
                 .as_str()
-                .ok_or_else(|| AlertError::new("Failed to parse from"))?
+                .ok_or_else(|| WictkError::parse("from"))?
                 .parse()
-                .map_err(|_| AlertError::new("Failed to parse from"))?,
+                .map_err(|_| WictkError::parse("from"))?,
             until: value["when"]["interval"][1]
                 .as_str()
-                .ok_or_else(|| AlertError::new("Failed to parse until"))?
+                .ok_or_else(|| WictkError::parse("until"))?
                 .parse()
-                .map_err(|_| AlertError::new("Failed to parse until"))?,
+                .map_err(|_| WictkError::parse("until"))?,
         };
         Ok(MetAlert {
             severity,
@@ -155,32 +160,48 @@ impl TryFrom<serde_json::Value> for MetAlert {
 }
 
 impl MetAlert {
-    pub async fn fetch(client: Client) -> Result<Vec<Alert>, AlertError> {
-        let result: Vec<Alert> = client
-            .get("https://api.met.no/weatherapi/metalerts/2.0/current.json")
-            .send()
-            .await
-            .map_err(|err| {
-                tracing::error!("Error {}", err);
-                AlertError::new("Request to Met.no failed")
-            })?
+    pub async fn fetch(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+    ) -> Result<Vec<Alert>, WictkError> {
+        Self::fetch_if_modified(client, endpoints, None)
+            .await?
+            .into_modified()
+    }
+
+    /// Fetches the current alerts unless they are unchanged since
+    /// `if_modified_since`.
+    pub async fn fetch_if_modified(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+        if_modified_since: Option<DateTime<Utc>>,
+    ) -> Result<Conditional<Vec<Alert>>, WictkError> {
+        let request = client.get(format!(
+            "{}/weatherapi/metalerts/2.0/current.json",
+            endpoints.met
+        ));
+        let request = with_if_modified_since(request, if_modified_since);
+        let response = client.send(Upstream::Met, request).await?;
+        let metadata = CacheMetadata::from_headers(response.headers());
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Conditional::NotModified(metadata));
+        }
+        let result: Vec<Alert> = WictkError::check_status(Upstream::Met, response)?
             .json::<Value>()
             .await
             .map_err(|err| {
                 tracing::error!("Error {}", err);
-                AlertError::new("Deserialization from Met.no failed")
+                WictkError::parse("Met.no alerts response")
             })?
             .get("features")
-            .ok_or(AlertError::new(
-                "Failed to convert get features value to alert type",
-            ))?
+            .ok_or_else(|| WictkError::parse("features"))?
             .as_array()
-            .ok_or(AlertError::new("Failed to convert value to alert type"))?
+            .ok_or_else(|| WictkError::parse("features"))?
             .iter()
             .filter_map(|alert| MetAlert::try_from(alert.clone()).ok())
             .map(|alert| alert.into())
             .collect();
-        Ok(result)
+        Ok(Conditional::Modified(result, metadata))
     }
 }
 
@@ -247,9 +268,72 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn met_fetch_from_endpoint() {
+        let json = r#"{"features":[{"geometry":{"coordinates":[[[10.0,63.0],[11.0,63.0],[11.0,64.0],[10.0,64.0],[10.0,63.0]]],"type":"Polygon"},"properties":{"certainty":"Likely","description":"Kraftige vindkast","event":"wind","severity":"Severe","title":"Vind, oransje nivå"},"type":"Feature","when":{"interval":["2025-01-20T12:00:00+00:00","2025-01-21T12:00:00+00:00"]}},{"geometry":{"type":"Unknown"},"properties":{},"type":"Feature"}],"type":"FeatureCollection"}"#;
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let alerts = MetAlert::fetch(&UpstreamClient::default(), &endpoints)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        // The feature with an unknown geometry is skipped
+        assert_eq!(alerts.len(), 1);
+        match &alerts[0] {
+            Alert::Met(alert) => {
+                assert_eq!(alert.severity, Severity::Orange);
+                assert_eq!(alert.event, "wind");
+            }
+            Alert::Nve => panic!("Expected Met alert"),
+        }
+    }
+
+    #[tokio::test]
+    async fn met_fetch_returns_cache_metadata() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/weatherapi/metalerts/2.0/current.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_header("expires", "Mon, 14 Aug 2023 18:21:07 GMT")
+            .with_header("last-modified", "Mon, 14 Aug 2023 18:16:07 GMT")
+            .with_body(r#"{"features":[],"type":"FeatureCollection"}"#)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let alerts = MetAlert::fetch_if_modified(&UpstreamClient::default(), &endpoints, None)
+            .await
+            .unwrap();
+
+        let Conditional::Modified(alerts, metadata) = alerts else {
+            panic!("Expected modified alerts");
+        };
+        assert!(alerts.is_empty());
+        assert_eq!(
+            metadata.last_modified,
+            Some("2023-08-14T18:16:07Z".parse().unwrap())
+        );
+        assert_eq!(
+            metadata.expires,
+            Some("2023-08-14T18:21:07Z".parse().unwrap())
+        );
+    }
+
+    // Predates clippy being enforced
+    #[allow(clippy::unnecessary_unwrap, clippy::assertions_on_constants)]
     #[tokio::test]
     async fn met_fetch() {
-        let client = Client::new();
+        let client = reqwest::Client::new();
 
         // First check if we can reach the API
         let ping_result = client
@@ -266,7 +350,7 @@ mod tests {
             return;
         }
 
-        let alerts = MetAlert::fetch(client).await;
+        let alerts = MetAlert::fetch(&UpstreamClient::from(client), &Endpoints::default()).await;
         match &alerts {
             Ok(_) => {
                 // Test passed
@@ -13,7 +13,8 @@ pub enum Alert {
     Nve,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+/// Ordered from the least to the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
 pub enum Severity {
     /// The alert is for a moderate event.
     Yellow,
@@ -22,24 +23,3 @@ pub enum Severity {
     /// The alert is for an extreme event.
     Red,
 }
-
-#[derive(Debug)]
-pub struct AlertError {
-    pub message: String,
-}
-
-impl std::fmt::Display for AlertError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "AlertError: {}", self.message)
-    }
-}
-
-impl std::error::Error for AlertError {}
-
-impl AlertError {
-    pub fn new(message: &str) -> Self {
-        AlertError {
-            message: message.to_owned(),
-        }
-    }
-}
@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use reqwest::{header, header::HeaderMap, RequestBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::WictkError;
+
+/// Caching headers of an upstream response.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheMetadata {
+    /// The upstream asks not to request the resource again before this time
+    pub expires: Option<DateTime<Utc>>,
+    /// Sent back as `If-Modified-Since` to revalidate a cached value
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl CacheMetadata {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            expires: http_date(headers, header::EXPIRES),
+            last_modified: http_date(headers, header::LAST_MODIFIED),
+        }
+    }
+}
+
+fn http_date(headers: &HeaderMap, name: header::HeaderName) -> Option<DateTime<Utc>> {
+    let value = headers.get(name)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// Formats `date` as an HTTP date, e.g. `Mon, 14 Aug 2023 18:16:07 GMT`.
+pub fn format_http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The result of a request that may be answered with `304 Not Modified`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conditional<T> {
+    Modified(T, CacheMetadata),
+    /// The value the caller has cached is still current
+    NotModified(CacheMetadata),
+}
+
+impl<T> Conditional<T> {
+    /// Returns the value of a request that was sent without a validator.
+    pub fn into_modified(self) -> Result<T, WictkError> {
+        match self {
+            Conditional::Modified(value, _) => Ok(value),
+            Conditional::NotModified(_) => Err(WictkError::parse(
+                "304 Not Modified response to an unconditional request",
+            )),
+        }
+    }
+
+    pub fn metadata(&self) -> &CacheMetadata {
+        match self {
+            Conditional::Modified(_, metadata) | Conditional::NotModified(metadata) => metadata,
+        }
+    }
+}
+
+pub(crate) fn with_if_modified_since(
+    request: RequestBuilder,
+    since: Option<DateTime<Utc>>,
+) -> RequestBuilder {
+    match since {
+        Some(since) => request.header(header::IF_MODIFIED_SINCE, format_http_date(&since)),
+        None => request,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn reads_http_dates() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::EXPIRES,
+            HeaderValue::from_static("Mon, 14 Aug 2023 18:21:07 GMT"),
+        );
+        headers.insert(header::LAST_MODIFIED, HeaderValue::from_static("invalid"));
+
+        let metadata = CacheMetadata::from_headers(&headers);
+
+        assert_eq!(
+            metadata.expires,
+            Some(Utc.with_ymd_and_hms(2023, 8, 14, 18, 21, 7).unwrap())
+        );
+        assert_eq!(metadata.last_modified, None);
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        let date = Utc.with_ymd_and_hms(2023, 8, 14, 18, 16, 7).unwrap();
+        assert_eq!(format_http_date(&date), "Mon, 14 Aug 2023 18:16:07 GMT");
+    }
+}
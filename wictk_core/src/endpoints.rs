@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+/// Base URLs of the upstream APIs used by the fetchers.
+///
+/// The defaults point at the public APIs, but every URL can be replaced to use
+/// a mirror, a proxy or a local mock server instead.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Endpoints {
+    /// MET Norway weather API, used for nowcasts and alerts
+    pub met: String,
+    /// OpenWeatherMap API, used for nowcasts and geocoding
+    pub openweathermap: String,
+    /// yr.no API, used for lightning
+    pub yr: String,
+    /// MET Norway Frost API, used for station observations
+    pub frost: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            met: "https://api.met.no".to_string(),
+            openweathermap: "https://api.openweathermap.org".to_string(),
+            yr: "https://www.yr.no".to_string(),
+            frost: "https://frost.met.no".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Creates endpoints where every upstream is served from the same base URL.
+    pub fn with_base_url(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            met: base_url.to_string(),
+            openweathermap: base_url.to_string(),
+            yr: base_url.to_string(),
+            frost: base_url.to_string(),
+        }
+    }
+
+    /// Creates endpoints where each upstream is served below its own path
+    /// prefix on `base_url`, e.g. `{base_url}/met` for MET Norway.
+    pub fn with_upstream_prefixes(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            met: format!("{base_url}/met"),
+            openweathermap: format!("{base_url}/openweathermap"),
+            yr: format!("{base_url}/yr"),
+            frost: format!("{base_url}/frost"),
+        }
+    }
+}
+
+/// The upstream APIs the fetchers talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    Met,
+    OpenWeatherMap,
+    Yr,
+    Frost,
+}
+
+impl Upstream {
+    pub const ALL: [Upstream; 4] = [
+        Upstream::Met,
+        Upstream::OpenWeatherMap,
+        Upstream::Yr,
+        Upstream::Frost,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Upstream::Met => "met",
+            Upstream::OpenWeatherMap => "openweathermap",
+            Upstream::Yr => "yr",
+            Upstream::Frost => "frost",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|upstream| upstream.name() == name)
+    }
+
+    pub fn base_url<'a>(&self, endpoints: &'a Endpoints) -> &'a str {
+        match self {
+            Upstream::Met => &endpoints.met,
+            Upstream::OpenWeatherMap => &endpoints.openweathermap,
+            Upstream::Yr => &endpoints.yr,
+            Upstream::Frost => &endpoints.frost,
+        }
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            Upstream::Met => "Met.no",
+            Upstream::OpenWeatherMap => "OpenWeatherMap",
+            Upstream::Yr => "Yr",
+            Upstream::Frost => "Frost",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_base_url_trims_trailing_slash() {
+        let endpoints = Endpoints::with_base_url("http://127.0.0.1:1234/");
+        assert_eq!(endpoints.met, "http://127.0.0.1:1234");
+        assert_eq!(endpoints.frost, "http://127.0.0.1:1234");
+    }
+
+    #[test]
+    fn with_upstream_prefixes() {
+        let endpoints = Endpoints::with_upstream_prefixes("http://127.0.0.1:1234/");
+        assert_eq!(endpoints.met, "http://127.0.0.1:1234/met");
+        assert_eq!(
+            endpoints.openweathermap,
+            "http://127.0.0.1:1234/openweathermap"
+        );
+        assert_eq!(endpoints.yr, "http://127.0.0.1:1234/yr");
+        assert_eq!(endpoints.frost, "http://127.0.0.1:1234/frost");
+    }
+}
@@ -0,0 +1,150 @@
+use reqwest::{header, Response, StatusCode};
+
+use crate::Upstream;
+
+/// Errors from fetching, parsing and looking up weather data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WictkError {
+    /// The upstream could not be reached or responded with an error
+    UpstreamUnavailable { upstream: Upstream, reason: String },
+    /// The upstream did not respond in time
+    UpstreamTimeout { upstream: Upstream },
+    /// The upstream rejected the request because of rate limiting
+    UpstreamRateLimited {
+        upstream: Upstream,
+        /// Seconds until the upstream accepts requests again, if it said so
+        retry_after: Option<u64>,
+    },
+    /// A field in an upstream response was missing or invalid
+    ParseError { field: String },
+    /// The requested resource does not exist
+    NotFound { message: String },
+    /// The input from the caller was invalid
+    InvalidInput { message: String },
+}
+
+impl std::fmt::Display for WictkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WictkError::UpstreamUnavailable { upstream, reason } => {
+                write!(f, "{upstream} is unavailable: {reason}")
+            }
+            WictkError::UpstreamTimeout { upstream } => {
+                write!(f, "{upstream} did not respond in time")
+            }
+            WictkError::UpstreamRateLimited { upstream, .. } => {
+                write!(f, "{upstream} is rate limiting requests")
+            }
+            WictkError::ParseError { field } => write!(f, "Could not parse {field}"),
+            WictkError::NotFound { message } | WictkError::InvalidInput { message } => {
+                write!(f, "{message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WictkError {}
+
+impl WictkError {
+    pub fn parse(field: &str) -> Self {
+        WictkError::ParseError {
+            field: field.to_owned(),
+        }
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        WictkError::NotFound {
+            message: message.to_owned(),
+        }
+    }
+
+    pub fn invalid_input(message: &str) -> Self {
+        WictkError::InvalidInput {
+            message: message.to_owned(),
+        }
+    }
+
+    /// Classifies a failed request to `upstream`.
+    pub fn request(upstream: Upstream, err: &reqwest::Error) -> Self {
+        if err.is_timeout() {
+            WictkError::UpstreamTimeout { upstream }
+        } else {
+            WictkError::UpstreamUnavailable {
+                upstream,
+                reason: "request failed".to_owned(),
+            }
+        }
+    }
+
+    /// Passes successful responses through and turns error statuses into errors.
+    pub fn check_status(upstream: Upstream, response: Response) -> Result<Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok());
+            return Err(WictkError::UpstreamRateLimited {
+                upstream,
+                retry_after,
+            });
+        }
+        Err(WictkError::UpstreamUnavailable {
+            upstream,
+            reason: format!("responded with {status}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    async fn response(status: usize, retry_after: Option<&str>) -> Response {
+        let mut server = mockito::Server::new_async().await;
+        let mut mock = server.mock("GET", "/").with_status(status);
+        if let Some(retry_after) = retry_after {
+            mock = mock.with_header("retry-after", retry_after);
+        }
+        mock.create_async().await;
+        reqwest::get(server.url()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn check_status_passes_success() {
+        let result = WictkError::check_status(Upstream::Met, response(200, None).await);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn check_status_rate_limited() {
+        let result = WictkError::check_status(Upstream::Met, response(429, Some("30")).await);
+        assert_eq!(
+            result.unwrap_err(),
+            WictkError::UpstreamRateLimited {
+                upstream: Upstream::Met,
+                retry_after: Some(30),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn check_status_server_error() {
+        let result = WictkError::check_status(Upstream::Frost, response(500, None).await);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Frost is unavailable: responded with 500 Internal Server Error"
+        );
+    }
+
+    #[test]
+    fn parse_error_names_field() {
+        assert_eq!(WictkError::parse("dt").to_string(), "Could not parse dt");
+    }
+}
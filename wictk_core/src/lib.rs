@@ -1,9 +1,24 @@
 mod alerts;
+mod conditional;
+mod endpoints;
+mod error;
 mod lightning;
 mod locations;
 mod nowcasts;
+mod observations;
+pub mod replay;
+mod resilience;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
 
 pub use alerts::*;
+pub use conditional::{format_http_date, CacheMetadata, Conditional};
+pub use endpoints::{Endpoints, Upstream};
+pub use error::WictkError;
 pub use lightning::Lightning;
 pub use locations::*;
 pub use nowcasts::*;
+pub use observations::*;
+pub use resilience::{
+    backoff, is_transient, retry_after, CallGuard, UpstreamClient, UpstreamPolicy,
+};
@@ -1,11 +1,12 @@
-use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use geo::Point;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tracing::error;
 use utoipa::ToSchema;
 
+use crate::{Endpoints, Upstream, UpstreamClient, WictkError};
+
 /// A geographic point with longitude (x) and latitude (y)
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct GeoPoint {
@@ -38,30 +39,40 @@ impl Lightning {
         }
     }
 
-    pub async fn find_ligntning(client: &Client, url: &str) -> Result<Vec<Lightning>> {
-        let response = client
-            .get(url)
-            .send()
-            .await
-            .with_context(|| format!("Failed to fetch lightning data from {url}"))?
+    /// Fetches lightning strikes from the last 24 hours from yr.no.
+    pub async fn fetch(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+    ) -> Result<Vec<Lightning>, WictkError> {
+        let url = format!("{}/api/v0/lightning-events?fromHours=24", endpoints.yr);
+        Self::find_ligntning(client, &url).await
+    }
+
+    pub async fn find_ligntning(
+        client: &UpstreamClient,
+        url: &str,
+    ) -> Result<Vec<Lightning>, WictkError> {
+        let response = client.send(Upstream::Yr, client.get(url)).await?;
+        let response = WictkError::check_status(Upstream::Yr, response)?
             .json::<Value>()
             .await
-            .with_context(|| format!("Failed to decode lightning response from {url}"))?;
+            .map_err(|err| {
+                error!("Failed to decode lightning response from {url}: {err}");
+                WictkError::parse("Yr lightning response")
+            })?;
         let response_string = response
             .get("historicalData")
-            .ok_or_else(|| {
-                anyhow::anyhow!("Invalid response format: 'historicalData' field is missing")
-            })?
+            .ok_or_else(|| WictkError::parse("historicalData"))?
             .to_string();
 
         let data = response_string.trim_matches('"');
 
-        let lightning_data: Value = serde_json::from_str(data)
-            .with_context(|| format!("Failed to parse 'historicalData' payload from {url}"))?;
+        let lightning_data: Value =
+            serde_json::from_str(data).map_err(|_| WictkError::parse("historicalData"))?;
 
         let lightning_data = lightning_data
             .as_array()
-            .ok_or_else(|| anyhow::anyhow!("Expected an array of lightning events"))?
+            .ok_or_else(|| WictkError::parse("historicalData"))?
             .iter()
             .filter_map(|event| {
                 // event is on the form: [timestamp, latitude, longitude, magic_value]
@@ -89,9 +100,13 @@ impl Lightning {
     }
 }
 
+// Predates clippy being enforced
+#[allow(clippy::empty_line_after_outer_attr)]
 #[cfg(test)]
 
 mod tests {
+    use anyhow::Result;
+
     use super::*;
     use geo::point;
 
@@ -122,7 +137,7 @@ mod tests {
             .create_async()
             .await;
 
-        let client = Client::new();
+        let client = UpstreamClient::default();
         let url = format!("{}/api/v0/lightning-events", server.url());
         let lightning_data = Lightning::find_ligntning(&client, &url).await?;
 
@@ -131,4 +146,33 @@ mod tests {
         assert_eq!(lightning_data[1].magic_value, 2);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_fetch_lightning_from_endpoint() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let mock_response = serde_json::json!({
+            "historicalData": "[[1700000000,59.9139,10.7522,1]]"
+        });
+
+        let mock = server
+            .mock("GET", "/api/v0/lightning-events")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "fromHours".into(),
+                "24".into(),
+            ))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(mock_response.to_string())
+            .create_async()
+            .await;
+
+        let client = UpstreamClient::default();
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let lightning_data = Lightning::fetch(&client, &endpoints).await?;
+
+        mock.assert_async().await;
+        assert_eq!(lightning_data.len(), 1);
+        assert_eq!(lightning_data[0].location.y(), 59.9139);
+        Ok(())
+    }
 }
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema, IntoParams)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct City {
     /// Location name to search for (e.g., "Oslo", "London")
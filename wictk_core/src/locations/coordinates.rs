@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use utoipa::ToSchema;
 
-use super::location::LocationError;
+use crate::WictkError;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub struct Coordinates {
@@ -23,17 +23,15 @@ pub struct CoordinatesAsString {
 }
 
 impl TryFrom<CoordinatesAsString> for Coordinates {
-    type Error = LocationError;
+    type Error = WictkError;
 
     fn try_from(value: CoordinatesAsString) -> Result<Self, Self::Error> {
-        let lon = value
-            .lon
-            .parse::<f32>()
-            .map_err(|_| LocationError::new(format!("Could not parse longitude: {}", value.lon)))?;
-        let lat = value
-            .lat
-            .parse::<f32>()
-            .map_err(|_| LocationError::new(format!("Could not parse latitude: {}", value.lat)))?;
+        let lon = value.lon.parse::<f32>().map_err(|_| {
+            WictkError::invalid_input(&format!("Could not parse longitude: {}", value.lon))
+        })?;
+        let lat = value.lat.parse::<f32>().map_err(|_| {
+            WictkError::invalid_input(&format!("Could not parse latitude: {}", value.lat))
+        })?;
         Ok(Self { lon, lat })
     }
 }
@@ -1,11 +1,12 @@
 use std::collections::HashMap;
 
 use redact::Secret;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tracing::{error, info};
 use utoipa::ToSchema;
 
+use crate::{Endpoints, Upstream, UpstreamClient, WictkError};
+
 use super::Coordinates;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
@@ -20,50 +21,89 @@ pub struct OpenWeatherMapLocation {
 
 impl OpenWeatherMapLocation {
     pub async fn fetch(
-        client: &Client,
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
         location: &str,
         apikey: &Secret<String>,
-    ) -> Option<Vec<Self>> {
-        match client
-            .get("https://api.openweathermap.org/geo/1.0/direct")
+    ) -> Result<Vec<Self>, WictkError> {
+        let request = client
+            .get(format!("{}/geo/1.0/direct", endpoints.openweathermap))
             .query(&[("q", location)])
-            .query(&[("appid", apikey.expose_secret())])
-            .send()
+            .query(&[("appid", apikey.expose_secret())]);
+        let response = client.send(Upstream::OpenWeatherMap, request).await?;
+        info!("Statuscode from openweathermap: {}", response.status());
+        WictkError::check_status(Upstream::OpenWeatherMap, response)?
+            .json::<Vec<OpenWeatherMapLocation>>()
             .await
-        {
-            Ok(result) => {
-                info!("Statuscode from openweathermap: {}", result.status());
-                match result.json::<Vec<OpenWeatherMapLocation>>().await {
-                    Ok(res) => Some(res),
-                    Err(err) => {
-                        error!("Error: {}", err);
-                        None
-                    }
-                }
-            }
-            Err(err) => {
+            .map_err(|err| {
                 error!("Error: {}", err);
-                None
-            }
-        }
+                WictkError::parse("OpenWeatherMap geocoding response")
+            })
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-pub struct LocationError {
-    pub message: String,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fetch_from_endpoint() {
+        let json = r#"[{"name":"Trondheim","local_names":{"no":"Trondheim"},"lat":63.4305,"lon":10.3951,"country":"NO","state":"Trøndelag"}]"#;
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("q".into(), "Trondheim".into()),
+                mockito::Matcher::UrlEncoded("appid".into(), "test_api_key".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let locations = OpenWeatherMapLocation::fetch(
+            &UpstreamClient::default(),
+            &endpoints,
+            "Trondheim",
+            &Secret::new("test_api_key".to_string()),
+        )
+        .await
+        .unwrap();
 
-impl LocationError {
-    pub fn new(message: String) -> Self {
-        Self { message }
+        mock.assert_async().await;
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].name, "Trondheim");
+        assert_eq!(locations[0].location, Coordinates::new(10.3951, 63.4305));
     }
-}
 
-impl std::fmt::Display for LocationError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "LocationError: {}", self.message)
+    #[tokio::test]
+    async fn fetch_invalid_body() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/geo/1.0/direct")
+            .match_query(mockito::Matcher::Any)
+            .with_status(401)
+            .with_body(r#"{"cod":401,"message":"Invalid API key"}"#)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let locations = OpenWeatherMapLocation::fetch(
+            &UpstreamClient::default(),
+            &endpoints,
+            "Trondheim",
+            &Secret::new("wrong".to_string()),
+        )
+        .await;
+
+        assert_eq!(
+            locations.unwrap_err(),
+            WictkError::UpstreamUnavailable {
+                upstream: Upstream::OpenWeatherMap,
+                reason: "responded with 401 Unauthorized".to_string(),
+            }
+        );
     }
 }
-
-impl std::error::Error for LocationError {}
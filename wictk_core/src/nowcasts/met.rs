@@ -1,13 +1,17 @@
 use chrono::{DateTime, Utc};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::error;
 use utoipa::ToSchema;
 
-use crate::locations::Coordinates;
+use reqwest::StatusCode;
 
-use super::{Nowcast, NowcastError};
+use crate::{
+    conditional::with_if_modified_since, locations::Coordinates, CacheMetadata, Conditional,
+    Endpoints, Upstream, UpstreamClient, WictkError,
+};
+
+use super::Nowcast;
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct MetNowcast {
@@ -42,62 +46,65 @@ impl std::fmt::Display for MetNowcast {
 }
 
 impl TryFrom<serde_json::Value> for MetNowcast {
-    type Error = NowcastError;
+    type Error = WictkError;
 
     fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
         let location = value["geometry"]["coordinates"]
             .as_array()
-            .ok_or_else(|| NowcastError::new("Could not find location"))?;
-        let location = Coordinates::new(
-            location[0].as_f64().unwrap() as f32,
-            location[1].as_f64().unwrap() as f32,
-        );
+            .ok_or_else(|| WictkError::parse("location"))?;
+        let location = match location.as_slice() {
+            [lon, lat, ..] => Coordinates::new(
+                lon.as_f64().ok_or_else(|| WictkError::parse("longitude"))? as f32,
+                lat.as_f64().ok_or_else(|| WictkError::parse("latitude"))? as f32,
+            ),
+            _ => return Err(WictkError::parse("location")),
+        };
 
         let time = value["properties"]["meta"]["updated_at"]
             .as_str()
-            .ok_or_else(|| NowcastError::new("Could not find time"))?;
+            .ok_or_else(|| WictkError::parse("time"))?;
 
         let description = value["properties"]["timeseries"][0]["data"]["next_1_hours"]["summary"]
             ["symbol_code"]
             .as_str()
-            .ok_or_else(|| NowcastError::new("Could not find description"))?;
+            .ok_or_else(|| WictkError::parse("description"))?;
 
         let air_temperature = value["properties"]["timeseries"][0]["data"]["instant"]["details"]
             ["air_temperature"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find air_temperature"))?;
+            .ok_or_else(|| WictkError::parse("air_temperature"))?;
 
         let relative_humidity = value["properties"]["timeseries"][0]["data"]["instant"]["details"]
             ["relative_humidity"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find relative_humidity"))?;
+            .ok_or_else(|| WictkError::parse("relative_humidity"))?;
 
         let precipitation_amount = value["properties"]["timeseries"][0]["data"]["next_1_hours"]
             ["details"]["precipitation_amount"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find precipitation_amount"))?;
+            .ok_or_else(|| WictkError::parse("precipitation_amount"))?;
 
         let wind_speed = value["properties"]["timeseries"][0]["data"]["instant"]["details"]
             ["wind_speed"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find wind_speed"))?;
+            .ok_or_else(|| WictkError::parse("wind_speed"))?;
 
         let wind_speed_gust = value["properties"]["timeseries"][0]["data"]["instant"]["details"]
             ["wind_speed_of_gust"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find wind_speed_of_gust"))?;
+            .ok_or_else(|| WictkError::parse("wind_speed_of_gust"))?;
 
         let wind_from_direction = value["properties"]["timeseries"][0]["data"]["instant"]
             ["details"]["wind_from_direction"]
             .as_f64()
-            .ok_or_else(|| NowcastError::new("Could not find wind_from_direction"))?;
+            .ok_or_else(|| WictkError::parse("wind_from_direction"))?;
 
         Ok(Self {
             location,
             time: time
                 .to_string()
                 .parse()
-                .map_err(|_| NowcastError::new("Failed to parse time"))?,
+                .map_err(|_| WictkError::parse("time"))?,
             description: description.to_string(),
             air_temperature: air_temperature as f32,
             relative_humidity: relative_humidity as f32,
@@ -117,28 +124,43 @@ impl From<MetNowcast> for Nowcast {
 }
 
 impl MetNowcast {
-    pub async fn fetch(client: &Client, location: &Coordinates) -> Result<Nowcast, NowcastError> {
-        let met_cast: MetNowcast = client
-            .get("https://api.met.no/weatherapi/nowcast/2.0/complete")
-            .query(&[("lat", location.lat), ("lon", location.lon)])
-            .send()
-            .await
-            .map_err(|err| {
-                error!("Error {}", err);
-                NowcastError::new("Request to Met.no failed")
-            })?
+    pub async fn fetch(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+        location: &Coordinates,
+    ) -> Result<Nowcast, WictkError> {
+        Self::fetch_if_modified(client, endpoints, location, None)
+            .await?
+            .into_modified()
+    }
+
+    /// Fetches the nowcast unless it is unchanged since `if_modified_since`,
+    /// which should be the `Last-Modified` time of the cached nowcast.
+    pub async fn fetch_if_modified(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+        location: &Coordinates,
+        if_modified_since: Option<DateTime<Utc>>,
+    ) -> Result<Conditional<Nowcast>, WictkError> {
+        let request = client
+            .get(format!("{}/weatherapi/nowcast/2.0/complete", endpoints.met))
+            .query(&[("lat", location.lat), ("lon", location.lon)]);
+        let request = with_if_modified_since(request, if_modified_since);
+        let response = client.send(Upstream::Met, request).await?;
+        let metadata = CacheMetadata::from_headers(response.headers());
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Conditional::NotModified(metadata));
+        }
+        let met_cast: MetNowcast = WictkError::check_status(Upstream::Met, response)?
             .json::<Value>()
             .await
             .map_err(|err| {
                 error!("Error {}", err);
-                NowcastError::new("Deserialization from Met.no failed")
+                WictkError::parse("Met.no nowcast response")
             })?
             .try_into()
-            .map_err(|err| {
-                error!("Error {}", err);
-                NowcastError::new("Failed to convert from met value into nowcast type")
-            })?;
-        Ok(met_cast.into())
+            .inspect_err(|err| error!("Error {}", err))?;
+        Ok(Conditional::Modified(met_cast.into(), metadata))
     }
 }
 
@@ -166,6 +188,119 @@ mod tests {
         assert_eq!(met.wind_from_direction, 294.4);
     }
 
+    #[tokio::test]
+    async fn met_fetch_from_endpoint() {
+        let json = r#"{"type":"Feature","geometry":{"type":"Point","coordinates":[10.4034,63.4308,0]},"properties":{"meta":{"updated_at":"2023-08-14T18:16:07Z"},"timeseries":[{"time":"2023-08-14T18:15:00Z","data":{"instant":{"details":{"air_temperature":17.7,"precipitation_rate":0.0,"relative_humidity":80.5,"wind_from_direction":294.4,"wind_speed":2.7,"wind_speed_of_gust":6.1}},"next_1_hours":{"summary":{"symbol_code":"cloudy"},"details":{"precipitation_amount":0.0}}}}]}}"#;
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("lat".into(), "63.4308".into()),
+                mockito::Matcher::UrlEncoded("lon".into(), "10.4034".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let location = Coordinates::new(10.4034, 63.4308);
+        let nowcast = MetNowcast::fetch(&UpstreamClient::default(), &endpoints, &location)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        match nowcast {
+            Nowcast::Met(met) => {
+                assert_eq!(met.description, "cloudy");
+                assert_eq!(met.air_temperature, 17.7);
+            }
+            Nowcast::OpenWeather(_) => panic!("Expected Met nowcast"),
+        }
+    }
+
+    #[tokio::test]
+    async fn met_fetch_not_modified() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .match_header("if-modified-since", "Mon, 14 Aug 2023 18:16:07 GMT")
+            .with_status(304)
+            .with_header("expires", "Mon, 14 Aug 2023 18:21:07 GMT")
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let location = Coordinates::new(10.4034, 63.4308);
+        let since = "2023-08-14T18:16:07Z".parse().unwrap();
+        let nowcast = MetNowcast::fetch_if_modified(
+            &UpstreamClient::default(),
+            &endpoints,
+            &location,
+            Some(since),
+        )
+        .await
+        .unwrap();
+
+        mock.assert_async().await;
+        let Conditional::NotModified(metadata) = nowcast else {
+            panic!("Expected not modified");
+        };
+        assert_eq!(
+            metadata,
+            CacheMetadata {
+                expires: Some("2023-08-14T18:21:07Z".parse().unwrap()),
+                last_modified: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn met_fetch_invalid_body() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let location = Coordinates::new(10.4034, 63.4308);
+        let nowcast = MetNowcast::fetch(&UpstreamClient::default(), &endpoints, &location).await;
+
+        assert_eq!(nowcast.unwrap_err(), WictkError::parse("location"));
+    }
+
+    #[tokio::test]
+    async fn met_fetch_rate_limited() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/weatherapi/nowcast/2.0/complete")
+            .match_query(mockito::Matcher::Any)
+            .with_status(429)
+            .with_header("retry-after", "120")
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let location = Coordinates::new(10.4034, 63.4308);
+        let nowcast = MetNowcast::fetch(&UpstreamClient::default(), &endpoints, &location).await;
+
+        assert_eq!(
+            nowcast.unwrap_err(),
+            WictkError::UpstreamRateLimited {
+                upstream: Upstream::Met,
+                retry_after: Some(120),
+            }
+        );
+    }
+
+    // Predates clippy being enforced
+    #[allow(clippy::unnecessary_unwrap, clippy::assertions_on_constants)]
     #[tokio::test]
     async fn met_fetch() {
         let client_builder = reqwest::Client::builder();
@@ -194,7 +329,12 @@ mod tests {
         }
 
         let location = Coordinates::new(10.4034, 63.4308);
-        let nowcast = MetNowcast::fetch(&client, &location).await;
+        let nowcast = MetNowcast::fetch(
+            &UpstreamClient::from(client),
+            &Endpoints::default(),
+            &location,
+        )
+        .await;
         match &nowcast {
             Ok(_) => {
                 // Test passed
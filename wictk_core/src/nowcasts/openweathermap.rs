@@ -1,14 +1,13 @@
 use chrono::{DateTime, Utc};
 use redact::Secret;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tracing::error;
 use utoipa::ToSchema;
 
-use crate::locations::Coordinates;
+use crate::{locations::Coordinates, Endpoints, Upstream, UpstreamClient, WictkError};
 
-use super::{Nowcast, NowcastError};
+use super::Nowcast;
 
 #[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct OpenWeatherNowcast {
@@ -54,62 +53,57 @@ impl std::fmt::Display for OpenWeatherNowcast {
 }
 
 impl TryFrom<Value> for OpenWeatherNowcast {
-    type Error = NowcastError;
+    type Error = WictkError;
 
     fn try_from(value: Value) -> Result<Self, Self::Error> {
-        let dt = DateTime::from_timestamp(
-            value["dt"]
-                .as_i64()
-                .ok_or(NowcastError::new("Could not find dt"))?,
-            0,
-        )
-        .ok_or(NowcastError::new("Could not find dt"))?;
+        let dt = DateTime::from_timestamp(value["dt"].as_i64().ok_or(WictkError::parse("dt"))?, 0)
+            .ok_or(WictkError::parse("dt"))?;
         let name = value["name"]
             .as_str()
-            .ok_or(NowcastError::new("Could not find name"))?
+            .ok_or(WictkError::parse("name"))?
             .to_string();
         let country = value["sys"]["country"]
             .as_str()
-            .ok_or(NowcastError::new("Could not find country"))?
+            .ok_or(WictkError::parse("country"))?
             .to_string();
         let lon = value["coord"]["lon"]
             .as_f64()
-            .ok_or(NowcastError::new("Could not find lon"))? as f32;
+            .ok_or(WictkError::parse("lon"))? as f32;
         let lat = value["coord"]["lat"]
             .as_f64()
-            .ok_or(NowcastError::new("Could not find lat"))? as f32;
+            .ok_or(WictkError::parse("lat"))? as f32;
         let main = value["weather"][0]["main"]
             .as_str()
-            .ok_or(NowcastError::new("Could not find main"))?
+            .ok_or(WictkError::parse("main"))?
             .to_string();
         let desc = value["weather"][0]["description"]
             .as_str()
-            .ok_or(NowcastError::new("Could not find desc"))?
+            .ok_or(WictkError::parse("desc"))?
             .to_string();
         let clouds = value["clouds"]["all"]
             .as_u64()
-            .ok_or(NowcastError::new("Could not find clouds"))? as u32;
+            .ok_or(WictkError::parse("clouds"))? as u32;
         let wind_speed = value["wind"]["speed"]
             .as_f64()
-            .ok_or(NowcastError::new("Could not find wind_speed"))? as f32;
+            .ok_or(WictkError::parse("wind_speed"))? as f32;
         let wind_deg = value["wind"]["deg"]
             .as_i64()
-            .ok_or(NowcastError::new("Could not find wind_deg"))? as i32;
+            .ok_or(WictkError::parse("wind_deg"))? as i32;
         let visibility = value["visibility"]
             .as_i64()
-            .ok_or(NowcastError::new("Could not find visibility"))? as i32;
+            .ok_or(WictkError::parse("visibility"))? as i32;
         let temp = value["main"]["temp"]
             .as_f64()
-            .ok_or(NowcastError::new("Could not find temp"))? as f32;
+            .ok_or(WictkError::parse("temp"))? as f32;
         let feels_like = value["main"]["feels_like"]
             .as_f64()
-            .ok_or(NowcastError::new("Could not find feels_like"))? as f32;
+            .ok_or(WictkError::parse("feels_like"))? as f32;
         let humidity = value["main"]["humidity"]
             .as_u64()
-            .ok_or(NowcastError::new("Could not find humidity"))? as u32;
+            .ok_or(WictkError::parse("humidity"))? as u32;
         let pressure = value["main"]["pressure"]
             .as_u64()
-            .ok_or(NowcastError::new("Could not find pressure"))? as u32;
+            .ok_or(WictkError::parse("pressure"))? as u32;
 
         Ok(Self {
             dt,
@@ -139,39 +133,41 @@ impl From<OpenWeatherNowcast> for Nowcast {
 
 impl OpenWeatherNowcast {
     pub async fn fetch(
-        client: &Client,
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
         location: &Coordinates,
         apikey: &Secret<String>,
-    ) -> Result<Nowcast, NowcastError> {
-        let openweathermap: OpenWeatherNowcast = client
-            .get("https://api.openweathermap.org/data/2.5/weather")
+    ) -> Result<Nowcast, WictkError> {
+        let request = client
+            .get(format!("{}/data/2.5/weather", endpoints.openweathermap))
             .query(&[("lat", location.lat), ("lon", location.lon)])
             .query(&[("appid", apikey.expose_secret())])
-            .query(&[("units", "metric")])
-            .send()
-            .await
-            .map_err(|err| {
-                error!("Error {}", err);
-                NowcastError::new("Request to OpenWeatherMap failed")
-            })?
-            .json::<Value>()
-            .await
-            .map_err(|err| {
-                error!("Error {}", err);
-                NowcastError::new("Deserialization from OpenWeatherMap failed")
-            })?
-            .try_into()
-            .map_err(|err| {
-                error!("Error {}", err);
-                NowcastError::new("Failed to convert OpenWeatherMap value into nowcast type")
-            })?;
+            .query(&[("units", "metric")]);
+        let response = client.send(Upstream::OpenWeatherMap, request).await?;
+        let openweathermap: OpenWeatherNowcast =
+            WictkError::check_status(Upstream::OpenWeatherMap, response)?
+                .json::<Value>()
+                .await
+                .map_err(|err| {
+                    error!("Error {}", err);
+                    WictkError::parse("OpenWeatherMap weather response")
+                })?
+                .try_into()
+                .inspect_err(|err| error!("Error {}", err))?;
         Ok(openweathermap.into())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::nowcasts::OpenWeatherNowcast;
+    use crate::UpstreamClient;
+    use redact::Secret;
+
+    use crate::{
+        locations::Coordinates,
+        nowcasts::{Nowcast, OpenWeatherNowcast},
+        Endpoints,
+    };
 
     #[test]
     fn open_weathermap_from_value() {
@@ -197,4 +193,40 @@ mod tests {
         assert_eq!(open_weather.humidity, 92);
         assert_eq!(open_weather.pressure, 1014);
     }
+
+    #[tokio::test]
+    async fn open_weathermap_fetch_from_endpoint() {
+        let json = r#"{"coord":{"lon":10.3951,"lat":63.4305},"weather":[{"id":804,"main":"Clouds","description":"overcast clouds","icon":"04d"}],"main":{"temp":15.21,"feels_like":15.19,"pressure":1014,"humidity":92},"visibility":10000,"wind":{"speed":0.89,"deg":270},"clouds":{"all":99},"dt":1692185222,"sys":{"country":"NO"},"name":"Trondheim","cod":200}"#;
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/data/2.5/weather")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("appid".into(), "test_api_key".into()),
+                mockito::Matcher::UrlEncoded("units".into(), "metric".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json)
+            .create_async()
+            .await;
+
+        let endpoints = Endpoints::with_base_url(&server.url());
+        let nowcast = OpenWeatherNowcast::fetch(
+            &UpstreamClient::default(),
+            &endpoints,
+            &Coordinates::new(10.3951, 63.4305),
+            &Secret::new("test_api_key".to_string()),
+        )
+        .await
+        .unwrap();
+
+        mock.assert_async().await;
+        match nowcast {
+            Nowcast::OpenWeather(open_weather) => {
+                assert_eq!(open_weather.name, "Trondheim");
+                assert_eq!(open_weather.temp, 15.21);
+            }
+            Nowcast::Met(_) => panic!("Expected OpenWeather nowcast"),
+        }
+    }
 }
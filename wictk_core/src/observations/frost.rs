@@ -0,0 +1,343 @@
+use chrono::{DateTime, Utc};
+use redact::Secret;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{locations::Coordinates, Endpoints, Upstream, UpstreamClient, WictkError};
+
+/// Elements requested from Frost when fetching the latest observations.
+const ELEMENTS: &str = "air_temperature,relative_humidity,wind_speed,wind_from_direction,air_pressure_at_sea_level,sum(precipitation_amount PT1H)";
+
+/// A weather station registered in the Frost API.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct FrostStation {
+    /// Station id, e.g. "SN18700"
+    pub id: String,
+    pub name: String,
+    pub location: Coordinates,
+    /// Height above mean sea level in meters
+    pub masl: Option<f32>,
+    /// Distance in kilometers from the queried location
+    pub distance: Option<f32>,
+}
+
+/// A single observed element from a Frost station.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
+pub struct FrostObservation {
+    pub station_id: String,
+    pub time: DateTime<Utc>,
+    pub element: String,
+    pub value: f32,
+    pub unit: String,
+}
+
+impl TryFrom<Value> for FrostStation {
+    type Error = WictkError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let id = value["id"]
+            .as_str()
+            .ok_or_else(|| WictkError::parse("station id"))?
+            .to_owned();
+        let name = value["name"]
+            .as_str()
+            .ok_or_else(|| WictkError::parse("station name"))?
+            .to_owned();
+        let coordinates = value["geometry"]["coordinates"]
+            .as_array()
+            .ok_or_else(|| WictkError::parse("station coordinates"))?;
+        let lon = coordinates
+            .first()
+            .and_then(Value::as_f64)
+            .ok_or_else(|| WictkError::parse("station longitude"))?;
+        let lat = coordinates
+            .get(1)
+            .and_then(Value::as_f64)
+            .ok_or_else(|| WictkError::parse("station latitude"))?;
+        Ok(FrostStation {
+            id,
+            name,
+            location: Coordinates::new(lon as f32, lat as f32),
+            masl: value["masl"].as_f64().map(|masl| masl as f32),
+            distance: value["distance"].as_f64().map(|distance| distance as f32),
+        })
+    }
+}
+
+/// Converts one entry of the Frost observations `data` array into observations.
+fn observations_from_value(value: &Value) -> Result<Vec<FrostObservation>, WictkError> {
+    // sourceId is on the form "SN18700:0", where the suffix is the sensor number
+    let station_id = value["sourceId"]
+        .as_str()
+        .ok_or_else(|| WictkError::parse("sourceId"))?
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+    let time: DateTime<Utc> = value["referenceTime"]
+        .as_str()
+        .ok_or_else(|| WictkError::parse("referenceTime"))?
+        .parse()
+        .map_err(|_| WictkError::parse("referenceTime"))?;
+    let observations = value["observations"]
+        .as_array()
+        .ok_or_else(|| WictkError::parse("observations"))?
+        .iter()
+        .filter_map(|observation| {
+            Some(FrostObservation {
+                station_id: station_id.clone(),
+                time,
+                element: observation["elementId"].as_str()?.to_owned(),
+                value: observation["value"].as_f64()? as f32,
+                unit: observation["unit"].as_str()?.to_owned(),
+            })
+        })
+        .collect();
+    Ok(observations)
+}
+
+impl FrostStation {
+    /// Finds the stations closest to `location`, ordered by distance.
+    pub async fn nearest(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+        location: &Coordinates,
+        max_count: u32,
+        client_id: &Secret<String>,
+    ) -> Result<Vec<FrostStation>, WictkError> {
+        let request = client
+            .get(format!("{}/sources/v0.jsonld", endpoints.frost))
+            .basic_auth(client_id.expose_secret(), None::<&str>)
+            .query(&[
+                ("types", "SensorSystem".to_string()),
+                (
+                    "geometry",
+                    format!("nearest(POINT({} {}))", location.lon, location.lat),
+                ),
+                ("nearestmaxcount", max_count.to_string()),
+            ]);
+        let response = client.send(Upstream::Frost, request).await?;
+        let stations = WictkError::check_status(Upstream::Frost, response)?
+            .json::<Value>()
+            .await
+            .map_err(|err| {
+                error!("Error {}", err);
+                WictkError::parse("Frost response")
+            })?
+            .get("data")
+            .ok_or_else(|| WictkError::parse("data"))?
+            .as_array()
+            .ok_or_else(|| WictkError::parse("data"))?
+            .iter()
+            .filter_map(|station| FrostStation::try_from(station.clone()).ok())
+            .collect();
+        Ok(stations)
+    }
+}
+
+impl FrostObservation {
+    /// Fetches the latest observations for the given stations.
+    ///
+    /// Frost answers 404 when none of the stations have recent data, which is
+    /// returned as an empty list.
+    pub async fn latest(
+        client: &UpstreamClient,
+        endpoints: &Endpoints,
+        station_ids: &[String],
+        client_id: &Secret<String>,
+    ) -> Result<Vec<FrostObservation>, WictkError> {
+        let request = client
+            .get(format!("{}/observations/v0.jsonld", endpoints.frost))
+            .basic_auth(client_id.expose_secret(), None::<&str>)
+            .query(&[
+                ("sources", station_ids.join(",").as_str()),
+                ("referencetime", "latest"),
+                ("maxage", "PT3H"),
+                ("elements", ELEMENTS),
+            ]);
+        let response = client.send(Upstream::Frost, request).await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+
+        let observations = WictkError::check_status(Upstream::Frost, response)?
+            .json::<Value>()
+            .await
+            .map_err(|err| {
+                error!("Error {}", err);
+                WictkError::parse("Frost response")
+            })?
+            .get("data")
+            .ok_or_else(|| WictkError::parse("data"))?
+            .as_array()
+            .ok_or_else(|| WictkError::parse("data"))?
+            .iter()
+            .map(observations_from_value)
+            .collect::<Result<Vec<Vec<FrostObservation>>, WictkError>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(observations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const SOURCES: &str = r#"{"@context":"https://frost.met.no/schema","@type":"SourceResponse","apiVersion":"v0","data":[{"@type":"SensorSystem","id":"SN68860","name":"TRONDHEIM - VOLL","shortName":"Trondheim - Voll","country":"Norge","countryCode":"NO","geometry":{"@type":"Point","coordinates":[10.4533,63.4107],"nearest":false},"distance":2.71,"masl":127},{"@type":"SensorSystem","id":"SN68173","name":"TRONDHEIM - GLØSHAUGEN","country":"Norge","countryCode":"NO","geometry":{"@type":"Point","coordinates":[10.4063,63.4159],"nearest":false},"distance":1.64}]}"#;
+
+    const OBSERVATIONS: &str = r#"{"@context":"https://frost.met.no/schema","@type":"ObservationResponse","apiVersion":"v0","data":[{"sourceId":"SN68860:0","referenceTime":"2025-01-20T12:00:00.000Z","observations":[{"elementId":"air_temperature","value":-3.4,"unit":"degC","level":{"levelType":"height_above_ground","unit":"m","value":2},"timeOffset":"PT0H","timeResolution":"PT1M","qualityCode":0},{"elementId":"wind_speed","value":4.1,"unit":"m/s","timeOffset":"PT0H","timeResolution":"PT10M"}]}]}"#;
+
+    #[test]
+    fn station_from_value() {
+        let value: Value = serde_json::from_str(SOURCES).unwrap();
+        let station = FrostStation::try_from(value["data"][0].clone()).unwrap();
+
+        assert_eq!(station.id, "SN68860");
+        assert_eq!(station.name, "TRONDHEIM - VOLL");
+        assert_eq!(station.location, Coordinates::new(10.4533, 63.4107));
+        assert_eq!(station.masl, Some(127.0));
+        assert_eq!(station.distance, Some(2.71));
+    }
+
+    #[test]
+    fn observations_from_json() {
+        let value: Value = serde_json::from_str(OBSERVATIONS).unwrap();
+        let observations = observations_from_value(&value["data"][0]).unwrap();
+
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[0].station_id, "SN68860");
+        assert_eq!(observations[0].element, "air_temperature");
+        assert_eq!(observations[0].value, -3.4);
+        assert_eq!(observations[0].unit, "degC");
+        assert_eq!(
+            observations[0].time.to_rfc3339(),
+            "2025-01-20T12:00:00+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_nearest_stations() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/sources/v0.jsonld")
+            .match_header("authorization", Matcher::Regex("^Basic ".to_string()))
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("types".into(), "SensorSystem".into()),
+                Matcher::UrlEncoded("geometry".into(), "nearest(POINT(10.4034 63.4308))".into()),
+                Matcher::UrlEncoded("nearestmaxcount".into(), "2".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(SOURCES)
+            .create_async()
+            .await;
+
+        let client = UpstreamClient::default();
+        let stations = FrostStation::nearest(
+            &client,
+            &Endpoints::with_base_url(&server.url()),
+            &Coordinates::new(10.4034, 63.4308),
+            2,
+            &Secret::new("client-id".to_string()),
+        )
+        .await
+        .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(stations.len(), 2);
+        assert_eq!(stations[1].id, "SN68173");
+        assert_eq!(stations[1].masl, None);
+    }
+
+    #[tokio::test]
+    async fn fetch_latest_observations() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/observations/v0.jsonld")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("sources".into(), "SN68860,SN68173".into()),
+                Matcher::UrlEncoded("referencetime".into(), "latest".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(OBSERVATIONS)
+            .create_async()
+            .await;
+
+        let client = UpstreamClient::default();
+        let observations = FrostObservation::latest(
+            &client,
+            &Endpoints::with_base_url(&server.url()),
+            &["SN68860".to_string(), "SN68173".to_string()],
+            &Secret::new("client-id".to_string()),
+        )
+        .await
+        .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(observations.len(), 2);
+        assert_eq!(observations[1].element, "wind_speed");
+        assert_eq!(observations[1].value, 4.1);
+    }
+
+    #[tokio::test]
+    async fn fetch_latest_observations_not_found() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/observations/v0.jsonld")
+            .match_query(Matcher::Any)
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let client = UpstreamClient::default();
+        let observations = FrostObservation::latest(
+            &client,
+            &Endpoints::with_base_url(&server.url()),
+            &["SN1".to_string()],
+            &Secret::new("client-id".to_string()),
+        )
+        .await
+        .unwrap();
+
+        assert!(observations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetch_nearest_stations_unauthorized() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("GET", "/sources/v0.jsonld")
+            .match_query(Matcher::Any)
+            .with_status(401)
+            .create_async()
+            .await;
+
+        let client = UpstreamClient::default();
+        let result = FrostStation::nearest(
+            &client,
+            &Endpoints::with_base_url(&server.url()),
+            &Coordinates::new(10.4034, 63.4308),
+            1,
+            &Secret::new("wrong".to_string()),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(WictkError::UpstreamUnavailable {
+                upstream: Upstream::Frost,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,3 @@
+mod frost;
+
+pub use frost::{FrostObservation, FrostStation};
@@ -0,0 +1,216 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::Upstream;
+
+/// Query parameters that carry credentials and are never recorded or matched on.
+const SECRET_PARAMS: [&str; 1] = ["appid"];
+
+/// A recorded upstream request and its response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub path: String,
+    /// Query parameters, sorted and without secrets
+    #[serde(default)]
+    pub query: Vec<(String, String)>,
+    pub status: u16,
+    pub content_type: Option<String>,
+    /// JSON responses are stored as JSON, anything else as a string
+    pub body: Value,
+}
+
+impl Interaction {
+    /// Returns the response body as sent by the upstream.
+    pub fn body_text(&self) -> String {
+        match &self.body {
+            Value::String(body) => body.clone(),
+            body => body.to_string(),
+        }
+    }
+
+    /// Creates the body value to store for a response.
+    pub fn body_from_text(content_type: Option<&str>, body: &str) -> Value {
+        let is_json = content_type.is_some_and(|content_type| content_type.contains("json"));
+        match serde_json::from_str(body) {
+            Ok(value) if is_json => value,
+            _ => Value::String(body.to_string()),
+        }
+    }
+}
+
+/// Normalizes a query string into sorted pairs without secret parameters.
+pub fn normalize_query(query: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), decode(value))
+        })
+        .filter(|(key, _)| !SECRET_PARAMS.contains(&key.as_str()))
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+/// Percent-decodes a query component, treating `+` as a space.
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// A set of recorded interactions for one upstream, stored as a JSON file.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Loads the cassette for `upstream` from `dir`, or an empty one if none exists.
+    pub fn load(dir: &Path, upstream: Upstream) -> io::Result<Self> {
+        let path = dir.join(format!("{}.json", upstream.name()));
+        let interactions = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self { path, interactions })
+    }
+
+    /// Finds a recorded interaction matching the request.
+    pub fn find(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(String, String)],
+    ) -> Option<&Interaction> {
+        self.interactions.iter().find(|interaction| {
+            interaction.method.eq_ignore_ascii_case(method)
+                && interaction.path == path
+                && interaction.query == query
+        })
+    }
+
+    /// Adds an interaction, replacing any earlier recording of the same request.
+    pub fn record(&mut self, interaction: Interaction) {
+        self.interactions.retain(|recorded| {
+            !(recorded.method.eq_ignore_ascii_case(&interaction.method)
+                && recorded.path == interaction.path
+                && recorded.query == interaction.query)
+        });
+        self.interactions.push(interaction);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let content = serde_json::to_string_pretty(&self.interactions)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        fs::write(&self.path, content)
+    }
+
+    pub fn interactions(&self) -> &[Interaction] {
+        &self.interactions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn interaction(query: Vec<(String, String)>) -> Interaction {
+        Interaction {
+            method: "GET".to_string(),
+            path: "/data/2.5/weather".to_string(),
+            query,
+            status: 200,
+            content_type: Some("application/json".to_string()),
+            body: serde_json::json!({"name": "Trondheim"}),
+        }
+    }
+
+    #[test]
+    fn normalize_query_sorts_and_removes_secrets() {
+        let query = normalize_query("lon=10.4&lat=63.4&appid=secret&q=S%C3%B8r+Trondelag");
+        assert_eq!(
+            query,
+            vec![
+                ("lat".to_string(), "63.4".to_string()),
+                ("lon".to_string(), "10.4".to_string()),
+                ("q".to_string(), "Sør Trondelag".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn body_from_text_keeps_json() {
+        let body = Interaction::body_from_text(Some("application/json"), r#"{"a":1}"#);
+        assert_eq!(body, serde_json::json!({"a": 1}));
+
+        let body = Interaction::body_from_text(Some("text/plain"), r#"{"a":1}"#);
+        assert_eq!(body, Value::String(r#"{"a":1}"#.to_string()));
+    }
+
+    #[test]
+    fn record_save_and_find() {
+        let dir = std::env::temp_dir().join(format!("wictk_replay_{}", std::process::id()));
+        let query = normalize_query("lat=63.4&lon=10.4&appid=secret");
+
+        let mut cassette = Cassette::load(&dir, Upstream::OpenWeatherMap).unwrap();
+        assert!(cassette.interactions().is_empty());
+        cassette.record(interaction(query.clone()));
+        cassette.record(interaction(query.clone()));
+        cassette.save().unwrap();
+
+        let cassette = Cassette::load(&dir, Upstream::OpenWeatherMap).unwrap();
+        assert_eq!(cassette.interactions().len(), 1);
+        let found = cassette.find("get", "/data/2.5/weather", &query).unwrap();
+        assert_eq!(found.body_text(), r#"{"name":"Trondheim"}"#);
+        assert!(cassette
+            .find("GET", "/data/2.5/weather", &normalize_query("lat=1&lon=2"))
+            .is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn upstream_names_round_trip() {
+        for upstream in Upstream::ALL {
+            assert_eq!(Upstream::from_name(upstream.name()), Some(upstream));
+        }
+        assert_eq!(Upstream::from_name("unknown"), None);
+    }
+}
@@ -0,0 +1,687 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use metrics::{counter, gauge, histogram};
+use reqwest::{header, Client, Request, RequestBuilder, Response, StatusCode, Url};
+use tracing::{error, field, info_span, warn, Instrument};
+
+use crate::{Upstream, WictkError};
+
+/// How requests to one upstream are timed out, retried and cut off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpstreamPolicy {
+    /// Time allowed for each attempt
+    pub timeout: Duration,
+    /// Attempts after the first one, only for idempotent requests
+    pub max_retries: u32,
+    /// Upper bound of the jittered delay before the first retry, doubled for each retry
+    pub base_delay: Duration,
+    /// Longest delay between attempts, longer `Retry-After` values are not waited for
+    pub max_delay: Duration,
+    /// Consecutive failed requests that open the circuit breaker
+    pub failure_threshold: u32,
+    /// How long an open breaker fails requests before letting a trial request through
+    pub open_for: Duration,
+}
+
+impl Default for UpstreamPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            max_retries: 2,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            failure_threshold: 5,
+            open_for: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A trial request is in flight, everything else fails fast until it completes
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn metric(&self) -> f64 {
+        match self {
+            BreakerState::Closed { .. } => 0.0,
+            BreakerState::HalfOpen => 1.0,
+            BreakerState::Open { .. } => 2.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    upstream: Upstream,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    fn new(upstream: Upstream) -> Self {
+        gauge!("upstream_circuit_state", "upstream" => upstream.name()).set(0.0);
+        Self {
+            upstream,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    fn set(&self, state: &mut BreakerState, new: BreakerState) {
+        *state = new;
+        gauge!("upstream_circuit_state", "upstream" => self.upstream.name()).set(new.metric());
+    }
+
+    /// Returns `None` if the request should fail fast.
+    fn allow<'a>(&'a self, policy: &'a UpstreamPolicy) -> Option<BreakerPermit<'a>> {
+        let mut state = self.state.lock().unwrap();
+        let trial = match *state {
+            BreakerState::Closed { .. } => false,
+            BreakerState::Open { until } if Instant::now() >= until => {
+                self.set(&mut state, BreakerState::HalfOpen);
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => return None,
+        };
+        Some(BreakerPermit {
+            breaker: self,
+            policy,
+            trial,
+        })
+    }
+
+    fn record(&self, success: bool, policy: &UpstreamPolicy) {
+        let mut state = self.state.lock().unwrap();
+        let new = match (*state, success) {
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false)
+                if failures + 1 < policy.failure_threshold =>
+            {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => {
+                if !matches!(*state, BreakerState::Open { .. }) {
+                    warn!(
+                        "Opening the circuit breaker of {} for {:?}",
+                        self.upstream, policy.open_for
+                    );
+                }
+                BreakerState::Open {
+                    until: Instant::now() + policy.open_for,
+                }
+            }
+        };
+        if new != *state {
+            self.set(&mut state, new);
+        }
+    }
+}
+
+/// A request let through by a [`CircuitBreaker`], recording its outcome.
+/// A trial request dropped before it completes, e.g. when the caller gives up,
+/// counts as failed, so the breaker does not stay half-open.
+struct BreakerPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    policy: &'a UpstreamPolicy,
+    trial: bool,
+}
+
+impl BreakerPermit<'_> {
+    fn record(mut self, success: bool) {
+        self.trial = false;
+        self.breaker.record(success, self.policy);
+    }
+
+    /// Gives up without sending the request, a trial is left to the next request.
+    fn release(mut self) {
+        if self.trial {
+            self.trial = false;
+            let mut state = self.breaker.state.lock().unwrap();
+            self.breaker.set(
+                &mut state,
+                BreakerState::Open {
+                    until: Instant::now(),
+                },
+            );
+        }
+    }
+}
+
+impl Drop for BreakerPermit<'_> {
+    fn drop(&mut self) {
+        if self.trial {
+            warn!("Trial request to {} was cancelled", self.breaker.upstream);
+            self.breaker.record(false, self.policy);
+        }
+    }
+}
+
+/// Asked right before every request to an upstream is sent, retries included,
+/// e.g. to count calls against a quota. Requests the circuit breaker rejects
+/// are not asked about. A rejected request is not sent and fails with the
+/// returned error.
+pub trait CallGuard: std::fmt::Debug + Send + Sync {
+    fn admit(&self, upstream: Upstream, url: &Url) -> Result<(), WictkError>;
+}
+
+/// HTTP client for the upstream APIs, applying the [`UpstreamPolicy`] of each
+/// upstream. Cheap to clone, clones share the circuit breakers.
+#[derive(Debug, Clone)]
+pub struct UpstreamClient {
+    client: Client,
+    policies: Arc<HashMap<Upstream, UpstreamPolicy>>,
+    breakers: Arc<HashMap<Upstream, CircuitBreaker>>,
+    guard: Option<Arc<dyn CallGuard>>,
+}
+
+impl Default for UpstreamClient {
+    fn default() -> Self {
+        Self::new(Client::new())
+    }
+}
+
+impl From<Client> for UpstreamClient {
+    fn from(client: Client) -> Self {
+        Self::new(client)
+    }
+}
+
+impl UpstreamClient {
+    /// Uses the default policy for every upstream.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            policies: Arc::new(
+                Upstream::ALL
+                    .into_iter()
+                    .map(|upstream| (upstream, UpstreamPolicy::default()))
+                    .collect(),
+            ),
+            breakers: Arc::new(
+                Upstream::ALL
+                    .into_iter()
+                    .map(|upstream| (upstream, CircuitBreaker::new(upstream)))
+                    .collect(),
+            ),
+            guard: None,
+        }
+    }
+
+    pub fn with_policy(mut self, upstream: Upstream, policy: UpstreamPolicy) -> Self {
+        Arc::make_mut(&mut self.policies).insert(upstream, policy);
+        self
+    }
+
+    pub fn with_guard(self, guard: Arc<dyn CallGuard>) -> Self {
+        Self {
+            guard: Some(guard),
+            ..self
+        }
+    }
+
+    pub fn policy(&self, upstream: Upstream) -> &UpstreamPolicy {
+        &self.policies[&upstream]
+    }
+
+    /// The underlying client, for requests that should bypass retries and breakers.
+    pub fn inner(&self) -> &Client {
+        &self.client
+    }
+
+    fn admit(&self, upstream: Upstream, url: &Url) -> Result<(), WictkError> {
+        match &self.guard {
+            Some(guard) => guard.admit(upstream, url),
+            None => Ok(()),
+        }
+    }
+
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// Sends `request` to `upstream`, retrying transient failures.
+    ///
+    /// Error statuses are returned as responses once retries are exhausted, so
+    /// callers decide what they mean with [`WictkError::check_status`].
+    pub async fn send(
+        &self,
+        upstream: Upstream,
+        request: RequestBuilder,
+    ) -> Result<Response, WictkError> {
+        let policy = self.policy(upstream);
+        let request = request.timeout(policy.timeout).build().map_err(|err| {
+            error!("Invalid request to {}: {}", upstream, err);
+            WictkError::request(upstream, &err)
+        })?;
+        let Some(permit) = self.breakers[&upstream].allow(policy) else {
+            counter!("upstream_circuit_rejections_total", "upstream" => upstream.name())
+                .increment(1);
+            return Err(WictkError::UpstreamUnavailable {
+                upstream,
+                reason: "circuit breaker is open after repeated failures".to_owned(),
+            });
+        };
+        if let Err(err) = self.admit(upstream, request.url()) {
+            permit.release();
+            return Err(err);
+        }
+        let retries = match request.method().is_idempotent() {
+            true => policy.max_retries,
+            false => 0,
+        };
+
+        let mut attempt = 0;
+        let result = loop {
+            let Some(this_attempt) = request.try_clone() else {
+                break self.execute(upstream, request).await;
+            };
+            let result = self.execute(upstream, this_attempt).await;
+            let delay = match &result {
+                Ok(response) if !is_transient(response.status()) => break result,
+                Ok(response) => retry_after(response)
+                    .unwrap_or_else(|| backoff(policy.base_delay, policy.max_delay, attempt)),
+                Err(_) => backoff(policy.base_delay, policy.max_delay, attempt),
+            };
+            if attempt >= retries
+                || delay > policy.max_delay
+                || self.admit(upstream, request.url()).is_err()
+            {
+                break result;
+            }
+            match &result {
+                Ok(response) => warn!(
+                    "{} responded with {}, retrying in {:?}",
+                    upstream,
+                    response.status(),
+                    delay
+                ),
+                Err(err) => warn!(
+                    "Request to {} failed: {}, retrying in {:?}",
+                    upstream, err, delay
+                ),
+            }
+            counter!("upstream_retries_total", "upstream" => upstream.name()).increment(1);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        };
+
+        match result {
+            Ok(response) => {
+                permit.record(!is_transient(response.status()));
+                Ok(response)
+            }
+            Err(err) => {
+                error!("Request to {} failed: {}", upstream, err);
+                permit.record(false);
+                Err(WictkError::request(upstream, &err))
+            }
+        }
+    }
+
+    /// Sends a single attempt in its own span, recording its status and latency.
+    /// The span has the path but not the query, which may hold credentials.
+    async fn execute(&self, upstream: Upstream, request: Request) -> reqwest::Result<Response> {
+        let span = info_span!(
+            "upstream_request",
+            otel.name = %format!("{} {}", request.method(), upstream.name()),
+            otel.kind = "client",
+            upstream = upstream.name(),
+            http.request.method = %request.method(),
+            url.path = request.url().path(),
+            http.response.status_code = field::Empty,
+        );
+        let started = Instant::now();
+        let result = self.client.execute(request).instrument(span.clone()).await;
+        if let Ok(response) = &result {
+            span.record("http.response.status_code", response.status().as_u16());
+        }
+        let status = match &result {
+            Ok(response) => response.status().as_str().to_owned(),
+            Err(err) if err.is_timeout() => "timeout".to_owned(),
+            Err(_) => "error".to_owned(),
+        };
+        let labels = [("upstream", upstream.name().to_owned()), ("status", status)];
+        counter!("upstream_requests_total", &labels).increment(1);
+        histogram!("upstream_request_duration_seconds", &labels).record(started.elapsed());
+        result
+    }
+}
+
+/// Statuses worth retrying, which also count as failures for the breaker.
+pub fn is_transient(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// The delay asked for by a `Retry-After` header given in seconds.
+pub fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Exponential backoff with full jitter, so replicas do not retry in lockstep.
+/// The ceiling starts at `base_delay`, doubles for each attempt and stops at `max_delay`.
+pub fn backoff(base_delay: Duration, max_delay: Duration, attempt: u32) -> Duration {
+    let ceiling = base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(max_delay);
+    ceiling.mul_f64(fastrand::f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn client(policy: UpstreamPolicy) -> UpstreamClient {
+        UpstreamClient::new(Client::new()).with_policy(Upstream::Met, policy)
+    }
+
+    fn fast() -> UpstreamPolicy {
+        UpstreamPolicy {
+            base_delay: Duration::from_millis(1),
+            ..UpstreamPolicy::default()
+        }
+    }
+
+    /// Admits `limit` requests and rejects the rest
+    #[derive(Debug)]
+    struct Limit {
+        limit: usize,
+        calls: Mutex<Vec<(Upstream, String)>>,
+    }
+
+    impl CallGuard for Limit {
+        fn admit(&self, upstream: Upstream, url: &Url) -> Result<(), WictkError> {
+            let mut calls = self.calls.lock().unwrap();
+            if calls.len() >= self.limit {
+                return Err(WictkError::UpstreamRateLimited {
+                    upstream,
+                    retry_after: None,
+                });
+            }
+            calls.push((upstream, url.path().to_string()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn guard_admits_every_attempt() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("GET", "/data")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+        let guard = Arc::new(Limit {
+            limit: 2,
+            calls: Mutex::default(),
+        });
+        let client = client(fast()).with_guard(guard.clone());
+
+        let url = format!("{}/data", server.url());
+        let response = client.send(Upstream::Met, client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            guard.calls.lock().unwrap()[0],
+            (Upstream::Met, "/data".to_string())
+        );
+
+        let err = client
+            .send(Upstream::Met, client.get(&url))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, WictkError::UpstreamRateLimited { .. }));
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn guard_is_not_asked_while_the_breaker_is_open() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("GET", "/")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        let guard = Arc::new(Limit {
+            limit: 10,
+            calls: Mutex::default(),
+        });
+        let client = client(UpstreamPolicy {
+            max_retries: 0,
+            failure_threshold: 1,
+            ..fast()
+        })
+        .with_guard(guard.clone());
+
+        client
+            .send(Upstream::Met, client.get(server.url()))
+            .await
+            .unwrap();
+        let rejected = client.send(Upstream::Met, client.get(server.url())).await;
+
+        assert!(matches!(
+            rejected,
+            Err(WictkError::UpstreamUnavailable { .. })
+        ));
+        assert_eq!(guard.calls.lock().unwrap().len(), 1);
+        failing.assert_async().await;
+    }
+
+    #[test]
+    fn released_trial_leaves_the_breaker_open() {
+        let policy = UpstreamPolicy {
+            failure_threshold: 1,
+            open_for: Duration::ZERO,
+            ..fast()
+        };
+        let breaker = CircuitBreaker::new(Upstream::Met);
+
+        breaker.allow(&policy).unwrap().record(false);
+        breaker.allow(&policy).unwrap().release();
+        assert!(breaker.allow(&policy).is_some());
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("GET", "/")
+            .with_status(503)
+            .expect(3)
+            .create_async()
+            .await;
+        let client = client(fast());
+
+        let response = client.send(Upstream::Met, client.get(server.url())).await;
+
+        assert_eq!(response.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let mut server = mockito::Server::new_async().await;
+        let not_found = server
+            .mock("GET", "/")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(fast());
+
+        let response = client.send(Upstream::Met, client.get(server.url())).await;
+
+        assert_eq!(response.unwrap().status(), StatusCode::NOT_FOUND);
+        not_found.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_wait_for_long_retry_after() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("GET", "/")
+            .with_status(429)
+            .with_header("retry-after", "60")
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(fast());
+
+        let response = client.send(Upstream::Met, client.get(server.url())).await;
+
+        assert_eq!(response.unwrap().status(), StatusCode::TOO_MANY_REQUESTS);
+        limited.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn honours_short_retry_after() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("GET", "/")
+            .with_status(429)
+            .with_header("retry-after", "1")
+            .expect(2)
+            .create_async()
+            .await;
+        let client = client(UpstreamPolicy {
+            max_retries: 1,
+            ..fast()
+        });
+
+        let started = Instant::now();
+        client
+            .send(Upstream::Met, client.get(server.url()))
+            .await
+            .unwrap();
+
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        limited.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_non_idempotent_requests() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("POST", "/")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        let client = client(fast());
+
+        let request = client.inner().post(server.url());
+        client.send(Upstream::Met, request).await.unwrap();
+
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn breaker_opens_and_recovers() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server
+            .mock("GET", "/")
+            .match_query(Matcher::Any)
+            .with_status(500)
+            .expect(2)
+            .create_async()
+            .await;
+        let client = client(UpstreamPolicy {
+            max_retries: 0,
+            failure_threshold: 2,
+            open_for: Duration::from_millis(50),
+            ..fast()
+        });
+
+        for _ in 0..2 {
+            client
+                .send(Upstream::Met, client.get(server.url()))
+                .await
+                .unwrap();
+        }
+        let rejected = client.send(Upstream::Met, client.get(server.url())).await;
+        failing.assert_async().await;
+        assert_eq!(
+            rejected.unwrap_err(),
+            WictkError::UpstreamUnavailable {
+                upstream: Upstream::Met,
+                reason: "circuit breaker is open after repeated failures".to_owned(),
+            }
+        );
+        // Other upstreams have their own breaker
+        assert!(client
+            .send(Upstream::Yr, client.get(server.url()))
+            .await
+            .is_ok());
+
+        failing.remove_async().await;
+        let ok = server
+            .mock("GET", "/")
+            .with_status(200)
+            .expect(2)
+            .create_async()
+            .await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        for _ in 0..2 {
+            let response = client.send(Upstream::Met, client.get(server.url())).await;
+            assert_eq!(response.unwrap().status(), StatusCode::OK);
+        }
+        ok.assert_async().await;
+    }
+
+    #[test]
+    fn dropped_trial_reopens_the_breaker() {
+        let policy = UpstreamPolicy {
+            failure_threshold: 1,
+            open_for: Duration::ZERO,
+            ..fast()
+        };
+        let breaker = CircuitBreaker::new(Upstream::Met);
+
+        breaker.allow(&policy).unwrap().record(false);
+        let trial = breaker.allow(&policy).unwrap();
+        assert!(breaker.allow(&policy).is_none());
+
+        drop(trial);
+        assert!(matches!(
+            *breaker.state.lock().unwrap(),
+            BreakerState::Open { .. }
+        ));
+        breaker.allow(&policy).unwrap().record(true);
+        assert_eq!(
+            *breaker.state.lock().unwrap(),
+            BreakerState::Closed { failures: 0 }
+        );
+    }
+
+    #[test]
+    fn backoff_is_bounded() {
+        let policy = UpstreamPolicy::default();
+        for attempt in 0..40 {
+            assert!(backoff(policy.base_delay, policy.max_delay, attempt) <= policy.max_delay);
+        }
+        assert!(backoff(policy.base_delay, policy.max_delay, 0) <= policy.base_delay);
+    }
+}
@@ -0,0 +1,250 @@
+//! Logging and OpenTelemetry trace export shared by the binaries.
+//!
+//! Traces follow requests across the services through W3C `traceparent`
+//! headers: clients add them with [`propagate`], the backend continues the
+//! trace with [`continue_trace`].
+
+use std::{fmt, str::FromStr};
+
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use reqwest::{header::HeaderMap, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::{level_filters::LevelFilter, Level, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::Targets, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer,
+};
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("unknown log format, expected text or json".to_string()),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Exports the remaining spans when dropped, keep it until the program exits.
+#[must_use = "spans are only flushed on exit while this is kept"]
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take() {
+            if let Err(err) = provider.shutdown() {
+                eprintln!("Failed to export the remaining spans: {err}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber, logging at `level` in `format`. With an
+/// `otlp_endpoint` such as `http://localhost:4318`, spans are also exported
+/// as `service` over OTLP/HTTP.
+pub fn init(
+    service: &'static str,
+    level: Level,
+    format: LogFormat,
+    otlp_endpoint: Option<&str>,
+) -> Result<Telemetry, ExporterBuildError> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = otlp_endpoint
+        .map(|endpoint| tracer_provider(service, endpoint))
+        .transpose()?;
+    tracing_subscriber::registry()
+        .with(layers(service, level, format, provider.as_ref()))
+        .init();
+    Ok(Telemetry { provider })
+}
+
+fn tracer_provider(
+    service: &'static str,
+    endpoint: &str,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(service).build())
+        .with_batch_exporter(exporter)
+        .build())
+}
+
+fn layers<S>(
+    service: &'static str,
+    level: Level,
+    format: LogFormat,
+    provider: Option<&SdkTracerProvider>,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span> + Send + Sync,
+{
+    let logs = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+    let traces = provider.map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer(service))
+            // The exporter's own HTTP requests are not worth tracing
+            .with_filter(
+                Targets::new()
+                    .with_default(LevelFilter::TRACE)
+                    .with_target("opentelemetry", LevelFilter::OFF)
+                    .with_target("opentelemetry_sdk", LevelFilter::OFF)
+                    .with_target("opentelemetry_otlp", LevelFilter::OFF)
+                    .with_target("hyper", LevelFilter::OFF)
+                    .with_target("hyper_util", LevelFilter::OFF)
+                    .with_target("reqwest", LevelFilter::OFF),
+            )
+    });
+    logs.and_then(traces)
+        .with_filter(LevelFilter::from_level(level))
+        .boxed()
+}
+
+/// The `traceparent` header of the current span, empty outside of a trace.
+pub fn trace_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(&mut headers))
+    });
+    headers
+}
+
+/// Sends the current trace along with `request`.
+pub fn propagate(request: RequestBuilder) -> RequestBuilder {
+    request.headers(trace_headers())
+}
+
+/// Makes `span` part of the trace a client sent in `headers`, if any.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    // Fails only without the OpenTelemetry layer, when nothing is exported anyway
+    let _ = span.set_parent(parent);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+    };
+
+    use opentelemetry::trace::TraceContextExt;
+    use tracing::info_span;
+
+    use super::*;
+
+    /// Stands in for an OpenTelemetry collector, keeping the request bodies.
+    fn collector() -> (String, Arc<Mutex<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let bodies = received.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.lock().unwrap().extend(body);
+                reader
+                    .into_inner()
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .unwrap();
+            }
+        });
+        (endpoint, received)
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle.as_bytes())
+    }
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!(LogFormat::Text.to_string(), "text");
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn exports_spans_continuing_the_client_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let (endpoint, received) = collector();
+        let provider = tracer_provider("wictk-test", &endpoint).unwrap();
+        let subscriber = tracing_subscriber::registry().with(layers(
+            "wictk-test",
+            Level::INFO,
+            LogFormat::Text,
+            Some(&provider),
+        ));
+
+        let (client_trace, server_trace) = tracing::subscriber::with_default(subscriber, || {
+            let client = info_span!("notify");
+            let headers = client.in_scope(trace_headers);
+            assert!(headers.contains_key("traceparent"));
+
+            let server = info_span!("GET /api/alerts");
+            continue_trace(&server, &headers);
+            let _entered = server.enter();
+            (
+                client.context().span().span_context().trace_id(),
+                server.context().span().span_context().trace_id(),
+            )
+        });
+        provider.shutdown().unwrap();
+
+        assert_eq!(client_trace, server_trace);
+        let received = received.lock().unwrap();
+        assert!(contains(&received, "GET /api/alerts"));
+        assert!(contains(&received, "wictk-test"));
+    }
+}